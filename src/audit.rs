@@ -0,0 +1,200 @@
+use std::{
+    collections::VecDeque,
+    fs::{File, OpenOptions},
+    future::Future,
+    io::{BufRead, BufReader, Write},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse},
+    web, Error,
+};
+use bragi_core::settings::{Role, Tokens};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::auth::{bearer_token, fingerprint};
+
+/// Entries served by `/api/v1/admin/audit`. The file keeps all of them.
+const RECENT_CAPACITY: usize = 1000;
+
+/// One admin operation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// unix seconds
+    pub at: u64,
+    /// fingerprint of the token, never the token itself. Absent if the api is open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+}
+
+/// Admin operations, appended to a json lines file if `filename` is present
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+    /// newest first
+    recent: Mutex<VecDeque<AuditEntry>>,
+}
+
+impl AuditLog {
+    pub fn try_new(filename: Option<String>) -> anyhow::Result<Self> {
+        let Some(filename) = filename else {
+            return Ok(Self::default());
+        };
+        if let Some(parent) = std::path::Path::new(&filename).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let mut recent = VecDeque::new();
+        if let Ok(file) = File::open(&filename) {
+            for line in BufReader::new(file).lines() {
+                if let Ok(entry) = serde_json::from_str(&line?) {
+                    recent.push_front(entry);
+                    recent.truncate(RECENT_CAPACITY);
+                }
+            }
+        }
+        info!("load audit log from {}: {} entries", filename, recent.len());
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&filename)?;
+        Ok(Self {
+            file: Some(Mutex::new(file)),
+            recent: Mutex::new(recent),
+        })
+    }
+
+    pub fn record(&self, entry: AuditEntry) {
+        info!(
+            "[Audit] {} {} {} by {}",
+            entry.method,
+            entry.path,
+            entry.status,
+            entry.token.as_deref().unwrap_or("anyone")
+        );
+        if let Some(file) = &self.file {
+            let written = serde_json::to_string(&entry)
+                .map_err(anyhow::Error::from)
+                .and_then(|line| Ok(writeln!(file.lock(), "{}", line)?));
+            if let Err(e) = written {
+                error!("write audit log failed: {}", e);
+            }
+        }
+        let mut recent = self.recent.lock();
+        recent.push_front(entry);
+        recent.truncate(RECENT_CAPACITY);
+    }
+
+    /// The latest entries, newest first
+    pub fn recent(&self, limit: usize) -> Vec<AuditEntry> {
+        self.recent.lock().iter().take(limit).cloned().collect()
+    }
+}
+
+/// Middleware of the admin scope recording every call but reads, along with the token which made
+/// it. Calls rejected by the authorization never get here.
+pub fn record<S, B>(
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<B>, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+{
+    let audited = !req.method().is_safe();
+    let log = req.app_data::<web::Data<AuditLog>>().cloned();
+    let token = bearer_token(req.headers()).map(str::to_string);
+    let role = match (&token, req.app_data::<web::Data<Tokens>>()) {
+        (Some(token), Some(tokens)) => tokens.role(token),
+        _ => None,
+    };
+    let method = req.method().to_string();
+    let path = req.path().to_string();
+
+    let call = srv.call(req);
+    async move {
+        let resp = call.await?;
+        if let (true, Some(log)) = (audited, log) {
+            log.record(AuditEntry {
+                at: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs(),
+                token: token.as_deref().map(fingerprint),
+                role,
+                method,
+                path,
+                status: resp.status().as_u16(),
+            });
+        }
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+    use bragi_core::settings::{Role, Tokens};
+
+    use crate::auth::fingerprint;
+
+    use super::{record, AuditLog};
+
+    #[actix_web::test]
+    async fn test_record() {
+        let filename = std::env::temp_dir()
+            .join(format!("bragi-audit-{}.jsonl", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let tokens = Tokens::Admin(["T0keN__01".to_string()].into());
+        let log = web::Data::new(AuditLog::try_new(Some(filename.clone())).unwrap());
+        let app = init_service(
+            App::new()
+                .app_data(log.clone())
+                .app_data(web::Data::new(tokens))
+                .service(
+                    web::scope("/admin")
+                        .wrap_fn(record)
+                        .default_service(web::to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        for method in ["GET", "POST"] {
+            let req = TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri("/admin/aliases")
+                .insert_header(("Authorization", "Bearer T0keN__01"))
+                .to_request();
+            call_service(&app, req).await;
+        }
+
+        // reads are not recorded
+        let entries = log.recent(10);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].method, "POST");
+        assert_eq!(entries[0].path, "/admin/aliases");
+        assert_eq!(entries[0].role, Some(Role::Admin));
+        assert_eq!(
+            entries[0].token.as_deref(),
+            Some(fingerprint("T0keN__01").as_str())
+        );
+
+        // reloaded from the file
+        let log = AuditLog::try_new(Some(filename.clone())).unwrap();
+        assert_eq!(log.recent(10).len(), 1);
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -0,0 +1,395 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    dev::ServiceRequest,
+    http::{
+        header::{HeaderMap, AUTHORIZATION},
+        Method, StatusCode,
+    },
+    web, Error,
+};
+use actix_web_httpauth::extractors::bearer::BearerAuth;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use bragi_core::settings::{Role, Tokens};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use sha2::Sha256;
+
+use crate::error::ApiError;
+
+/// Role required by the api call: admin for `/admin`, user for every call other than a read, like
+/// changes of the library, rooms and devices or recognizing a clip, readonly for reads
+fn required_role(method: &Method, path: &str) -> Role {
+    let path = path.trim_start_matches("/api/v1");
+    if path.starts_with("/admin") {
+        return Role::Admin;
+    }
+    match *method {
+        Method::GET | Method::HEAD => Role::Readonly,
+        _ => Role::User,
+    }
+}
+
+/// Paths open to calls requiring no more than the readonly role without a token
+#[derive(Debug, Default)]
+pub struct ExemptPaths(Vec<String>);
+
+impl ExemptPaths {
+    pub fn new(paths: Vec<String>) -> Self {
+        Self(paths)
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        let path = path.trim_start_matches("/api/v1");
+        self.0.iter().any(|exempt| match exempt.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == exempt,
+        })
+    }
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+}
+
+lazy_static! {
+    /// Key of the url signatures, new on every start
+    static ref SIGNING_KEY: [u8; 32] = rand::random();
+}
+
+/// Lifetime of signed urls in seconds, urls are not signed if 0
+static SIGNED_URL_TTL: AtomicU64 = AtomicU64::new(0);
+
+/// Sign the urls handed out from now on, for the whole process. Only needed with tokens, since
+/// the api is open without.
+pub fn sign_urls(ttl: Duration) {
+    SIGNED_URL_TTL.store(ttl.as_secs().max(1), Ordering::Relaxed);
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn mac(url: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(&*SIGNING_KEY).expect("hmac takes any key");
+    mac.update(url.as_bytes());
+    mac
+}
+
+/// The url of this server with an expiry and a signature appended, so that players can load it
+/// without the bearer token, like `<audio>` or HLS clients. As it is if urls are not signed.
+pub fn sign(url: &str) -> String {
+    match SIGNED_URL_TTL.load(Ordering::Relaxed) {
+        0 => url.to_string(),
+        ttl => sign_at(url, now() + ttl),
+    }
+}
+
+fn sign_at(url: &str, expires: u64) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    let url = format!("{}{}expires={}", url, separator, expires);
+    let signature = URL_SAFE_NO_PAD.encode(mac(&url).finalize().into_bytes());
+    format!("{}&sig={}", url, signature)
+}
+
+/// Whether the path and query are signed by `sign` and not expired yet
+fn verify(path_and_query: &str, now: u64) -> bool {
+    let Some((url, signature)) = path_and_query.rsplit_once("&sig=") else {
+        return false;
+    };
+    let expires = url
+        .rsplit_once(['?', '&'])
+        .and_then(|(_, last)| last.strip_prefix("expires="))
+        .and_then(|e| e.parse::<u64>().ok());
+    match (expires, URL_SAFE_NO_PAD.decode(signature)) {
+        (Some(expires), Ok(signature)) if expires >= now => {
+            mac(url).verify_slice(&signature).is_ok()
+        }
+        _ => false,
+    }
+}
+
+/// Same for the same token across restarts, so that tokens can be told apart without storing
+/// them
+pub fn fingerprint(token: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    token.hash(&mut hasher);
+    format!("#{:08x}", hasher.finish() >> 32)
+}
+
+/// Check the bearer token against the role required by the call. Unknown or missing tokens get
+/// 401, tokens of a lower role 403. Everything is allowed if no tokens are configured, readonly
+/// calls of exempt paths or of signed urls without a token as well.
+pub async fn authorize(
+    req: ServiceRequest,
+    auth: Option<BearerAuth>,
+) -> Result<ServiceRequest, (Error, ServiceRequest)> {
+    let Some(tokens) = req.app_data::<web::Data<Tokens>>() else {
+        return Ok(req);
+    };
+    if tokens.is_empty() {
+        return Ok(req);
+    }
+
+    let required = required_role(req.method(), req.path());
+    let exempt = req
+        .app_data::<web::Data<ExemptPaths>>()
+        .is_some_and(|e| e.contains(req.path()));
+    let signed = req
+        .uri()
+        .path_and_query()
+        .is_some_and(|pq| verify(pq.as_str(), now()));
+    if auth.is_none() && (exempt || signed) && required == Role::Readonly {
+        return Ok(req);
+    }
+    let error = match auth.and_then(|auth| tokens.role(auth.token())) {
+        Some(role) if role >= required => return Ok(req),
+        Some(_) => ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!("token lacks the {:?} role", required).to_lowercase(),
+        ),
+        None => ApiError::new(StatusCode::UNAUTHORIZED, "missing or unknown token"),
+    };
+    Err((error.into(), req))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::{BTreeMap, HashSet};
+
+    use actix_web::{
+        http::StatusCode,
+        test::{call_service, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+    use actix_web_httpauth::middleware::HttpAuthentication;
+    use bragi_core::settings::{Role, Tokens};
+
+    use super::{authorize, sign_at, verify, ExemptPaths};
+
+    #[actix_web::test]
+    async fn test_roles() {
+        let tokens = Tokens::Roles(BTreeMap::from([
+            (Role::Admin, HashSet::from(["admin".to_string()])),
+            (Role::User, HashSet::from(["user".to_string()])),
+            (Role::Readonly, HashSet::from(["readonly".to_string()])),
+        ]));
+        let app = init_service(
+            App::new().app_data(web::Data::new(tokens)).service(
+                web::scope("/api/v1")
+                    .wrap(HttpAuthentication::with_fn(authorize))
+                    .default_service(web::to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+
+        let cases = [
+            (
+                "GET",
+                "/api/v1/scrape/search",
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "GET",
+                "/api/v1/scrape/search",
+                Some("other"),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "GET",
+                "/api/v1/scrape/search",
+                Some("readonly"),
+                StatusCode::OK,
+            ),
+            (
+                "GET",
+                "/api/v1/library/feed",
+                Some("readonly"),
+                StatusCode::OK,
+            ),
+            (
+                "PUT",
+                "/api/v1/library/favorites/netease/1",
+                Some("readonly"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "PUT",
+                "/api/v1/library/favorites/netease/1",
+                Some("user"),
+                StatusCode::OK,
+            ),
+            ("POST", "/api/v1/rooms", Some("user"), StatusCode::OK),
+            // spawns fpcalc and calls a paid lookup
+            (
+                "POST",
+                "/api/v1/recognize",
+                Some("readonly"),
+                StatusCode::FORBIDDEN,
+            ),
+            ("POST", "/api/v1/recognize", Some("user"), StatusCode::OK),
+            (
+                "POST",
+                "/api/v1/devices",
+                Some("readonly"),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                "GET",
+                "/api/v1/admin/quota",
+                Some("user"),
+                StatusCode::FORBIDDEN,
+            ),
+            ("GET", "/api/v1/admin/quota", Some("admin"), StatusCode::OK),
+        ];
+        for (method, uri, token, status) in cases {
+            let mut req = TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(uri);
+            if let Some(token) = token {
+                req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+            }
+            let resp = call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), status, "{} {} {:?}", method, uri, token);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_exempt() {
+        let tokens: Tokens = serde_json::from_str(r#"["admin"]"#).unwrap();
+        let exempt = ExemptPaths::new(vec![
+            "/health".into(),
+            "/metrics/*".into(),
+            "/library/*".into(),
+        ]);
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(tokens))
+                .app_data(web::Data::new(exempt))
+                .service(
+                    web::scope("/api/v1")
+                        .wrap(HttpAuthentication::with_fn(authorize))
+                        .default_service(web::to(HttpResponse::Ok)),
+                ),
+        )
+        .await;
+
+        let cases = [
+            ("GET", "/api/v1/health", None, StatusCode::OK),
+            ("GET", "/api/v1/metrics/latency", None, StatusCode::OK),
+            (
+                "GET",
+                "/api/v1/health/other",
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "GET",
+                "/api/v1/scrape/search",
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            ("GET", "/api/v1/library/feed", None, StatusCode::OK),
+            // changes require a token even under exempt paths
+            (
+                "PUT",
+                "/api/v1/library/favorites/netease/1",
+                None,
+                StatusCode::UNAUTHORIZED,
+            ),
+            // tokens given are still checked
+            (
+                "GET",
+                "/api/v1/health",
+                Some("other"),
+                StatusCode::UNAUTHORIZED,
+            ),
+        ];
+        for (method, uri, token, status) in cases {
+            let mut req = TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(uri);
+            if let Some(token) = token {
+                req = req.insert_header(("Authorization", format!("Bearer {}", token)));
+            }
+            let resp = call_service(&app, req.to_request()).await;
+            assert_eq!(resp.status(), status, "{} {} {:?}", method, uri, token);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_signed_urls() {
+        let url = sign_at(
+            "/api/v1/stream/proxy?provider=netease&id=1&quality=lossless",
+            1000,
+        );
+        assert!(url.starts_with(
+            "/api/v1/stream/proxy?provider=netease&id=1&quality=lossless&expires=1000&sig="
+        ));
+        assert!(verify(&url, 1000));
+        assert!(!verify(&url, 1001));
+        assert!(!verify(&url.replace("id=1", "id=2"), 1000));
+        assert!(!verify(&url.replace("expires=1000", "expires=9999"), 1000));
+        assert!(!verify("/api/v1/stream/local?id=1&expires=1000", 0));
+        assert!(verify(&sign_at("/api/v1/stream/local", 1000), 0));
+
+        let tokens: Tokens = serde_json::from_str(r#"["admin"]"#).unwrap();
+        let app = init_service(
+            App::new().app_data(web::Data::new(tokens)).service(
+                web::scope("/api/v1")
+                    .wrap(HttpAuthentication::with_fn(authorize))
+                    .default_service(web::to(HttpResponse::Ok)),
+            ),
+        )
+        .await;
+        let signed = sign_at("/api/v1/stream/local?id=1", u64::MAX);
+        let cases = [
+            ("GET", signed.clone(), StatusCode::OK),
+            (
+                "GET",
+                signed.replace("id=1", "id=2"),
+                StatusCode::UNAUTHORIZED,
+            ),
+            (
+                "GET",
+                sign_at("/api/v1/stream/local?id=1", 1),
+                StatusCode::UNAUTHORIZED,
+            ),
+            // only reads
+            ("DELETE", signed, StatusCode::UNAUTHORIZED),
+        ];
+        for (method, uri, status) in cases {
+            let req = TestRequest::default()
+                .method(method.parse().unwrap())
+                .uri(&uri)
+                .to_request();
+            let resp = call_service(&app, req).await;
+            assert_eq!(resp.status(), status, "{} {}", method, uri);
+        }
+    }
+
+    #[test]
+    fn test_plain_list() {
+        let tokens: Tokens = serde_json::from_str(r#"["T0keN__01"]"#).unwrap();
+        assert_eq!(tokens.role("T0keN__01"), Some(Role::Admin));
+        assert_eq!(tokens.role("other"), None);
+
+        let tokens: Tokens =
+            serde_json::from_str(r#"{"user": ["both"], "admin": ["both"]}"#).unwrap();
+        assert_eq!(tokens.role("both"), Some(Role::Admin));
+        assert!(!tokens.is_empty());
+        assert!(serde_json::from_str::<Tokens>("[]").unwrap().is_empty());
+    }
+}
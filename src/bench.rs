@@ -0,0 +1,380 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use bragi_core::{
+    scraper::{
+        id::{CollectionId, TrackId},
+        latency::LatencyTracker,
+        stale::StaleCache,
+    },
+    settings::Settings,
+    Artist, BragiBuilder, Provider, ScrapeItem, ScrapeType, Scraper, ScraperManager, SearchPage,
+    Song, SongCollection, Stream,
+};
+use clap::Args;
+use rand::Rng;
+
+/// Upper bounds of the latency histogram buckets in milliseconds
+const BUCKETS_MS: [u64; 12] = [1, 2, 5, 10, 20, 50, 100, 200, 500, 1000, 2000, 5000];
+
+const PROVIDERS: [Provider; 3] = [Provider::Bilibili, Provider::NetEase, Provider::Youtube];
+
+/// Load test of the fan-out path against mock providers, without any network access. Tasks run
+/// on a single thread like one worker of the server.
+#[derive(Debug, Args)]
+pub struct BenchArgs {
+    /// concurrent clients
+    #[arg(long, default_value_t = 64)]
+    concurrency: usize,
+    /// requests in total
+    #[arg(long, default_value_t = 10000)]
+    requests: usize,
+    /// share of stream requests, the others are searches of all types
+    #[arg(long, default_value_t = 0.3)]
+    stream_ratio: f64,
+    /// distinct keywords and song ids requested. Fewer means more repeated requests
+    #[arg(long, default_value_t = 100)]
+    keys: usize,
+    /// upstream latency of the mock providers in milliseconds
+    #[arg(long, default_value_t = 50)]
+    latency_ms: u64,
+    /// random extra upstream latency up to this many milliseconds
+    #[arg(long, default_value_t = 50)]
+    jitter_ms: u64,
+    /// share of the mock provider calls failing
+    #[arg(long, default_value_t = 0.0)]
+    error_rate: f64,
+    /// in-flight upstream calls of each mock provider. Unbounded if absent
+    #[arg(long)]
+    budget: Option<usize>,
+}
+
+/// Provider answering after a simulated latency with generated results
+struct MockScraper {
+    provider: Provider,
+    latency: Duration,
+    jitter_ms: u64,
+    error_rate: f64,
+}
+
+impl MockScraper {
+    async fn upstream(&self) -> anyhow::Result<()> {
+        let (jitter, failed) = {
+            let mut rng = rand::thread_rng();
+            (
+                rng.gen_range(0..=self.jitter_ms),
+                rng.gen_bool(self.error_rate),
+            )
+        };
+        tokio::time::sleep(self.latency + Duration::from_millis(jitter)).await;
+        if failed {
+            bail!("mock upstream error");
+        }
+        Ok(())
+    }
+
+    fn song(&self, n: usize) -> Song {
+        Song {
+            id: track_id(&self.provider, n).into(),
+            name: format!("song {}", n),
+            artists: vec![Artist {
+                id: n.to_string().into(),
+                name: format!("artist {}", n),
+                description: None,
+                avatar: None,
+            }],
+            cover: None,
+            duration: Some(180),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+}
+
+#[async_trait]
+impl Scraper for MockScraper {
+    async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
+        self.upstream().await?;
+        Ok((0..10).map(|i| format!("{} {}", keyword, i)).collect())
+    }
+
+    async fn search(
+        &self,
+        keyword: String,
+        _t: ScrapeType,
+        _continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        self.upstream().await?;
+        let seed = keyword.len();
+        Ok((0..20)
+            .map(|i| ScrapeItem::Song(self.song(seed * 20 + i)))
+            .collect::<Vec<_>>()
+            .into())
+    }
+
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        self.upstream().await?;
+        Ok(SongCollection {
+            id,
+            name: "playlist".into(),
+            artists: vec![],
+            cover: None,
+            description: None,
+            songs: (0..50).map(|i| self.song(i)).collect(),
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
+        })
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        self.upstream().await?;
+        Ok([320000, 128000]
+            .into_iter()
+            .map(|bitrate| Stream {
+                quality: format!("{}k", bitrate / 1000),
+                url: format!("https://example.com/{}/{}", id, bitrate),
+                bitrate: Some(bitrate),
+                codec: Some("mp4a.40.2".into()),
+                mirror: false,
+                loudness: None,
+                preview: false,
+            })
+            .collect())
+    }
+}
+
+/// A raw id of the id format of the provider
+fn track_id(provider: &Provider, n: usize) -> String {
+    match provider {
+        Provider::Bilibili => format!("BV{:010}::1", n),
+        Provider::Radio => format!("00000000-0000-0000-0000-{:012}", n),
+        Provider::Youtube => format!("{:011}", n),
+        Provider::Local | Provider::NetEase | Provider::Spotify => n.to_string(),
+    }
+}
+
+#[derive(Default)]
+struct Report {
+    searches: Vec<Duration>,
+    streams: Vec<Duration>,
+    errors: usize,
+    /// searches where some provider was skipped for its exhausted budget
+    throttled: usize,
+}
+
+impl Report {
+    fn merge(&mut self, other: Report) {
+        self.searches.extend(other.searches);
+        self.streams.extend(other.streams);
+        self.errors += other.errors;
+        self.throttled += other.throttled;
+    }
+}
+
+/// Build the manager with the mock providers. Stale cache, stream sort and timeouts follow the
+/// config file if given.
+async fn manager(args: &BenchArgs, settings: Option<&Settings>) -> ScraperManager {
+    let mut builder = BragiBuilder::new();
+    for provider in PROVIDERS {
+        builder = builder.with_scraper(
+            provider.clone(),
+            MockScraper {
+                provider: provider.clone(),
+                latency: Duration::from_millis(args.latency_ms),
+                jitter_ms: args.jitter_ms,
+                error_rate: args.error_rate,
+            },
+        );
+        if let Some(permits) = args.budget {
+            builder = builder.with_budget(provider, permits);
+        }
+    }
+    if let Some(settings) = settings {
+        if let Some(cache) = StaleCache::from_setting(&settings.stale) {
+            builder = builder.with_cache(cache);
+        }
+        builder = builder
+            .with_stream_sort(settings.stream_sort.clone())
+            .with_latency_tracker(LatencyTracker::from_setting(&settings.timeout));
+    }
+    builder.build().await
+}
+
+async fn client(
+    manager: ScraperManager,
+    remaining: Arc<AtomicUsize>,
+    stream_ratio: f64,
+    keys: usize,
+) -> Report {
+    let mut report = Report::default();
+    while remaining
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+        .is_ok()
+    {
+        let (stream, key, provider) = {
+            let mut rng = rand::thread_rng();
+            (
+                rng.gen_bool(stream_ratio),
+                rng.gen_range(0..keys),
+                PROVIDERS[rng.gen_range(0..PROVIDERS.len())].clone(),
+            )
+        };
+
+        let start = Instant::now();
+        if stream {
+            let result = manager.stream(track_id(&provider, key), provider).await;
+            report.streams.push(start.elapsed());
+            report.errors += result.is_err() as usize;
+        } else {
+            let result = manager
+                .search(format!("keyword {}", key), ScrapeType::All, None)
+                .await;
+            report.searches.push(start.elapsed());
+            report.throttled += !result.throttled.is_empty() as usize;
+        }
+    }
+    report
+}
+
+pub async fn run(args: BenchArgs, settings: Option<Settings>) -> anyhow::Result<()> {
+    if args.concurrency == 0 || args.keys == 0 {
+        bail!("concurrency and keys must be positive");
+    }
+    if !(0.0..=1.0).contains(&args.stream_ratio) || !(0.0..=1.0).contains(&args.error_rate) {
+        bail!("stream_ratio and error_rate must be within 0..=1");
+    }
+
+    let manager = manager(&args, settings.as_ref()).await;
+    let remaining = Arc::new(AtomicUsize::new(args.requests));
+    let rss_before = memory("VmRSS");
+
+    let start = Instant::now();
+    let clients = (0..args.concurrency)
+        .map(|_| {
+            tokio::spawn(client(
+                manager.clone(),
+                remaining.clone(),
+                args.stream_ratio,
+                args.keys,
+            ))
+        })
+        .collect::<Vec<_>>();
+    let mut report = Report::default();
+    for c in clients {
+        report.merge(c.await?);
+    }
+    let elapsed = start.elapsed();
+
+    let total = report.searches.len() + report.streams.len();
+    println!(
+        "{} requests in {:.2}s: {:.1} req/s, {} concurrent clients",
+        total,
+        elapsed.as_secs_f64(),
+        total as f64 / elapsed.as_secs_f64(),
+        args.concurrency
+    );
+    println!(
+        "stream errors: {}, throttled searches: {}",
+        report.errors, report.throttled
+    );
+    print_latencies("search", &mut report.searches);
+    print_latencies("stream", &mut report.streams);
+    match (rss_before, memory("VmRSS"), memory("VmHWM")) {
+        (Some(before), Some(after), Some(peak)) => println!(
+            "memory: rss {} KiB before, {} KiB after, {} KiB peak",
+            before, after, peak
+        ),
+        _ => println!("memory: unavailable on this platform"),
+    }
+    Ok(())
+}
+
+fn print_latencies(name: &str, latencies: &mut [Duration]) {
+    if latencies.is_empty() {
+        return;
+    }
+    latencies.sort();
+    println!(
+        "{} latency: p50 {:?}, p95 {:?}, p99 {:?}, max {:?}",
+        name,
+        percentile(latencies, 0.50),
+        percentile(latencies, 0.95),
+        percentile(latencies, 0.99),
+        latencies[latencies.len() - 1]
+    );
+    let counts = histogram(latencies);
+    for (i, count) in counts.iter().enumerate() {
+        let label = match BUCKETS_MS.get(i) {
+            Some(ms) => format!("<= {}ms", ms),
+            None => format!("> {}ms", BUCKETS_MS[BUCKETS_MS.len() - 1]),
+        };
+        let bar = "#".repeat(count * 50 / latencies.len());
+        println!("  {:>10} {:>8} {}", label, count, bar);
+    }
+}
+
+/// Nearest rank percentile of sorted latencies
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = ((sorted.len() as f64 * p).ceil() as usize).clamp(1, sorted.len());
+    sorted[rank - 1]
+}
+
+/// Counts of the latencies in each bucket, plus one for those above the last bucket
+fn histogram(latencies: &[Duration]) -> Vec<usize> {
+    let mut counts = vec![0; BUCKETS_MS.len() + 1];
+    for latency in latencies {
+        let ms = latency.as_millis() as u64;
+        let bucket = BUCKETS_MS
+            .iter()
+            .position(|b| ms <= *b)
+            .unwrap_or(BUCKETS_MS.len());
+        counts[bucket] += 1;
+    }
+    counts
+}
+
+/// Memory of the process in KiB from /proc, like `VmRSS` or the peak `VmHWM`
+fn memory(field: &str) -> Option<u64> {
+    std::fs::read_to_string("/proc/self/status")
+        .ok()?
+        .lines()
+        .find_map(|l| l.strip_prefix(field)?.strip_prefix(':'))?
+        .trim()
+        .trim_end_matches("kB")
+        .trim()
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{histogram, percentile};
+
+    #[test]
+    fn test_stats() {
+        let latencies = (1..=100).map(Duration::from_millis).collect::<Vec<_>>();
+        assert_eq!(percentile(&latencies, 0.5), Duration::from_millis(50));
+        assert_eq!(percentile(&latencies, 0.99), Duration::from_millis(99));
+        assert_eq!(percentile(&latencies[..1], 0.99), Duration::from_millis(1));
+
+        let counts = histogram(&latencies);
+        assert_eq!(counts.iter().sum::<usize>(), 100);
+        // 1, 2, 3..=5, 6..=10, ...
+        assert_eq!(&counts[..4], &[1, 1, 3, 5]);
+        assert_eq!(counts[counts.len() - 1], 0);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{
+    scraper::{
+        alias::ArtistAliases, analytics::SearchAnalytics, event::EventHandler,
+        favorite::FavoriteStore, filter::ResultFilter, follow::FollowStore,
+        keyword::KeywordNormalizer, latency::LatencyTracker, lyrics::LyricsFallback,
+        quota::QuotaTracker, rewrite::HostRewriter, stale::StaleCache,
+        unavailable::UnavailableStore, Provider, ScrapeType, Scraper, ScraperManager,
+    },
+    settings::{KeywordVariant, PrioritySettings, StreamSortSettings},
+};
+
+/// Builder of the aggregation engine, for embedding bragi-core into other applications without
+/// running the http server.
+///
+/// ```no_run
+/// # async fn example() {
+/// use bragi_core::{scraper::youtube::YouTubeScraper, BragiBuilder, Provider, ScrapeType};
+///
+/// let manager = BragiBuilder::new()
+///     .with_scraper(Provider::Youtube, YouTubeScraper::default())
+///     .with_budget(Provider::Youtube, 4)
+///     .build()
+///     .await;
+///
+/// let results = manager.search("夜に駆ける".into(), ScrapeType::All, None).await;
+/// # }
+/// ```
+#[derive(Default)]
+pub struct BragiBuilder {
+    scrapers: Vec<(Provider, Box<dyn Scraper>)>,
+    budgets: Vec<(Provider, usize)>,
+    keyword_variants: Vec<(Provider, Vec<KeywordVariant>)>,
+    search_zones: Vec<(Provider, Vec<ScrapeType>)>,
+    host_rewrites: Vec<(Provider, HostRewriter)>,
+    normalizer: Option<KeywordNormalizer>,
+    relax_zero_result: bool,
+    aliases: Option<ArtistAliases>,
+    unavailable: Option<UnavailableStore>,
+    favorites: Option<FavoriteStore>,
+    follows: Option<FollowStore>,
+    filter: Option<ResultFilter>,
+    cache: Option<StaleCache>,
+    stream_sort: Option<StreamSortSettings>,
+    priority: Option<PrioritySettings>,
+    latency: Option<LatencyTracker>,
+    quota: Option<QuotaTracker>,
+    analytics: Option<SearchAnalytics>,
+    lyrics_fallback: Option<LyricsFallback>,
+    handlers: Vec<Box<dyn EventHandler>>,
+}
+
+impl BragiBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register the scraper of the provider. A later scraper of the same provider replaces it.
+    pub fn with_scraper(mut self, provider: Provider, scraper: impl Scraper + 'static) -> Self {
+        self.scrapers.push((provider, Box::new(scraper)));
+        self
+    }
+
+    /// Limit the number of in-flight upstream calls of the provider. Unbounded by default.
+    pub fn with_budget(mut self, provider: Provider, permits: usize) -> Self {
+        self.budgets.push((provider, permits));
+        self
+    }
+
+    /// Search the provider with these keyword variants besides the original keyword
+    pub fn with_keyword_variants(
+        mut self,
+        provider: Provider,
+        variants: Vec<KeywordVariant>,
+    ) -> Self {
+        self.keyword_variants.push((provider, variants));
+        self
+    }
+
+    /// Search these types of the provider for `All` instead of the provider default
+    pub fn with_search_zones(mut self, provider: Provider, zones: Vec<ScrapeType>) -> Self {
+        self.search_zones.push((provider, zones));
+        self
+    }
+
+    /// Rewrite the hosts of stream urls returned by the provider
+    pub fn with_host_rewrites(mut self, provider: Provider, rewriter: HostRewriter) -> Self {
+        self.host_rewrites.push((provider, rewriter));
+        self
+    }
+
+    /// Dictionaries of the chinese keyword variants
+    pub fn with_keyword_normalizer(mut self, normalizer: KeywordNormalizer) -> Self {
+        self.normalizer = Some(normalizer);
+        self
+    }
+
+    /// Names of the same artist, searched and matched for `artist:` filters. Empty by default.
+    pub fn with_artist_aliases(mut self, aliases: ArtistAliases) -> Self {
+        self.aliases = Some(aliases);
+        self
+    }
+
+    /// Retry searches which found nothing with relaxed keywords, like without the featured
+    /// artists. Disabled by default.
+    pub fn with_zero_result_relaxation(mut self, enabled: bool) -> Self {
+        self.relax_zero_result = enabled;
+        self
+    }
+
+    /// Remember ids deleted or blocked upstream. In memory only by default.
+    pub fn with_unavailable_store(mut self, store: UnavailableStore) -> Self {
+        self.unavailable = Some(store);
+        self
+    }
+
+    /// Songs and collections saved to the library. In memory only by default.
+    pub fn with_favorite_store(mut self, store: FavoriteStore) -> Self {
+        self.favorites = Some(store);
+        self
+    }
+
+    /// Followed artists and the feed of their releases. In memory only by default.
+    pub fn with_follow_store(mut self, store: FollowStore) -> Self {
+        self.follows = Some(store);
+        self
+    }
+
+    /// Drop unwanted search results and collection songs. Nothing is dropped by default.
+    pub fn with_result_filter(mut self, filter: ResultFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Serve the last known results of providers marked as stale when they fail. Disabled by
+    /// default.
+    pub fn with_cache(mut self, cache: StaleCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Order of the returned streams. Kept as the provider orders them by default.
+    pub fn with_stream_sort(mut self, policy: StreamSortSettings) -> Self {
+        self.stream_sort = Some(policy);
+        self
+    }
+
+    /// Order of the providers in merged results. By provider name by default.
+    pub fn with_provider_priority(mut self, policy: PrioritySettings) -> Self {
+        self.priority = Some(policy);
+        self
+    }
+
+    /// Time out provider calls adaptively. Latencies are tracked without timeouts by default.
+    pub fn with_latency_tracker(mut self, tracker: LatencyTracker) -> Self {
+        self.latency = Some(tracker);
+        self
+    }
+
+    /// Limit the daily calls of providers. Calls are counted without any limit by default.
+    pub fn with_quota_tracker(mut self, tracker: QuotaTracker) -> Self {
+        self.quota = Some(tracker);
+        self
+    }
+
+    /// Count keywords, zero-result queries and provider contributions of the searches. Disabled
+    /// by default.
+    pub fn with_search_analytics(mut self, analytics: SearchAnalytics) -> Self {
+        self.analytics = Some(analytics);
+        self
+    }
+
+    /// Look up lyrics by title where the provider has none. Disabled by default.
+    pub fn with_lyrics_fallback(mut self, fallback: LyricsFallback) -> Self {
+        self.lyrics_fallback = Some(fallback);
+        self
+    }
+
+    /// Called on searches, resolved streams and provider errors. Handlers are called in order.
+    pub fn with_event_handler(mut self, handler: impl EventHandler + 'static) -> Self {
+        self.handlers.push(Box::new(handler));
+        self
+    }
+
+    pub async fn build(self) -> ScraperManager {
+        let mut manager = ScraperManager::default();
+
+        for (provider, scraper) in self.scrapers {
+            manager.add_scraper(provider, scraper).await;
+        }
+        for (provider, permits) in self.budgets {
+            manager.set_budget(provider, permits).await;
+        }
+        for (provider, variants) in self.keyword_variants {
+            manager.set_keyword_variants(provider, variants).await;
+        }
+        for (provider, zones) in self.search_zones {
+            manager.set_search_zones(provider, zones).await;
+        }
+        for (provider, rewriter) in self.host_rewrites {
+            manager.set_host_rewrites(provider, rewriter).await;
+        }
+        if let Some(normalizer) = self.normalizer {
+            manager.set_keyword_normalizer(normalizer);
+        }
+        manager.set_zero_result_relaxation(self.relax_zero_result);
+        if let Some(aliases) = self.aliases {
+            manager.set_artist_aliases(aliases);
+        }
+        if let Some(store) = self.unavailable {
+            manager.set_unavailable_store(store);
+        }
+        if let Some(store) = self.favorites {
+            manager.set_favorite_store(store);
+        }
+        if let Some(store) = self.follows {
+            manager.set_follow_store(store);
+        }
+        if let Some(filter) = self.filter {
+            manager.set_result_filter(filter);
+        }
+        if let Some(cache) = self.cache {
+            manager.set_stale_cache(cache);
+        }
+        if let Some(policy) = self.stream_sort {
+            manager.set_stream_sort(policy);
+        }
+        if let Some(policy) = self.priority {
+            manager.set_provider_priority(policy);
+        }
+        if let Some(tracker) = self.latency {
+            manager.set_latency_tracker(tracker);
+        }
+        if let Some(tracker) = self.quota {
+            manager.set_quota_tracker(tracker);
+        }
+        if let Some(analytics) = self.analytics {
+            manager.set_search_analytics(analytics);
+        }
+        if let Some(fallback) = self.lyrics_fallback {
+            manager.set_lyrics_fallback(fallback);
+        }
+        for handler in self.handlers {
+            manager.add_event_handler(handler);
+        }
+
+        manager
+    }
+}
@@ -0,0 +1,926 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufWriter, Write},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    http::header::{self, ContentDisposition, DispositionParam, DispositionType},
+    web::{self, Json, Path},
+    HttpRequest, HttpResponse,
+};
+use bragi_core::{
+    privacy::redact,
+    scraper::{capability, id, Provider, Song, SongCollection, Stream},
+    settings::Capabilities,
+    LimitedBody,
+};
+use futures::TryStreamExt;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::{error, info, warn};
+
+use crate::{
+    cache::AudioCache,
+    client_capabilities,
+    device::{owner, random_id},
+    download::serve_file,
+    transcode::{Format, Transcoder},
+    Context,
+};
+
+/// Jobs kept per token. The oldest finished one is dropped with its archive beyond it.
+const MAX_JOBS: usize = 8;
+
+/// Bytes of a downloaded track or cover, held in memory until written to the archive
+const MAX_DOWNLOAD_SIZE: usize = 512 * 1024 * 1024;
+
+/// Characters left out of the file names in the archive
+const UNSAFE_CHARS: &[char] = &['/', '\\', ':', '*', '?', '"', '<', '>', '|'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum JobState {
+    Running,
+    Done,
+    Failed,
+}
+
+/// Build of the offline bundle of a library playlist
+#[derive(Debug, Clone, Serialize)]
+pub struct BundleJob {
+    pub id: String,
+    /// fingerprint of the token which started the job
+    #[serde(skip)]
+    pub owner: Option<String>,
+    pub provider: Provider,
+    pub playlist: String,
+    /// tracks converted to the format, as picked for the client otherwise
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub format: Option<Format>,
+    pub state: JobState,
+    /// songs processed so far, bundled or skipped
+    pub done: usize,
+    pub total: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// unix seconds
+    pub created_at: u64,
+}
+
+/// Bundle jobs by id. Jobs live in memory, their archives in `dir` until the job is dropped.
+#[derive(Debug)]
+pub struct BundleJobs {
+    dir: PathBuf,
+    client: reqwest::Client,
+    jobs: RwLock<BTreeMap<String, BundleJob>>,
+}
+
+impl BundleJobs {
+    pub fn try_new(dir: Option<String>) -> anyhow::Result<Self> {
+        let dir = dir
+            .map(PathBuf::from)
+            .unwrap_or_else(|| std::env::temp_dir().join("bragi-bundles"));
+        std::fs::create_dir_all(&dir)?;
+        let jobs = Self {
+            dir,
+            client: Default::default(),
+            jobs: Default::default(),
+        };
+        // jobs live in memory, so every archive left by an earlier run is abandoned
+        jobs.collect_garbage();
+        Ok(jobs)
+    }
+
+    pub fn start(
+        &self,
+        owner: Option<String>,
+        provider: Provider,
+        playlist: String,
+        format: Option<Format>,
+    ) -> anyhow::Result<BundleJob> {
+        let mut jobs = self.jobs.write();
+        let owned = jobs
+            .values()
+            .filter(|j| j.owner == owner)
+            .collect::<Vec<_>>();
+        if owned.len() >= MAX_JOBS {
+            let Some(oldest) = owned
+                .iter()
+                .filter(|j| j.state != JobState::Running)
+                .min_by_key(|j| j.created_at)
+                .map(|j| j.id.clone())
+            else {
+                anyhow::bail!("at most {} bundle jobs per token", MAX_JOBS);
+            };
+            jobs.remove(&oldest);
+            self.remove_archive(&oldest);
+        }
+        self.sweep(&jobs);
+
+        let mut id = random_id();
+        while jobs.contains_key(&id) {
+            id = random_id();
+        }
+        let job = BundleJob {
+            id: id.clone(),
+            owner,
+            provider,
+            playlist,
+            format,
+            state: JobState::Running,
+            done: 0,
+            total: 0,
+            error: None,
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        jobs.insert(id, job.clone());
+        Ok(job)
+    }
+
+    pub fn list(&self, owner: Option<&str>) -> Vec<BundleJob> {
+        self.jobs
+            .read()
+            .values()
+            .filter(|j| j.owner.as_deref() == owner)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, owner: Option<&str>, id: &str) -> Option<BundleJob> {
+        self.jobs
+            .read()
+            .get(id)
+            .filter(|j| j.owner.as_deref() == owner)
+            .cloned()
+    }
+
+    /// Returns false if the owner has no such job. A running job stops at its next song.
+    pub fn remove(&self, owner: Option<&str>, id: &str) -> bool {
+        let mut jobs = self.jobs.write();
+        if jobs.get(id).map(|j| j.owner.as_deref()) != Some(owner) {
+            return false;
+        }
+        jobs.remove(id);
+        self.remove_archive(id);
+        true
+    }
+
+    /// Remove the archives no job owns from the directory: the ones of dropped jobs, and the
+    /// partial ones of builds which stopped without finishing. Files not named after a job are
+    /// left alone.
+    pub fn collect_garbage(&self) {
+        self.sweep(&self.jobs.read());
+    }
+
+    fn sweep(&self, jobs: &BTreeMap<String, BundleJob>) {
+        let Ok(entries) = std::fs::read_dir(&self.dir) else {
+            return;
+        };
+        for path in entries.flatten().map(|e| e.path()) {
+            let Some((id, extension)) = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.split_once('.'))
+                .filter(|(id, _)| is_job_id(id))
+            else {
+                continue;
+            };
+            let abandoned = match (extension, jobs.get(id).map(|j| j.state)) {
+                ("tar", Some(JobState::Done)) | ("part", Some(JobState::Running)) => false,
+                ("tar" | "part", _) => true,
+                _ => false,
+            };
+            if abandoned {
+                info!("remove abandoned bundle {}", path.display());
+                if let Err(e) = std::fs::remove_file(&path) {
+                    error!("remove bundle {} failed: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    /// Returns false if the job was removed meanwhile
+    fn update(&self, id: &str, f: impl FnOnce(&mut BundleJob)) -> bool {
+        self.jobs.write().get_mut(id).map(f).is_some()
+    }
+
+    fn archive(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.tar", id))
+    }
+
+    fn remove_archive(&self, id: &str) {
+        for path in [self.archive(id), self.archive(id).with_extension("part")] {
+            if path.exists() {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    error!("remove bundle {} failed: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    async fn download(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+    ) -> anyhow::Result<Download> {
+        let mut req = self.client.get(url);
+        for (name, value) in headers {
+            req = req.header(*name, value);
+        }
+        let resp = req.send().await?.error_for_status()?;
+        let content_type = resp
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|c| c.to_str().ok())
+            .map(str::to_string);
+        Ok(Download {
+            content_type,
+            data: resp.bytes_up_to(MAX_DOWNLOAD_SIZE).await?,
+        })
+    }
+}
+
+/// Whether the name is one `random_id` gives, so that the sweep leaves any other file in the
+/// directory alone
+fn is_job_id(name: &str) -> bool {
+    name.len() == 16 && name.bytes().all(|b| b.is_ascii_alphanumeric())
+}
+
+struct Download {
+    content_type: Option<String>,
+    data: Vec<u8>,
+}
+
+/// Song in the bundle along with the stream it was taken from
+#[derive(Debug, Serialize)]
+struct BundledTrack {
+    file: String,
+    song: Song,
+    quality: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bitrate: Option<u64>,
+}
+
+/// Song left out of the bundle
+#[derive(Debug, Serialize)]
+struct SkippedTrack {
+    song: Song,
+    reason: String,
+}
+
+/// `metadata.json` of the bundle
+#[derive(Debug, Serialize)]
+struct BundleMetadata<'a> {
+    provider: &'a Provider,
+    playlist: &'a SongCollection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cover: Option<&'a str>,
+    tracks: &'a [BundledTrack],
+    skipped: &'a [SkippedTrack],
+}
+
+/// Tar archive of the bundle, every file under a directory named after the playlist
+struct BundleWriter {
+    archive: tar::Builder<BufWriter<File>>,
+    root: String,
+    cover: Option<String>,
+    tracks: Vec<BundledTrack>,
+    skipped: Vec<SkippedTrack>,
+}
+
+impl BundleWriter {
+    fn create(path: &std::path::Path, name: &str) -> std::io::Result<Self> {
+        Ok(Self {
+            archive: tar::Builder::new(BufWriter::new(File::create(path)?)),
+            root: file_name(name),
+            cover: None,
+            tracks: Vec::new(),
+            skipped: Vec::new(),
+        })
+    }
+
+    fn append(&mut self, name: &str, data: &[u8]) -> std::io::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+        self.archive
+            .append_data(&mut header, format!("{}/{}", self.root, name), data)
+    }
+
+    fn add_cover(&mut self, cover: Download) -> std::io::Result<()> {
+        let name = format!("cover.{}", image_extension(cover.content_type.as_deref()));
+        self.append(&name, &cover.data)?;
+        self.cover = Some(name);
+        Ok(())
+    }
+
+    /// `index` counts from 0, `total` pads the track numbers of the file names
+    fn add_track(
+        &mut self,
+        index: usize,
+        total: usize,
+        song: Song,
+        stream: Stream,
+        audio: Download,
+    ) -> std::io::Result<()> {
+        let width = total.to_string().len().max(2);
+        let file = format!(
+            "{:0width$} - {}.{}",
+            index + 1,
+            file_name(&song.name),
+            audio_extension(&stream, audio.content_type.as_deref()),
+        );
+        self.append(&file, &audio.data)?;
+        self.tracks.push(BundledTrack {
+            file,
+            song,
+            quality: stream.quality,
+            codec: stream.codec,
+            bitrate: stream.bitrate,
+        });
+        Ok(())
+    }
+
+    fn skip(&mut self, song: Song, reason: String) {
+        self.skipped.push(SkippedTrack { song, reason });
+    }
+
+    /// Run `f` writing the archive on a blocking thread, the writer handed back once it is done
+    async fn write(
+        mut self,
+        f: impl FnOnce(&mut Self) -> std::io::Result<()> + Send + 'static,
+    ) -> anyhow::Result<Self> {
+        Ok(tokio::task::spawn_blocking(move || f(&mut self).map(|()| self)).await??)
+    }
+
+    /// Write the playlist and the metadata after the songs
+    fn finish(mut self, provider: &Provider, playlist: &SongCollection) -> anyhow::Result<()> {
+        let m3u = m3u(&self.tracks);
+        self.append("playlist.m3u8", m3u.as_bytes())?;
+        // the songs are listed by the tracks and the skipped ones
+        let playlist = SongCollection {
+            songs: Vec::new(),
+            ..playlist.clone()
+        };
+        let metadata = serde_json::to_vec_pretty(&BundleMetadata {
+            provider,
+            playlist: &playlist,
+            cover: self.cover.as_deref(),
+            tracks: &self.tracks,
+            skipped: &self.skipped,
+        })?;
+        self.append("metadata.json", &metadata)?;
+        self.archive.into_inner()?.flush()?;
+        Ok(())
+    }
+}
+
+/// Extended M3U of the bundled songs, paths relative to the playlist
+fn m3u(tracks: &[BundledTrack]) -> String {
+    let mut m3u = String::from("#EXTM3U\n");
+    for track in tracks {
+        let artists = track
+            .song
+            .artists
+            .iter()
+            .map(|a| a.name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let title = match artists.is_empty() {
+            true => track.song.name.clone(),
+            false => format!("{} - {}", artists, track.song.name),
+        };
+        let duration = track.song.duration.map_or(-1, i64::from);
+        m3u.push_str(&format!(
+            "#EXTINF:{},{}\n{}\n",
+            duration,
+            title.replace('\n', " "),
+            track.file
+        ));
+    }
+    m3u
+}
+
+/// Name usable as a file name on every file system, at most 100 characters
+fn file_name(name: &str) -> String {
+    let name = name
+        .chars()
+        .map(|c| match c.is_control() || UNSAFE_CHARS.contains(&c) {
+            true => '_',
+            false => c,
+        })
+        .take(100)
+        .collect::<String>();
+    let name = name.trim().trim_matches('.');
+    match name.is_empty() {
+        true => "untitled".to_string(),
+        false => name.to_string(),
+    }
+}
+
+/// Extension of the audio file by its content type, else by the codec or the url of the stream
+fn audio_extension(stream: &Stream, content_type: Option<&str>) -> String {
+    let by_type = match content_type
+        .and_then(|c| c.split(';').next())
+        .map(str::trim)
+    {
+        Some("audio/mpeg") => Some("mp3"),
+        Some("audio/flac" | "audio/x-flac") => Some("flac"),
+        Some("audio/mp4" | "audio/x-m4a" | "video/mp4") => Some("m4a"),
+        Some("audio/webm" | "video/webm") => Some("webm"),
+        Some("audio/ogg") => Some("ogg"),
+        Some("audio/aac") => Some("aac"),
+        Some("audio/wav") => Some("wav"),
+        _ => None,
+    };
+    let codec = stream.codec.as_deref().unwrap_or_default().to_lowercase();
+    let by_codec = match codec.as_str() {
+        c if c.starts_with("mp4a") || c == "ec-3" => Some("m4a"),
+        "flac" => Some("flac"),
+        "opus" => Some("webm"),
+        "mp3" => Some("mp3"),
+        _ => None,
+    };
+    by_type
+        .or(by_codec)
+        .map(str::to_string)
+        .or_else(|| {
+            let path = stream.url.split(['?', '#']).next().unwrap_or_default();
+            let (_, extension) = path.rsplit('/').next()?.rsplit_once('.')?;
+            (extension.len() <= 4 && extension.chars().all(|c| c.is_ascii_alphanumeric()))
+                .then(|| extension.to_lowercase())
+        })
+        .unwrap_or_else(|| "bin".to_string())
+}
+
+fn image_extension(content_type: Option<&str>) -> &'static str {
+    match content_type
+        .and_then(|c| c.split(';').next())
+        .map(str::trim)
+    {
+        Some("image/png") => "png",
+        Some("image/webp") => "webp",
+        _ => "jpg",
+    }
+}
+
+/// The stream describing its audio converted to the format
+fn converted(transcoder: &Transcoder, format: Format, stream: Stream) -> Stream {
+    Stream {
+        bitrate: transcoder.bitrate(format),
+        codec: Some(format.codec().to_string()),
+        ..stream
+    }
+}
+
+/// The audio converted by ffmpeg, along with the stream describing the converted audio
+async fn convert(
+    transcoder: &Transcoder,
+    format: Format,
+    stream: Stream,
+    audio: Download,
+) -> anyhow::Result<(Stream, Download)> {
+    let input = futures::stream::once(async move {
+        Ok::<_, std::convert::Infallible>(web::Bytes::from(audio.data))
+    });
+    let data = transcoder
+        .transcode(input, format)
+        .await?
+        .try_fold(vec![], |mut data, chunk| async move {
+            data.extend_from_slice(&chunk);
+            Ok(data)
+        })
+        .await?;
+    let stream = converted(transcoder, format, stream);
+    let audio = Download {
+        content_type: Some(format.content_type().to_string()),
+        data,
+    };
+    Ok((stream, audio))
+}
+
+/// Audio of the first stream the client can play which downloads, or of the best one converted
+/// to the format of the job. Audio kept in the cache is taken from it.
+async fn fetch_track(
+    ctx: &Context,
+    job: &BundleJob,
+    song: &Song,
+    capabilities: Option<&Capabilities>,
+    headers: &[(&'static str, String)],
+) -> anyhow::Result<(Stream, Download)> {
+    let mut streams = ctx
+        .manager
+        .stream(song.id.to_string(), job.provider.clone())
+        .await?;
+    let transcode = match (job.format, &ctx.transcoder) {
+        (Some(format), Some(transcoder)) => Some((transcoder, format)),
+        (Some(_), None) => anyhow::bail!("transcoding is not enabled"),
+        (None, _) => None,
+    };
+    if let (None, Some(capabilities)) = (transcode, capabilities) {
+        capability::filter_streams(&mut streams, capabilities);
+    }
+    let mut last_error = anyhow::anyhow!("no playable stream");
+    for stream in streams {
+        let cache = ctx.cache.as_ref().map(|cache| {
+            let key = AudioCache::key(&job.provider, &song.id, Some(&stream.quality), job.format);
+            (cache, key)
+        });
+        if let Some((path, content_type)) = cache.as_ref().and_then(|(c, key)| c.get(key)) {
+            if let Ok(data) = tokio::fs::read(path).await {
+                let stream = match transcode {
+                    Some((transcoder, format)) => converted(transcoder, format, stream),
+                    None => stream,
+                };
+                let content_type = Some(content_type);
+                return Ok((stream, Download { content_type, data }));
+            }
+        }
+
+        match ctx.bundles.download(&stream.url, headers).await {
+            Ok(audio) => {
+                let (stream, audio) = match transcode {
+                    Some((transcoder, format)) => {
+                        convert(transcoder, format, stream, audio).await?
+                    }
+                    None => (stream, audio),
+                };
+                if let Some((cache, key)) = &cache {
+                    let content_type = audio.content_type.as_deref();
+                    cache
+                        .put(
+                            key,
+                            content_type.unwrap_or("application/octet-stream"),
+                            &audio.data,
+                        )
+                        .await;
+                }
+                return Ok((stream, audio));
+            }
+            Err(e) => {
+                warn!("download stream {} failed: {}", stream.quality, e);
+                last_error = e;
+            }
+        }
+    }
+    Err(last_error)
+}
+
+async fn build(
+    ctx: &Context,
+    job: &BundleJob,
+    capabilities: Option<&Capabilities>,
+) -> anyhow::Result<()> {
+    let bundles = &ctx.bundles;
+    let playlist = ctx
+        .manager
+        .collection_detail(job.playlist.clone(), job.provider.clone())
+        .await?;
+    let total = playlist.songs.len();
+    bundles.update(&job.id, |j| j.total = total);
+
+    let part = bundles.archive(&job.id).with_extension("part");
+    let mut writer = {
+        let (part, name) = (part.clone(), playlist.name.clone());
+        tokio::task::spawn_blocking(move || BundleWriter::create(&part, &name)).await??
+    };
+    if let Some(cover) = &playlist.cover {
+        match bundles.download(cover, &[]).await {
+            Ok(cover) => writer = writer.write(|w| w.add_cover(cover)).await?,
+            Err(e) => warn!("download cover of bundle {} failed: {}", job.id, e),
+        }
+    }
+
+    let headers = ctx.manager.stream_headers(&job.provider).await;
+    for (index, song) in playlist.songs.iter().enumerate() {
+        if song.unavailable {
+            writer.skip(song.clone(), "unavailable upstream".to_string());
+        } else {
+            match fetch_track(ctx, job, song, capabilities, &headers).await {
+                Ok((stream, audio)) => {
+                    let song = song.clone();
+                    writer = writer
+                        .write(move |w| w.add_track(index, total, song, stream, audio))
+                        .await?
+                }
+                Err(e) => writer.skip(song.clone(), e.to_string()),
+            }
+        }
+        if !bundles.update(&job.id, |j| j.done = index + 1) {
+            info!("bundle {} removed, stop building", job.id);
+            drop(writer);
+            let _ = tokio::fs::remove_file(&part).await;
+            return Ok(());
+        }
+    }
+
+    let provider = job.provider.clone();
+    tokio::task::spawn_blocking(move || writer.finish(&provider, &playlist)).await??;
+    tokio::fs::rename(&part, bundles.archive(&job.id)).await?;
+    Ok(())
+}
+
+/// Build the bundle in the background, recording the outcome in the job
+async fn run(ctx: web::Data<Context>, job: BundleJob, capabilities: Option<Capabilities>) {
+    info!(
+        "[Bundle] build {}: provider: {:?}, playlist: {}",
+        job.id,
+        job.provider,
+        redact(&job.playlist)
+    );
+    let result = build(&ctx, &job, capabilities.as_ref()).await;
+    let failed = result.is_err();
+    ctx.bundles.update(&job.id, |j| match result {
+        Ok(()) => j.state = JobState::Done,
+        Err(e) => {
+            error!("build bundle {} failed: {}", j.id, e);
+            j.state = JobState::Failed;
+            j.error = Some(e.to_string());
+        }
+    });
+    if failed {
+        ctx.bundles.collect_garbage();
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct BundleParam {
+    provider: Provider,
+    /// id of a playlist or album saved to the library
+    id: String,
+    /// convert the tracks to the format
+    format: Option<Format>,
+}
+
+/// Start bundling a library playlist for the capabilities of the client, see
+/// `client_capabilities`. Poll the returned job until it is done, then download its archive.
+pub async fn create_handler(
+    req: HttpRequest,
+    param: Json<BundleParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let BundleParam {
+        provider,
+        id,
+        format,
+    } = param.into_inner();
+    info!(
+        "[Handler] create bundle: provider: {:?}, id: {}, format: {:?}",
+        provider,
+        redact(&id),
+        format
+    );
+    if format.is_some() && ctx.transcoder.is_none() {
+        return Err(actix_web::error::ErrorBadRequest(
+            "transcoding is not enabled",
+        ));
+    }
+
+    let playlist = id::decode(&id).into_owned();
+    if !ctx.manager.favorites().contains(&provider, &playlist) {
+        return Err(actix_web::error::ErrorNotFound(format!(
+            "playlist not in the library: {}",
+            id
+        )));
+    }
+    let capabilities = client_capabilities(&req, &ctx)?;
+    let job = ctx
+        .bundles
+        .start(owner(&req), provider, playlist, format)
+        .map_err(actix_web::error::ErrorTooManyRequests)?;
+    actix_web::rt::spawn(run(ctx.clone(), job.clone(), capabilities));
+    Ok(HttpResponse::Accepted().json(job))
+}
+
+pub async fn list_handler(req: HttpRequest, ctx: web::Data<Context>) -> Json<Vec<BundleJob>> {
+    Json(ctx.bundles.list(owner(&req).as_deref()))
+}
+
+fn job(req: &HttpRequest, ctx: &Context, id: &str) -> actix_web::Result<BundleJob> {
+    ctx.bundles
+        .get(owner(req).as_deref(), id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("bundle not found: {}", id)))
+}
+
+pub async fn get_handler(
+    req: HttpRequest,
+    id: Path<String>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<BundleJob>> {
+    job(&req, &ctx, &id).map(Json)
+}
+
+/// The tar archive of a finished job. Downloads are resumable by byte ranges.
+pub async fn archive_handler(
+    req: HttpRequest,
+    id: Path<String>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let job = job(&req, &ctx, &id)?;
+    if job.state != JobState::Done {
+        return Err(actix_web::error::ErrorConflict(format!(
+            "bundle is {}",
+            serde_json::to_string(&job.state).unwrap_or_default()
+        )));
+    }
+
+    let file = tokio::fs::File::open(ctx.bundles.archive(&job.id))
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut resp = serve_file(&req, ctx.downloads.clone(), file, "application/x-tar").await?;
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!("{}.tar", job.id))],
+    };
+    resp.headers_mut().insert(
+        header::CONTENT_DISPOSITION,
+        disposition
+            .to_string()
+            .parse()
+            .map_err(actix_web::error::ErrorInternalServerError)?,
+    );
+    Ok(resp)
+}
+
+pub async fn remove_handler(
+    req: HttpRequest,
+    id: Path<String>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    info!("[Handler] remove bundle: {}", redact(&id));
+    match ctx.bundles.remove(owner(&req).as_deref(), &id) {
+        true => Ok(HttpResponse::NoContent().finish()),
+        false => Err(actix_web::error::ErrorNotFound(format!(
+            "bundle not found: {}",
+            id
+        ))),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read;
+
+    use bragi_core::scraper::{Artist, Provider, Song, SongCollection, Stream};
+
+    use super::{audio_extension, file_name, BundleJobs, BundleWriter, Download, JobState};
+
+    fn song(id: &str, name: &str) -> Song {
+        Song {
+            id: id.into(),
+            name: name.into(),
+            artists: vec![Artist {
+                id: "1".into(),
+                name: "YOASOBI".into(),
+                description: None,
+                avatar: None,
+            }],
+            cover: None,
+            duration: Some(261),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+
+    fn stream(url: &str, codec: Option<&str>) -> Stream {
+        Stream {
+            quality: "320k".into(),
+            url: url.into(),
+            bitrate: Some(320_000),
+            codec: codec.map(Into::into),
+            mirror: false,
+            loudness: None,
+            preview: false,
+        }
+    }
+
+    #[test]
+    fn test_file_names() {
+        assert_eq!(file_name("AC/DC: Live?"), "AC_DC_ Live_");
+        assert_eq!(file_name(" .. "), "untitled");
+        let mp3 = stream("https://m701.music.126.net/a/b/1001.mp3?vuutv=x", None);
+        assert_eq!(audio_extension(&mp3, None), "mp3");
+        assert_eq!(audio_extension(&mp3, Some("audio/flac")), "flac");
+        assert_eq!(audio_extension(&mp3, Some("audio/aac")), "aac");
+        let opus = stream("https://rr1.googlevideo.com/videoplayback", Some("opus"));
+        assert_eq!(audio_extension(&opus, None), "webm");
+        assert_eq!(audio_extension(&stream("https://cdn/x", None), None), "bin");
+    }
+
+    #[test]
+    fn test_bundle_writer() {
+        let path = std::env::temp_dir().join(format!("bragi-bundle-{}.tar", std::process::id()));
+        let playlist = SongCollection {
+            id: "2001".into(),
+            name: "夜/朝".into(),
+            artists: vec![],
+            cover: Some("https://p1.music.126.net/cover.jpg".into()),
+            description: None,
+            songs: vec![song("1001", "夜に駆ける"), song("1002", "群青")],
+            version: None,
+            unavailable: false,
+            saved: true,
+            stale: false,
+        };
+
+        let mut writer = BundleWriter::create(&path, &playlist.name).unwrap();
+        writer
+            .add_cover(Download {
+                content_type: Some("image/png".into()),
+                data: b"png".to_vec(),
+            })
+            .unwrap();
+        writer
+            .add_track(
+                0,
+                2,
+                playlist.songs[0].clone(),
+                stream("https://cdn/1001.mp3", None),
+                Download {
+                    content_type: None,
+                    data: b"audio".to_vec(),
+                },
+            )
+            .unwrap();
+        writer.skip(playlist.songs[1].clone(), "no playable stream".into());
+        writer.finish(&Provider::NetEase, &playlist).unwrap();
+
+        let mut archive = tar::Archive::new(std::fs::File::open(&path).unwrap());
+        let mut files = Vec::new();
+        let mut m3u = String::new();
+        for entry in archive.entries().unwrap() {
+            let mut entry = entry.unwrap();
+            let name = entry.path().unwrap().to_string_lossy().to_string();
+            if name.ends_with(".m3u8") {
+                entry.read_to_string(&mut m3u).unwrap();
+            }
+            files.push(name);
+        }
+        assert_eq!(
+            files,
+            vec![
+                "夜_朝/cover.png",
+                "夜_朝/01 - 夜に駆ける.mp3",
+                "夜_朝/playlist.m3u8",
+                "夜_朝/metadata.json",
+            ]
+        );
+        assert_eq!(
+            m3u,
+            "#EXTM3U\n#EXTINF:261,YOASOBI - 夜に駆ける\n01 - 夜に駆ける.mp3\n"
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_collect_garbage() {
+        let dir = std::env::temp_dir().join(format!("bragi-bundles-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // left by an earlier run
+        std::fs::write(dir.join("0123456789abcdef.tar"), b"").unwrap();
+        std::fs::write(dir.join("0123456789abcdef.part"), b"").unwrap();
+        // not bundles, put there by someone else
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+        std::fs::write(dir.join("backup.tar"), b"").unwrap();
+        std::fs::write(dir.join("0123456789abcdef.tar.gz"), b"").unwrap();
+
+        let jobs = BundleJobs::try_new(Some(dir.to_string_lossy().to_string())).unwrap();
+        assert!(!dir.join("0123456789abcdef.tar").exists());
+        assert!(!dir.join("0123456789abcdef.part").exists());
+        for kept in ["notes.txt", "backup.tar", "0123456789abcdef.tar.gz"] {
+            assert!(dir.join(kept).exists(), "{}", kept);
+        }
+
+        let running = jobs
+            .start(None, Provider::NetEase, "1".into(), None)
+            .unwrap();
+        let failed = jobs
+            .start(None, Provider::NetEase, "2".into(), None)
+            .unwrap();
+        std::fs::write(jobs.archive(&running.id).with_extension("part"), b"").unwrap();
+        std::fs::write(jobs.archive(&failed.id).with_extension("part"), b"").unwrap();
+        jobs.update(&failed.id, |j| j.state = JobState::Failed);
+        jobs.collect_garbage();
+        assert!(jobs.archive(&running.id).with_extension("part").exists());
+        assert!(!jobs.archive(&failed.id).with_extension("part").exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
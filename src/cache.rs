@@ -0,0 +1,465 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    path::PathBuf,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::web::Bytes;
+use anyhow::anyhow;
+use bragi_core::{scraper::Provider, settings::CacheSettings};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+};
+use tracing::{error, info, warn};
+
+use crate::transcode::Format;
+
+/// Entries of the cache, rewritten whenever one is added or evicted
+const INDEX: &str = "index.json";
+/// The index is written here first and renamed over, so that a crash cannot truncate it
+const INDEX_TEMP: &str = "index.json.tmp";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Entry {
+    size: u64,
+    content_type: String,
+    /// milliseconds since the epoch, the least recently used entries are evicted first
+    used: u64,
+}
+
+/// Whether the file is named like an entry, `<key>` or the part `<key>.<random>.part`. Only
+/// these are ever removed, so other files of the directory are left alone.
+fn is_cache_file(name: &str) -> bool {
+    let hex = |s: &str, len: usize| s.len() == len && s.bytes().all(|b| b.is_ascii_hexdigit());
+    match name.split('.').collect::<Vec<_>>()[..] {
+        [key] => hex(key, 16),
+        [key, random, "part"] => hex(key, 16) && hex(random, 8),
+        _ => false,
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+/// Audio of the stream proxy and offline bundles kept on disk up to a size, so that songs played
+/// again are neither fetched nor converted again. Files are named by a hash of the song, quality
+/// and format, the index tells their content type.
+#[derive(Debug)]
+pub struct AudioCache {
+    dir: PathBuf,
+    max_size: u64,
+    entries: Mutex<HashMap<String, Entry>>,
+    /// Held while the index is written, so that the last write is of the latest entries
+    saving: Mutex<()>,
+}
+
+impl AudioCache {
+    /// None if no directory is configured. Cache files of the directory missing from the index,
+    /// like the parts of writes cut short, are removed. An index that cannot be read is an error
+    /// rather than an empty cache, which would remove all of them.
+    pub fn try_new(setting: &CacheSettings) -> anyhow::Result<Option<Self>> {
+        let Some(dir) = &setting.dir else {
+            return Ok(None);
+        };
+        let dir = PathBuf::from(dir);
+        std::fs::create_dir_all(&dir)?;
+
+        let index = dir.join(INDEX);
+        let mut entries: HashMap<String, Entry> = match std::fs::File::open(&index) {
+            Ok(f) => serde_json::from_reader(std::io::BufReader::new(f)).map_err(|e| {
+                anyhow!(
+                    "cache index {} is corrupt, remove it to start over: {}",
+                    index.display(),
+                    e
+                )
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                return Err(anyhow!(
+                    "read cache index {} failed: {}",
+                    index.display(),
+                    e
+                ))
+            }
+        };
+        entries.retain(|key, entry| {
+            is_cache_file(key)
+                && std::fs::metadata(dir.join(key)).is_ok_and(|m| m.len() == entry.size)
+        });
+        for file in std::fs::read_dir(&dir)?.flatten() {
+            let name = file.file_name().to_string_lossy().to_string();
+            if is_cache_file(&name) && !entries.contains_key(&name) {
+                let _ = std::fs::remove_file(file.path());
+            }
+        }
+        info!(
+            "audio cache in {}: {} songs, {} bytes",
+            dir.display(),
+            entries.len(),
+            entries.values().map(|e| e.size).sum::<u64>()
+        );
+
+        let cache = Self {
+            dir,
+            max_size: setting.max_size,
+            entries: Mutex::new(entries),
+            saving: Mutex::new(()),
+        };
+        let evicted = cache.evict(&mut cache.entries.lock());
+        cache.remove(&evicted);
+        cache.save();
+        Ok(Some(cache))
+    }
+
+    /// Name of the audio of the song in the quality, converted to the format if any. The hash
+    /// may change with the toolchain, which only leaves the older entries to be evicted.
+    pub fn key(
+        provider: &Provider,
+        id: &str,
+        quality: Option<&str>,
+        format: Option<Format>,
+    ) -> String {
+        let mut hasher = DefaultHasher::new();
+        (
+            provider.to_string(),
+            id,
+            quality,
+            format.map(Format::extension),
+        )
+            .hash(&mut hasher);
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Path and content type of the audio, marked as just used
+    pub fn get(&self, key: &str) -> Option<(PathBuf, String)> {
+        let mut entries = self.entries.lock();
+        let entry = entries.get_mut(key)?;
+        let path = self.dir.join(key);
+        if !path.exists() {
+            entries.remove(key);
+            return None;
+        }
+        entry.used = now();
+        Some((path, entry.content_type.clone()))
+    }
+
+    /// Writer of the audio, kept once finished. None if the file cannot be created.
+    pub async fn writer(self: &Arc<Self>, key: &str, content_type: &str) -> Option<CacheWriter> {
+        let part = self
+            .dir
+            .join(format!("{}.{:08x}.part", key, rand::random::<u32>()));
+        match File::create(&part).await {
+            Ok(file) => Some(CacheWriter {
+                cache: self.clone(),
+                key: key.to_string(),
+                content_type: content_type.to_string(),
+                part,
+                file: Some(BufWriter::new(file)),
+                size: 0,
+            }),
+            Err(e) => {
+                warn!("create cache file {} failed: {}", part.display(), e);
+                None
+            }
+        }
+    }
+
+    pub async fn put(self: &Arc<Self>, key: &str, content_type: &str, data: &[u8]) {
+        if let Some(mut writer) = self.writer(key, content_type).await {
+            match writer.write(data).await {
+                Ok(()) => writer.finish().await,
+                Err(e) => warn!("write cache file failed: {}", e),
+            }
+        }
+    }
+
+    /// The body as is, written to the cache as it goes. Kept only if it ends without an error,
+    /// a body dropped midway like by a client going away leaves nothing. The file is created
+    /// once the first chunk is polled.
+    pub fn tee<S, E>(
+        self: &Arc<Self>,
+        key: &str,
+        content_type: &str,
+        body: S,
+    ) -> impl futures::Stream<Item = Result<Bytes, E>> + 'static
+    where
+        S: futures::Stream<Item = Result<Bytes, E>> + 'static,
+        E: 'static,
+    {
+        let open = Some((self.clone(), key.to_string(), content_type.to_string()));
+        futures::stream::unfold(
+            (Box::pin(body), open, None),
+            |(mut body, mut open, mut writer)| async move {
+                if let Some((cache, key, content_type)) = open.take() {
+                    writer = cache.writer(&key, &content_type).await;
+                }
+                match body.next().await {
+                    Some(Ok(chunk)) => {
+                        if let Some(w) = writer.as_mut() {
+                            if let Err(e) = w.write(&chunk).await {
+                                warn!("write cache file failed: {}", e);
+                                writer = None;
+                            }
+                        }
+                        Some((Ok(chunk), (body, None, writer)))
+                    }
+                    Some(Err(e)) => Some((Err(e), (body, None, None))),
+                    None => {
+                        if let Some(writer) = writer {
+                            writer.finish().await;
+                        }
+                        None
+                    }
+                }
+            },
+        )
+    }
+
+    fn commit(&self, key: String, part: PathBuf, content_type: String, size: u64) {
+        if size > self.max_size {
+            let _ = std::fs::remove_file(&part);
+            return;
+        }
+        let path = self.dir.join(&key);
+        if let Err(e) = std::fs::rename(&part, &path) {
+            error!("keep cache file {} failed: {}", path.display(), e);
+            let _ = std::fs::remove_file(&part);
+            return;
+        }
+        let evicted = {
+            let mut entries = self.entries.lock();
+            entries.insert(
+                key,
+                Entry {
+                    size,
+                    content_type,
+                    used: now(),
+                },
+            );
+            self.evict(&mut entries)
+        };
+        self.remove(&evicted);
+        self.save();
+    }
+
+    /// Drop the least recently used entries until the rest fits the size. The keys dropped are
+    /// returned for their files to be removed once the lock is released.
+    fn evict(&self, entries: &mut HashMap<String, Entry>) -> Vec<String> {
+        let mut size: u64 = entries.values().map(|e| e.size).sum();
+        if size <= self.max_size {
+            return vec![];
+        }
+        let mut lru: Vec<(u64, String)> = entries
+            .iter()
+            .map(|(key, entry)| (entry.used, key.clone()))
+            .collect();
+        lru.sort();
+        let mut evicted = vec![];
+        for (_, key) in lru {
+            if size <= self.max_size {
+                break;
+            }
+            if let Some(entry) = entries.remove(&key) {
+                size -= entry.size;
+                evicted.push(key);
+            }
+        }
+        evicted
+    }
+
+    fn remove(&self, keys: &[String]) {
+        for key in keys {
+            if let Err(e) = std::fs::remove_file(self.dir.join(key)) {
+                warn!("evict cache file {} failed: {}", key, e);
+            }
+        }
+    }
+
+    fn save(&self) {
+        let _saving = self.saving.lock();
+        let (temp, path) = (self.dir.join(INDEX_TEMP), self.dir.join(INDEX));
+        let index = serde_json::to_vec(&*self.entries.lock());
+        if let Err(e) = index
+            .map_err(anyhow::Error::from)
+            .and_then(|index| Ok(std::fs::write(&temp, index)?))
+            .and_then(|()| Ok(std::fs::rename(&temp, &path)?))
+        {
+            error!("save cache index to {} failed: {}", path.display(), e);
+        }
+    }
+}
+
+/// Audio being written to the cache through tokio, so that writing never blocks the runtime. Its
+/// part file is removed if dropped before `finish`.
+pub struct CacheWriter {
+    cache: Arc<AudioCache>,
+    key: String,
+    content_type: String,
+    part: PathBuf,
+    file: Option<BufWriter<File>>,
+    size: u64,
+}
+
+impl CacheWriter {
+    pub async fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(data).await?;
+            self.size += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Keep the audio written, off the runtime since renaming and evicting are blocking
+    pub async fn finish(mut self) {
+        let Some(mut file) = self.file.take() else {
+            return;
+        };
+        if let Err(e) = file.flush().await {
+            warn!("write cache file failed: {}", e);
+            let _ = tokio::fs::remove_file(&self.part).await;
+            return;
+        }
+        drop(file);
+        let (cache, key, part) = (
+            self.cache.clone(),
+            std::mem::take(&mut self.key),
+            self.part.clone(),
+        );
+        let (content_type, size) = (std::mem::take(&mut self.content_type), self.size);
+        let commit =
+            tokio::task::spawn_blocking(move || cache.commit(key, part, content_type, size));
+        if let Err(e) = commit.await {
+            error!("keep cache file failed: {}", e);
+        }
+    }
+}
+
+impl Drop for CacheWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.part);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, sync::Arc};
+
+    use actix_web::web::Bytes;
+    use bragi_core::{scraper::Provider, settings::CacheSettings};
+    use futures::StreamExt;
+
+    use crate::transcode::Format;
+
+    use super::{is_cache_file, AudioCache, INDEX};
+
+    fn setting(name: &str, max_size: u64) -> CacheSettings {
+        let dir = std::env::temp_dir().join(format!("bragi-cache-{}-{}", name, std::process::id()));
+        CacheSettings {
+            dir: Some(dir.to_string_lossy().to_string()),
+            max_size,
+        }
+    }
+
+    #[test]
+    fn test_key() {
+        let key = |quality, format| AudioCache::key(&Provider::NetEase, "1", quality, format);
+        assert_eq!(key(Some("lossless"), None), key(Some("lossless"), None));
+        assert_ne!(key(Some("lossless"), None), key(Some("exhigh"), None));
+        assert_ne!(
+            key(Some("lossless"), None),
+            key(Some("lossless"), Some(Format::Mp3))
+        );
+    }
+
+    #[test]
+    fn test_cache_file() {
+        assert!(is_cache_file("0123456789abcdef"));
+        assert!(is_cache_file("0123456789abcdef.0a1b2c3d.part"));
+        assert!(!is_cache_file(INDEX));
+        assert!(!is_cache_file("notes.txt"));
+        assert!(!is_cache_file("0123456789abcdef.part"));
+    }
+
+    #[actix_web::test]
+    async fn test_lru() {
+        let setting = setting("lru", 10);
+        let cache = Arc::new(AudioCache::try_new(&setting).unwrap().unwrap());
+        let key = |id| AudioCache::key(&Provider::NetEase, id, None, None);
+        let (a, b, c, d) = (key("a"), key("b"), key("c"), key("d"));
+        cache.put(&a, "audio/mpeg", b"aaaa").await;
+        cache.put(&b, "audio/mpeg", b"bbbb").await;
+        // a is used after b, so b goes first
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        assert_eq!(cache.get(&a).unwrap().1, "audio/mpeg");
+        cache.put(&c, "audio/flac", b"cccc").await;
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&c).is_some());
+        // larger than the whole cache
+        cache.put(&d, "audio/mpeg", &[0; 11]).await;
+        assert!(cache.get(&d).is_none());
+
+        // the index survives a restart, stray parts do not, files of the user do
+        let dir = std::path::Path::new(setting.dir.as_ref().unwrap());
+        let part = dir.join(format!("{}.0000000e.part", key("e")));
+        std::fs::write(&part, b"e").unwrap();
+        std::fs::write(dir.join("notes.txt"), b"").unwrap();
+        let cache = AudioCache::try_new(&setting).unwrap().unwrap();
+        let (path, content_type) = cache.get(&c).unwrap();
+        assert_eq!(content_type, "audio/flac");
+        assert_eq!(std::fs::read(path).unwrap(), b"cccc");
+        assert!(!part.exists());
+        assert!(dir.join("notes.txt").exists());
+
+        // a corrupt index is refused rather than emptying the cache
+        std::fs::write(dir.join(INDEX), b"{\"").unwrap();
+        assert!(AudioCache::try_new(&setting).is_err());
+        assert!(dir.join(&c).exists());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_tee() {
+        let setting = setting("tee", 1024);
+        let cache = Arc::new(AudioCache::try_new(&setting).unwrap().unwrap());
+        let body = || {
+            futures::stream::iter(["fLaC", "frames"].map(|c| Ok::<_, Infallible>(Bytes::from(c))))
+        };
+
+        let played = cache
+            .tee("whole", "audio/flac", body())
+            .map(|c| c.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert_eq!(played, b"fLaCframes");
+        let (path, _) = cache.get("whole").unwrap();
+        assert_eq!(std::fs::read(path).unwrap(), b"fLaCframes");
+
+        // the client went away after the first chunk
+        let mut tee = Box::pin(cache.tee("partial", "audio/flac", body()));
+        tee.next().await.unwrap().unwrap();
+        drop(tee);
+        assert!(cache.get("partial").is_none());
+        let files = std::fs::read_dir(setting.dir.as_ref().unwrap())
+            .unwrap()
+            .count();
+        // the index and the whole song
+        assert_eq!(files, 2);
+
+        std::fs::remove_dir_all(setting.dir.unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,186 @@
+use anyhow::{anyhow, bail};
+
+/// Size of the head fetched first. The init part and the segment index of audio streams fit in it
+/// unless the upload is hours long.
+pub const HEAD_SIZE: u64 = 64 * 1024;
+/// Largest head fetched for a long segment index
+pub const MAX_HEAD_SIZE: u64 = 1024 * 1024;
+
+/// Subsegment of a fragmented mp4, as listed by its `sidx` box
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub offset: u64,
+    pub size: u64,
+    /// seconds
+    pub start: f64,
+    pub duration: f64,
+}
+
+/// Init part and segments of a fragmented mp4, like the DASH audio of Bilibili and YouTube
+#[derive(Debug, PartialEq)]
+pub struct Index {
+    /// length of the init part, `ftyp` and `moov`, from the start of the file
+    pub init: usize,
+    pub segments: Vec<Segment>,
+}
+
+/// The head is too short, fetch this many bytes from the start instead
+#[derive(Debug, PartialEq)]
+pub struct Incomplete(pub u64);
+
+fn u32_at(data: &[u8], at: usize) -> anyhow::Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_be_bytes(b.try_into().unwrap()))
+        .ok_or(anyhow!("truncated box"))
+}
+
+fn u64_at(data: &[u8], at: usize) -> anyhow::Result<u64> {
+    data.get(at..at + 8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .ok_or(anyhow!("truncated box"))
+}
+
+/// Walk the top level boxes in the head up to the segment index
+pub fn parse_index(head: &[u8]) -> anyhow::Result<Result<Index, Incomplete>> {
+    let mut offset = 0usize;
+    loop {
+        if offset + 8 > head.len() {
+            return Ok(Err(Incomplete(offset as u64 + 16)));
+        }
+        let mut size = u32_at(head, offset)? as u64;
+        let kind = &head[offset + 4..offset + 8];
+        if size == 1 {
+            if offset + 16 > head.len() {
+                return Ok(Err(Incomplete(offset as u64 + 16)));
+            }
+            size = u64_at(head, offset + 8)?;
+        }
+        match kind {
+            b"sidx" => {
+                let end = offset as u64 + size;
+                if end > head.len() as u64 {
+                    return Ok(Err(Incomplete(end)));
+                }
+                let segments = parse_sidx(&head[offset..end as usize], end)?;
+                return Ok(Ok(Index {
+                    init: offset,
+                    segments,
+                }));
+            }
+            b"moof" | b"mdat" => bail!("no segment index before the media"),
+            _ if size < 8 => bail!("invalid box size {}", size),
+            _ => offset += size as usize,
+        }
+    }
+}
+
+/// Segments of the `sidx` box ending at `end` of the file
+fn parse_sidx(sidx: &[u8], end: u64) -> anyhow::Result<Vec<Segment>> {
+    let version = *sidx.get(8).ok_or(anyhow!("truncated box"))?;
+    let timescale = u32_at(sidx, 16)? as f64;
+    if timescale == 0.0 {
+        bail!("invalid timescale");
+    }
+    let (earliest, first_offset, mut at) = match version {
+        0 => (u32_at(sidx, 20)? as u64, u32_at(sidx, 24)? as u64, 28),
+        _ => (u64_at(sidx, 20)?, u64_at(sidx, 28)?, 36),
+    };
+    // 2 reserved bytes
+    let count = u32_at(sidx, at)? & 0xffff;
+    at += 4;
+
+    let mut segments = Vec::with_capacity(count as usize);
+    let mut offset = end + first_offset;
+    let mut time = earliest;
+    for _ in 0..count {
+        let reference = u32_at(sidx, at)?;
+        let duration = u32_at(sidx, at + 4)? as u64;
+        if reference >> 31 == 1 {
+            bail!("nested segment index");
+        }
+        let size = (reference & 0x7fff_ffff) as u64;
+        segments.push(Segment {
+            offset,
+            size,
+            start: time as f64 / timescale,
+            duration: duration as f64 / timescale,
+        });
+        offset += size;
+        time += duration;
+        at += 12;
+    }
+    Ok(segments)
+}
+
+/// Byte range, inclusive, of the segments overlapping the time range. Cut at segment boundaries,
+/// so the clip may start and end a few seconds early or late.
+pub fn byte_range(segments: &[Segment], start: u32, end: Option<u32>) -> Option<(u64, u64)> {
+    let start = f64::from(start);
+    let end = end.map_or(f64::INFINITY, f64::from);
+    let mut overlapping = segments
+        .iter()
+        .filter(|s| s.start < end && s.start + s.duration > start);
+    let first = overlapping.next()?;
+    let last = overlapping.next_back().unwrap_or(first);
+    Some((first.offset, last.offset + last.size - 1))
+}
+
+#[cfg(test)]
+pub mod test {
+    use super::{byte_range, parse_index, Incomplete};
+
+    /// `ftyp`, `moov` and a version 0 `sidx` of three 10s segments in 1000 units per second
+    pub fn head() -> Vec<u8> {
+        let mut head = vec![0, 0, 0, 16];
+        head.extend(b"ftypdash");
+        head.extend([0; 4]);
+        head.extend([0, 0, 0, 12]);
+        head.extend(b"moov");
+        head.extend([0; 4]);
+
+        let mut sidx = vec![0; 4];
+        sidx.extend(b"sidx");
+        sidx.extend([0; 4]);
+        sidx.extend(1u32.to_be_bytes());
+        sidx.extend(1000u32.to_be_bytes());
+        sidx.extend(0u32.to_be_bytes());
+        sidx.extend(0u32.to_be_bytes());
+        sidx.extend(3u32.to_be_bytes());
+        for size in [100u32, 200, 300] {
+            sidx.extend(size.to_be_bytes());
+            sidx.extend(10_000u32.to_be_bytes());
+            sidx.extend(0x9000_0000u32.to_be_bytes());
+        }
+        let size = sidx.len() as u32;
+        sidx[..4].copy_from_slice(&size.to_be_bytes());
+        head.extend(sidx);
+        head
+    }
+
+    #[test]
+    fn test_parse_index() {
+        let head = head();
+        let index = parse_index(&head).unwrap().unwrap();
+        assert_eq!(index.init, 28);
+        let end = head.len() as u64;
+        let offsets = index.segments.iter().map(|s| s.offset).collect::<Vec<_>>();
+        assert_eq!(offsets, vec![end, end + 100, end + 300]);
+        assert_eq!(index.segments[2].start, 20.0);
+
+        assert_eq!(
+            parse_index(&head[..40]).unwrap(),
+            Err(Incomplete(head.len() as u64))
+        );
+
+        // from the second segment up to the end
+        assert_eq!(
+            byte_range(&index.segments, 12, None),
+            Some((end + 100, end + 599))
+        );
+        assert_eq!(
+            byte_range(&index.segments, 0, Some(10)),
+            Some((end, end + 99))
+        );
+        assert_eq!(byte_range(&index.segments, 45, None), None);
+    }
+}
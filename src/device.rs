@@ -0,0 +1,227 @@
+use std::{
+    collections::BTreeMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{
+    web::{self, Json, Path},
+    HttpRequest, HttpResponse,
+};
+use bragi_core::{privacy::redact, settings::Capabilities};
+use parking_lot::RwLock;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use tracing::{error, info};
+
+use crate::{
+    auth::{bearer_token, fingerprint},
+    Context,
+};
+
+/// Devices registered by a single token, so that a leaked token cannot fill the disk
+const MAX_DEVICES: usize = 64;
+
+/// A client registered with a name and its playback capabilities. Passing its id as
+/// `X-Bragi-Device` applies the capabilities without declaring them on every call.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Device {
+    pub id: String,
+    pub name: String,
+    /// fingerprint of the token which registered the device. Absent if the api is open.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owner: Option<String>,
+    #[serde(flatten)]
+    pub capabilities: Capabilities,
+    /// unix seconds
+    pub registered_at: u64,
+}
+
+/// Devices by id, persisted as json if `filename` is present. Every token only sees the devices it
+/// registered.
+#[derive(Debug, Default)]
+pub struct DeviceRegistry {
+    filename: Option<String>,
+    devices: RwLock<BTreeMap<String, Device>>,
+}
+
+impl DeviceRegistry {
+    pub fn try_new(filename: Option<String>) -> anyhow::Result<Self> {
+        let Some(filename) = filename else {
+            return Ok(Self::default());
+        };
+        if let Some(parent) = std::path::Path::new(&filename).parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let devices: BTreeMap<String, Device> = std::fs::File::open(&filename)
+            .ok()
+            .map(std::io::BufReader::new)
+            .and_then(|r| serde_json::from_reader(r).ok())
+            .unwrap_or_default();
+        info!("load devices from {}: {}", filename, devices.len());
+
+        Ok(Self {
+            filename: Some(filename),
+            devices: RwLock::new(devices),
+        })
+    }
+
+    pub fn register(
+        &self,
+        owner: Option<String>,
+        name: String,
+        capabilities: Capabilities,
+    ) -> anyhow::Result<Device> {
+        let mut devices = self.devices.write();
+        if devices.values().filter(|d| d.owner == owner).count() >= MAX_DEVICES {
+            anyhow::bail!("at most {} devices per token", MAX_DEVICES);
+        }
+        let mut id = random_id();
+        while devices.contains_key(&id) {
+            id = random_id();
+        }
+        let device = Device {
+            id: id.clone(),
+            name,
+            owner,
+            capabilities,
+            registered_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        };
+        devices.insert(id, device.clone());
+        self.save(&devices)?;
+        Ok(device)
+    }
+
+    pub fn list(&self, owner: Option<&str>) -> Vec<Device> {
+        self.devices
+            .read()
+            .values()
+            .filter(|d| d.owner.as_deref() == owner)
+            .cloned()
+            .collect()
+    }
+
+    pub fn get(&self, owner: Option<&str>, id: &str) -> Option<Device> {
+        self.devices
+            .read()
+            .get(id)
+            .filter(|d| d.owner.as_deref() == owner)
+            .cloned()
+    }
+
+    /// Returns false if the owner has no such device
+    pub fn remove(&self, owner: Option<&str>, id: &str) -> anyhow::Result<bool> {
+        let mut devices = self.devices.write();
+        if devices.get(id).map(|d| d.owner.as_deref()) != Some(owner) {
+            return Ok(false);
+        }
+        devices.remove(id);
+        self.save(&devices)?;
+        Ok(true)
+    }
+
+    /// Write registry back to disk
+    fn save(&self, devices: &BTreeMap<String, Device>) -> anyhow::Result<()> {
+        let Some(filename) = &self.filename else {
+            return Ok(());
+        };
+        std::fs::File::create(filename)
+            .map(std::io::BufWriter::new)
+            .map_err(anyhow::Error::from)
+            .and_then(|w| Ok(serde_json::to_writer(w, devices)?))
+            .inspect_err(|e| error!("save devices to {} failed: {}", filename, e))
+    }
+}
+
+pub fn random_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
+
+/// Fingerprint of the token of the call, which owns the devices it registers
+pub fn owner(req: &HttpRequest) -> Option<String> {
+    bearer_token(req.headers()).map(fingerprint)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterParam {
+    name: String,
+    #[serde(flatten)]
+    capabilities: Capabilities,
+}
+
+pub async fn register_handler(
+    req: HttpRequest,
+    param: Json<RegisterParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let RegisterParam { name, capabilities } = param.into_inner();
+    info!("[Handler] register device: {}", redact(&name));
+    let device = ctx
+        .devices
+        .register(owner(&req), name, capabilities)
+        .map_err(actix_web::error::ErrorBadRequest)?;
+    Ok(HttpResponse::Created().json(device))
+}
+
+pub async fn list_handler(req: HttpRequest, ctx: web::Data<Context>) -> Json<Vec<Device>> {
+    Json(ctx.devices.list(owner(&req).as_deref()))
+}
+
+pub async fn remove_handler(
+    req: HttpRequest,
+    id: Path<String>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    info!("[Handler] remove device: {}", redact(&id));
+    match ctx.devices.remove(owner(&req).as_deref(), &id) {
+        Ok(true) => Ok(HttpResponse::NoContent().finish()),
+        Ok(false) => Err(actix_web::error::ErrorNotFound(format!(
+            "device not found: {}",
+            id
+        ))),
+        Err(e) => Err(actix_web::error::ErrorInternalServerError(e)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use bragi_core::settings::Capabilities;
+
+    use super::DeviceRegistry;
+
+    #[test]
+    fn test_registry() {
+        let filename = std::env::temp_dir()
+            .join(format!("bragi-devices-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+        let registry = DeviceRegistry::try_new(Some(filename.clone())).unwrap();
+        let capabilities = Capabilities {
+            codecs: vec!["mp4a".into()],
+            max_bitrate: Some(320_000),
+        };
+        let phone = registry
+            .register(Some("#a".into()), "phone".into(), capabilities.clone())
+            .unwrap();
+
+        // devices of other tokens are invisible
+        assert!(registry.get(Some("#b"), &phone.id).is_none());
+        assert!(registry.list(None).is_empty());
+        assert!(!registry.remove(Some("#b"), &phone.id).unwrap());
+
+        let reloaded = DeviceRegistry::try_new(Some(filename.clone())).unwrap();
+        let device = reloaded.get(Some("#a"), &phone.id).unwrap();
+        assert_eq!(device.capabilities, capabilities);
+
+        assert!(reloaded.remove(Some("#a"), &phone.id).unwrap());
+        assert!(reloaded.list(Some("#a")).is_empty());
+        std::fs::remove_file(filename).unwrap();
+    }
+}
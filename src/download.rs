@@ -0,0 +1,298 @@
+use std::{
+    collections::HashMap,
+    io::SeekFrom,
+    sync::Arc,
+    time::{Duration, Instant, UNIX_EPOCH},
+};
+
+use actix_web::{
+    body::SizedStream,
+    http::header::{self, EntityTag, HttpDate},
+    web::Bytes,
+    HttpRequest, HttpResponse,
+};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt};
+
+use crate::device::owner;
+
+/// Size of the chunks read from the file
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Pace of the downloads of every token, shared by its concurrent downloads so that opening more
+/// of them gains nothing. Unlimited if `rate` is absent.
+#[derive(Debug, Default)]
+pub struct Bandwidth {
+    /// bytes per second
+    rate: Option<u64>,
+    /// when the bytes reserved so far are sent, by token fingerprint
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl Bandwidth {
+    pub fn new(rate: Option<u64>) -> Self {
+        Self {
+            rate: rate.filter(|r| *r > 0),
+            next: Default::default(),
+        }
+    }
+
+    /// Time to wait before sending `n` bytes for the token
+    fn reserve(&self, owner: &str, n: usize) -> Duration {
+        let Some(rate) = self.rate else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let mut next = self.next.lock();
+        // tokens idle since their last reservation are at full speed again
+        next.retain(|_, at| *at > now);
+        let at = next.entry(owner.to_string()).or_insert(now);
+        let start = *at;
+        *at = start + Duration::from_secs_f64(n as f64 / rate as f64);
+        start - now
+    }
+}
+
+/// First and last byte of a single range like `bytes=100-`, `bytes=100-199` or `bytes=-500`,
+/// within the size. None for the whole file if the header is of another form, Err if the range is
+/// beyond the end.
+fn parse_range(range: &str, size: u64) -> Result<Option<(u64, u64)>, ()> {
+    let Some((first, last)) = range
+        .strip_prefix("bytes=")
+        .filter(|r| !r.contains(','))
+        .and_then(|r| r.split_once('-'))
+    else {
+        return Ok(None);
+    };
+    let (first, last) = match (first.trim(), last.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return Err(()),
+            Ok(n) => (size.saturating_sub(n), size.wrapping_sub(1)),
+            Err(_) => return Ok(None),
+        },
+        (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => (first, last.min(size.wrapping_sub(1))),
+            (Ok(first), _) if last.is_empty() => (first, size.wrapping_sub(1)),
+            _ => return Ok(None),
+        },
+    };
+    match first < size {
+        true => Ok(Some((first, last))),
+        false => Err(()),
+    }
+}
+
+fn chunks(
+    reader: impl AsyncRead + Unpin + 'static,
+) -> impl futures::Stream<Item = std::io::Result<Bytes>> + 'static {
+    futures::stream::unfold(reader, |mut reader| async move {
+        let mut buf = vec![0; CHUNK_SIZE];
+        match reader.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(Bytes::from(buf)), reader))
+            }
+            Err(e) => Some((Err(e), reader)),
+        }
+    })
+}
+
+/// Whether the range may be served: `If-Range` is absent, or names the current version of the
+/// file by its strong ETag or its exact modification date
+fn range_applies(req: &HttpRequest, etag: &EntityTag, modified: &HttpDate) -> bool {
+    let Some(if_range) = req
+        .headers()
+        .get(header::IF_RANGE)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return true;
+    };
+    match if_range.trim().parse::<EntityTag>() {
+        Ok(tag) => tag.strong_eq(etag),
+        Err(_) => if_range.trim() == modified.to_string(),
+    }
+}
+
+/// File on disk, in whole or the single byte range of the request, at the pace of the token
+/// downloading it. A changed file is sent whole when the client resumes with `If-Range`.
+pub async fn serve_file(
+    req: &HttpRequest,
+    bandwidth: Arc<Bandwidth>,
+    mut file: tokio::fs::File,
+    content_type: &str,
+) -> actix_web::Result<HttpResponse> {
+    let metadata = file.metadata().await?;
+    let size = metadata.len();
+    let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+    let etag = EntityTag::new_strong(format!(
+        "{:x}-{:x}",
+        size,
+        modified
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    ));
+    let last_modified = HttpDate::from(modified);
+
+    let range = req
+        .headers()
+        .get(header::RANGE)
+        .and_then(|r| r.to_str().ok())
+        .filter(|_| range_applies(req, &etag, &last_modified))
+        .map(|r| parse_range(r, size));
+    let range = match range {
+        Some(Err(_)) => {
+            return Ok(HttpResponse::RangeNotSatisfiable()
+                .insert_header((header::CONTENT_RANGE, format!("bytes */{}", size)))
+                .finish())
+        }
+        Some(Ok(range)) => range,
+        None => None,
+    };
+
+    let mut resp = match range {
+        Some((first, last)) => {
+            let mut resp = HttpResponse::PartialContent();
+            resp.insert_header((
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, size),
+            ));
+            resp
+        }
+        None => HttpResponse::Ok(),
+    };
+    let (first, length) = match range {
+        Some((first, last)) => (first, last - first + 1),
+        None => (0, size),
+    };
+    file.seek(SeekFrom::Start(first)).await?;
+
+    let owner = owner(req).unwrap_or_default();
+    let body = chunks(file.take(length)).then(move |chunk| {
+        let wait = chunk
+            .as_ref()
+            .map_or(Duration::ZERO, |c| bandwidth.reserve(&owner, c.len()));
+        async move {
+            if !wait.is_zero() {
+                tokio::time::sleep(wait).await;
+            }
+            chunk
+        }
+    });
+    Ok(resp
+        .content_type(content_type)
+        .insert_header((header::ACCEPT_RANGES, "bytes"))
+        .insert_header((header::ETAG, etag))
+        .insert_header((header::LAST_MODIFIED, last_modified))
+        .body(SizedStream::new(length, Box::pin(body))))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::Arc,
+        time::{Duration, UNIX_EPOCH},
+    };
+
+    use actix_web::{
+        body::to_bytes,
+        http::{header, StatusCode},
+        test::TestRequest,
+    };
+
+    use super::{parse_range, serve_file, Bandwidth};
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), Ok(Some((0, 99))));
+        assert_eq!(parse_range("bytes=900-", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=900-2000", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-100", 1000), Ok(Some((900, 999))));
+        assert_eq!(parse_range("bytes=-2000", 1000), Ok(Some((0, 999))));
+        assert_eq!(parse_range("bytes=1000-", 1000), Err(()));
+        assert_eq!(parse_range("bytes=0-", 0), Err(()));
+        // the whole file
+        assert_eq!(parse_range("bytes=0-1,5-9", 1000), Ok(None));
+        assert_eq!(parse_range("items=0-1", 1000), Ok(None));
+        assert_eq!(parse_range("bytes=9-1", 1000), Ok(None));
+    }
+
+    #[test]
+    fn test_bandwidth() {
+        let bandwidth = Bandwidth::new(Some(1000));
+        assert_eq!(bandwidth.reserve("#1", 500), Duration::ZERO);
+        // shared by the downloads of the token, not by other tokens
+        let wait = bandwidth.reserve("#1", 500);
+        assert!(wait > Duration::from_millis(400) && wait <= Duration::from_millis(500));
+        assert_eq!(bandwidth.reserve("#2", 500), Duration::ZERO);
+
+        let unlimited = Bandwidth::new(None);
+        assert_eq!(unlimited.reserve("#1", 1 << 30), Duration::ZERO);
+        assert_eq!(unlimited.reserve("#1", 1 << 30), Duration::ZERO);
+    }
+
+    #[actix_web::test]
+    async fn test_serve_file() {
+        let path = std::env::temp_dir().join(format!("bragi-download-{}", std::process::id()));
+        std::fs::write(&path, b"0123456789").unwrap();
+        let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+        let serve = |req: TestRequest| {
+            let path = path.clone();
+            async move {
+                let file = tokio::fs::File::open(&path).await.unwrap();
+                let req = req.to_http_request();
+                serve_file(
+                    &req,
+                    Arc::new(Bandwidth::default()),
+                    file,
+                    "application/x-tar",
+                )
+                .await
+                .unwrap()
+            }
+        };
+
+        let resp = serve(TestRequest::get()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get(header::ETAG).unwrap().clone();
+        assert_eq!(
+            etag.to_str().unwrap(),
+            format!(
+                "\"a-{:x}\"",
+                modified.duration_since(UNIX_EPOCH).unwrap().as_secs()
+            )
+        );
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "0123456789");
+
+        // resumed on the same version of the file
+        let resp = serve(
+            TestRequest::get()
+                .insert_header((header::RANGE, "bytes=4-"))
+                .insert_header((header::IF_RANGE, etag)),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 4-9/10"
+        );
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "456789");
+
+        // the file changed since
+        let resp = serve(
+            TestRequest::get()
+                .insert_header((header::RANGE, "bytes=4-"))
+                .insert_header((header::IF_RANGE, "\"other\"")),
+        )
+        .await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(to_bytes(resp.into_body()).await.unwrap(), "0123456789");
+
+        let resp = serve(TestRequest::get().insert_header((header::RANGE, "bytes=10-"))).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
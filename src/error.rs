@@ -0,0 +1,148 @@
+use std::fmt;
+
+use actix_web::{
+    http::{header::RETRY_AFTER, StatusCode},
+    HttpResponse, ResponseError,
+};
+use bragi_core::{InvalidId, RateLimited, Restricted, Restriction};
+use serde::Serialize;
+
+/// Error body of the api, like: `{"error": "...", "retry_after": 600}`
+#[derive(Debug, Serialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    status: StatusCode,
+    /// kind of the error for clients to tell apart, like `auth_required`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+    error: String,
+    /// seconds to wait before retrying, also sent as `Retry-After`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    retry_after: Option<u64>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        Self {
+            status,
+            code: None,
+            error: error.into(),
+            retry_after: None,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.error)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut resp = HttpResponse::build(self.status);
+        if let Some(retry_after) = self.retry_after {
+            resp.insert_header((RETRY_AFTER, retry_after));
+        }
+        resp.json(self)
+    }
+}
+
+/// Malformed ids are the fault of the client rather than of the provider. A rate limited
+/// provider is unavailable until `retry_after`, so that clients back off instead of retrying.
+/// Restricted content is refused with a code telling whether an account or the region is the
+/// reason.
+pub fn provider_error(e: anyhow::Error) -> ApiError {
+    let (status, code, retry_after) = if e.downcast_ref::<InvalidId>().is_some() {
+        (StatusCode::BAD_REQUEST, None, None)
+    } else if let Some(limited) = e.downcast_ref::<RateLimited>() {
+        (
+            StatusCode::SERVICE_UNAVAILABLE,
+            None,
+            Some(limited.retry_after.as_secs()),
+        )
+    } else if let Some(restricted) = e.downcast_ref::<Restricted>() {
+        match restricted.restriction {
+            Restriction::AuthRequired => (StatusCode::FORBIDDEN, Some("auth_required"), None),
+            Restriction::RegionLocked => (
+                StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS,
+                Some("region_locked"),
+                None,
+            ),
+        }
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, None, None)
+    };
+    ApiError {
+        status,
+        code,
+        error: e.to_string(),
+        retry_after,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use actix_web::{body::to_bytes, http::StatusCode, ResponseError};
+    use bragi_core::{RateLimited, Restricted, Restriction};
+
+    use super::provider_error;
+
+    #[actix_web::test]
+    async fn test_retry_after() {
+        let e = provider_error(
+            RateLimited {
+                reason: "bilibili risk control -352".into(),
+                retry_after: Duration::from_secs(600),
+            }
+            .into(),
+        );
+        let resp = e.error_response();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(resp.headers().get("Retry-After").unwrap(), "600");
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["retry_after"], 600);
+
+        let resp = provider_error(anyhow::anyhow!("down")).error_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(resp.headers().get("Retry-After").is_none());
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], br#"{"error":"down"}"#);
+    }
+
+    #[actix_web::test]
+    async fn test_restricted() {
+        let restricted = |restriction| {
+            provider_error(
+                Restricted {
+                    restriction,
+                    reason: "netease song 1866231828".into(),
+                }
+                .into(),
+            )
+            .error_response()
+        };
+        let resp = restricted(Restriction::AuthRequired);
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "auth_required");
+        assert_eq!(
+            body["error"],
+            "authentication required: netease song 1866231828"
+        );
+
+        let resp = restricted(Restriction::RegionLocked);
+        assert_eq!(resp.status(), StatusCode::UNAVAILABLE_FOR_LEGAL_REASONS);
+        let body = to_bytes(resp.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "region_locked");
+    }
+}
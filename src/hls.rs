@@ -0,0 +1,234 @@
+use std::fmt::Write;
+
+use actix_web::{
+    web::{self, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use bragi_core::{privacy::redact, scraper::Provider};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    auth, client_capabilities,
+    clip::Index,
+    proxy::{clippable, playable, proxy_url},
+    transcode::Format,
+    Context,
+};
+
+const CONTENT_TYPE: &str = "application/vnd.apple.mpegurl";
+
+/// Segment of a media playlist, a byte range of its uri if `range` is set
+#[derive(Debug, PartialEq)]
+struct MediaSegment {
+    uri: String,
+    /// seconds
+    duration: f64,
+    /// offset and size
+    range: Option<(u64, u64)>,
+}
+
+/// Media playlist of a whole song, every segment listed up front
+#[derive(Debug, PartialEq)]
+struct MediaPlaylist {
+    /// uri and size of the init part of fMP4 segments, at the start of the uri
+    map: Option<(String, u64)>,
+    segments: Vec<MediaSegment>,
+}
+
+impl MediaPlaylist {
+    /// The segments of a fragmented mp4 as byte ranges of the uri, so that no segment has to be
+    /// cut or stored
+    fn fragmented(uri: &str, index: &Index) -> Self {
+        Self {
+            map: Some((uri.to_string(), index.init as u64)),
+            segments: index
+                .segments
+                .iter()
+                .map(|s| MediaSegment {
+                    uri: uri.to_string(),
+                    duration: s.duration,
+                    range: Some((s.offset, s.size)),
+                })
+                .collect(),
+        }
+    }
+
+    /// The whole file as a single segment of packed audio, for streams without a segment index
+    fn packed(uri: &str, duration: f64) -> Self {
+        Self {
+            map: None,
+            segments: vec![MediaSegment {
+                uri: uri.to_string(),
+                duration,
+                range: None,
+            }],
+        }
+    }
+
+    fn render(&self) -> String {
+        let target = self
+            .segments
+            .iter()
+            .map(|s| s.duration.ceil() as u64)
+            .max()
+            .unwrap_or_default();
+        // fMP4 segments need version 7, byte ranges 4 and fractional durations 3
+        let version = match self.map {
+            Some(_) => 7,
+            None => 3,
+        };
+
+        let mut playlist = String::new();
+        let _ = writeln!(playlist, "#EXTM3U");
+        let _ = writeln!(playlist, "#EXT-X-VERSION:{}", version);
+        let _ = writeln!(playlist, "#EXT-X-TARGETDURATION:{}", target);
+        let _ = writeln!(playlist, "#EXT-X-PLAYLIST-TYPE:VOD");
+        let _ = writeln!(playlist, "#EXT-X-INDEPENDENT-SEGMENTS");
+        if let Some((uri, size)) = &self.map {
+            let _ = writeln!(
+                playlist,
+                "#EXT-X-MAP:URI=\"{}\",BYTERANGE=\"{}@0\"",
+                uri, size
+            );
+        }
+        for segment in &self.segments {
+            let _ = writeln!(playlist, "#EXTINF:{:.3},", segment.duration);
+            if let Some((offset, size)) = segment.range {
+                let _ = writeln!(playlist, "#EXT-X-BYTERANGE:{}@{}", size, offset);
+            }
+            let _ = writeln!(playlist, "{}", segment.uri);
+        }
+        let _ = writeln!(playlist, "#EXT-X-ENDLIST");
+        playlist
+    }
+}
+
+/// Seconds of a stream of the size and bits per second
+fn duration(size: u64, bitrate: u64) -> f64 {
+    (size * 8) as f64 / bitrate as f64
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HlsParam {
+    /// quality of the stream as listed by the stream endpoint. The first playable one if absent
+    quality: Option<String>,
+}
+
+/// HLS media playlist of the song for players like Safari and AVPlayer, with the segments served
+/// by the stream proxy. Fragmented mp4 streams, the DASH audio of Bilibili and YouTube, are
+/// segmented by their segment index. Other streams are played as a single segment of packed audio,
+/// converted to AAC unless they are MP3 already, which requires transcoding to be enabled.
+pub async fn playlist_handler(
+    req: HttpRequest,
+    path: Path<(Provider, String)>,
+    param: Query<HlsParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let (provider, id) = path.into_inner();
+    info!(
+        "[Handler] hls playlist: provider: {:?}, id: {}",
+        provider,
+        redact(&id)
+    );
+
+    let capabilities = client_capabilities(&req, &ctx)?;
+    let headers = ctx.manager.stream_headers(&provider).await;
+    let streams = playable(
+        &ctx,
+        &provider,
+        &id,
+        param.quality.as_ref(),
+        capabilities.as_ref(),
+    )
+    .await?;
+
+    let mut playlist = None;
+    for stream in streams.iter().filter(|s| clippable(s)) {
+        match ctx.proxy.index(&stream.url, &headers).await {
+            Ok((_, index)) => {
+                let uri = auth::sign(&proxy_url(&provider, &id, &stream.quality, None));
+                playlist = Some(MediaPlaylist::fragmented(&uri, &index));
+                break;
+            }
+            Err(e) => warn!("index stream {} failed: {}", stream.quality, e),
+        }
+    }
+
+    let playlist = match playlist {
+        Some(playlist) => playlist,
+        None => {
+            let stream = &streams[0];
+            let mp3 = stream
+                .codec
+                .as_deref()
+                .is_some_and(|c| c.eq_ignore_ascii_case("mp3"));
+            let format = match (mp3, &ctx.transcoder) {
+                (true, _) => None,
+                (false, Some(_)) => Some(Format::Aac),
+                (false, None) => {
+                    return Err(actix_web::error::ErrorNotFound(
+                        "no stream can be segmented without transcoding",
+                    ))
+                }
+            };
+            let bitrate = stream.bitrate.filter(|b| *b > 0).ok_or_else(|| {
+                actix_web::error::ErrorBadGateway("duration of the stream unknown")
+            })?;
+            let size = ctx.proxy.size(&stream.url, &headers).await.map_err(|e| {
+                actix_web::error::ErrorBadGateway(format!("fetch stream failed: {}", e))
+            })?;
+            let uri = auth::sign(&proxy_url(&provider, &id, &stream.quality, format));
+            MediaPlaylist::packed(&uri, duration(size, bitrate))
+        }
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(CONTENT_TYPE)
+        .body(playlist.render()))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clip::{self, parse_index};
+
+    use super::{duration, MediaPlaylist};
+
+    #[test]
+    fn test_fragmented() {
+        let head = clip::test::head();
+        let index = parse_index(&head).unwrap().unwrap();
+        let end = head.len();
+        let playlist = MediaPlaylist::fragmented("/api/v1/stream/proxy?id=1", &index).render();
+        let expected = format!(
+            "#EXTM3U\n\
+            #EXT-X-VERSION:7\n\
+            #EXT-X-TARGETDURATION:10\n\
+            #EXT-X-PLAYLIST-TYPE:VOD\n\
+            #EXT-X-INDEPENDENT-SEGMENTS\n\
+            #EXT-X-MAP:URI=\"/api/v1/stream/proxy?id=1\",BYTERANGE=\"28@0\"\n\
+            #EXTINF:10.000,\n\
+            #EXT-X-BYTERANGE:100@{}\n\
+            /api/v1/stream/proxy?id=1\n\
+            #EXTINF:10.000,\n\
+            #EXT-X-BYTERANGE:200@{}\n\
+            /api/v1/stream/proxy?id=1\n\
+            #EXTINF:10.000,\n\
+            #EXT-X-BYTERANGE:300@{}\n\
+            /api/v1/stream/proxy?id=1\n\
+            #EXT-X-ENDLIST\n",
+            end,
+            end + 100,
+            end + 300
+        );
+        assert_eq!(playlist, expected);
+    }
+
+    #[test]
+    fn test_packed() {
+        let playlist = MediaPlaylist::packed("/a.mp3", duration(8_000_000, 320_000)).render();
+        assert!(playlist.contains("#EXT-X-VERSION:3\n"));
+        assert!(playlist.contains("#EXT-X-TARGETDURATION:200\n#"));
+        assert!(playlist.contains("#EXTINF:200.000,\n/a.mp3\n#EXT-X-ENDLIST"));
+        assert!(!playlist.contains("BYTERANGE"));
+    }
+}
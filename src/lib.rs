@@ -1,3 +1,19 @@
+mod builder;
+pub mod privacy;
 pub mod scraper;
 pub mod settings;
 pub(crate) mod util;
+
+pub use builder::BragiBuilder;
+pub use scraper::{
+    cursor::Cursor,
+    event::EventHandler,
+    explain::StreamTrace,
+    health::{Health, RateLimited},
+    id::{ArtistId, CollectionId, InvalidId, TrackId},
+    query::Query,
+    unavailable::{Restricted, Restriction},
+    Artist, FanOut, Loudness, Provider, ProviderStatus, ScrapeItem, ScrapeType, Scraper,
+    ScraperManager, SearchPage, Song, SongCollection, Stream, WithProvider,
+};
+pub use util::{body::LimitedBody, task::supervise};
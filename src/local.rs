@@ -0,0 +1,64 @@
+use std::path::Path;
+
+use actix_web::{
+    http::StatusCode,
+    web::{self, Query},
+    HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    download::serve_file,
+    error::{provider_error, ApiError},
+    Context,
+};
+
+/// Content types of the extensions indexed by the local provider
+const CONTENT_TYPES: &[(&str, &str)] = &[
+    ("flac", "audio/flac"),
+    ("mp3", "audio/mpeg"),
+    ("m4a", "audio/mp4"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/ogg"),
+    ("wav", "audio/wav"),
+];
+
+fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_lowercase);
+    CONTENT_TYPES
+        .iter()
+        .find(|(e, _)| Some(*e) == extension.as_deref())
+        .map_or("application/octet-stream", |(_, t)| t)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FileParam {
+    id: String,
+}
+
+/// File of the local provider as it is on disk. Single byte ranges are served, so that players
+/// can seek.
+pub async fn file_handler(
+    req: HttpRequest,
+    param: Query<FileParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let not_found = || ApiError::new(StatusCode::NOT_FOUND, "no local file of the id");
+    let path = ctx
+        .manager
+        .local_file(&param.id)
+        .await
+        .map_err(provider_error)?
+        .ok_or_else(not_found)?;
+    info!("[Handler] local file: {}", path.display());
+
+    let file = tokio::fs::File::open(&path).await.map_err(|e| {
+        warn!("open local file {}: {}", path.display(), e);
+        not_found()
+    })?;
+    serve_file(&req, ctx.downloads.clone(), file, content_type(&path)).await
+}
@@ -0,0 +1,434 @@
+use std::future::Future;
+
+use actix_web::{
+    body::{BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{HeaderValue, ACCEPT_LANGUAGE, CONTENT_LANGUAGE, CONTENT_TYPE, VARY},
+    web::Bytes,
+    Error,
+};
+use bragi_core::Provider;
+use serde_json::Value;
+
+use crate::response::{is_json, is_streamed, map_body, map_json_body};
+
+/// Languages of the text generated by bragi. English is the language of the code and of the
+/// untranslated text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lang {
+    En,
+    Zh,
+    Ja,
+}
+
+impl Lang {
+    fn tag(self) -> &'static str {
+        match self {
+            Lang::En => "en",
+            Lang::Zh => "zh",
+            Lang::Ja => "ja",
+        }
+    }
+
+    /// The preferred language of an `Accept-Language` header like `ja-JP,ja;q=0.9,en;q=0.8`, if
+    /// any of them is supported
+    pub fn negotiate(accept: &str) -> Option<Self> {
+        let mut ranges = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';').map(str::trim);
+                let tag = parts.next()?.to_lowercase();
+                let q = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .map_or(Some(1.0), |q| q.parse::<f32>().ok())?;
+                Some((tag, q))
+            })
+            .filter(|(_, q)| *q > 0.0)
+            .collect::<Vec<_>>();
+        // stable, the order of the header breaks ties
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges
+            .iter()
+            .find_map(|(tag, _)| match tag.split('-').next().unwrap_or_default() {
+                "en" => Some(Lang::En),
+                "zh" => Some(Lang::Zh),
+                "ja" => Some(Lang::Ja),
+                _ => None,
+            })
+    }
+
+    /// The text of the language among the english, chinese and japanese ones
+    fn pick(self, texts: &[&'static str; 3]) -> &'static str {
+        match self {
+            Lang::En => texts[0],
+            Lang::Zh => texts[1],
+            Lang::Ja => texts[2],
+        }
+    }
+}
+
+/// Labels of the quality names of the providers, the bitrate in brackets left out
+const QUALITIES: &[(&str, [&str; 3])] = &[
+    (
+        "Hi-Res lossless",
+        ["Hi-Res lossless", "Hi-Res 无损", "ハイレゾロスレス"],
+    ),
+    ("lossless", ["lossless", "无损", "ロスレス"]),
+    ("Dolby", ["Dolby Atmos", "杜比全景声", "ドルビーアトモス"]),
+    (
+        "original",
+        ["original file", "原始文件", "オリジナルファイル"],
+    ),
+    ("live", ["live", "直播", "ライブ"]),
+    ("preview", ["preview", "试听片段", "試聴"]),
+    ("unknown", ["unknown", "未知", "不明"]),
+    ("AUDIO_QUALITY_LOW", ["low", "低音质", "低音質"]),
+    ("AUDIO_QUALITY_MEDIUM", ["medium", "标准音质", "標準音質"]),
+    ("AUDIO_QUALITY_HIGH", ["high", "高音质", "高音質"]),
+];
+
+/// Fixed error messages of the api, or the part before `: ` of messages with details
+const ERRORS: &[[&str; 3]] = &[
+    [
+        "no local file of the id",
+        "该 id 没有本地文件",
+        "この id のローカルファイルはありません",
+    ],
+    [
+        "search analytics are disabled",
+        "搜索统计已关闭",
+        "検索統計は無効です",
+    ],
+    [
+        "transcoding is not enabled",
+        "转码未启用",
+        "トランスコードは無効です",
+    ],
+    [
+        "min_duration is greater than max_duration",
+        "min_duration 大于 max_duration",
+        "min_duration が max_duration より大きいです",
+    ],
+    [
+        "no stream can be segmented without transcoding",
+        "不转码则没有可分段的音频流",
+        "トランスコードなしで分割できるストリームはありません",
+    ],
+    [
+        "duration of the stream unknown",
+        "音频流时长未知",
+        "ストリームの長さが不明です",
+    ],
+    [
+        "fetch stream failed",
+        "获取音频流失败",
+        "ストリームの取得に失敗しました",
+    ],
+    ["room not found", "找不到房间", "ルームが見つかりません"],
+    [
+        "room already has a host",
+        "房间已有房主连接",
+        "ルームにはすでにホストが接続しています",
+    ],
+    ["device not found", "找不到设备", "デバイスが見つかりません"],
+    ["unknown device", "未知设备", "不明なデバイス"],
+    [
+        "bundle not found",
+        "找不到离线包",
+        "オフラインバンドルが見つかりません",
+    ],
+    [
+        "authentication required",
+        "需要有权限的账号",
+        "権限のあるアカウントが必要です",
+    ],
+    ["region locked", "所在地区不可用", "地域制限されています"],
+    [
+        "recognition is not enabled",
+        "未启用听歌识曲",
+        "楽曲認識は有効になっていません",
+    ],
+    [
+        "audio clip is empty",
+        "音频片段为空",
+        "音声クリップが空です",
+    ],
+    [
+        "fingerprint failed",
+        "提取音频指纹失败",
+        "フィンガープリントの生成に失敗しました",
+    ],
+    ["recognition failed", "识曲失败", "楽曲認識に失敗しました"],
+    [
+        "start fpcalc failed",
+        "无法启动 fpcalc",
+        "fpcalc を起動できません",
+    ],
+    ["removed upstream", "已下架", "配信が終了しました"],
+    ["no copyright", "暂无版权", "配信権がありません"],
+    ["not public upstream", "未公开", "公開されていません"],
+    [
+        "for supporters of the uploader only",
+        "充电专属",
+        "投稿者の支援者限定です",
+    ],
+    [
+        "playlist not in the library",
+        "歌单不在曲库中",
+        "プレイリストはライブラリにありません",
+    ],
+];
+
+/// Label of a quality like `lossless(999000)` or `192k`. Qualities of no label, like bitrates,
+/// are labels themselves.
+pub fn quality_label(quality: &str, lang: Lang) -> String {
+    let name = quality.split('(').next().unwrap_or_default();
+    match QUALITIES.iter().find(|(q, _)| *q == name) {
+        Some((_, labels)) => lang.pick(labels).to_string(),
+        None => quality.to_string(),
+    }
+}
+
+pub fn provider_name(provider: &Provider, lang: Lang) -> &'static str {
+    lang.pick(match provider {
+        Provider::Bilibili => &["Bilibili", "哔哩哔哩", "ビリビリ"],
+        Provider::Local => &["Local files", "本地文件", "ローカルファイル"],
+        Provider::NetEase => &["NetEase Cloud Music", "网易云音乐", "NetEase Cloud Music"],
+        Provider::Radio => &["Internet radio", "网络电台", "インターネットラジオ"],
+        Provider::Spotify => &["Spotify", "Spotify", "Spotify"],
+        Provider::Youtube => &["YouTube", "YouTube", "YouTube"],
+    })
+}
+
+/// The error message in the language, details after `: ` kept as they are. Unknown messages are
+/// left in english.
+pub fn error_message(message: &str, lang: Lang) -> String {
+    let (head, detail) = match message.split_once(": ") {
+        Some((head, detail)) => (head, Some(detail)),
+        None => (message, None),
+    };
+    match ERRORS.iter().find(|e| e[0] == head) {
+        Some(texts) => {
+            let head = lang.pick(texts);
+            detail.map_or(head.to_string(), |d| format!("{}: {}", head, d))
+        }
+        None => message.to_string(),
+    }
+}
+
+/// Label the qualities and providers of every object in the value, and translate its error and
+/// why it is unavailable
+pub fn localize_value(value: &mut Value, lang: Lang) {
+    match value {
+        Value::Object(map) => {
+            map.values_mut().for_each(|v| localize_value(v, lang));
+            let quality = map.get("quality").and_then(Value::as_str);
+            if let Some(label) = quality.map(|q| quality_label(q, lang)) {
+                map.insert("quality_label".into(), label.into());
+            }
+            let provider = map
+                .get("provider")
+                .and_then(|p| serde_json::from_value::<Provider>(p.clone()).ok());
+            if let Some(provider) = provider {
+                map.insert(
+                    "provider_name".into(),
+                    provider_name(&provider, lang).into(),
+                );
+            }
+            for key in ["error", "unavailable_reason"] {
+                if let Some(Value::String(text)) = map.get_mut(key) {
+                    *text = error_message(text, lang);
+                }
+            }
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(|v| localize_value(v, lang)),
+        _ => {}
+    }
+}
+
+/// Middleware localizing responses to the language of `Accept-Language`, english if none of the
+/// requested ones is supported. Json responses are buffered whole to be changed, and plain text
+/// errors are translated. English ones are left as they are, and so are streamed bodies of large
+/// collections, which buffering would hold in memory whole. Errors of the middlewares within,
+/// like the authentication, are passed on untranslated since they are not responses yet.
+pub fn localize<S, B>(
+    enabled: bool,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let lang = req
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Lang::negotiate)
+        .unwrap_or(Lang::En);
+    let call = srv.call(req);
+    async move {
+        let resp = call.await?.map_into_boxed_body();
+        if !enabled || is_streamed(&resp) {
+            return Ok(resp);
+        }
+
+        let text_error = resp.status().is_client_error() || resp.status().is_server_error();
+        let text_error = text_error
+            && resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|v| v.to_str().ok())
+                .is_some_and(|v| v.starts_with("text/plain"));
+        let mut resp = if lang == Lang::En {
+            resp
+        } else if is_json(&resp) {
+            map_json_body(resp, |value| localize_value(value, lang)).await?
+        } else if text_error {
+            map_body(resp, |body| match std::str::from_utf8(&body) {
+                Ok(message) => Bytes::from(error_message(message, lang)),
+                Err(_) => body,
+            })
+            .await?
+        } else {
+            resp
+        };
+        let headers = resp.headers_mut();
+        headers.insert(CONTENT_LANGUAGE, HeaderValue::from_static(lang.tag()));
+        headers.append(VARY, HeaderValue::from_static("accept-language"));
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{
+        body::{BodySize, MessageBody},
+        http::StatusCode,
+        test::{call_service, init_service, read_body, TestRequest},
+        web, App, HttpResponse,
+    };
+    use bragi_core::Provider;
+    use serde_json::{json, Value};
+
+    use super::{error_message, localize, localize_value, provider_name, quality_label, Lang};
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Lang::negotiate("ja-JP,ja;q=0.9,en;q=0.8"), Some(Lang::Ja));
+        assert_eq!(
+            Lang::negotiate("fr;q=1, en;q=0.5, zh-CN;q=0.8"),
+            Some(Lang::Zh)
+        );
+        assert_eq!(Lang::negotiate("zh-Hant-TW"), Some(Lang::Zh));
+        assert_eq!(Lang::negotiate("ja;q=0, en"), Some(Lang::En));
+        assert_eq!(Lang::negotiate("fr, *;q=0.1"), None);
+        assert_eq!(Lang::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(quality_label("lossless(999000)", Lang::Zh), "无损");
+        assert_eq!(
+            quality_label("Hi-Res lossless", Lang::Ja),
+            "ハイレゾロスレス"
+        );
+        assert_eq!(
+            quality_label("AUDIO_QUALITY_MEDIUM(128000)", Lang::En),
+            "medium"
+        );
+        assert_eq!(quality_label("192k", Lang::Zh), "192k");
+        assert_eq!(provider_name(&Provider::NetEase, Lang::Zh), "网易云音乐");
+        assert_eq!(
+            error_message("room not found: 42", Lang::Ja),
+            "ルームが見つかりません: 42"
+        );
+        assert_eq!(error_message("upstream down", Lang::Zh), "upstream down");
+
+        let mut song = json!({"unavailable": true, "unavailable_reason": "removed upstream"});
+        localize_value(&mut song, Lang::Zh);
+        assert_eq!(song["unavailable_reason"], "已下架");
+    }
+
+    #[actix_web::test]
+    async fn test_localize() {
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, srv| localize(true, req, srv))
+                .route(
+                    "/stream",
+                    web::get().to(|| async {
+                        HttpResponse::Ok().json(json!({
+                            "provider": "bilibili",
+                            "data": [{"quality": "Dolby", "url": "https://"}],
+                        }))
+                    }),
+                )
+                .route(
+                    "/collection",
+                    web::get().to(|| async {
+                        let songs = vec![
+                            json!({"quality": "lossless"});
+                            crate::STREAMING_COLLECTION_THRESHOLD + 1
+                        ];
+                        crate::response::json(json!({"songs": songs}), true)
+                    }),
+                )
+                .route(
+                    "/error",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(actix_web::error::ErrorNotFound(
+                            "device not found: 1",
+                        ))
+                    }),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/stream")
+            .insert_header(("Accept-Language", "zh-CN,zh;q=0.9"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Content-Language").unwrap(), "zh");
+        let body: Value = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(body["provider"], "bilibili");
+        assert_eq!(body["provider_name"], "哔哩哔哩");
+        assert_eq!(body["data"][0]["quality"], "Dolby");
+        assert_eq!(body["data"][0]["quality_label"], "杜比全景声");
+
+        // english needs no labels
+        let req = TestRequest::get().uri("/stream").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Content-Language").unwrap(), "en");
+        let body: Value = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert!(body.get("provider_name").is_none());
+
+        // large collections are still streamed
+        let req = TestRequest::get()
+            .uri("/collection")
+            .insert_header(("Accept-Language", "ja"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.response().body().size(), BodySize::Stream);
+        let body: Value = serde_json::from_slice(&read_body(resp).await).unwrap();
+        assert_eq!(
+            body["songs"].as_array().unwrap().len(),
+            crate::STREAMING_COLLECTION_THRESHOLD + 1
+        );
+
+        let req = TestRequest::get().uri("/error").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get("Content-Language").unwrap(), "en");
+        assert_eq!(read_body(resp).await, "device not found: 1");
+
+        let req = TestRequest::get()
+            .uri("/error")
+            .insert_header(("Accept-Language", "ja"))
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(read_body(resp).await, "デバイスが見つかりません: 1");
+    }
+}
@@ -1,22 +1,82 @@
+mod audit;
+mod auth;
+mod bench;
+mod bundle;
+mod cache;
+mod clip;
+mod device;
+mod download;
+mod error;
+mod hls;
+mod local;
+mod locale;
+mod proxy;
+mod recognize;
+mod response;
+mod room;
+mod systemd;
+mod transcode;
+#[cfg(feature = "web-ui")]
+mod ui;
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::{SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+
 use actix_web::{
+    http::header::{ETag, EntityTag, HeaderValue, IfNoneMatch, LINK, USER_AGENT},
     middleware::Logger,
-    web::{self, Json, Query},
-    App, HttpServer,
+    web::{self, Json, Path, Query},
+    App, HttpMessage, HttpRequest, HttpResponse, HttpServer,
 };
 
+use actix_web_httpauth::middleware::HttpAuthentication;
 use bragi_core::{
+    privacy::redact,
     scraper::{
-        Provider, ScrapeItem, ScrapeType, ScraperManager, SongCollection, Stream, WithProvider,
+        analytics::AnalyticsReport,
+        capability::{self, ClientProfiles},
+        compare::Source,
+        cursor::Cursor,
+        explain::StreamTrace,
+        follow::{self, Release},
+        health::Health,
+        id::{self, ArtistId},
+        instance::InstanceStats,
+        latency::LatencyStats,
+        lyrics::{Lyrics, LyricsQuery},
+        query::SearchFilter,
+        quota::QuotaUsage,
+        ArtistDetail, FanOut, Provider, ProviderStatus, ScrapeType, ScraperManager, Song, Stream,
     },
-    settings::Settings,
+    settings::{Capabilities, Settings},
+};
+use clap::{Parser, Subcommand};
+use error::provider_error;
+use response::FieldSet;
+use serde::{
+    de::{Error as _, IntoDeserializer},
+    Deserialize, Deserializer, Serialize,
 };
-use clap::Parser;
-use serde::Deserialize;
-use tracing::info;
+use socket2::{Domain, Socket, Type};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{info, warn};
 
 #[derive(Clone)]
 struct Context {
     manager: ScraperManager,
+    rooms: Arc<room::Rooms>,
+    profiles: Arc<ClientProfiles>,
+    devices: Arc<device::DeviceRegistry>,
+    proxy: Arc<proxy::StreamProxy>,
+    bundles: Arc<bundle::BundleJobs>,
+    downloads: Arc<download::Bandwidth>,
+    transcoder: Option<Arc<transcode::Transcoder>>,
+    cache: Option<Arc<cache::AudioCache>>,
+    recognizer: Option<Arc<recognize::Recognizer>>,
     settings: Settings,
 }
 
@@ -26,6 +86,17 @@ struct Args {
     /// path of config file
     #[arg(short, long)]
     config: Option<String>,
+
+    /// serve the http api if absent
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Load test the search and stream paths against mock providers. The stale cache, stream sort
+    /// and timeouts follow the config file if given.
+    Bench(bench::BenchArgs),
 }
 
 #[actix_web::main]
@@ -35,52 +106,328 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let arg = Args::parse();
+    if let Some(Command::Bench(args)) = arg.command {
+        let settings = match arg.config {
+            Some(config) => Some(Settings::new(Some(config), None)?),
+            None => None,
+        };
+        return bench::run(args, settings).await;
+    }
     let settings = Settings::new(arg.config, None)?;
 
     let ctx = Context {
         manager: ScraperManager::try_from_settings(&settings).await?,
+        rooms: Default::default(),
+        profiles: Arc::new(ClientProfiles::try_from_setting(
+            settings.client_profiles.clone(),
+        )?),
+        devices: Arc::new(device::DeviceRegistry::try_new(
+            settings.application.devices_path.clone(),
+        )?),
+        proxy: Default::default(),
+        bundles: Arc::new(bundle::BundleJobs::try_new(
+            settings.application.bundle_dir.clone(),
+        )?),
+        downloads: Arc::new(download::Bandwidth::new(settings.application.download_rate)),
+        transcoder: transcode::Transcoder::from_setting(&settings.transcode).map(Arc::new),
+        // the files tell what was played
+        cache: match settings.application.privacy_mode {
+            true => None,
+            false => cache::AudioCache::try_new(&settings.cache)?.map(Arc::new),
+        },
+        recognizer: recognize::Recognizer::from_setting(&settings.recognize).map(Arc::new),
         settings: settings.clone(),
     };
+    let (manager, setting) = (ctx.manager.clone(), settings.follow.clone());
+    bragi_core::supervise("follow watch", move || {
+        follow::watch(manager.clone(), setting.clone())
+    });
 
-    Ok(HttpServer::new(move || {
+    // the default format has the client address, the query string and the referer
+    let log_format = match settings.application.privacy_mode {
+        true => r#""%U" %s %b %T"#,
+        false => r#"%a "%r" %s %b "%{Referer}i" "%{User-Agent}i" %T"#,
+    };
+    if settings.application.tokens.is_empty() {
+        warn!("no api tokens configured, the api is open to anyone");
+    } else {
+        auth::sign_urls(Duration::from_secs(settings.application.stream_url_ttl));
+    }
+    let tokens = web::Data::new(settings.application.tokens.clone());
+    if !settings.application.auth_exempt.is_empty() {
+        info!(
+            "readable without a token: {:?}",
+            settings.application.auth_exempt
+        );
+    }
+    let exempt = web::Data::new(auth::ExemptPaths::new(
+        settings.application.auth_exempt.clone(),
+    ));
+    let audit = web::Data::new(audit::AuditLog::try_new(
+        settings.application.audit_path.clone(),
+    )?);
+    let json_case = settings.application.json_case;
+    response::set_json_case(json_case);
+    let localize = settings.application.localize;
+    let max_clip = settings.recognize.max_clip;
+    let mut server = HttpServer::new(move || {
         App::new()
             .app_data(web::Data::new(ctx.clone()))
-            .wrap(Logger::default())
+            .app_data(tokens.clone())
+            .app_data(exempt.clone())
+            .app_data(audit.clone())
+            .wrap(Logger::new(log_format))
             .service(
                 web::scope("/api/v1")
+                    .wrap(HttpAuthentication::with_fn(auth::authorize))
+                    // sees the fields before they are renamed
+                    .wrap_fn(move |req, srv| locale::localize(localize, req, srv))
+                    .wrap_fn(move |req, srv| response::rename_fields(json_case, req, srv))
                     .service(
                         web::scope("/scrape")
                             .route("/suggest", web::get().to(suggest_handler))
                             .route("/search", web::get().to(search_handler))
                             .route("/collection", web::get().to(collection_handler))
-                            .route("/stream", web::get().to(stream_handler)),
+                            .route("/artist", web::get().to(artist_handler))
+                            .route("/lyrics", web::get().to(lyrics_handler))
+                            .route("/chapters", web::get().to(chapters_handler))
+                            .route("/stream", web::get().to(stream_handler))
+                            .route("/stream/explain", web::get().to(stream_explain_handler))
+                            .route("/compare", web::get().to(compare_handler)),
+                    )
+                    .service(
+                        web::scope("/library")
+                            .route("/favorites", web::get().to(favorite_list_handler))
+                            .route(
+                                "/favorites/import/{provider}",
+                                web::post().to(favorite_import_handler),
+                            )
+                            .route(
+                                "/favorites/{provider}/{id}",
+                                web::put().to(favorite_save_handler),
+                            )
+                            .route(
+                                "/favorites/{provider}/{id}",
+                                web::delete().to(favorite_remove_handler),
+                            )
+                            .route("/follows", web::get().to(follow_list_handler))
+                            .route("/follows/{provider}/{id}", web::put().to(follow_handler))
+                            .route(
+                                "/follows/{provider}/{id}",
+                                web::delete().to(unfollow_handler),
+                            )
+                            .route("/feed", web::get().to(feed_handler))
+                            .route("/bundles", web::get().to(bundle::list_handler))
+                            .route("/bundles", web::post().to(bundle::create_handler))
+                            .route("/bundles/{id}", web::get().to(bundle::get_handler))
+                            .route("/bundles/{id}", web::delete().to(bundle::remove_handler))
+                            .route(
+                                "/bundles/{id}/archive",
+                                web::get().to(bundle::archive_handler),
+                            ),
+                    )
+                    .route("/metrics/latency", web::get().to(latency_handler))
+                    .route("/health", web::get().to(health_handler))
+                    .service(
+                        web::resource("/recognize")
+                            .app_data(web::PayloadConfig::new(max_clip))
+                            .route(web::post().to(recognize::recognize_handler)),
+                    )
+                    .service(
+                        web::scope("/admin")
+                            .wrap_fn(audit::record)
+                            .route("/audit", web::get().to(audit_handler))
+                            .route("/analytics", web::get().to(analytics_handler))
+                            .route("/quota", web::get().to(quota_handler))
+                            .route("/instances", web::get().to(instances_handler))
+                            .route("/aliases", web::get().to(alias_list_handler))
+                            .route("/aliases", web::post().to(alias_learn_handler)),
+                    )
+                    .service(
+                        web::scope("/devices")
+                            .route("", web::get().to(device::list_handler))
+                            .route("", web::post().to(device::register_handler))
+                            .route("/{id}", web::delete().to(device::remove_handler)),
                     )
                     .service(
-                        web::scope("/stream").route("/spotify", web::get().to(stream_handler)),
+                        web::scope("/rooms")
+                            .route("", web::post().to(room::create_handler))
+                            .route("/{id}/ws", web::get().to(room::join_handler)),
+                    )
+                    .service(
+                        web::scope("/stream")
+                            .route("/spotify", web::get().to(stream_handler))
+                            .route("/proxy", web::get().to(proxy::proxy_handler))
+                            .route("/local", web::get().to(local::file_handler))
+                            .route(
+                                "/hls/{provider}/{id}/playlist.m3u8",
+                                web::get().to(hls::playlist_handler),
+                            ),
                     ),
             )
-    })
-    .bind((settings.application.host, settings.application.port))?
-    .run()
-    .await?)
+            .configure(|_cfg| {
+                #[cfg(feature = "web-ui")]
+                ui::configure(_cfg);
+            })
+    });
+
+    // sockets passed by systemd take priority over the configured addresses
+    let activated = systemd::listen_fds();
+    if !activated.is_empty() {
+        for l in activated {
+            server = server.listen(l)?;
+        }
+    } else if settings.application.listen.is_empty() {
+        server = server.bind((settings.application.host, settings.application.port))?;
+    } else {
+        for addr in settings.application.listen {
+            info!("listen on {}", addr);
+            server = server.listen(listener(addr)?)?;
+        }
+    }
+
+    let server = server.run();
+    // registered before reporting ready so that no early stop request is missed
+    let mut term = signal(SignalKind::terminate())?;
+    actix_web::rt::spawn(async move {
+        tokio::select! {
+            _ = term.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+        systemd::notify("STOPPING=1");
+    });
+    // providers are initialized and all sockets are bound
+    systemd::notify("READY=1");
+
+    Ok(server.await?)
+}
+
+/// IPv6 sockets only accept IPv6 so that the same port can also be listened on IPv4
+fn listener(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(socket.into())
+}
+
+/// Header listing the providers skipped because their concurrency budget was exhausted
+const THROTTLED_HEADER: &str = "X-Bragi-Throttled";
+
+/// Header carrying the cursor of the next page. Absent if all providers are exhausted
+const CURSOR_HEADER: &str = "X-Bragi-Cursor";
+
+/// Header listing the providers which failed, whether or not a stale result was served for them
+const FAILED_HEADER: &str = "X-Bragi-Failed";
+
+/// Fan-out results with the status of every provider, instead of the bare items
+#[derive(Debug, Serialize)]
+struct Envelope<T> {
+    items: T,
+    providers: BTreeMap<Provider, ProviderStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    next: Option<String>,
 }
 
-// async fn validator() -> Result<ServiceRequest, (actix_web::error::Error, ServiceRequest)> {
+fn fan_out_response<T: Serialize>(
+    fan_out: FanOut<T>,
+    fields: Option<&FieldSet>,
+    envelope: bool,
+) -> actix_web::Result<HttpResponse> {
+    let mut resp = HttpResponse::Ok();
+    let failed = fan_out
+        .providers
+        .iter()
+        .filter(|(_, s)| matches!(s, ProviderStatus::Failed(_) | ProviderStatus::Stale(_)))
+        .map(|(p, _)| p.to_string())
+        .collect::<Vec<_>>();
+    if !failed.is_empty() {
+        resp.insert_header((FAILED_HEADER, failed.join(",")));
+    }
+    if !fan_out.throttled.is_empty() {
+        resp.insert_header((
+            THROTTLED_HEADER,
+            fan_out
+                .throttled
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(","),
+        ));
+    }
+    if let Some(next) = &fan_out.next {
+        resp.insert_header((CURSOR_HEADER, next.encode()));
+    }
 
-// }
+    if fields.is_none() && !envelope {
+        return Ok(resp.json(fan_out.items));
+    }
+
+    let mut items =
+        serde_json::to_value(fan_out.items).map_err(actix_web::error::ErrorInternalServerError)?;
+    if let (Some(fields), Some(items)) = (fields, items.as_array_mut()) {
+        items
+            .iter_mut()
+            .filter_map(|i| i.get_mut("data"))
+            .for_each(|data| match data.as_object_mut() {
+                // scrape items are tagged by their type, like: {"song": {...}}
+                Some(tagged) if tagged.len() == 1 => {
+                    tagged.values_mut().for_each(|v| fields.select(v))
+                }
+                _ => fields.select(data),
+            });
+    }
+    match envelope {
+        true => Ok(resp.json(Envelope {
+            items,
+            providers: fan_out.providers,
+            next: fan_out.next.as_ref().map(Cursor::encode),
+        })),
+        false => Ok(resp.json(items)),
+    }
+}
 
 #[derive(Debug, Deserialize)]
 struct SuggestParam {
     keyword: String,
+    /// respond with the status of every provider next to the items
+    #[serde(default)]
+    envelope: bool,
+    /// providers asked, like `bilibili,netease`. All of them if absent
+    #[serde(default, deserialize_with = "deserialize_providers")]
+    providers: Vec<Provider>,
+}
+
+/// Comma separated providers
+fn deserialize_providers<'de, D>(deserializer: D) -> Result<Vec<Provider>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    s.split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| Provider::deserialize(p.into_deserializer()))
+        .collect::<Result<_, serde::de::value::Error>>()
+        .map_err(D::Error::custom)
 }
 
 async fn suggest_handler(
     param: Query<SuggestParam>,
     ctx: web::Data<Context>,
-) -> Json<Vec<WithProvider<String>>> {
-    info!("[Handler] suggest with param: {:?}", param);
+) -> actix_web::Result<HttpResponse> {
+    info!("[Handler] suggest: keyword: {}", redact(&param.keyword));
 
-    Json(ctx.manager.suggest(param.keyword.clone()).await)
+    fan_out_response(
+        ctx.manager
+            .suggest_from(param.keyword.clone(), &param.providers)
+            .await,
+        None,
+        param.envelope,
+    )
 }
 
 #[derive(Debug, Deserialize)]
@@ -88,42 +435,332 @@ struct SearchParam {
     keyword: String,
     #[serde(default = "default_type")]
     t: ScrapeType,
+    fields: Option<FieldSet>,
+    /// cursor returned by the previous page
+    cursor: Option<Cursor>,
+    /// seconds. Songs of unknown duration are kept
+    #[serde(alias = "minDuration")]
+    min_duration: Option<u32>,
+    #[serde(alias = "maxDuration")]
+    max_duration: Option<u32>,
+    /// drop short-form videos and live broadcasts of providers which can tell, like YouTube
+    #[serde(default, alias = "excludeShorts")]
+    exclude_shorts: bool,
+    #[serde(default, alias = "excludeLive")]
+    exclude_live: bool,
+    /// results per page of each provider, as far as the provider can page by size
+    limit: Option<usize>,
+    /// respond with the status of every provider and the next cursor next to the items
+    #[serde(default)]
+    envelope: bool,
+    /// providers searched, like `bilibili,netease`. All of them if absent
+    #[serde(default, deserialize_with = "deserialize_providers")]
+    providers: Vec<Provider>,
+    /// list the same song found on several providers once, with the others as its alternatives
+    #[serde(default)]
+    merge: bool,
 }
 
+/// Largest page size a client may ask a provider for
+const MAX_SEARCH_LIMIT: usize = 100;
+
 fn default_type() -> ScrapeType {
     ScrapeType::All
 }
 
 async fn search_handler(
+    req: HttpRequest,
     param: Query<SearchParam>,
     ctx: web::Data<Context>,
-) -> Json<Vec<WithProvider<ScrapeItem>>> {
-    info!("[Handler] search with param: {:?}", param);
+) -> actix_web::Result<HttpResponse> {
+    info!(
+        "[Handler] search: keyword: {}, type: {:?}, next page: {}",
+        redact(&param.keyword),
+        param.t,
+        param.cursor.is_some()
+    );
 
-    Json(
-        ctx.manager
-            .search(param.keyword.clone(), param.t.clone())
-            .await,
-    )
+    let filter = SearchFilter {
+        min_duration: param.min_duration,
+        max_duration: param.max_duration,
+        exclude_shorts: param.exclude_shorts,
+        exclude_live: param.exclude_live,
+        limit: param.limit,
+        providers: param.providers.clone(),
+        merge: param.merge,
+    };
+    if let (Some(min), Some(max)) = (filter.min_duration, filter.max_duration) {
+        if min > max {
+            return Err(actix_web::error::ErrorBadRequest(
+                "min_duration is greater than max_duration",
+            ));
+        }
+    }
+    if filter.limit.is_some_and(|l| l == 0 || l > MAX_SEARCH_LIMIT) {
+        return Err(actix_web::error::ErrorBadRequest(format!(
+            "limit must be between 1 and {}",
+            MAX_SEARCH_LIMIT
+        )));
+    }
+
+    let fan_out = ctx
+        .manager
+        .search_filtered(
+            param.keyword.clone(),
+            param.t.clone(),
+            param.cursor.clone(),
+            &filter,
+        )
+        .await;
+    let next = fan_out
+        .next
+        .as_ref()
+        .map(|c| next_link(&req, "cursor", &c.encode()));
+    let mut resp = fan_out_response(fan_out, param.fields.as_ref(), param.envelope)?;
+    if let Some(next) = next {
+        resp.headers_mut().insert(
+            LINK,
+            HeaderValue::from_str(&next).map_err(actix_web::error::ErrorInternalServerError)?,
+        );
+    }
+    Ok(resp)
+}
+
+/// `Link` header of the next page: the same request with the `name` parameter of the next page,
+/// like the cursor of a search
+fn next_link(req: &HttpRequest, name: &str, value: &str) -> String {
+    let param = format!("{}={}", name, value);
+    let prefix = format!("{}=", name);
+    let query = req
+        .query_string()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with(&prefix))
+        .chain(Some(param.as_str()))
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("<{}?{}>; rel=\"next\"", req.path(), query)
 }
 
 #[derive(Debug, Deserialize)]
 struct CollectionParam {
     provider: Provider,
     id: String,
+    fields: Option<FieldSet>,
+    /// first song of the page
+    #[serde(default)]
+    offset: usize,
+    /// songs of the page, up to the configured page size. The configured page size if absent
+    limit: Option<usize>,
+    /// give the unavailable songs of the page the same song on other providers
+    #[serde(default)]
+    substitute: bool,
+}
+
+/// Keep the items of the page from the offset, and tell the offset of the next page if any
+fn page<T>(items: &mut Vec<T>, offset: usize, size: Option<usize>) -> Option<usize> {
+    items.drain(..offset.min(items.len()));
+    let size = size?;
+    (items.len() > size).then(|| {
+        items.truncate(size);
+        offset + size
+    })
+}
+
+/// Collections with more songs than this are serialized incrementally into a streaming body
+const STREAMING_COLLECTION_THRESHOLD: usize = 500;
+
+/// Songs of a response given substitutes at most, since each one searches every other provider
+const MAX_SUBSTITUTED: usize = 20;
+
+/// Give the first unavailable songs their substitutes on the other providers
+async fn substitute(manager: &ScraperManager, provider: &Provider, songs: &mut [Song]) {
+    let unavailable = songs
+        .iter_mut()
+        .filter(|s| s.unavailable)
+        .take(MAX_SUBSTITUTED)
+        .map(|s| async move {
+            s.alternatives = manager.substitutes(s, provider).await;
+        });
+    futures::future::join_all(unavailable).await;
+}
+
+/// Weak since the representation differs with the requested fields
+fn collection_etag(provider: &Provider, version: &str) -> EntityTag {
+    EntityTag::new_weak(format!("{}-{}", provider, version))
+}
+
+fn not_modified(if_none_match: &Option<IfNoneMatch>, etag: &EntityTag) -> bool {
+    match if_none_match {
+        Some(IfNoneMatch::Any) => true,
+        Some(IfNoneMatch::Items(tags)) => tags.iter().any(|t| t.weak_eq(etag)),
+        None => false,
+    }
 }
 
 async fn collection_handler(
+    req: HttpRequest,
     param: Query<CollectionParam>,
     ctx: web::Data<Context>,
-) -> actix_web::Result<Json<SongCollection>> {
-    info!("[Handler] collection detail with param: {:?}", param);
+) -> actix_web::Result<HttpResponse> {
+    info!(
+        "[Handler] collection detail: provider: {:?}, id: {}",
+        param.provider,
+        redact(&param.id)
+    );
+
+    let max_page = ctx.settings.limits.collection_page;
+    let page_size = match (param.limit, max_page) {
+        (Some(0), _) => return Err(actix_web::error::ErrorBadRequest("limit must be positive")),
+        (Some(limit), Some(max)) if limit > max => {
+            return Err(actix_web::error::ErrorBadRequest(format!(
+                "limit must be between 1 and {}",
+                max
+            )))
+        }
+        (limit, max) => limit.or(max),
+    };
+
+    let if_none_match = req.get_header::<IfNoneMatch>();
+
+    // Ask for the version marker first so that unchanged collections skip the full fetch
+    if if_none_match.is_some() {
+        // the provider may be down. Leave it to the collection detail, which may serve stale data
+        let version = ctx
+            .manager
+            .collection_version(param.id.clone(), param.provider.clone())
+            .await
+            .unwrap_or_else(|e| {
+                warn!("collection version failed: {}", e);
+                None
+            });
+        if let Some(version) = version {
+            let etag = collection_etag(&param.provider, &version);
+            if not_modified(&if_none_match, &etag) {
+                return Ok(HttpResponse::NotModified()
+                    .insert_header(ETag(etag))
+                    .finish());
+            }
+        }
+    }
+
+    let collection = ctx
+        .manager
+        .collection_detail(param.id.clone(), param.provider.clone())
+        .await
+        .map_err(provider_error)?;
+
+    let etag = collection
+        .version
+        .as_ref()
+        .map(|v| collection_etag(&param.provider, v));
+    if let Some(etag) = &etag {
+        if not_modified(&if_none_match, etag) {
+            return Ok(HttpResponse::NotModified()
+                .insert_header(ETag(etag.clone()))
+                .finish());
+        }
+    }
+
+    let mut collection = collection;
+    let next = page(&mut collection.songs, param.offset, page_size)
+        .map(|offset| next_link(&req, "offset", &offset.to_string()));
+    if param.substitute {
+        substitute(&ctx.manager, &param.provider, &mut collection.songs).await;
+    }
+
+    let streaming = collection.songs.len() > STREAMING_COLLECTION_THRESHOLD;
+    let mut resp = match &param.fields {
+        Some(fields) => {
+            // the songs are selected one by one while serialized
+            let songs = std::mem::take(&mut collection.songs);
+            let head = serde_json::to_value(collection)
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            let selected = response::SelectedList::new(head, "songs", songs, fields.clone());
+            response::json(selected, streaming)
+        }
+        None => response::json(collection, streaming),
+    };
+
+    if let Some(etag) = etag {
+        resp.headers_mut().insert(
+            actix_web::http::header::ETAG,
+            etag.to_string()
+                .parse()
+                .map_err(actix_web::error::ErrorInternalServerError)?,
+        );
+    }
+    if let Some(next) = next {
+        resp.headers_mut().insert(
+            LINK,
+            HeaderValue::from_str(&next).map_err(actix_web::error::ErrorInternalServerError)?,
+        );
+    }
+    Ok(resp)
+}
+
+#[derive(Debug, Deserialize)]
+struct ArtistParam {
+    provider: Provider,
+    id: String,
+}
+
+/// Profile of the artist with their top tracks, albums and playlists
+async fn artist_handler(
+    param: Query<ArtistParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<ArtistDetail>> {
+    info!(
+        "[Handler] artist detail: provider: {:?}, id: {}",
+        param.provider,
+        redact(&param.id)
+    );
 
     Ok(Json(
         ctx.manager
-            .collection_detail(param.id.clone(), param.provider.clone())
+            .artist_detail(param.id.clone(), param.provider.clone())
             .await
-            .map_err(actix_web::error::ErrorInternalServerError)?,
+            .map_err(provider_error)?,
+    ))
+}
+
+#[derive(Debug, Deserialize)]
+struct LyricsParam {
+    provider: Provider,
+    id: String,
+    /// the song looked up if the provider has no lyrics
+    title: Option<String>,
+    artist: Option<String>,
+    duration: Option<u32>,
+}
+
+/// Lyrics of the song, empty if none are found
+async fn lyrics_handler(
+    param: Query<LyricsParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<Lyrics>> {
+    info!(
+        "[Handler] lyrics: provider: {:?}, id: {}",
+        param.provider,
+        redact(&param.id)
+    );
+
+    let LyricsParam {
+        provider,
+        id,
+        title,
+        artist,
+        duration,
+    } = param.into_inner();
+    let query = title.map(|title| LyricsQuery {
+        title,
+        artist,
+        duration,
+    });
+    Ok(Json(
+        ctx.manager
+            .lyrics(id, provider, query)
+            .await
+            .map_err(provider_error)?,
     ))
 }
 
@@ -131,18 +768,481 @@ async fn collection_handler(
 struct StreamParam {
     provider: Provider,
     id: String,
+    /// samples of the song if its full streams are restricted, like the trial of NetEase VIP songs
+    #[serde(default)]
+    preview: bool,
 }
 
-async fn stream_handler(
+#[derive(Debug, Deserialize)]
+struct FormatParam {
+    /// streams converted to the format by the stream proxy
+    format: Option<transcode::Format>,
+}
+
+/// Chapters of a long upload as songs with the `clip` to play through the stream proxy. Empty if
+/// the upload has no chapters.
+async fn chapters_handler(
     param: Query<StreamParam>,
     ctx: web::Data<Context>,
-) -> actix_web::Result<Json<Vec<Stream>>> {
-    info!("[Handler] stream with param: {:?}", param);
+) -> actix_web::Result<Json<Vec<Song>>> {
+    info!(
+        "[Handler] chapters: provider: {:?}, id: {}",
+        param.provider,
+        redact(&param.id)
+    );
 
+    let StreamParam { provider, id, .. } = param.into_inner();
     Ok(Json(
         ctx.manager
-            .stream(param.id.clone(), param.provider.clone())
+            .chapters(id, provider)
             .await
-            .map_err(actix_web::error::ErrorInternalServerError)?,
+            .map_err(provider_error)?,
     ))
 }
+
+/// Header declaring the playback capabilities of the client, like
+/// `codecs=mp4a,flac; max-bitrate=320000`
+const CAPABILITIES_HEADER: &str = "X-Bragi-Capabilities";
+
+/// Header carrying the id of a registered device
+const DEVICE_HEADER: &str = "X-Bragi-Device";
+
+/// Capabilities declared by the client, or else those of its registered device, or else those of
+/// the profile matching its User-Agent
+fn client_capabilities(
+    req: &HttpRequest,
+    ctx: &Context,
+) -> actix_web::Result<Option<Capabilities>> {
+    if let Some(declared) = req.headers().get(CAPABILITIES_HEADER) {
+        return declared
+            .to_str()
+            .map_err(anyhow::Error::from)
+            .and_then(capability::parse_capabilities)
+            .map(Some)
+            .map_err(|e| {
+                actix_web::error::ErrorBadRequest(format!("invalid {}: {}", CAPABILITIES_HEADER, e))
+            });
+    }
+    if let Some(id) = req.headers().get(DEVICE_HEADER) {
+        let id = id.to_str().unwrap_or_default();
+        return match ctx.devices.get(device::owner(req).as_deref(), id) {
+            Some(device) => Ok(Some(device.capabilities)),
+            None => Err(actix_web::error::ErrorBadRequest(format!(
+                "unknown device: {}",
+                id
+            ))),
+        };
+    }
+    Ok(req
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|ua| ua.to_str().ok())
+        .and_then(|ua| ctx.profiles.matching(ua))
+        .cloned())
+}
+
+/// Streams the client cannot play are left out
+async fn stream_handler(
+    req: HttpRequest,
+    param: Query<StreamParam>,
+    format: Query<FormatParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<Vec<Stream>>> {
+    info!(
+        "[Handler] stream: provider: {:?}, id: {}, format: {:?}",
+        param.provider,
+        redact(&param.id),
+        format.format
+    );
+
+    let transcoder = match (format.format, &ctx.transcoder) {
+        (Some(_), None) => {
+            return Err(actix_web::error::ErrorBadRequest(
+                "transcoding is not enabled",
+            ))
+        }
+        (format, transcoder) => format.zip(transcoder.as_ref()),
+    };
+    let capabilities = client_capabilities(&req, &ctx)?;
+    let mut streams = match ctx
+        .manager
+        .stream(param.id.clone(), param.provider.clone())
+        .await
+    {
+        Ok(streams) => streams,
+        Err(e) if param.preview => {
+            let previews = ctx
+                .manager
+                .preview(param.id.clone(), param.provider.clone())
+                .await
+                .unwrap_or_else(|pe| {
+                    warn!("preview failed: {}", pe);
+                    vec![]
+                });
+            // the error of the full streams tells more than the absence of a sample
+            if previews.is_empty() {
+                return Err(provider_error(e).into());
+            }
+            previews
+        }
+        Err(e) => return Err(provider_error(e).into()),
+    };
+    if let Some((format, transcoder)) = transcoder {
+        streams = transcode::proxied(
+            streams,
+            &param.provider,
+            &param.id,
+            format,
+            transcoder.bitrate(format),
+        );
+    }
+    if let Some(capabilities) = capabilities {
+        capability::filter_streams(&mut streams, &capabilities);
+    }
+    // urls of this server, like the proxy and local files, are loaded by players without a token
+    for stream in streams.iter_mut().filter(|s| s.url.starts_with('/')) {
+        stream.url = auth::sign(&stream.url);
+    }
+    Ok(Json(streams))
+}
+
+/// Dry run of the stream resolution, explaining which quality tiers were found or filtered
+async fn stream_explain_handler(
+    req: HttpRequest,
+    param: Query<StreamParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<StreamTrace>> {
+    info!(
+        "[Handler] stream explain: provider: {:?}, id: {}",
+        param.provider,
+        redact(&param.id)
+    );
+
+    let capabilities = client_capabilities(&req, &ctx)?;
+    let mut trace = ctx
+        .manager
+        .explain_stream(param.id.clone(), param.provider.clone())
+        .await;
+    if let Some(capabilities) = capabilities {
+        capability::filter_trace(&mut trace, &capabilities);
+    }
+    Ok(Json(trace))
+}
+
+#[derive(Debug, Deserialize)]
+struct CompareParam {
+    /// name and artists of the track, searched on every provider
+    track: String,
+    /// seconds, to tell the track from its live or extended versions
+    duration: Option<u32>,
+}
+
+/// The track on every provider side by side: quality, codec, bitrate and loudness of the stream
+/// played by default, or why there is none. Helps to pick the order of the providers.
+async fn compare_handler(param: Query<CompareParam>, ctx: web::Data<Context>) -> Json<Vec<Source>> {
+    info!(
+        "[Handler] compare: track: {}, duration: {:?}",
+        redact(&param.track),
+        param.duration
+    );
+    Json(
+        ctx.manager
+            .compare(param.track.clone(), param.duration)
+            .await,
+    )
+}
+
+/// Ids are encoded like the ids of songs and collections, opaque if they need escaping
+async fn favorite_list_handler(
+    ctx: web::Data<Context>,
+) -> Json<BTreeMap<Provider, BTreeSet<String>>> {
+    Json(
+        ctx.manager
+            .favorites()
+            .list()
+            .into_iter()
+            .map(|(provider, ids)| (provider, ids.iter().map(|i| id::encode(i)).collect()))
+            .collect(),
+    )
+}
+
+/// Idempotent: saving an already saved id succeeds as well
+async fn favorite_save_handler(
+    path: Path<(Provider, String)>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let (provider, id) = path.into_inner();
+    info!(
+        "[Handler] save favorite: provider: {:?}, id: {}",
+        provider,
+        redact(&id)
+    );
+
+    ctx.manager
+        .favorites()
+        .insert(provider, id::decode(&id).into_owned())
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Idempotent: removing an id not saved succeeds as well
+async fn favorite_remove_handler(
+    path: Path<(Provider, String)>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let (provider, id) = path.into_inner();
+    info!(
+        "[Handler] remove favorite: provider: {:?}, id: {}",
+        provider,
+        redact(&id)
+    );
+
+    ctx.manager
+        .favorites()
+        .remove(&provider, &id::decode(&id))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Ids are encoded like the ids of favorites
+async fn follow_list_handler(
+    ctx: web::Data<Context>,
+) -> Json<BTreeMap<Provider, BTreeSet<String>>> {
+    Json(
+        ctx.manager
+            .follows()
+            .list()
+            .into_iter()
+            .map(|(provider, ids)| (provider, ids.iter().map(|i| id::encode(i)).collect()))
+            .collect(),
+    )
+}
+
+/// Idempotent: following an already followed artist succeeds as well. Releases are only reported
+/// from the second check on, the first one takes note of the existing releases.
+async fn follow_handler(
+    path: Path<(Provider, String)>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let (provider, id) = path.into_inner();
+    info!(
+        "[Handler] follow: provider: {:?}, id: {}",
+        provider,
+        redact(&id)
+    );
+
+    let aid = ArtistId::parse(&provider, &id).map_err(|e| provider_error(e.into()))?;
+    ctx.manager
+        .follows()
+        .follow(provider, aid.into_inner())
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Idempotent: unfollowing an artist not followed succeeds as well
+async fn unfollow_handler(
+    path: Path<(Provider, String)>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let (provider, id) = path.into_inner();
+    info!(
+        "[Handler] unfollow: provider: {:?}, id: {}",
+        provider,
+        redact(&id)
+    );
+
+    ctx.manager
+        .follows()
+        .unfollow(&provider, &id::decode(&id))
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedParam {
+    #[serde(default = "default_feed_limit")]
+    limit: usize,
+}
+
+fn default_feed_limit() -> usize {
+    50
+}
+
+/// New releases of the followed artists, newest first
+async fn feed_handler(param: Query<FeedParam>, ctx: web::Data<Context>) -> Json<Vec<Release>> {
+    Json(ctx.manager.follows().feed(param.limit))
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParam {
+    /// report the songs that would be saved without saving them
+    #[serde(default, alias = "dryRun")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct ImportResult {
+    imported: Vec<String>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    dry_run: bool,
+}
+
+/// Save the songs liked in the provider account to the favorites
+async fn favorite_import_handler(
+    provider: Path<Provider>,
+    param: Query<ImportParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<ImportResult>> {
+    info!(
+        "[Handler] import favorites: provider: {:?}, dry run: {}",
+        provider, param.dry_run
+    );
+
+    Ok(Json(ImportResult {
+        imported: ctx
+            .manager
+            .import_favorites(provider.into_inner(), param.dry_run)
+            .await
+            .map_err(actix_web::error::ErrorInternalServerError)?
+            .iter()
+            .map(|i| id::encode(i))
+            .collect(),
+        dry_run: param.dry_run,
+    }))
+}
+
+/// Rolling latency percentiles and adaptive timeouts of each provider
+async fn latency_handler(ctx: web::Data<Context>) -> Json<HashMap<Provider, LatencyStats>> {
+    Json(ctx.manager.latency().stats())
+}
+
+/// Providers known to be degraded, like under Bilibili risk control, and when they recover
+async fn health_handler(ctx: web::Data<Context>) -> Json<BTreeMap<Provider, Health>> {
+    Json(ctx.manager.health().await)
+}
+
+/// Aggregated search counters, if enabled
+async fn analytics_handler(ctx: web::Data<Context>) -> actix_web::Result<Json<AnalyticsReport>> {
+    match ctx.manager.analytics() {
+        Some(analytics) => Ok(Json(analytics.report())),
+        None => Err(actix_web::error::ErrorNotFound(
+            "search analytics are disabled",
+        )),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AuditParam {
+    #[serde(default = "default_audit_limit")]
+    limit: usize,
+}
+
+fn default_audit_limit() -> usize {
+    100
+}
+
+/// Latest admin operations, newest first
+async fn audit_handler(
+    param: Query<AuditParam>,
+    audit: web::Data<audit::AuditLog>,
+) -> Json<Vec<audit::AuditEntry>> {
+    Json(audit.recent(param.limit))
+}
+
+/// Calls of each provider today against its daily quota
+async fn quota_handler(ctx: web::Data<Context>) -> Json<HashMap<Provider, QuotaUsage>> {
+    Json(ctx.manager.quota().report())
+}
+
+/// Upstream instances of the providers with their success rates and latencies, and whether they
+/// are benched
+async fn instances_handler(
+    ctx: web::Data<Context>,
+) -> Json<BTreeMap<Provider, Vec<InstanceStats>>> {
+    Json(ctx.manager.instances().await)
+}
+
+#[derive(Debug, Deserialize)]
+struct AliasParam {
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AliasGroup {
+    names: Vec<String>,
+}
+
+/// All known names of the artist
+async fn alias_list_handler(param: Query<AliasParam>, ctx: web::Data<Context>) -> Json<AliasGroup> {
+    Json(AliasGroup {
+        names: ctx.manager.aliases().names(&param.name),
+    })
+}
+
+/// Remember the names as the same artist
+async fn alias_learn_handler(
+    group: Json<AliasGroup>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<AliasGroup>> {
+    let Some(first) = group.names.first() else {
+        return Err(actix_web::error::ErrorBadRequest("no names"));
+    };
+    let aliases = ctx.manager.aliases();
+    aliases.learn(group.names.clone());
+    Ok(Json(AliasGroup {
+        names: aliases.names(first),
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::test::TestRequest;
+    use bragi_core::{scraper::cursor::Cursor, Provider};
+
+    use super::{next_link, page};
+
+    #[test]
+    fn test_next_link() {
+        let mut next = Cursor::default();
+        next.insert(Provider::NetEase, "20".into());
+
+        let req = TestRequest::get()
+            .uri("/api/v1/scrape/search?keyword=yoasobi&cursor=old&limit=20")
+            .to_http_request();
+        assert_eq!(
+            next_link(&req, "cursor", &next.encode()),
+            format!(
+                r#"</api/v1/scrape/search?keyword=yoasobi&limit=20&cursor={}>; rel="next""#,
+                next.encode()
+            )
+        );
+    }
+
+    #[test]
+    fn test_page() {
+        let mut songs = (0..10).collect::<Vec<_>>();
+        assert_eq!(page(&mut songs, 0, Some(4)), Some(4));
+        assert_eq!(songs, [0, 1, 2, 3]);
+
+        let mut songs = (0..10).collect::<Vec<_>>();
+        assert_eq!(page(&mut songs, 8, Some(4)), None);
+        assert_eq!(songs, [8, 9]);
+
+        // whole collections without a page size
+        let mut songs = (0..10).collect::<Vec<_>>();
+        assert_eq!(page(&mut songs, 3, None), None);
+        assert_eq!(songs.len(), 7);
+
+        let mut songs = (0..10).collect::<Vec<_>>();
+        assert_eq!(page(&mut songs, 20, Some(4)), None);
+        assert!(songs.is_empty());
+
+        let req = TestRequest::get()
+            .uri("/api/v1/scrape/collection?provider=netease&id=1&offset=4")
+            .to_http_request();
+        assert_eq!(
+            next_link(&req, "offset", "8"),
+            r#"</api/v1/scrape/collection?provider=netease&id=1&offset=8>; rel="next""#
+        );
+    }
+}
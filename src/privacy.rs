@@ -0,0 +1,55 @@
+use std::{
+    borrow::Cow,
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use lazy_static::lazy_static;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+lazy_static! {
+    /// Fresh on every start, so that hashes cannot be looked up across runs or deployments
+    static ref SALT: u64 = rand::random();
+}
+
+/// Hash search keywords and ids in logs and analytics from now on, for the whole process
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The value as is, or a salted hash of it in privacy mode. The same value gets the same hash
+/// within a run, so that log lines can still be correlated.
+pub fn redact(value: &str) -> Cow<'_, str> {
+    match enabled() {
+        true => Cow::Owned(hash(value)),
+        false => Cow::Borrowed(value),
+    }
+}
+
+fn hash(value: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    SALT.hash(&mut hasher);
+    value.hash(&mut hasher);
+    format!("#{:016x}", hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use super::hash;
+
+    // privacy mode itself is not switched on, since it is global to the tests as well
+    #[test]
+    fn test_hash() {
+        let hashed = hash("夜に駆ける");
+        assert!(hashed.starts_with('#'));
+        assert!(!hashed.contains("夜"));
+        assert_eq!(hashed, hash("夜に駆ける"));
+        assert_ne!(hashed, hash("アイドル"));
+    }
+}
@@ -0,0 +1,813 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use actix_web::{
+    body::{BodyStream, SizedStream},
+    http::{
+        header::{self, HeaderName, HeaderValue},
+        StatusCode,
+    },
+    web::{self, Query},
+    HttpRequest, HttpResponse,
+};
+use bragi_core::{
+    privacy::redact,
+    scraper::{capability, Provider, Stream},
+    settings::Capabilities,
+};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::{
+    cache::AudioCache,
+    client_capabilities,
+    clip::{self, Incomplete, Index},
+    download::serve_file,
+    error::provider_error,
+    transcode::{Format, Transcoder},
+    Context,
+};
+
+/// Resolved stream urls are reused for the range requests of a player within this time, instead
+/// of resolving the song again for every chunk
+const URL_TTL: Duration = Duration::from_secs(60);
+
+/// Most times a stream breaking mid-way is fetched again from the byte it broke at
+const MAX_RESUMES: usize = 2;
+
+/// Request headers of the client passed upstream
+const FORWARDED_REQUEST: [HeaderName; 2] = [header::RANGE, header::IF_RANGE];
+
+/// Response headers of the upstream passed to the client
+const FORWARDED_RESPONSE: [HeaderName; 6] = [
+    header::CONTENT_TYPE,
+    header::CONTENT_RANGE,
+    header::ACCEPT_RANGES,
+    header::ETAG,
+    header::LAST_MODIFIED,
+    header::CACHE_CONTROL,
+];
+
+/// provider, song id and quality
+type UrlKey = (Provider, String, Option<String>);
+
+/// Proxy of the stream urls for clients unable to send the headers required upstream, or to
+/// outlive their expiry
+#[derive(Debug, Default)]
+pub struct StreamProxy {
+    client: reqwest::Client,
+    urls: Mutex<HashMap<UrlKey, (String, Instant)>>,
+}
+
+impl StreamProxy {
+    fn cached(&self, key: &UrlKey) -> Option<String> {
+        self.urls
+            .lock()
+            .get(key)
+            .filter(|(_, at)| at.elapsed() < URL_TTL)
+            .map(|(url, _)| url.clone())
+    }
+
+    fn remember(&self, key: UrlKey, url: String) {
+        let mut urls = self.urls.lock();
+        urls.retain(|_, (_, at)| at.elapsed() < URL_TTL);
+        urls.insert(key, (url, Instant::now()));
+    }
+
+    fn forget(&self, key: &UrlKey) {
+        self.urls.lock().remove(key);
+    }
+
+    /// The whole stream, or the range asked for by the request of the client if present
+    async fn fetch(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+        req: Option<&HttpRequest>,
+    ) -> reqwest::Result<reqwest::Response> {
+        let mut upstream = self.client.get(url);
+        for (name, value) in headers {
+            upstream = upstream.header(*name, value);
+        }
+        for name in FORWARDED_REQUEST {
+            if let Some(value) = req.and_then(|r| r.headers().get(&name)) {
+                upstream = upstream.header(name.as_str(), value.as_bytes());
+            }
+        }
+        upstream.send().await
+    }
+
+    /// Bytes `first` to `last` of the url, inclusive
+    async fn fetch_range(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+        first: u64,
+        last: u64,
+    ) -> anyhow::Result<reqwest::Response> {
+        let mut upstream = self
+            .client
+            .get(url)
+            .header(header::RANGE.as_str(), format!("bytes={}-{}", first, last));
+        for (name, value) in headers {
+            upstream = upstream.header(*name, value);
+        }
+        let upstream = upstream.send().await?.error_for_status()?;
+        if upstream.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+            anyhow::bail!("range requests not supported upstream");
+        }
+        Ok(upstream)
+    }
+
+    /// Head of the fragmented mp4 at the url, up to its segment index, and the index
+    pub async fn index(
+        &self,
+        url: &str,
+        headers: &[(&'static str, String)],
+    ) -> anyhow::Result<(web::Bytes, Index)> {
+        let mut head_size = clip::HEAD_SIZE;
+        loop {
+            let head = self
+                .fetch_range(url, headers, 0, head_size - 1)
+                .await?
+                .bytes()
+                .await?;
+            match clip::parse_index(&head)? {
+                Ok(index) => return Ok((head, index)),
+                Err(Incomplete(size)) if size > head_size && size <= clip::MAX_HEAD_SIZE => {
+                    head_size = size
+                }
+                Err(_) => anyhow::bail!("segment index not found"),
+            }
+        }
+    }
+
+    /// Length of the stream at the url, as told by the range response of its first byte
+    pub async fn size(&self, url: &str, headers: &[(&'static str, String)]) -> anyhow::Result<u64> {
+        let upstream = self.fetch_range(url, headers, 0, 0).await?;
+        upstream
+            .headers()
+            .get(header::CONTENT_RANGE.as_str())
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit_once('/'))
+            .and_then(|(_, size)| size.parse().ok())
+            .ok_or(anyhow::anyhow!("size of the stream unknown"))
+    }
+}
+
+/// Url of the stream proxy for the stream of the quality, converted to the format if given
+pub fn proxy_url(provider: &Provider, id: &str, quality: &str, format: Option<Format>) -> String {
+    let mut url = reqwest::Url::parse("http://localhost/api/v1/stream/proxy").unwrap();
+    url.query_pairs_mut()
+        .append_pair("provider", &provider.to_string())
+        .append_pair("id", id)
+        .append_pair("quality", quality);
+    if let Some(format) = format {
+        url.query_pairs_mut()
+            .append_pair("format", format.extension());
+    }
+    format!("{}?{}", url.path(), url.query().unwrap_or_default())
+}
+
+fn chunks(
+    upstream: reqwest::Response,
+) -> impl futures::Stream<Item = reqwest::Result<web::Bytes>> + 'static {
+    futures::stream::unfold(upstream, |mut upstream| async move {
+        upstream
+            .chunk()
+            .await
+            .transpose()
+            .map(|chunk| (chunk, upstream))
+    })
+}
+
+/// Stream the upstream response with its status, so that partial content and unsatisfiable
+/// ranges reach the client as they are
+fn response(upstream: reqwest::Response) -> HttpResponse {
+    response_with(upstream, chunks)
+}
+
+/// The upstream response with its body streamed by `body`
+fn response_with<S, E>(
+    upstream: reqwest::Response,
+    body: impl FnOnce(reqwest::Response) -> S,
+) -> HttpResponse
+where
+    S: futures::Stream<Item = Result<web::Bytes, E>> + 'static,
+    E: Into<Box<dyn std::error::Error>> + 'static,
+{
+    let status =
+        StatusCode::from_u16(upstream.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut resp = HttpResponse::build(status);
+    for name in FORWARDED_RESPONSE {
+        if let Some(value) = upstream
+            .headers()
+            .get(name.as_str())
+            .and_then(|v| HeaderValue::from_bytes(v.as_bytes()).ok())
+        {
+            resp.insert_header((name, value));
+        }
+    }
+
+    let length = upstream.content_length();
+    let chunks = body(upstream);
+    match length {
+        Some(length) => resp.body(SizedStream::new(length, Box::pin(chunks))),
+        None => resp.body(BodyStream::new(chunks)),
+    }
+}
+
+/// Bytes of the upstream response within the stream: the first, the last and the size of the whole
+/// stream. None if the length is unknown.
+fn served_range(upstream: &reqwest::Response) -> Option<(u64, u64, u64)> {
+    match upstream.status() {
+        reqwest::StatusCode::OK => {
+            let size = upstream.content_length().filter(|s| *s > 0)?;
+            Some((0, size - 1, size))
+        }
+        reqwest::StatusCode::PARTIAL_CONTENT => {
+            let range = upstream
+                .headers()
+                .get(header::CONTENT_RANGE.as_str())?
+                .to_str()
+                .ok()?
+                .strip_prefix("bytes ")?;
+            let (range, size) = range.split_once('/')?;
+            let (first, last) = range.split_once('-')?;
+            Some((first.parse().ok()?, last.parse().ok()?, size.parse().ok()?))
+        }
+        _ => None,
+    }
+}
+
+/// Upstream response being streamed to the client, from `offset` on
+struct Resumable<F> {
+    upstream: Option<reqwest::Response>,
+    offset: u64,
+    last: u64,
+    size: u64,
+    resumes: usize,
+    proxy: Arc<StreamProxy>,
+    headers: Vec<(&'static str, String)>,
+    resolve: F,
+}
+
+impl<F, Fut> Resumable<F>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = anyhow::Result<String>>,
+{
+    /// The rest of the stream from a freshly resolved url, if it is the same stream as told by
+    /// its size
+    async fn resume(&self) -> anyhow::Result<reqwest::Response> {
+        let url = (self.resolve)().await?;
+        let upstream = self
+            .proxy
+            .fetch_range(&url, &self.headers, self.offset, self.last)
+            .await?;
+        match served_range(&upstream) {
+            Some((first, _, size)) if first == self.offset && size == self.size => Ok(upstream),
+            _ => anyhow::bail!("the stream resolved again differs"),
+        }
+    }
+}
+
+/// Body of the upstream response which, when the connection breaks or the url expires
+/// mid-stream, resolves the stream again and goes on from the same byte. Clients see the stream
+/// fail only if that fails too.
+fn resumable<F, Fut>(
+    upstream: reqwest::Response,
+    (first, last, size): (u64, u64, u64),
+    proxy: Arc<StreamProxy>,
+    headers: Vec<(&'static str, String)>,
+    resolve: F,
+) -> impl futures::Stream<Item = std::io::Result<web::Bytes>> + 'static
+where
+    F: Fn() -> Fut + 'static,
+    Fut: Future<Output = anyhow::Result<String>>,
+{
+    let state = Resumable {
+        upstream: Some(upstream),
+        offset: first,
+        last,
+        size,
+        resumes: 0,
+        proxy,
+        headers,
+        resolve,
+    };
+    futures::stream::unfold(state, |mut s| async move {
+        loop {
+            let error = match s.upstream.as_mut()?.chunk().await {
+                Ok(Some(chunk)) => {
+                    s.offset += chunk.len() as u64;
+                    return Some((Ok(chunk), s));
+                }
+                Ok(None) if s.offset > s.last => return None,
+                Ok(None) => anyhow::anyhow!("stream ended at byte {} of {}", s.offset, s.size),
+                Err(e) => e.into(),
+            };
+            if s.resumes >= MAX_RESUMES {
+                s.upstream = None;
+                return Some((Err(std::io::Error::other(error.to_string())), s));
+            }
+            s.resumes += 1;
+            warn!("stream broke at byte {}: {}, resume", s.offset, error);
+            match s.resume().await {
+                Ok(upstream) => s.upstream = Some(upstream),
+                Err(e) => {
+                    warn!("resume stream at byte {} failed: {}", s.offset, e);
+                    s.upstream = None;
+                    return Some((Err(std::io::Error::other(error.to_string())), s));
+                }
+            }
+        }
+    })
+}
+
+/// Only fragmented mp4 streams have a segment index to cut at, not the WebM of opus
+pub fn clippable(stream: &Stream) -> bool {
+    !matches!(
+        stream.codec.as_deref().map(str::to_lowercase).as_deref(),
+        Some("opus" | "vorbis")
+    )
+}
+
+/// Upstream refusals which a freshly resolved url may not get
+fn expired(status: reqwest::StatusCode) -> bool {
+    matches!(
+        status,
+        reqwest::StatusCode::FORBIDDEN | reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ProxyParam {
+    provider: Provider,
+    id: String,
+    /// quality of the stream as listed by the stream endpoint. The first playable one if absent
+    quality: Option<String>,
+    /// seconds, the `clip` of chapter songs. Only the range is played if either is present.
+    start: Option<u32>,
+    end: Option<u32>,
+    /// convert to the format, whatever the client is able to play otherwise
+    format: Option<Format>,
+}
+
+/// Streams of the song the client can play, of the quality if one is asked for
+pub async fn playable(
+    ctx: &Context,
+    provider: &Provider,
+    id: &str,
+    quality: Option<&String>,
+    capabilities: Option<&Capabilities>,
+) -> actix_web::Result<Vec<Stream>> {
+    let mut streams = ctx
+        .manager
+        .stream(id.to_string(), provider.clone())
+        .await
+        .map_err(provider_error)?;
+    if let Some(capabilities) = capabilities {
+        capability::filter_streams(&mut streams, capabilities);
+    }
+    if let Some(quality) = quality {
+        streams.retain(|s| &s.quality == quality);
+    }
+    match streams.is_empty() {
+        true => Err(actix_web::error::ErrorNotFound(format!(
+            "no playable stream{}",
+            quality
+                .map(|q| format!(" of quality {}", q))
+                .unwrap_or_default()
+        ))),
+        false => Ok(streams),
+    }
+}
+
+/// Url of the first playable stream, of the quality if the key has one, remembered for the range
+/// requests to come
+async fn resolve(
+    ctx: &Context,
+    key: &UrlKey,
+    capabilities: Option<&Capabilities>,
+) -> actix_web::Result<String> {
+    let (provider, id, quality) = key;
+    let stream = playable(ctx, provider, id, quality.as_ref(), capabilities)
+        .await?
+        .swap_remove(0);
+    ctx.proxy.remember(key.clone(), stream.url.clone());
+    Ok(stream.url)
+}
+
+/// The init part of the stream followed by the segments of the time range, a playable file on its
+/// own without transcoding. Timestamps stay those of the upload. Returns the content type, the
+/// length and the body.
+async fn clip(
+    proxy: &StreamProxy,
+    stream: &Stream,
+    headers: &[(&'static str, String)],
+    start: u32,
+    end: Option<u32>,
+) -> anyhow::Result<(
+    Option<HeaderValue>,
+    u64,
+    impl futures::Stream<Item = reqwest::Result<web::Bytes>> + 'static,
+)> {
+    let (head, index) = proxy.index(&stream.url, headers).await?;
+    let (first, last) = clip::byte_range(&index.segments, start, end)
+        .ok_or(anyhow::anyhow!("clip beyond the end of the stream"))?;
+
+    let upstream = proxy.fetch_range(&stream.url, headers, first, last).await?;
+    let content_type = upstream
+        .headers()
+        .get(header::CONTENT_TYPE.as_str())
+        .and_then(|v| HeaderValue::from_bytes(v.as_bytes()).ok());
+    let init = head.slice(..index.init);
+    let length = init.len() as u64 + last - first + 1;
+    let body = futures::stream::once(async move { Ok(init) }).chain(chunks(upstream));
+    Ok((content_type, length, body))
+}
+
+fn clipped(
+    content_type: Option<HeaderValue>,
+    length: u64,
+    body: impl futures::Stream<Item = reqwest::Result<web::Bytes>> + 'static,
+) -> HttpResponse {
+    let mut resp = HttpResponse::Ok();
+    if let Some(content_type) = content_type {
+        resp.insert_header((header::CONTENT_TYPE, content_type));
+    }
+    resp.body(SizedStream::new(length, Box::pin(body)))
+}
+
+/// Converted on the fly, so neither the length is known nor can ranges be served until the
+/// output is cached under the key if given
+async fn transcoded(
+    transcoder: &Transcoder,
+    body: impl futures::Stream<Item = reqwest::Result<web::Bytes>> + 'static,
+    format: Format,
+    cache: Option<(&Arc<AudioCache>, &str)>,
+) -> actix_web::Result<HttpResponse> {
+    let output = transcoder
+        .transcode(body, format)
+        .await
+        .map_err(actix_web::error::ErrorInternalServerError)?;
+    let mut resp = HttpResponse::Ok();
+    resp.content_type(format.content_type())
+        .insert_header((header::ACCEPT_RANGES, "none"));
+    Ok(match cache {
+        Some((cache, key)) => resp.body(BodyStream::new(cache.tee(
+            key,
+            format.content_type(),
+            output,
+        ))),
+        None => resp.body(BodyStream::new(output)),
+    })
+}
+
+/// Audio of the song proxied from upstream with the headers it requires. Range requests are
+/// passed through, so that players can seek.
+pub async fn proxy_handler(
+    req: HttpRequest,
+    param: Query<ProxyParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let ProxyParam {
+        provider,
+        id,
+        quality,
+        start,
+        end,
+        format,
+    } = param.into_inner();
+    info!(
+        "[Handler] stream proxy: provider: {:?}, id: {}",
+        provider,
+        redact(&id)
+    );
+
+    let transcode = format
+        .map(|format| match &ctx.transcoder {
+            Some(transcoder) => Ok((transcoder, format)),
+            None => Err(actix_web::error::ErrorBadRequest(
+                "transcoding is not enabled",
+            )),
+        })
+        .transpose()?;
+    // the client plays the converted stream, whatever the original is
+    let capabilities = match transcode {
+        Some(_) => None,
+        None => client_capabilities(&req, &ctx)?,
+    };
+    let headers = ctx.manager.stream_headers(&provider).await;
+
+    // ranges of the client do not apply to clips, which are cut on every call
+    if start.is_some() || end.is_some() {
+        let streams = playable(
+            &ctx,
+            &provider,
+            &id,
+            quality.as_ref(),
+            capabilities.as_ref(),
+        )
+        .await?;
+        let mut last_error = anyhow::anyhow!("no stream can be clipped");
+        for stream in streams.iter().filter(|s| clippable(s)) {
+            match clip(&ctx.proxy, stream, &headers, start.unwrap_or_default(), end).await {
+                Ok((content_type, length, body)) => {
+                    return match transcode {
+                        Some((transcoder, format)) => {
+                            transcoded(transcoder, body, format, None).await
+                        }
+                        None => Ok(clipped(content_type, length, body)),
+                    }
+                }
+                Err(e) => {
+                    warn!("clip stream {} failed: {}", stream.quality, e);
+                    last_error = e;
+                }
+            }
+        }
+        return Err(actix_web::error::ErrorBadGateway(format!(
+            "clip stream failed: {}",
+            last_error
+        )));
+    }
+
+    // whole songs only, of a quality known before resolving. The first playable one depends on
+    // the client unless converted.
+    let cache = ctx
+        .cache
+        .as_ref()
+        .filter(|_| quality.is_some() || transcode.is_some())
+        .map(|cache| {
+            let format = transcode.map(|(_, format)| format);
+            (
+                cache,
+                AudioCache::key(&provider, &id, quality.as_deref(), format),
+            )
+        });
+    if let Some((path, content_type)) = cache.as_ref().and_then(|(cache, key)| cache.get(key)) {
+        if let Ok(file) = tokio::fs::File::open(path).await {
+            return serve_file(&req, Default::default(), file, &content_type).await;
+        }
+    }
+    let cache = cache.as_ref().map(|(cache, key)| (*cache, key.as_str()));
+
+    if let Some((transcoder, format)) = transcode {
+        let stream = playable(&ctx, &provider, &id, quality.as_ref(), None)
+            .await?
+            .swap_remove(0);
+        let upstream = ctx
+            .proxy
+            .fetch(&stream.url, &headers, None)
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .map_err(|e| {
+                actix_web::error::ErrorBadGateway(format!("fetch stream failed: {}", e))
+            })?;
+        return transcoded(transcoder, chunks(upstream), format, cache).await;
+    }
+
+    let key = (provider.clone(), id.clone(), quality.clone());
+    let mut cached = ctx.proxy.cached(&key);
+
+    loop {
+        let fresh = cached.is_none();
+        let url = match cached.take() {
+            Some(url) => url,
+            None => resolve(&ctx, &key, capabilities.as_ref()).await?,
+        };
+
+        match ctx.proxy.fetch(&url, &headers, Some(&req)).await {
+            Ok(upstream) if expired(upstream.status()) && !fresh => {
+                // resolve once more, the url may have expired meanwhile
+                warn!(
+                    "stream url refused with {}, resolve again",
+                    upstream.status()
+                );
+                ctx.proxy.forget(&key);
+            }
+            Ok(upstream) => {
+                let Some(range) = served_range(&upstream) else {
+                    return Ok(response(upstream));
+                };
+                let proxy = ctx.proxy.clone();
+                let (ctx, key) = (ctx.clone(), key.clone());
+                let refetch = move || {
+                    let (ctx, key, capabilities) = (ctx.clone(), key.clone(), capabilities.clone());
+                    async move {
+                        ctx.proxy.forget(&key);
+                        resolve(&ctx, &key, capabilities.as_ref())
+                            .await
+                            .map_err(|e| anyhow::anyhow!("{}", e))
+                    }
+                };
+                let whole = range.0 == 0 && range.1 + 1 == range.2;
+                let content_type = upstream
+                    .headers()
+                    .get(header::CONTENT_TYPE.as_str())
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or("application/octet-stream")
+                    .to_string();
+                return Ok(match cache.filter(|_| whole) {
+                    Some((cache, key)) => response_with(upstream, |upstream| {
+                        cache.tee(
+                            key,
+                            &content_type,
+                            resumable(upstream, range, proxy, headers, refetch),
+                        )
+                    }),
+                    None => response_with(upstream, |upstream| {
+                        resumable(upstream, range, proxy, headers, refetch)
+                    }),
+                });
+            }
+            Err(e) => {
+                ctx.proxy.forget(&key);
+                return Err(actix_web::error::ErrorBadGateway(format!(
+                    "fetch stream failed: {}",
+                    e
+                )));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use actix_web::{
+        body::SizedStream,
+        http::{header, StatusCode},
+        test::TestRequest,
+        web, App, HttpRequest, HttpResponse, HttpServer,
+    };
+    use futures::StreamExt;
+
+    use bragi_core::scraper::Stream;
+
+    use super::{clip, clipped, response, response_with, resumable, served_range, StreamProxy};
+
+    const AUDIO: &[u8] = b"0123456789";
+
+    /// Fragmented mp4 of three segments of `a`, `b` and `c`, 10s each
+    fn fragmented() -> Vec<u8> {
+        let mut file = crate::clip::test::head();
+        for (byte, size) in [(b'a', 100), (b'b', 200), (b'c', 300)] {
+            file.extend(vec![byte; size]);
+        }
+        file
+    }
+
+    /// Serves `AUDIO`, or the fragmented mp4 at `/fragmented.m4s`, only with the Referer. Single
+    /// byte ranges like `bytes=2-5` are honored. At `/broken` the connection breaks after the first
+    /// half of `AUDIO`.
+    async fn upstream(req: HttpRequest) -> HttpResponse {
+        if req.headers().get(header::REFERER).is_none() {
+            return HttpResponse::Forbidden().finish();
+        }
+        if req.path() == "/broken" {
+            // the head and the first half reach the client before the break
+            let body = futures::stream::once(async {
+                Ok::<_, std::io::Error>(web::Bytes::from_static(&AUDIO[..5]))
+            })
+            .chain(futures::stream::once(async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                Err(std::io::Error::other("reset"))
+            }));
+            return HttpResponse::Ok()
+                .content_type("audio/mp4")
+                .body(SizedStream::new(AUDIO.len() as u64, Box::pin(body)));
+        }
+        let audio = match req.path() {
+            "/fragmented.m4s" => fragmented(),
+            _ => AUDIO.to_vec(),
+        };
+        let range = req
+            .headers()
+            .get(header::RANGE)
+            .and_then(|r| r.to_str().ok())
+            .and_then(|r| r.strip_prefix("bytes="))
+            .and_then(|r| r.split_once('-'))
+            .and_then(|(start, end)| {
+                Some((start.parse::<usize>().ok()?, end.parse::<usize>().ok()?))
+            });
+        match range {
+            Some((start, end)) => {
+                let end = end.min(audio.len() - 1);
+                HttpResponse::PartialContent()
+                    .insert_header((header::ACCEPT_RANGES, "bytes"))
+                    .insert_header((
+                        header::CONTENT_RANGE,
+                        format!("bytes {}-{}/{}", start, end, audio.len()),
+                    ))
+                    .content_type("audio/mp4")
+                    .body(audio[start..=end].to_vec())
+            }
+            None => HttpResponse::Ok().content_type("audio/mp4").body(audio),
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_range() {
+        let server = HttpServer::new(|| App::new().default_service(web::to(upstream)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let url = format!("http://{}/audio.m4a", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        let proxy = StreamProxy::default();
+        let headers = [("Referer", "https://www.bilibili.com".to_string())];
+        let req = TestRequest::default()
+            .insert_header((header::RANGE, "bytes=2-5"))
+            .to_http_request();
+        let resp = response(proxy.fetch(&url, &headers, Some(&req)).await.unwrap());
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(
+            resp.headers().get(header::CONTENT_RANGE).unwrap(),
+            "bytes 2-5/10"
+        );
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"2345");
+
+        let resp = proxy.fetch(&url, &[], None).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 403);
+
+        let full = response(proxy.fetch(&url, &headers, None).await.unwrap());
+        let body = actix_web::body::to_bytes(full.into_body()).await.unwrap();
+        assert_eq!(&body[..], AUDIO);
+    }
+
+    #[actix_web::test]
+    async fn test_resume() {
+        let server = HttpServer::new(|| App::new().default_service(web::to(upstream)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let base = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+
+        let proxy = Arc::new(StreamProxy::default());
+        let headers = vec![("Referer", "https://www.bilibili.com".to_string())];
+        // resolved again to the same stream, or to another one
+        for (url, resumed) in [("/audio.m4a", true), ("/fragmented.m4s", false)] {
+            let upstream = proxy
+                .fetch(&format!("{}/broken", base), &headers, None)
+                .await
+                .unwrap();
+            let range = served_range(&upstream).unwrap();
+            assert_eq!(range, (0, 9, 10));
+            let url = format!("{}{}", base, url);
+            let resp = response_with(upstream, |upstream| {
+                resumable(upstream, range, proxy.clone(), headers.clone(), move || {
+                    let url = url.clone();
+                    async move { Ok(url) }
+                })
+            });
+            let body = actix_web::body::to_bytes(resp.into_body()).await;
+            match resumed {
+                true => assert_eq!(&body.unwrap()[..], AUDIO),
+                false => assert!(body.is_err()),
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_clip() {
+        let server = HttpServer::new(|| App::new().default_service(web::to(upstream)))
+            .workers(1)
+            .bind(("127.0.0.1", 0))
+            .unwrap();
+        let stream = Stream {
+            quality: "192k".into(),
+            url: format!("http://{}/fragmented.m4s", server.addrs()[0]),
+            bitrate: None,
+            codec: Some("mp4a.40.2".into()),
+            mirror: false,
+            loudness: None,
+            preview: false,
+        };
+        actix_web::rt::spawn(server.run());
+
+        let proxy = StreamProxy::default();
+        let headers = [("Referer", "https://www.bilibili.com".to_string())];
+        let (_, length, body) = clip(&proxy, &stream, &headers, 12, Some(18)).await.unwrap();
+        let resp = clipped(None, length, body);
+        let body = actix_web::body::to_bytes(resp.into_body()).await.unwrap();
+        // the init part and the second segment
+        let init = &fragmented()[..28];
+        assert_eq!(&body[..28], init);
+        assert_eq!(&body[28..], vec![b'b'; 200].as_slice());
+
+        assert!(clip(&proxy, &stream, &headers, 45, None).await.is_err());
+    }
+}
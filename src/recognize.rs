@@ -0,0 +1,375 @@
+use std::{path::PathBuf, process::Stdio, time::Duration};
+
+use actix_web::web::{self, Json};
+use anyhow::{anyhow, bail};
+use bragi_core::{
+    privacy::redact,
+    scraper::{merge::Alternative, Artist, Song},
+    settings::RecognizeSettings,
+    LimitedBody,
+};
+use serde::{Deserialize, Serialize};
+use tokio::{io::AsyncReadExt, process::Command};
+use tracing::info;
+
+use crate::Context;
+
+/// fpcalc decodes the clip before fingerprinting it, a few seconds for a short clip
+const FINGERPRINT_TIMEOUT: Duration = Duration::from_secs(30);
+/// Bytes of the json output of fpcalc read at most
+const MAX_OUTPUT: u64 = 1024 * 1024;
+/// Recordings of the best results looked up on the providers
+const MAX_MATCHES: usize = 3;
+
+#[derive(Debug, Deserialize)]
+struct Fingerprint {
+    /// seconds of the clip
+    duration: f64,
+    fingerprint: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcoustIdResponse {
+    status: String,
+    error: Option<AcoustIdError>,
+    #[serde(default)]
+    results: Vec<AcoustIdResult>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcoustIdError {
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcoustIdResult {
+    score: f64,
+    /// absent if the fingerprint is known but not linked to MusicBrainz
+    #[serde(default)]
+    recordings: Vec<AcoustIdRecording>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcoustIdRecording {
+    id: String,
+    title: Option<String>,
+    /// seconds
+    duration: Option<f64>,
+    #[serde(default)]
+    artists: Vec<AcoustIdArtist>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AcoustIdArtist {
+    name: String,
+}
+
+/// fpcalc could not be run, a misconfiguration of the server rather than a bad clip
+#[derive(Debug)]
+struct SpawnFailed(std::io::Error);
+
+impl std::fmt::Display for SpawnFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "start fpcalc failed: {}", self.0)
+    }
+}
+
+impl std::error::Error for SpawnFailed {}
+
+/// Temporary file of a clip, removed when dropped, also if the request is dropped midway
+struct ClipFile(PathBuf);
+
+impl ClipFile {
+    async fn write(clip: &[u8]) -> std::io::Result<Self> {
+        let path = std::env::temp_dir().join(format!("bragi-clip-{:016x}", rand::random::<u64>()));
+        let file = Self(path);
+        tokio::fs::write(&file.0, clip).await?;
+        Ok(file)
+    }
+}
+
+impl Drop for ClipFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Track the clip is recognized as, with the songs to play or add to the library
+#[derive(Debug, Serialize)]
+pub struct Recognized {
+    /// MusicBrainz recording id
+    pub recording: String,
+    pub title: String,
+    pub artists: Vec<String>,
+    /// seconds of the whole track
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u32>,
+    /// confidence of the match, from 0 to 1
+    pub score: f64,
+    /// the track on the providers, in the priority order of songs
+    pub songs: Vec<Alternative>,
+}
+
+/// The best scored recordings of the lookup, each once, as tracks without songs yet
+fn parse(resp: AcoustIdResponse) -> anyhow::Result<Vec<Recognized>> {
+    if resp.status != "ok" {
+        bail!(
+            "acoustid lookup failed: {}",
+            resp.error.map(|e| e.message).unwrap_or(resp.status)
+        );
+    }
+    let mut results = resp.results;
+    results.sort_by(|a, b| b.score.total_cmp(&a.score));
+
+    let mut recognized: Vec<Recognized> = vec![];
+    for (score, recording) in results
+        .into_iter()
+        .flat_map(|r| r.recordings.into_iter().map(move |rec| (r.score, rec)))
+    {
+        let Some(title) = recording.title else {
+            continue;
+        };
+        if recognized.iter().any(|r| r.recording == recording.id) {
+            continue;
+        }
+        recognized.push(Recognized {
+            recording: recording.id,
+            title,
+            artists: recording.artists.into_iter().map(|a| a.name).collect(),
+            duration: recording.duration.map(|d| d.round() as u32),
+            score,
+            songs: vec![],
+        });
+    }
+    recognized.truncate(MAX_MATCHES);
+    Ok(recognized)
+}
+
+/// Recognizes songs from short audio clips by their Chromaprint fingerprint, looked up on AcoustID
+pub struct Recognizer {
+    fpcalc: String,
+    key: String,
+    endpoint: String,
+    client: reqwest::Client,
+}
+
+impl Recognizer {
+    pub fn from_setting(setting: &RecognizeSettings) -> Option<Self> {
+        let (Some(fpcalc), Some(key)) = (&setting.fpcalc, &setting.acoustid_key) else {
+            return None;
+        };
+        info!("recognize songs with {} and {}", fpcalc, setting.endpoint);
+        Some(Self {
+            fpcalc: fpcalc.clone(),
+            key: key.clone(),
+            endpoint: setting.endpoint.clone(),
+            client: reqwest::Client::new(),
+        })
+    }
+
+    /// Fingerprint of the clip by fpcalc, which is killed if it takes longer than
+    /// `FINGERPRINT_TIMEOUT` or prints more than `MAX_OUTPUT`. The clip goes through a temporary
+    /// file, since fpcalc probes the format by seeking. Fails with `SpawnFailed` if fpcalc cannot
+    /// be run.
+    async fn fingerprint(&self, clip: &[u8]) -> anyhow::Result<Fingerprint> {
+        let file = ClipFile::write(clip).await?;
+        let mut child = Command::new(&self.fpcalc)
+            .arg("-json")
+            .arg(&file.0)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(SpawnFailed)?;
+        let Some(stdout) = child.stdout.take() else {
+            bail!("stdout of fpcalc not available");
+        };
+
+        let extract = async {
+            let mut output = vec![];
+            // one byte over the cap tells that fpcalc has more to write
+            stdout.take(MAX_OUTPUT + 1).read_to_end(&mut output).await?;
+            if output.len() as u64 > MAX_OUTPUT {
+                child.kill().await?;
+                bail!("fpcalc printed more than {} bytes", MAX_OUTPUT);
+            }
+            let status = child.wait().await?;
+            match status.success() {
+                true => Ok(serde_json::from_slice(&output)?),
+                false => bail!("fpcalc failed with {}", status),
+            }
+        };
+        tokio::time::timeout(FINGERPRINT_TIMEOUT, extract)
+            .await
+            .map_err(|_| anyhow!("fpcalc timed out after {}s", FINGERPRINT_TIMEOUT.as_secs()))?
+    }
+
+    /// Posted as a form, since fingerprints are too long for a query string
+    async fn lookup(&self, fingerprint: &Fingerprint) -> anyhow::Result<Vec<Recognized>> {
+        let resp = self
+            .client
+            .post(&self.endpoint)
+            .form(&[
+                ("client", self.key.as_str()),
+                ("meta", "recordings"),
+                ("duration", &(fingerprint.duration as u32).to_string()),
+                ("fingerprint", &fingerprint.fingerprint),
+            ])
+            .send()
+            .await?
+            .limited_json::<AcoustIdResponse>()
+            .await?;
+        parse(resp)
+    }
+}
+
+/// The tracks an audio clip in the body is recognized as, best first, each with the matching
+/// songs of the providers
+pub async fn recognize_handler(
+    clip: web::Bytes,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<Vec<Recognized>>> {
+    let Some(recognizer) = &ctx.recognizer else {
+        return Err(actix_web::error::ErrorBadRequest(
+            "recognition is not enabled",
+        ));
+    };
+    if clip.is_empty() {
+        return Err(actix_web::error::ErrorBadRequest("audio clip is empty"));
+    }
+    info!("[Handler] recognize: {} bytes", clip.len());
+
+    let fingerprint =
+        recognizer
+            .fingerprint(&clip)
+            .await
+            .map_err(|e| match e.downcast_ref::<SpawnFailed>() {
+                Some(e) => actix_web::error::ErrorInternalServerError(e.to_string()),
+                None => actix_web::error::ErrorBadRequest(format!("fingerprint failed: {}", e)),
+            })?;
+    let mut recognized = recognizer
+        .lookup(&fingerprint)
+        .await
+        .map_err(|e| actix_web::error::ErrorBadGateway(format!("recognition failed: {}", e)))?;
+
+    let found = recognized.iter().map(|r| {
+        let song = Song {
+            id: String::new().into(),
+            name: r.title.clone(),
+            artists: r
+                .artists
+                .iter()
+                .map(|name| Artist {
+                    id: String::new().into(),
+                    name: name.clone(),
+                    description: None,
+                    avatar: None,
+                })
+                .collect(),
+            cover: None,
+            duration: r.duration,
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        };
+        let manager = ctx.manager.clone();
+        async move { manager.find(&song).await }
+    });
+    let found = futures::future::join_all(found).await;
+    for (r, songs) in recognized.iter_mut().zip(found) {
+        info!(
+            "recognized {} on {} providers",
+            redact(&r.title),
+            songs.len()
+        );
+        r.songs = songs;
+    }
+    Ok(Json(recognized))
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use bragi_core::settings::RecognizeSettings;
+
+    use super::{parse, AcoustIdResponse, Recognizer, SpawnFailed};
+
+    #[test]
+    fn test_parse() {
+        let resp: AcoustIdResponse = serde_json::from_str(
+            r#"{"status": "ok", "results": [
+                {"id": "a", "score": 0.62, "recordings": [{"id": "live", "title": "Plastic Love (live)",
+                    "duration": 611.2, "artists": [{"id": "1", "name": "竹内まりや"}]}]},
+                {"id": "b", "score": 0.97, "recordings": [
+                    {"id": "original", "title": "Plastic Love", "duration": 479.6,
+                     "artists": [{"id": "1", "name": "竹内まりや"}]},
+                    {"id": "untitled"},
+                    {"id": "original", "title": "Plastic Love", "duration": 479.6}
+                ]},
+                {"id": "c", "score": 0.5}
+            ]}"#,
+        )
+        .unwrap();
+        let recognized = parse(resp).unwrap();
+        assert_eq!(recognized.len(), 2);
+        assert_eq!(recognized[0].recording, "original");
+        assert_eq!(recognized[0].duration, Some(480));
+        assert_eq!(recognized[0].artists, vec!["竹内まりや".to_string()]);
+        assert_eq!(recognized[1].recording, "live");
+
+        let resp: AcoustIdResponse = serde_json::from_str(
+            r#"{"status": "error", "error": {"code": 4, "message": "invalid API key"}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse(resp).unwrap_err().to_string(),
+            "acoustid lookup failed: invalid API key"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fingerprint() {
+        // stands in for fpcalc, printing the fingerprint of any file
+        let program = std::env::temp_dir().join(format!("bragi-fpcalc-{}", std::process::id()));
+        std::fs::write(
+            &program,
+            "#!/bin/sh\ntest -f \"$2\" || exit 2\necho '{\"duration\": 12.5, \"fingerprint\": \"AQADtEmUaEmS\"}'\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let recognizer = Recognizer::from_setting(&RecognizeSettings {
+            fpcalc: Some(program.to_string_lossy().to_string()),
+            acoustid_key: Some("key".into()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let fingerprint = recognizer.fingerprint(b"ID3").await.unwrap();
+        assert_eq!(fingerprint.duration, 12.5);
+        assert_eq!(fingerprint.fingerprint, "AQADtEmUaEmS");
+
+        std::fs::write(&program, "#!/bin/sh\nexit 1\n").unwrap();
+        assert!(recognizer.fingerprint(b"ID3").await.is_err());
+
+        // killed once over the cap rather than left blocked on a full pipe
+        std::fs::write(&program, "#!/bin/sh\nyes\n").unwrap();
+        let e = recognizer.fingerprint(b"ID3").await.unwrap_err();
+        assert!(e.to_string().starts_with("fpcalc printed more than"));
+
+        std::fs::remove_file(&program).unwrap();
+        let e = recognizer.fingerprint(b"ID3").await.unwrap_err();
+        assert!(e.downcast_ref::<SpawnFailed>().is_some());
+
+        // no clip is left behind
+        let clips = std::fs::read_dir(std::env::temp_dir())
+            .unwrap()
+            .flatten()
+            .filter(|f| f.file_name().to_string_lossy().starts_with("bragi-clip-"))
+            .count();
+        assert_eq!(clips, 0);
+    }
+}
@@ -0,0 +1,613 @@
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    io::{self, BufWriter, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use actix_web::{
+    body::{to_bytes, BoxBody, MessageBody},
+    dev::{Service, ServiceRequest, ServiceResponse},
+    http::header::{ContentType, CONTENT_TYPE},
+    web::Bytes,
+    Error, HttpResponse,
+};
+use bragi_core::settings::JsonCase;
+use serde::{
+    ser::{Error as _, SerializeMap, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use serde_json::{
+    ser::{CharEscape, CompactFormatter, Formatter},
+    Map, Value,
+};
+use tokio::sync::mpsc;
+use tracing::error;
+
+/// Size of every chunk written to the response body
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// Number of chunks buffered between the serializer and the client
+const CHUNK_BUFFER: usize = 4;
+
+static CAMEL_CASE: AtomicBool = AtomicBool::new(false);
+
+/// Case of the fields of streamed responses from now on, for the whole process. The renaming
+/// middleware leaves streamed bodies as they are, so they are renamed while serialized.
+pub fn set_json_case(case: JsonCase) {
+    CAMEL_CASE.store(case == JsonCase::Camel, Ordering::Relaxed);
+}
+
+fn json_case() -> JsonCase {
+    match CAMEL_CASE.load(Ordering::Relaxed) {
+        true => JsonCase::Camel,
+        false => JsonCase::Snake,
+    }
+}
+
+/// Compact json with the object keys in camelCase, renamed as they are written
+#[derive(Default)]
+struct CamelCaseFormatter {
+    in_key: bool,
+    /// the key being written, renamed once complete
+    key: Option<String>,
+}
+
+impl Formatter for CamelCaseFormatter {
+    fn begin_object_key<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        first: bool,
+    ) -> io::Result<()> {
+        self.in_key = true;
+        CompactFormatter.begin_object_key(writer, first)
+    }
+
+    fn end_object_key<W: ?Sized + Write>(&mut self, _writer: &mut W) -> io::Result<()> {
+        self.in_key = false;
+        Ok(())
+    }
+
+    fn begin_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if self.in_key {
+            self.key = Some(String::new());
+        }
+        writer.write_all(b"\"")
+    }
+
+    fn end_string<W: ?Sized + Write>(&mut self, writer: &mut W) -> io::Result<()> {
+        if let Some(key) = self.key.take() {
+            writer.write_all(to_camel_case(&key).as_bytes())?;
+        }
+        writer.write_all(b"\"")
+    }
+
+    fn write_string_fragment<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        fragment: &str,
+    ) -> io::Result<()> {
+        match &mut self.key {
+            Some(key) => {
+                key.push_str(fragment);
+                Ok(())
+            }
+            None => writer.write_all(fragment.as_bytes()),
+        }
+    }
+
+    /// Keys with escaped characters are no field names, and are written as they are
+    fn write_char_escape<W: ?Sized + Write>(
+        &mut self,
+        writer: &mut W,
+        char_escape: CharEscape,
+    ) -> io::Result<()> {
+        if let Some(key) = self.key.take() {
+            writer.write_all(key.as_bytes())?;
+        }
+        CompactFormatter.write_char_escape(writer, char_escape)
+    }
+}
+
+/// Serialize the value with its fields in the case
+fn write_json<W: Write, T: Serialize>(
+    writer: W,
+    value: &T,
+    case: JsonCase,
+) -> serde_json::Result<()> {
+    match case {
+        JsonCase::Snake => serde_json::to_writer(writer, value),
+        JsonCase::Camel => value.serialize(&mut serde_json::Serializer::with_formatter(
+            writer,
+            CamelCaseFormatter::default(),
+        )),
+    }
+}
+
+/// Forward everything written to it into the response body channel.
+/// Writes block while the client is slow, which keeps at most `CHUNK_BUFFER` chunks in memory.
+struct ChannelWriter {
+    tx: mpsc::Sender<Bytes>,
+}
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.tx
+            .blocking_send(Bytes::copy_from_slice(buf))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Marks the responses of `streaming_json`, whose bodies middlewares pass on as they are
+#[derive(Debug, Clone, Copy)]
+pub struct Streamed;
+
+/// Whether the body is serialized incrementally, which buffering it would defeat
+pub fn is_streamed<B>(resp: &ServiceResponse<B>) -> bool {
+    resp.response().extensions().contains::<Streamed>()
+}
+
+/// Serialize the value incrementally into a chunked response body instead of buffering the whole
+/// JSON document in memory.
+pub fn streaming_json<T>(value: T) -> HttpResponse
+where
+    T: Serialize + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel(CHUNK_BUFFER);
+    let case = json_case();
+
+    tokio::task::spawn_blocking(move || {
+        let mut writer = BufWriter::with_capacity(CHUNK_SIZE, ChannelWriter { tx });
+        if let Err(e) = write_json(&mut writer, &value, case)
+            .map_err(io::Error::from)
+            .and_then(|_| writer.flush())
+        {
+            error!("streaming json serialization failed: {}", e);
+        }
+    });
+
+    let mut resp = HttpResponse::Ok()
+        .content_type(ContentType::json())
+        .streaming(futures::stream::unfold(rx, |mut rx| async move {
+            rx.recv().await.map(|chunk| (Ok::<_, io::Error>(chunk), rx))
+        }));
+    resp.extensions_mut().insert(Streamed);
+    resp
+}
+
+/// Respond with JSON, serialized incrementally if `streaming` is set
+pub fn json<T>(value: T, streaming: bool) -> HttpResponse
+where
+    T: Serialize + Send + 'static,
+{
+    match streaming {
+        true => streaming_json(value),
+        false => HttpResponse::Ok().json(value),
+    }
+}
+
+/// `max_bitrate` to `maxBitrate`
+pub fn to_camel_case(name: &str) -> String {
+    let mut parts = name.split('_').filter(|p| !p.is_empty());
+    let first = parts.next().unwrap_or_default().to_string();
+    parts.fold(first, |mut camel, part| {
+        let mut chars = part.chars();
+        camel.extend(chars.next().map(|c| c.to_ascii_uppercase()));
+        camel.push_str(chars.as_str());
+        camel
+    })
+}
+
+/// `maxBitrate` to `max_bitrate`
+pub fn to_snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for c in name.chars() {
+        if c.is_ascii_uppercase() {
+            if !snake.is_empty() {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Rename the fields of every object in the value to camelCase
+pub fn camel_case(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            *map = std::mem::take(map)
+                .into_iter()
+                .map(|(k, mut v)| {
+                    camel_case(&mut v);
+                    (to_camel_case(&k), v)
+                })
+                .collect::<Map<_, _>>();
+        }
+        Value::Array(arr) => arr.iter_mut().for_each(camel_case),
+        _ => {}
+    }
+}
+
+/// Middleware renaming the fields of json responses to the configured case. Responses are
+/// buffered whole to be renamed. Streamed responses are passed on, they are serialized in the
+/// case of `set_json_case` already.
+pub fn rename_fields<S, B>(
+    case: JsonCase,
+    req: ServiceRequest,
+    srv: &S,
+) -> impl Future<Output = Result<ServiceResponse<BoxBody>, Error>> + 'static
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: MessageBody + 'static,
+{
+    let call = srv.call(req);
+    async move {
+        let resp = call.await?;
+        if case == JsonCase::Snake || !is_json(&resp) || is_streamed(&resp) {
+            return Ok(resp.map_into_boxed_body());
+        }
+
+        map_json_body(resp, camel_case).await
+    }
+}
+
+/// Whether the response is of json content
+pub fn is_json<B>(resp: &ServiceResponse<B>) -> bool {
+    resp.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+/// Buffer the whole body of the response and replace it by `f` of it
+pub async fn map_body<B>(
+    resp: ServiceResponse<B>,
+    f: impl FnOnce(Bytes) -> Bytes,
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    let (req, resp) = resp.into_parts();
+    let (resp, body) = resp.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|e| actix_web::error::ErrorInternalServerError(e.into()))?;
+    Ok(ServiceResponse::new(
+        req,
+        resp.set_body(BoxBody::new(f(body))),
+    ))
+}
+
+/// Buffer the json body of the response and change it by `f`. Invalid json is kept as it is.
+pub async fn map_json_body<B>(
+    resp: ServiceResponse<B>,
+    f: impl FnOnce(&mut Value),
+) -> Result<ServiceResponse<BoxBody>, Error>
+where
+    B: MessageBody + 'static,
+{
+    map_body(resp, |body| match serde_json::from_slice::<Value>(&body) {
+        Ok(mut value) => {
+            f(&mut value);
+            serde_json::to_vec(&value).map_or(body, Bytes::from)
+        }
+        Err(e) => {
+            error!("change of an invalid json response: {}", e);
+            body
+        }
+    })
+    .await
+}
+
+/// Sparse fieldset parsed from a query like `fields=id,name,artists.name`.
+/// Nested fields are separated by '.' and arrays are traversed transparently.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FieldSet {
+    /// the whole subtree is selected
+    all: bool,
+    children: BTreeMap<String, FieldSet>,
+}
+
+impl FieldSet {
+    pub fn parse(s: &str) -> Self {
+        let mut set = Self::default();
+        for path in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            // fields are named in camelCase as well if responses are
+            let node = path.split('.').fold(&mut set, |node, seg| {
+                node.children.entry(to_snake_case(seg)).or_default()
+            });
+            node.all = true;
+        }
+        set
+    }
+
+    /// Fields selected of the field, None if it is not selected
+    fn field(&self, name: &str) -> Option<&FieldSet> {
+        static ALL: FieldSet = FieldSet {
+            all: true,
+            children: BTreeMap::new(),
+        };
+        match self.all || self.children.is_empty() {
+            true => Some(&ALL),
+            false => self.children.get(name),
+        }
+    }
+
+    /// Remove every field not selected from the value
+    pub fn select(&self, value: &mut Value) {
+        if self.all || self.children.is_empty() {
+            return;
+        }
+
+        match value {
+            Value::Object(map) => {
+                map.retain(|k, _| self.children.contains_key(k));
+                map.iter_mut().for_each(|(k, v)| self.children[k].select(v));
+            }
+            Value::Array(arr) => arr.iter_mut().for_each(|v| self.select(v)),
+            _ => {}
+        }
+    }
+}
+
+/// A value with only the selected fields, whose list field like the songs of a collection is
+/// selected item by item while serialized. Only one item at a time is held as a `Value`, rather
+/// than a copy of the whole list, so large lists can still be streamed.
+pub struct SelectedList<T> {
+    /// the value without the items, its fields already selected
+    head: Value,
+    name: &'static str,
+    items: Vec<T>,
+    fields: FieldSet,
+}
+
+impl<T: Serialize> SelectedList<T> {
+    pub fn new(mut head: Value, name: &'static str, items: Vec<T>, fields: FieldSet) -> Self {
+        fields.select(&mut head);
+        Self {
+            head,
+            name,
+            items,
+            fields,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for SelectedList<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let Value::Object(head) = &self.head else {
+            return self.head.serialize(serializer);
+        };
+        let mut map = serializer.serialize_map(Some(head.len()))?;
+        for (name, value) in head {
+            match self.fields.field(name).filter(|_| name == self.name) {
+                Some(fields) => map.serialize_entry(
+                    name,
+                    &SelectedItems {
+                        items: &self.items,
+                        fields,
+                    },
+                )?,
+                None => map.serialize_entry(name, value)?,
+            }
+        }
+        map.end()
+    }
+}
+
+struct SelectedItems<'a, T> {
+    items: &'a [T],
+    fields: &'a FieldSet,
+}
+
+impl<T: Serialize> Serialize for SelectedItems<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.items.len()))?;
+        for item in self.items {
+            let mut value = serde_json::to_value(item).map_err(S::Error::custom)?;
+            self.fields.select(&mut value);
+            seq.serialize_element(&value)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for FieldSet {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        Ok(Self::parse(&s))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{
+        test::{call_and_read_body, init_service, TestRequest},
+        web, App, HttpResponse,
+    };
+    use bragi_core::{
+        scraper::{Loudness, Stream},
+        settings::JsonCase,
+    };
+    use serde_json::{json, Value};
+
+    use super::{
+        camel_case, rename_fields, streaming_json, to_camel_case, to_snake_case, write_json,
+        FieldSet, SelectedList,
+    };
+
+    /// Every field name of the value, nested ones included
+    fn keys(value: &Value) -> Vec<String> {
+        match value {
+            Value::Object(map) => map
+                .iter()
+                .flat_map(|(k, v)| std::iter::once(k.clone()).chain(keys(v)))
+                .collect(),
+            Value::Array(arr) => arr.iter().flat_map(keys).collect(),
+            _ => vec![],
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_streaming_json() {
+        let value = (0..10000)
+            .map(|i| format!("song-{}", i))
+            .collect::<Vec<_>>();
+
+        let body = actix_web::body::to_bytes(streaming_json(value.clone()).into_body())
+            .await
+            .unwrap();
+        assert_eq!(body, serde_json::to_vec(&value).unwrap());
+    }
+
+    #[test]
+    fn test_field_set() {
+        let mut value = json!({
+            "id": "1",
+            "name": "playlist",
+            "description": "long description",
+            "artists": [{"id": "2", "name": "artist", "avatar": "https://"}],
+            "songs": [{"id": "3", "name": "song", "artists": [{"id": "2", "name": "artist"}]}],
+        });
+
+        FieldSet::parse("id,name,artists.name,songs").select(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "id": "1",
+                "name": "playlist",
+                "artists": [{"name": "artist"}],
+                "songs": [{"id": "3", "name": "song", "artists": [{"id": "2", "name": "artist"}]}],
+            })
+        );
+    }
+
+    #[test]
+    fn test_selected_list() {
+        let songs = vec![
+            json!({"id": "3", "name": "song", "artists": [{"id": "2", "name": "artist"}]}),
+            json!({"id": "4", "name": "other", "artists": []}),
+        ];
+        let head = json!({"id": "1", "name": "playlist", "description": "long", "songs": []});
+        let mut value = head.clone();
+        value["songs"] = songs.clone().into();
+
+        for fields in ["id,songs.name,songs.artists.name", "id,name", "", "songs"] {
+            let fields = FieldSet::parse(fields);
+            let selected = SelectedList::new(head.clone(), "songs", songs.clone(), fields.clone());
+            let mut expected = value.clone();
+            fields.select(&mut expected);
+            assert_eq!(serde_json::to_value(selected).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_case() {
+        assert_eq!(to_camel_case("max_bitrate"), "maxBitrate");
+        assert_eq!(to_camel_case("id"), "id");
+        assert_eq!(to_snake_case("relaxedMatch"), "relaxed_match");
+        assert_eq!(to_snake_case("true_peak"), "true_peak");
+
+        let stream = Stream {
+            quality: "lossless".into(),
+            url: "https://m701.music.126.net/1.flac".into(),
+            bitrate: Some(900_000),
+            codec: Some("flac".into()),
+            mirror: false,
+            loudness: Some(Loudness {
+                integrated: -9.5,
+                range: Some(6.0),
+                true_peak: Some(-0.3),
+                gain: None,
+            }),
+            preview: false,
+        };
+        let mut value = serde_json::to_value(&stream).unwrap();
+        // declared in snake case, and back to the same names from camel case
+        for key in keys(&value) {
+            assert_eq!(to_snake_case(&key), key);
+            assert_eq!(to_snake_case(&to_camel_case(&key)), key);
+        }
+        camel_case(&mut value);
+        assert_eq!(value["loudness"]["truePeak"], json!(-0.3));
+        assert!(keys(&value).iter().all(|k| !k.contains('_')));
+
+        // renamed the same while serialized, escaped values and keys included
+        let mut written = vec![];
+        write_json(&mut written, &stream, JsonCase::Camel).unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&written).unwrap(), value);
+        let mut written = vec![];
+        let escaped = json!({"top_keywords": {"a\"_b": "c\"_d", "1": [{"play_count": 2}]}});
+        write_json(&mut written, &escaped, JsonCase::Camel).unwrap();
+        assert_eq!(
+            serde_json::from_slice::<Value>(&written).unwrap(),
+            json!({"topKeywords": {"a\"_b": "c\"_d", "1": [{"playCount": 2}]}})
+        );
+
+        let mut value = json!({"top_keywords": [{"keyword": "a_b", "count": 1}]});
+        FieldSet::parse("topKeywords.keyword").select(&mut value);
+        assert_eq!(value, json!({"top_keywords": [{"keyword": "a_b"}]}));
+    }
+
+    #[actix_web::test]
+    async fn test_rename_fields() {
+        for (case, expected) in [
+            (
+                JsonCase::Snake,
+                json!({"max_bitrate": 1, "codec_name": "flac"}),
+            ),
+            (
+                JsonCase::Camel,
+                json!({"maxBitrate": 1, "codecName": "flac"}),
+            ),
+        ] {
+            let app = init_service(
+                App::new()
+                    .wrap_fn(move |req, srv| rename_fields(case, req, srv))
+                    .route(
+                        "/json",
+                        web::get().to(|| async {
+                            HttpResponse::Ok().json(json!({"max_bitrate": 1, "codec_name": "flac"}))
+                        }),
+                    )
+                    .route(
+                        "/text",
+                        web::get().to(|| async { HttpResponse::Ok().body("max_bitrate") }),
+                    )
+                    .route(
+                        "/streamed",
+                        web::get().to(|| async { streaming_json(json!({"max_bitrate": 1})) }),
+                    ),
+            )
+            .await;
+            let body = call_and_read_body(&app, TestRequest::get().uri("/json").to_request()).await;
+            assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), expected);
+            let body = call_and_read_body(&app, TestRequest::get().uri("/text").to_request()).await;
+            assert_eq!(body, "max_bitrate");
+            // left to the serializer, which is in snake case here
+            let body =
+                call_and_read_body(&app, TestRequest::get().uri("/streamed").to_request()).await;
+            assert_eq!(body, r#"{"max_bitrate":1}"#);
+        }
+    }
+
+    #[test]
+    fn test_field_set_empty() {
+        let mut value = json!({"id": "1", "name": "song"});
+
+        FieldSet::parse("").select(&mut value);
+        assert_eq!(value, json!({"id": "1", "name": "song"}));
+    }
+}
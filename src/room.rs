@@ -0,0 +1,494 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::{
+    body::{BodyStream, BoxBody},
+    ws::{self, Codec, Frame, Message},
+};
+use actix_web::{
+    web::{self, Bytes, BytesMut, Json, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use bragi_core::{
+    scraper::{capability::filter_streams, sort::sort_streams, Provider, ScraperManager, Stream},
+    settings::{BitrateOrder, Capabilities, StreamSortSettings},
+};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tracing::{info, warn};
+
+use crate::{device, Context};
+
+/// Room states buffered for members. Slow members skip to the latest state.
+const STATE_BUFFER: usize = 16;
+
+/// Connections dropped without a close frame are only noticed by the missing pongs
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Outgoing messages buffered per member before the socket applies backpressure
+const MESSAGE_BUFFER: usize = 16;
+
+/// Rooms whose host has not joined within this are dropped, like ones created and never used
+const HOST_JOIN_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Rooms open per token at most
+const MAX_ROOMS: usize = 4;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub provider: Provider,
+    pub id: String,
+}
+
+/// Playback state sent by the host of the room
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Playback {
+    pub queue: Vec<QueueItem>,
+    /// index of the playing item in the queue
+    pub index: usize,
+    pub position_ms: u64,
+    pub playing: bool,
+}
+
+/// Playback state broadcast to the members with the streams of the playing item. Members
+/// extrapolate the position from `updated_at` while playing.
+#[derive(Debug, Clone, Serialize)]
+struct RoomState {
+    #[serde(flatten)]
+    playback: Playback,
+    /// unix timestamp in milliseconds
+    updated_at: u128,
+    streams: Vec<Stream>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Listen-together room. The host controls the playback and every member follows it.
+struct Room {
+    host_key: String,
+    /// fingerprint of the token which created the room
+    owner: Option<String>,
+    created_at: Instant,
+    /// set while the host is connected, only one connection controls the room
+    hosted: AtomicBool,
+    /// None once the room is closed, which ends the subscriptions of all members
+    states: Mutex<Option<broadcast::Sender<Arc<RoomState>>>>,
+    /// for members joining later
+    last: Mutex<Option<Arc<RoomState>>>,
+}
+
+impl Room {
+    fn publish(&self, state: RoomState) {
+        let state = Arc::new(state);
+        *self.last.lock() = Some(state.clone());
+        if let Some(states) = &*self.states.lock() {
+            // no receiver is fine: nobody has joined yet
+            let _ = states.send(state);
+        }
+    }
+
+    fn subscribe(&self) -> Option<broadcast::Receiver<Arc<RoomState>>> {
+        self.states
+            .lock()
+            .as_ref()
+            .map(broadcast::Sender::subscribe)
+    }
+
+    fn close(&self) {
+        self.states.lock().take();
+    }
+
+    /// Take the control of the room, false if another connection of the host has it
+    fn claim_host(&self) -> bool {
+        self.hosted
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+
+    /// The host never joined in time. Rooms are closed when their host leaves, so a room without
+    /// a host connection has never had one.
+    fn expired(&self, now: Instant) -> bool {
+        !self.hosted.load(Ordering::SeqCst)
+            && now.saturating_duration_since(self.created_at) > HOST_JOIN_TIMEOUT
+    }
+}
+
+/// Close the rooms whose host never joined
+fn sweep(rooms: &mut HashMap<String, Arc<Room>>, now: Instant) {
+    rooms.retain(|id, room| {
+        let expired = room.expired(now);
+        if expired {
+            info!("host never joined, close room: {}", id);
+            room.close();
+        }
+        !expired
+    });
+}
+
+/// Rooms are kept in memory and closed when their host leaves, or if the host does not join
+/// within `HOST_JOIN_TIMEOUT`
+#[derive(Default)]
+pub struct Rooms {
+    rooms: Mutex<HashMap<String, Arc<Room>>>,
+}
+
+impl Rooms {
+    /// Returns the room id and the key joining as host
+    fn create(&self, owner: Option<String>) -> anyhow::Result<(String, String)> {
+        let mut rooms = self.rooms.lock();
+        sweep(&mut rooms, Instant::now());
+        if rooms.values().filter(|r| r.owner == owner).count() >= MAX_ROOMS {
+            anyhow::bail!("at most {} open rooms per token", MAX_ROOMS);
+        }
+
+        let (states, _) = broadcast::channel(STATE_BUFFER);
+        let room = Arc::new(Room {
+            host_key: random_string(32),
+            owner,
+            created_at: Instant::now(),
+            hosted: AtomicBool::new(false),
+            states: Mutex::new(Some(states)),
+            last: Mutex::new(None),
+        });
+        let mut id = random_string(8);
+        while rooms.contains_key(&id) {
+            id = random_string(8);
+        }
+        rooms.insert(id.clone(), room.clone());
+        Ok((id, room.host_key.clone()))
+    }
+
+    fn get(&self, id: &str) -> Option<Arc<Room>> {
+        self.rooms.lock().get(id).cloned()
+    }
+
+    fn close(&self, id: &str) {
+        if let Some(room) = self.rooms.lock().remove(id) {
+            room.close();
+        }
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .map(char::from)
+        .collect()
+}
+
+fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+#[derive(Debug, Serialize)]
+pub struct CreatedRoom {
+    id: String,
+    /// pass as `host_key` when joining to control the room
+    host_key: String,
+}
+
+pub async fn create_handler(
+    req: HttpRequest,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<Json<CreatedRoom>> {
+    let (id, host_key) = ctx
+        .rooms
+        .create(device::owner(&req))
+        .map_err(actix_web::error::ErrorTooManyRequests)?;
+    info!("[Handler] create room: {}", id);
+    Ok(Json(CreatedRoom { id, host_key }))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct JoinParam {
+    #[serde(alias = "hostKey")]
+    host_key: Option<String>,
+    /// stream preferences of the member, applied to the streams of the playing item
+    bitrate: Option<BitrateOrder>,
+    #[serde(default, alias = "preferLossless")]
+    prefer_lossless: bool,
+    #[serde(default, alias = "dolbyLast")]
+    dolby_last: bool,
+    /// registered device of the member, whose capabilities apply to the streams
+    device: Option<String>,
+}
+
+/// Join the room over WebSocket. The host sends its playback state as json text messages and
+/// every member, the host included, receives the room state with the resolved streams. One
+/// connection of the host is accepted at a time.
+pub async fn join_handler(
+    req: HttpRequest,
+    payload: web::Payload,
+    id: Path<String>,
+    param: Query<JoinParam>,
+    ctx: web::Data<Context>,
+) -> actix_web::Result<HttpResponse> {
+    let id = id.into_inner();
+    let room = ctx
+        .rooms
+        .get(&id)
+        .ok_or_else(|| actix_web::error::ErrorNotFound(format!("room not found: {}", id)))?;
+    let host = param.host_key.as_deref() == Some(room.host_key.as_str());
+    let capabilities = match &param.device {
+        Some(id) => match ctx.devices.get(device::owner(&req).as_deref(), id) {
+            Some(device) => Some(device.capabilities),
+            None => {
+                return Err(actix_web::error::ErrorBadRequest(format!(
+                    "unknown device: {}",
+                    id
+                )))
+            }
+        },
+        None => None,
+    };
+    info!("[Handler] join room: {}, host: {}", id, host);
+
+    let mut resp = ws::handshake(req.head())?;
+    if host && !room.claim_host() {
+        return Err(actix_web::error::ErrorConflict("room already has a host"));
+    }
+
+    let (tx, rx) = mpsc::channel(MESSAGE_BUFFER);
+    let member = Member {
+        host,
+        sort: StreamSortSettings {
+            bitrate: param.bitrate,
+            prefer_lossless: param.prefer_lossless,
+            dolby_last: param.dolby_last,
+            ..Default::default()
+        },
+        capabilities,
+        tx,
+    };
+    let ctx = ctx.into_inner();
+    actix_web::rt::spawn(async move {
+        member.run(&room, payload, &ctx.manager).await;
+        if host {
+            info!("host left, close room: {}", id);
+            ctx.rooms.close(&id);
+        }
+    });
+
+    let frames = futures::stream::unfold((rx, Codec::new()), |(mut rx, mut codec)| async move {
+        let msg = rx.recv().await?;
+        let mut buf = BytesMut::new();
+        let frame = codec.encode(msg, &mut buf).map(|_| buf.freeze());
+        Some((frame, (rx, codec)))
+    });
+    Ok(resp
+        .message_body(BoxBody::new(BodyStream::new(frames)))?
+        .into())
+}
+
+struct Member {
+    host: bool,
+    sort: StreamSortSettings,
+    capabilities: Option<Capabilities>,
+    tx: mpsc::Sender<Message>,
+}
+
+impl Member {
+    /// Relay the room states to the member and the playback of the host to the room until either
+    /// side closes
+    async fn run(&self, room: &Room, mut payload: web::Payload, manager: &ScraperManager) {
+        let Some(mut states) = room.subscribe() else {
+            let _ = self.tx.send(Message::Close(None)).await;
+            return;
+        };
+        let last = room.last.lock().clone();
+        if let Some(state) = last {
+            if !self.send_state(&state).await {
+                return;
+            }
+        }
+
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut last_seen = Instant::now();
+        loop {
+            tokio::select! {
+                _ = heartbeat.tick() => {
+                    if last_seen.elapsed() > CLIENT_TIMEOUT {
+                        warn!("room member timed out");
+                        return;
+                    }
+                    if self.tx.send(Message::Ping(Bytes::new())).await.is_err() {
+                        return;
+                    }
+                }
+                chunk = payload.next() => {
+                    let Some(Ok(chunk)) = chunk else {
+                        return;
+                    };
+                    last_seen = Instant::now();
+                    buf.extend_from_slice(&chunk);
+                    loop {
+                        match codec.decode(&mut buf) {
+                            Ok(Some(frame)) => {
+                                if !self.handle(frame, room, manager).await {
+                                    return;
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(e) => {
+                                warn!("decode websocket frame failed: {}", e);
+                                return;
+                            }
+                        }
+                    }
+                }
+                state = states.recv() => match state {
+                    Ok(state) => {
+                        if !self.send_state(&state).await {
+                            return;
+                        }
+                    }
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    // the host left
+                    Err(broadcast::error::RecvError::Closed) => {
+                        let _ = self.tx.send(Message::Close(None)).await;
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    /// Returns false if the connection should be closed
+    async fn handle(&self, frame: Frame, room: &Room, manager: &ScraperManager) -> bool {
+        match frame {
+            Frame::Text(text) if self.host => {
+                match serde_json::from_slice::<Playback>(&text) {
+                    Ok(playback) => room.publish(resolve(playback, manager).await),
+                    Err(e) => return self.send_error(format!("invalid playback: {}", e)).await,
+                }
+                true
+            }
+            Frame::Text(_) => {
+                self.send_error("only the host controls the room".into())
+                    .await
+            }
+            Frame::Ping(msg) => self.tx.send(Message::Pong(msg)).await.is_ok(),
+            Frame::Close(reason) => {
+                let _ = self.tx.send(Message::Close(reason)).await;
+                false
+            }
+            _ => true,
+        }
+    }
+
+    async fn send_state(&self, state: &RoomState) -> bool {
+        let mut state = state.clone();
+        if let Some(capabilities) = &self.capabilities {
+            filter_streams(&mut state.streams, capabilities);
+        }
+        sort_streams(&mut state.streams, &self.sort);
+        match serde_json::to_string(&state) {
+            Ok(text) => self.tx.send(Message::Text(text.into())).await.is_ok(),
+            Err(e) => self.send_error(e.to_string()).await,
+        }
+    }
+
+    async fn send_error(&self, error: String) -> bool {
+        let text = serde_json::json!({ "error": error }).to_string();
+        self.tx.send(Message::Text(text.into())).await.is_ok()
+    }
+}
+
+/// Resolve the streams of the playing item once for all members
+async fn resolve(playback: Playback, manager: &ScraperManager) -> RoomState {
+    let (streams, error) = match playback.queue.get(playback.index) {
+        Some(item) => match manager.stream(item.id.clone(), item.provider.clone()).await {
+            Ok(streams) => (streams, None),
+            Err(e) => (vec![], Some(e.to_string())),
+        },
+        None => (vec![], None),
+    };
+    RoomState {
+        playback,
+        updated_at: now_ms(),
+        streams,
+        error,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+
+    use bragi_core::scraper::ScraperManager;
+
+    use super::{resolve, sweep, Playback, QueueItem, Rooms, HOST_JOIN_TIMEOUT, MAX_ROOMS};
+
+    #[tokio::test]
+    async fn test_rooms() {
+        let rooms = Rooms::default();
+        let (id, host_key) = rooms.create(None).unwrap();
+        assert_eq!(rooms.get(&id).unwrap().host_key, host_key);
+
+        let room = rooms.get(&id).unwrap();
+        let mut states = room.subscribe().unwrap();
+        let playback = Playback {
+            queue: vec![QueueItem {
+                provider: bragi_core::Provider::NetEase,
+                id: "1901371647".into(),
+            }],
+            index: 0,
+            position_ms: 1000,
+            playing: true,
+        };
+        room.publish(resolve(playback, &ScraperManager::default()).await);
+
+        let state = states.recv().await.unwrap();
+        assert!(state.streams.is_empty());
+        assert!(state.error.is_some());
+        assert_eq!(
+            room.last.lock().as_ref().unwrap().playback.position_ms,
+            1000
+        );
+
+        rooms.close(&id);
+        assert!(rooms.get(&id).is_none());
+        assert!(room.subscribe().is_none());
+        assert!(states.recv().await.is_err());
+    }
+
+    #[test]
+    fn test_expire() {
+        let rooms = Rooms::default();
+        let owner = Some("token".to_string());
+        let ids = (0..MAX_ROOMS)
+            .map(|_| rooms.create(owner.clone()).unwrap().0)
+            .collect::<Vec<_>>();
+        assert!(rooms.create(owner.clone()).is_err());
+        // other tokens have rooms of their own
+        assert!(rooms.create(None).is_ok());
+
+        let hosted = rooms.get(&ids[0]).unwrap();
+        assert!(hosted.claim_host());
+        // a second connection of the host
+        assert!(!hosted.claim_host());
+
+        let later = Instant::now() + HOST_JOIN_TIMEOUT * 2;
+        sweep(&mut rooms.rooms.lock(), later);
+        assert!(rooms.get(&ids[0]).is_some());
+        assert!(rooms.get(&ids[1]).is_none());
+        assert_eq!(rooms.rooms.lock().len(), 1);
+    }
+}
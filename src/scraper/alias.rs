@@ -0,0 +1,255 @@
+use std::collections::{BTreeSet, HashMap};
+
+use anyhow::anyhow;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing::{info, warn};
+
+use crate::{settings::ApplicationSettings, util};
+
+use super::{
+    keyword::{to_halfwidth, to_romaji},
+    ScrapeItem,
+};
+
+/// Names of the same artist across scripts and providers, like 米津玄師 / Kenshi Yonezu /
+/// よねづけんし. Seeded from MusicBrainz artist aliases and extended by learned matches, persisted
+/// as json if `filename` is present.
+#[derive(Debug, Default)]
+pub struct ArtistAliases {
+    filename: Option<String>,
+    table: RwLock<AliasTable>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct AliasTable {
+    /// groups of names, emptied when merged into another one
+    groups: Vec<BTreeSet<String>>,
+    /// normalized name to its group
+    index: HashMap<String, usize>,
+}
+
+/// Saved as the groups only, the index is built again when loaded
+impl Serialize for AliasTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.groups.iter().filter(|g| !g.is_empty()))
+    }
+}
+
+impl AliasTable {
+    /// Put the names into one group, merging the groups of any of them. Returns true if
+    /// anything changed.
+    fn merge(&mut self, names: impl IntoIterator<Item = String>) -> bool {
+        let names = names
+            .into_iter()
+            .filter(|n| !normalize(n).is_empty())
+            .collect::<Vec<_>>();
+        if names.is_empty() {
+            return false;
+        }
+        let mut target = None;
+        for name in &names {
+            if let Some(&group) = self.index.get(&normalize(name)) {
+                target = Some(target.map_or(group, |t: usize| t.min(group)));
+            }
+        }
+        let target = target.unwrap_or_else(|| {
+            self.groups.push(BTreeSet::new());
+            self.groups.len() - 1
+        });
+
+        let mut changed = false;
+        for name in names {
+            let key = normalize(&name);
+            match self.index.get(&key) {
+                Some(&group) if group == target => {}
+                Some(&group) => {
+                    for moved in std::mem::take(&mut self.groups[group]) {
+                        self.index.insert(normalize(&moved), target);
+                        self.groups[target].insert(moved);
+                    }
+                    changed = true;
+                }
+                None => {
+                    self.index.insert(key, target);
+                    self.groups[target].insert(name);
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// One artist of the MusicBrainz json dump or `/ws/2/artist?inc=aliases&fmt=json`
+#[derive(Deserialize)]
+struct MusicBrainzArtist {
+    name: String,
+    #[serde(default)]
+    aliases: Vec<MusicBrainzAlias>,
+}
+
+#[derive(Deserialize)]
+struct MusicBrainzAlias {
+    name: String,
+}
+
+/// Compared ignoring case, width, spacing and kana versus romaji
+fn normalize(name: &str) -> String {
+    to_romaji(&to_halfwidth(name))
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+impl ArtistAliases {
+    pub fn try_new(filename: Option<String>) -> anyhow::Result<Self> {
+        let Some(filename) = filename else {
+            return Ok(Self::default());
+        };
+
+        let groups: Vec<BTreeSet<String>> = util::load_json(&filename)?;
+        let mut table = AliasTable::default();
+        for group in groups {
+            table.merge(group);
+        }
+        info!(
+            "load artist aliases from {}: {} names",
+            filename,
+            table.index.len()
+        );
+
+        Ok(Self {
+            filename: Some(filename),
+            table: RwLock::new(table),
+        })
+    }
+
+    /// The table of `aliases_path`, seeded from `musicbrainz_aliases` if present
+    pub fn try_from_setting(setting: &ApplicationSettings) -> anyhow::Result<Self> {
+        let aliases = Self::try_new(setting.aliases_path.clone())?;
+        if let Some(path) = &setting.musicbrainz_aliases {
+            aliases.seed_musicbrainz(
+                &std::fs::read_to_string(path)
+                    .map_err(|e| anyhow!("read musicbrainz aliases {}: {}", path, e))?,
+            );
+        }
+        Ok(aliases)
+    }
+
+    /// Merge the aliases of a MusicBrainz artist dump, one artist json per line. Lines which are
+    /// not artists are skipped.
+    pub fn seed_musicbrainz(&self, content: &str) {
+        let mut seeded = 0;
+        // a failed save is logged, the seed is merged again on the next start
+        let _ = self.update(|table| {
+            for line in content.lines().filter(|l| !l.trim().is_empty()) {
+                match serde_json::from_str::<MusicBrainzArtist>(line) {
+                    Ok(artist) => {
+                        let names = Some(artist.name)
+                            .into_iter()
+                            .chain(artist.aliases.into_iter().map(|a| a.name));
+                        seeded += table.merge(names) as usize;
+                    }
+                    Err(e) => warn!("skip musicbrainz artist: {}", e),
+                }
+            }
+            seeded > 0
+        });
+        info!("seed artist aliases from musicbrainz: {} artists", seeded);
+    }
+
+    /// Remember the names as the same artist, e.g. a match confirmed by a user
+    pub fn learn(&self, names: Vec<String>) {
+        let _ = self.update(|table| table.merge(names));
+    }
+
+    /// Change the table by `f`, saved if it returns true
+    fn update(&self, f: impl FnOnce(&mut AliasTable) -> bool) -> anyhow::Result<bool> {
+        util::update_json(self.filename.as_deref(), &mut *self.table.write(), f)
+    }
+
+    /// All known names of the artist, the name itself included
+    pub fn names(&self, name: &str) -> Vec<String> {
+        let table = self.table.read();
+        match table.index.get(&normalize(name)) {
+            Some(&group) => table.groups[group].iter().cloned().collect(),
+            None => vec![name.to_string()],
+        }
+    }
+
+    /// Other names of the artist, without those normalized to the same
+    pub fn aliases(&self, name: &str) -> Vec<String> {
+        let key = normalize(name);
+        self.names(name)
+            .into_iter()
+            .filter(|n| normalize(n) != key)
+            .collect()
+    }
+
+    pub fn same_artist(&self, a: &str, b: &str) -> bool {
+        let (a, b) = (normalize(a), normalize(b));
+        if a == b {
+            return true;
+        }
+        let table = self.table.read();
+        matches!((table.index.get(&a), table.index.get(&b)), (Some(x), Some(y)) if x == y)
+    }
+
+    /// The item is by the artist, or is the artist
+    pub fn matches(&self, artist: &str, item: &ScrapeItem) -> bool {
+        let artists = match item {
+            ScrapeItem::Artist(a) => std::slice::from_ref(a),
+            ScrapeItem::Song(s) => &s.artists,
+            ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => &c.artists,
+            ScrapeItem::Radio(_) => &[],
+        };
+        artists.iter().any(|a| self.same_artist(artist, &a.name))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::ArtistAliases;
+
+    #[test]
+    fn test_aliases() {
+        let aliases = ArtistAliases::default();
+        aliases.seed_musicbrainz(concat!(
+            r#"{"name":"米津玄師","aliases":[{"name":"Kenshi Yonezu"},{"name":"ハチ"}]}"#,
+            "\n",
+            "not json\n",
+            r#"{"name":"YOASOBI","aliases":[]}"#,
+        ));
+
+        assert!(aliases.same_artist("kenshi  yonezu", "米津玄師"));
+        assert!(aliases.same_artist("ＹＯＡＳＯＢＩ", "yoasobi"));
+        assert!(!aliases.same_artist("YOASOBI", "米津玄師"));
+        assert_eq!(aliases.aliases("米津玄師"), vec!["Kenshi Yonezu", "ハチ"]);
+        assert!(aliases.aliases("unknown").is_empty());
+
+        // kana and their romanization are the same name
+        assert!(aliases.same_artist("ヨアソビ", "yoasobi"));
+        assert!(aliases.same_artist("hachi", "米津玄師"));
+
+        // a learned match, like a name order missing in musicbrainz
+        aliases.learn(vec!["Yonezu Kenshi".into(), "kenshi yonezu".into()]);
+        assert!(aliases.same_artist("yonezu kenshi", "ハチ"));
+    }
+
+    #[test]
+    fn test_persist() {
+        let filename = std::env::temp_dir()
+            .join(format!("bragi-aliases-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        let aliases = ArtistAliases::try_new(Some(filename.clone())).unwrap();
+        aliases.learn(vec!["周杰倫".into(), "Jay Chou".into()]);
+
+        let aliases = ArtistAliases::try_new(Some(filename.clone())).unwrap();
+        assert!(aliases.same_artist("jay chou", "周杰倫"));
+        std::fs::remove_file(filename).unwrap();
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::{privacy::redact, settings::AnalyticsSettings};
+
+use super::{event::EventHandler, FanOut, Provider, ScrapeItem, ScrapeType};
+
+/// Aggregated counters of the searches, for operators deciding which providers to enable. Only
+/// normalized keywords and counts are kept, nothing about the clients. Every page counts as a
+/// search.
+#[derive(Debug)]
+pub struct SearchAnalytics {
+    setting: AnalyticsSettings,
+    counters: Mutex<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    searches: u64,
+    keywords: HashMap<String, u64>,
+    zero_result: HashMap<String, u64>,
+    providers: HashMap<Provider, ProviderCounters>,
+}
+
+#[derive(Debug, Default)]
+struct ProviderCounters {
+    searches: u64,
+    items: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeywordCount {
+    pub keyword: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProviderContribution {
+    /// searches with at least one result of the provider
+    pub searches: u64,
+    pub items: u64,
+    /// share of all searches the provider contributed to
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AnalyticsReport {
+    pub searches: u64,
+    pub top_keywords: Vec<KeywordCount>,
+    pub zero_result: Vec<KeywordCount>,
+    pub providers: HashMap<Provider, ProviderContribution>,
+}
+
+impl SearchAnalytics {
+    pub fn new(setting: AnalyticsSettings) -> Self {
+        Self {
+            setting,
+            counters: Default::default(),
+        }
+    }
+
+    pub fn from_setting(setting: &AnalyticsSettings) -> Option<Self> {
+        setting.enabled.then(|| Self::new(setting.clone()))
+    }
+
+    /// Count the keyword unless the distinct keywords are at `max_keywords` already
+    fn count(&self, counts: &mut HashMap<String, u64>, keyword: &str) {
+        let full = counts.len() >= self.setting.max_keywords;
+        match counts.get_mut(keyword) {
+            Some(count) => *count += 1,
+            None if !full => {
+                counts.insert(keyword.to_string(), 1);
+            }
+            None => {}
+        }
+    }
+
+    /// Most searched keywords first, leaving out those below `min_count`
+    fn top(&self, counts: &HashMap<String, u64>) -> Vec<KeywordCount> {
+        let mut top = counts
+            .iter()
+            .filter(|(_, count)| **count >= self.setting.min_count)
+            .map(|(keyword, count)| KeywordCount {
+                keyword: keyword.clone(),
+                count: *count,
+            })
+            .collect::<Vec<_>>();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then(a.keyword.cmp(&b.keyword)));
+        top.truncate(self.setting.top);
+        top
+    }
+
+    pub fn report(&self) -> AnalyticsReport {
+        let counters = self.counters.lock();
+        AnalyticsReport {
+            searches: counters.searches,
+            top_keywords: self.top(&counters.keywords),
+            zero_result: self.top(&counters.zero_result),
+            providers: counters
+                .providers
+                .iter()
+                .map(|(p, c)| {
+                    let contribution = ProviderContribution {
+                        searches: c.searches,
+                        items: c.items,
+                        rate: c.searches as f64 / counters.searches.max(1) as f64,
+                    };
+                    (p.clone(), contribution)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl EventHandler for Arc<SearchAnalytics> {
+    fn on_search(&self, keyword: &str, _t: &ScrapeType, results: &FanOut<ScrapeItem>) {
+        let keyword = keyword.split_whitespace().collect::<Vec<_>>().join(" ");
+        let keyword = redact(&keyword.to_lowercase()).into_owned();
+
+        let mut counters = self.counters.lock();
+        counters.searches += 1;
+        self.count(&mut counters.keywords, &keyword);
+        if results.items.is_empty() {
+            self.count(&mut counters.zero_result, &keyword);
+        }
+
+        let mut contributed = HashSet::new();
+        for item in &results.items {
+            let provider = counters
+                .providers
+                .entry(item.provider().clone())
+                .or_default();
+            provider.items += 1;
+            if contributed.insert(item.provider().clone()) {
+                provider.searches += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use crate::{
+        scraper::{event::EventHandler, Artist, FanOut, Provider, ScrapeItem, ScrapeType},
+        settings::AnalyticsSettings,
+        WithProvider,
+    };
+
+    use super::SearchAnalytics;
+
+    fn results(providers: &[Provider]) -> FanOut<ScrapeItem> {
+        FanOut {
+            items: providers
+                .iter()
+                .map(|p| {
+                    let artist = Artist {
+                        id: "1".into(),
+                        name: "YOASOBI".into(),
+                        description: None,
+                        avatar: None,
+                    };
+                    WithProvider::new(p.clone(), ScrapeItem::Artist(artist))
+                })
+                .collect(),
+            throttled: vec![],
+            next: None,
+            providers: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_report() {
+        let analytics = Arc::new(SearchAnalytics::new(AnalyticsSettings {
+            enabled: true,
+            top: 2,
+            min_count: 2,
+            max_keywords: 2,
+        }));
+        let t = ScrapeType::All;
+        for keyword in ["YOASOBI", "yoasobi ", "yoasobi", "ado", "ado"] {
+            let found = results(&[Provider::Bilibili, Provider::Bilibili, Provider::NetEase]);
+            analytics.on_search(keyword, &t, &found);
+        }
+        analytics.on_search("private query", &t, &results(&[]));
+        analytics.on_search("zutomayo", &t, &results(&[]));
+        analytics.on_search("zutomayo", &t, &results(&[]));
+
+        let report = analytics.report();
+        assert_eq!(report.searches, 8);
+        // zutomayo is beyond max_keywords
+        let top = report
+            .top_keywords
+            .iter()
+            .map(|k| (k.keyword.as_str(), k.count))
+            .collect::<Vec<_>>();
+        assert_eq!(top, [("yoasobi", 3), ("ado", 2)]);
+        // the private query is below min_count
+        assert_eq!(report.zero_result.len(), 1);
+        assert_eq!(report.zero_result[0].keyword, "zutomayo");
+
+        let bili = &report.providers[&Provider::Bilibili];
+        assert_eq!(bili.searches, 5);
+        assert_eq!(bili.items, 10);
+        assert_eq!(bili.rate, 5.0 / 8.0);
+    }
+}
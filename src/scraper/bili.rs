@@ -2,47 +2,59 @@ use std::{
     io::Write,
     ops::Sub,
     sync::Arc,
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use anyhow::bail;
+use anyhow::{anyhow, bail};
 use async_trait::async_trait;
 use chrono::Timelike;
-use lazy_static::lazy_static;
-use parking_lot::RwLock;
-use serde::{de::IgnoredAny, Deserialize, Deserializer};
-use tracing::{error, info};
+use parking_lot::{Mutex, RwLock};
+use reqwest::{cookie::CookieStore, StatusCode};
+use serde::{
+    de::{DeserializeOwned, IgnoredAny},
+    Deserialize, Deserializer,
+};
+use tracing::{info, warn};
 
 use crate::{
-    settings::BiliSettings,
-    util::{self, cookie::PersistCookieStore},
+    privacy::redact,
+    settings::{BiliSettings, IpFamily},
+    util::{self, body::LimitedBody, cookie::PersistCookieStore, text::deserialize_text},
 };
 
-use super::{Artist, ScrapeItem, ScrapeType, Scraper, Song, SongCollection, Stream};
+use super::{
+    chapter::{self, Chapter},
+    explain::StreamTrace,
+    health::{Health, RateLimited},
+    id::{ArtistId, BiliTrackId, CollectionId, TrackId},
+    query::SearchFilter,
+    tracklist,
+    unavailable::Unavailable,
+    Artist, ArtistDetail, Loudness, ScrapeItem, ScrapeType, Scraper, SearchPage, Song,
+    SongCollection, Stream,
+};
 
 const DEFAULT_UA: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/98.0.4758.102 Safari/537.36 Edg/98.0.1108.62";
 
+const PROBE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Largest page size of the typed search
+const MAX_PAGE_SIZE: usize = 50;
+
+/// Codes of risk control: -352 for the request signature or the device, -412 for the request
+/// rate. The same -412 may come as http status instead.
+const RISK_CONTROL_CODES: [i32; 2] = [-352, -412];
+/// Bilibili keeps blocking a flagged client for minutes, retrying earlier extends the block
+const RISK_COOLDOWN: Duration = Duration::from_secs(600);
+/// Least interval between requests during the cooldown
+const RISK_COOLDOWN_INTERVAL: Duration = Duration::from_secs(2);
+
 const MIXIN_KEY_ENC_TAB: [usize; 64] = [
     46, 47, 18, 2, 53, 8, 23, 32, 15, 50, 10, 31, 58, 3, 45, 35, 27, 43, 5, 49, 33, 9, 42, 19, 29,
     28, 14, 39, 12, 38, 41, 13, 37, 48, 7, 16, 24, 55, 40, 61, 26, 17, 0, 1, 60, 51, 30, 4, 22, 25,
     54, 21, 56, 59, 6, 63, 57, 62, 11, 36, 20, 34, 44, 52,
 ];
 
-lazy_static! {
-    static ref TITLE_REPLACER: regex::Regex =
-        regex::RegexBuilder::new(r#"(<([^>]+)>)"#).build().unwrap();
-}
-
-/// origin title format may be like: 【永雏塔菲】<em class=\"keyword\">taffy</em>已经开摆了
-/// therefore, remove <em> tags to get pure title
-fn deserialize_title<'de, D>(deserializer: D) -> Result<String, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let s: String = Deserialize::deserialize(deserializer)?;
-    Result::Ok(TITLE_REPLACER.replace_all(s.as_str(), "").into())
-}
-
 /// origin cover url may be like: //i0.hdslb.com/bfs/archive/23c4be1b7f62848b95e9b4b2e1d6ce2e50bedf17.jpg
 /// therefore, add 'https:' scheme
 /// Or if the url star with http, replace it with https
@@ -82,20 +94,74 @@ where
 struct BiliResponse<T> {
     code: i32,
     message: Option<String>,
+    /// absent or of another shape if the code is not 0
     #[serde(alias = "result")]
-    data: T,
+    data: Option<T>,
 }
 
 impl<T> BiliResponse<T> {
+    fn check(&self) -> anyhow::Result<()> {
+        let message = self.message.clone().unwrap_or_default();
+        match self.code {
+            0 => Ok(()),
+            // -404: the video has been deleted
+            -404 => Err(Unavailable(message).into()),
+            _ => bail!(
+                "[Bilibili] call request failed: status code: {} resp message: {}",
+                self.code,
+                message
+            ),
+        }
+    }
+
     fn data(self) -> anyhow::Result<T> {
-        if self.code == 0 {
-            return Ok(self.data);
+        self.check()?;
+        self.data
+            .ok_or_else(|| anyhow!("[Bilibili] call request failed: no data in response"))
+    }
+}
+
+#[derive(Deserialize)]
+struct Buvid {
+    b_3: String,
+    b_4: String,
+}
+
+/// Risk control state, shared by all requests of the scraper
+#[derive(Debug, Default)]
+struct RiskControl {
+    /// end of the cooldown and the code which started it
+    cooldown: Mutex<Option<(Instant, i32)>>,
+    /// time of the last request during the cooldown, locked while waiting for the next slot
+    last_request: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl RiskControl {
+    fn active(&self) -> Option<(Duration, i32)> {
+        let cooldown = *self.cooldown.lock();
+        cooldown.and_then(|(until, code)| {
+            let remaining = until.checked_duration_since(Instant::now())?;
+            Some((remaining, code))
+        })
+    }
+
+    /// Start or extend the cooldown. Returns true if there was none, i.e. a new detection.
+    fn trigger(&self, code: i32) -> bool {
+        let detected = self.active().is_none();
+        *self.cooldown.lock() = Some((Instant::now() + RISK_COOLDOWN, code));
+        detected
+    }
+
+    /// Wait for the next request slot while cooling down
+    async fn pace(&self) {
+        if self.active().is_none() {
+            return;
         }
-        bail!(
-            "[Bilibili] call request failed: status code: {} resp message: {}",
-            self.code,
-            self.message.unwrap_or_default()
-        );
+        let mut last = self.last_request.lock().await;
+        if let Some(last) = *last {
+            tokio::time::sleep_until((last + RISK_COOLDOWN_INTERVAL).into()).await;
+        }
+        *last = Some(Instant::now());
     }
 }
 
@@ -123,11 +189,56 @@ struct Suggestion {
 #[derive(Debug, Deserialize)]
 struct ComprehensiveSearch {
     result: Vec<SearchItem>,
+    #[serde(rename = "numPages", default)]
+    num_pages: u32,
 }
 
 #[derive(Debug, Deserialize)]
 struct TypedSearch {
+    #[serde(default)]
     result: Vec<TypedSearchItem>,
+    #[serde(rename = "numPages", default)]
+    num_pages: u32,
+}
+
+/// Videos uploaded by a user, newest first
+#[derive(Debug, Deserialize)]
+struct BiliUploads {
+    list: BiliUploadList,
+}
+
+#[derive(Debug, Deserialize)]
+struct BiliUploadList {
+    #[serde(default)]
+    vlist: Vec<BiliVideo>,
+}
+
+/// Space profile of a user
+#[derive(Debug, Deserialize)]
+struct BiliSpaceInfo {
+    mid: u64,
+    #[serde(deserialize_with = "deserialize_text")]
+    name: String,
+    #[serde(deserialize_with = "deserialize_cover_url")]
+    face: String,
+    #[serde(deserialize_with = "deserialize_text")]
+    sign: String,
+}
+
+impl From<BiliSpaceInfo> for Artist {
+    fn from(val: BiliSpaceInfo) -> Self {
+        Self {
+            id: val.mid.to_string().into(),
+            name: val.name,
+            description: Some(val.sign),
+            avatar: Some(val.face),
+        }
+    }
+}
+
+/// continuation of bilibili search is the next page number
+fn next_page(page: u32, num_pages: u32) -> Option<String> {
+    (page < num_pages).then(|| (page + 1).to_string())
 }
 
 #[derive(Debug, Deserialize)]
@@ -156,16 +267,16 @@ struct BiliUser {
     author_id: u64,
     #[serde(deserialize_with = "deserialize_cover_url")]
     upic: String,
-    #[serde(rename = "uname")]
+    #[serde(rename = "uname", deserialize_with = "deserialize_text")]
     name: String,
-    #[serde(rename = "usign")]
+    #[serde(rename = "usign", deserialize_with = "deserialize_text")]
     description: String,
 }
 
 impl From<BiliUser> for Artist {
     fn from(val: BiliUser) -> Self {
         Self {
-            id: val.author_id.to_string(),
+            id: val.author_id.to_string().into(),
             name: val.name,
             description: Some(val.description),
             avatar: Some(val.upic),
@@ -177,13 +288,15 @@ impl From<BiliUser> for Artist {
 struct BiliVideo {
     #[serde(rename = "bvid")]
     id: String,
+    #[serde(deserialize_with = "deserialize_text")]
     author: String,
     #[serde(rename = "mid")]
     author_id: u64,
-    #[serde(deserialize_with = "deserialize_title")]
+    #[serde(deserialize_with = "deserialize_text")]
     title: String,
     #[serde(deserialize_with = "deserialize_cover_url")]
     pic: String,
+    #[serde(deserialize_with = "deserialize_text")]
     description: String,
 }
 
@@ -192,10 +305,10 @@ struct BiliVideo {
 impl From<BiliVideo> for SongCollection {
     fn from(val: BiliVideo) -> Self {
         Self {
-            id: val.id,
+            id: val.id.into(),
             name: val.title,
             artists: vec![Artist {
-                id: val.author_id.to_string(),
+                id: val.author_id.to_string().into(),
                 name: val.author,
                 description: None,
                 avatar: None,
@@ -203,6 +316,10 @@ impl From<BiliVideo> for SongCollection {
             cover: Some(val.pic),
             description: Some(val.description),
             songs: vec![],
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
         }
     }
 }
@@ -211,18 +328,43 @@ impl From<BiliVideo> for SongCollection {
 struct BiliVideoDetail {
     #[serde(rename = "bvid")]
     id: String,
+    /// the old numeric id, which replies are still looked up by
+    #[serde(default)]
+    aid: u64,
     #[serde(deserialize_with = "deserialize_cover_url")]
     pic: String,
+    #[serde(deserialize_with = "deserialize_text")]
     title: String,
+    #[serde(deserialize_with = "deserialize_text")]
     desc: String,
     pages: Vec<BiliPagedVideo>,
     owner: BiliOwner,
+    /// negative while under review, locked or not yet published
+    #[serde(default)]
+    state: i32,
+    /// played by the paying supporters of the uploader only
+    #[serde(default)]
+    is_upower_exclusive: bool,
+}
+
+impl BiliVideoDetail {
+    /// Why every page of the video is greyed out. Bilibili lists the remaining pages only, so
+    /// deleted pages are never seen.
+    fn unavailable_reason(&self) -> Option<&'static str> {
+        if self.state < 0 {
+            Some("not public upstream")
+        } else if self.is_upower_exclusive {
+            Some("for supporters of the uploader only")
+        } else {
+            None
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct BiliPagedVideo {
     cid: i64,
-    #[serde(rename = "part")]
+    #[serde(rename = "part", deserialize_with = "deserialize_text")]
     name: String,
     duration: u32,
 }
@@ -230,6 +372,7 @@ struct BiliPagedVideo {
 #[derive(Debug, Clone, Deserialize)]
 struct BiliOwner {
     mid: u64,
+    #[serde(deserialize_with = "deserialize_text")]
     name: String,
     #[serde(deserialize_with = "deserialize_cover_url")]
     face: String,
@@ -238,7 +381,7 @@ struct BiliOwner {
 impl From<BiliOwner> for Artist {
     fn from(val: BiliOwner) -> Self {
         Self {
-            id: val.mid.to_string(),
+            id: val.mid.to_string().into(),
             name: val.name,
             description: None,
             avatar: Some(val.face),
@@ -248,23 +391,86 @@ impl From<BiliOwner> for Artist {
 
 impl From<BiliVideoDetail> for SongCollection {
     fn from(val: BiliVideoDetail) -> Self {
+        let reason = val.unavailable_reason();
         Self {
             songs: val
                 .pages
                 .into_iter()
                 .map(|i| Song {
-                    id: format!("{}::{}", val.id, i.cid),
+                    id: BiliTrackId {
+                        bvid: val.id.clone(),
+                        cid: i.cid as u64,
+                    }
+                    .into(),
                     name: i.name,
                     artists: vec![val.owner.clone().into()],
                     cover: Some(val.pic.clone()),
                     duration: Some(i.duration),
+                    unavailable: reason.is_some(),
+                    unavailable_reason: reason.map(str::to_string),
+                    alternatives: vec![],
+                    saved: false,
+                    clip: None,
                 })
                 .collect(),
-            id: val.id,
+            id: val.id.into(),
             name: val.title,
             artists: vec![val.owner.into()],
             cover: Some(val.pic),
             description: Some(val.desc),
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
+        }
+    }
+}
+
+/// Player info of a page, with the highlights marked by the uploader
+#[derive(Debug, Deserialize)]
+struct BiliPlayerInfo {
+    /// null or absent without highlights
+    view_points: Option<Vec<BiliViewPoint>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BiliViewPoint {
+    #[serde(deserialize_with = "deserialize_text")]
+    content: String,
+    from: u32,
+    to: u32,
+}
+
+/// First page of the replies to a video
+#[derive(Debug, Deserialize)]
+struct BiliReplies {
+    upper: Option<BiliRepliesUpper>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BiliRepliesUpper {
+    /// reply pinned by the uploader
+    top: Option<BiliReply>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BiliReply {
+    content: BiliReplyContent,
+}
+
+#[derive(Debug, Deserialize)]
+struct BiliReplyContent {
+    #[serde(deserialize_with = "deserialize_text")]
+    message: String,
+}
+
+impl From<BiliViewPoint> for Chapter {
+    fn from(val: BiliViewPoint) -> Self {
+        Self {
+            title: val.content,
+            artists: vec![],
+            start: val.from,
+            end: Some(val.to).filter(|to| *to > val.from),
         }
     }
 }
@@ -272,6 +478,27 @@ impl From<BiliVideoDetail> for SongCollection {
 #[derive(Debug, Deserialize)]
 struct BiliStream {
     dash: BiliDash,
+    /// loudnorm measurements of the audio, absent for older videos
+    volume: Option<BiliVolume>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BiliVolume {
+    measured_i: f64,
+    measured_lra: Option<f64>,
+    measured_tp: Option<f64>,
+    target_i: Option<f64>,
+}
+
+impl From<BiliVolume> for Loudness {
+    fn from(val: BiliVolume) -> Self {
+        Loudness {
+            integrated: val.measured_i,
+            range: val.measured_lra,
+            true_peak: val.measured_tp,
+            gain: val.target_i.map(|t| t - val.measured_i),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -286,20 +513,41 @@ struct BiliDashAudio {
     #[serde(rename = "id", deserialize_with = "deserialize_audio_quality")]
     quality: String,
     base_url: String,
+    #[serde(default, deserialize_with = "deserialize_backup_url")]
+    backup_url: Vec<String>,
+    bandwidth: Option<u64>,
+    codecs: Option<String>,
+}
+
+/// `backup_url` is null if there is no backup CDN
+fn deserialize_backup_url<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<Vec<String>>::deserialize(deserializer)?.unwrap_or_default())
 }
 
 impl From<BiliDashAudio> for Vec<Stream> {
     fn from(val: BiliDashAudio) -> Self {
-        vec![Stream {
-            quality: val.quality.clone(),
+        let primary = Stream {
+            quality: val.quality,
             url: val.base_url,
-        }]
-        // .into_iter()
-        // // .chain(val.backup_url.into_iter().map(|s| Stream {
-        // //     quality: format!("{}(backup)", val.quality),
-        // //     url: s,
-        // // }))
-        // .collect()
+            bitrate: val.bandwidth,
+            codec: val.codecs,
+            mirror: false,
+            loudness: None,
+            preview: false,
+        };
+        let mirrors = val
+            .backup_url
+            .into_iter()
+            .map(|url| Stream {
+                url,
+                mirror: true,
+                ..primary.clone()
+            })
+            .collect::<Vec<_>>();
+        std::iter::once(primary).chain(mirrors).collect()
     }
 }
 
@@ -320,14 +568,20 @@ pub type WbiCacheData = ((String, String), chrono::DateTime<chrono::FixedOffset>
 #[derive(Debug)]
 pub struct BiliScraper {
     client: reqwest::Client,
+    jar: Arc<PersistCookieStore>,
+    risk: RiskControl,
     enable_dolby: bool,
+    probe_mirrors: bool,
 
     wbi_cache: Arc<RwLock<Option<WbiCacheData>>>,
     wbi_cache_file: String,
 }
 
 impl BiliScraper {
-    pub fn try_from_setting(setting: BiliSettings) -> anyhow::Result<Option<Self>> {
+    pub fn try_from_setting(
+        setting: BiliSettings,
+        outbound_family: Option<IpFamily>,
+    ) -> anyhow::Result<Option<Self>> {
         if setting.enabled {
             util::ensure_file(&setting.cookie_path)?;
             util::ensure_file(&setting.wbi_path)?;
@@ -337,12 +591,15 @@ impl BiliScraper {
                 std::fs::File::open(&setting.wbi_path).map(std::io::BufReader::new)?;
 
             return Ok(Some(Self {
-                client: reqwest::Client::builder()
-                    .cookie_provider(jar)
+                client: util::client_builder(outbound_family)
+                    .cookie_provider(jar.clone())
                     .user_agent(DEFAULT_UA)
                     .build()
                     .unwrap(),
+                jar,
+                risk: Default::default(),
                 enable_dolby: setting.enable_dolby,
+                probe_mirrors: setting.probe_mirrors,
                 wbi_cache_file: setting.wbi_path,
                 wbi_cache: Arc::new(RwLock::new(
                     serde_json::from_reader(wbi_cache_file).unwrap_or_default(),
@@ -356,6 +613,135 @@ impl BiliScraper {
 }
 
 impl BiliScraper {
+    /// Order the primary url and backup urls of one quality by latency if `probe_mirrors` is
+    /// enabled. Unreachable urls are kept last and returned as well.
+    async fn rank_mirrors(&self, streams: Vec<Stream>) -> (Vec<Stream>, Vec<String>) {
+        if !self.probe_mirrors || streams.len() < 2 {
+            return (streams, vec![]);
+        }
+
+        let latencies = futures::future::join_all(streams.iter().map(|s| self.probe(&s.url))).await;
+        let mut ranked = latencies.into_iter().zip(streams).collect::<Vec<_>>();
+        ranked.sort_by_key(|(latency, _)| latency.unwrap_or(Duration::MAX));
+
+        let unreachable = ranked
+            .iter()
+            .filter(|(latency, _)| latency.is_none())
+            .map(|(_, s)| s.url.clone())
+            .collect();
+        (ranked.into_iter().map(|(_, s)| s).collect(), unreachable)
+    }
+
+    /// Time to the response headers of the url, any status counts as reachable
+    async fn probe(&self, url: &str) -> Option<Duration> {
+        let start = Instant::now();
+        match self
+            .client
+            .head(url)
+            .header("Referer", "https://www.bilibili.com")
+            .timeout(PROBE_TIMEOUT)
+            .send()
+            .await
+        {
+            Ok(_) => Some(start.elapsed()),
+            Err(e) => {
+                warn!("probe mirror failed: {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    /// Send the api request, detecting risk control before the body is parsed as `T`, since its
+    /// `data` differs
+    async fn send(&self, req: reqwest::RequestBuilder) -> anyhow::Result<Vec<u8>> {
+        self.risk.pace().await;
+        let resp = req.send().await?;
+        if resp.status() == StatusCode::PRECONDITION_FAILED {
+            return Err(self.risk_controlled(-412).await);
+        }
+        let body = resp.limited_bytes().await?;
+        if let Ok(BiliResponse::<IgnoredAny> { code, .. }) = serde_json::from_slice(&body) {
+            if RISK_CONTROL_CODES.contains(&code) {
+                return Err(self.risk_controlled(code).await);
+            }
+        }
+        Ok(body)
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        req: reqwest::RequestBuilder,
+    ) -> anyhow::Result<T> {
+        let body = self.send(req).await?;
+        serde_json::from_slice::<BiliResponse<IgnoredAny>>(&body)?.check()?;
+        serde_json::from_slice::<BiliResponse<T>>(&body)?.data()
+    }
+
+    /// Start the cooldown. On a new detection the client is flagged, so drop the wbi keys and get
+    /// a new device id.
+    async fn risk_controlled(&self, code: i32) -> anyhow::Error {
+        if self.risk.trigger(code) {
+            warn!(
+                "[Bilibili] risk control {} detected, cooling down for {}s",
+                code,
+                RISK_COOLDOWN.as_secs()
+            );
+            self.wbi_cache.write().take();
+            if let Err(e) = self.rotate_buvid().await {
+                warn!("[Bilibili] rotate buvid failed: {}", e);
+            }
+        }
+        RateLimited {
+            reason: format!("bilibili risk control {}", code),
+            retry_after: RISK_COOLDOWN,
+        }
+        .into()
+    }
+
+    /// Replace the buvid3 and buvid4 device cookies with newly issued ones
+    async fn rotate_buvid(&self) -> anyhow::Result<()> {
+        let buvid = self
+            .client
+            .get("https://api.bilibili.com/x/frontend/finger/spi")
+            .send()
+            .await?
+            .limited_json::<BiliResponse<Buvid>>()
+            .await?
+            .data()?;
+
+        let url = "https://bilibili.com".parse()?;
+        let cookies = [("buvid3", buvid.b_3), ("buvid4", buvid.b_4)]
+            .into_iter()
+            .map(|(name, value)| {
+                format!("{}={}; Domain=.bilibili.com; Path=/", name, value).parse()
+            })
+            .collect::<Result<Vec<reqwest::header::HeaderValue>, _>>()?;
+        self.jar.set_cookies(&mut cookies.iter(), &url);
+        info!("[Bilibili] buvid rotated");
+        Ok(())
+    }
+
+    /// Message of the reply pinned by the uploader, where mixes often list their tracks
+    async fn pinned_reply(&self, aid: u64) -> anyhow::Result<Option<String>> {
+        let (img_key, sub_key) = self.get_wbi_keys().await?;
+        let query = self.encode_wbi(
+            vec![
+                ("oid", aid.to_string()),
+                ("type", "1".to_string()),
+                ("mode", "3".to_string()),
+            ],
+            img_key,
+            sub_key,
+        );
+        let replies = self
+            .request::<BiliReplies>(self.client.get(format!(
+                "https://api.bilibili.com/x/v2/reply/wbi/main?{}",
+                query
+            )))
+            .await?;
+        Ok(replies.upper.and_then(|u| u.top).map(|r| r.content.message))
+    }
+
     pub async fn get_wbi_keys(&self) -> anyhow::Result<(String, String)> {
         let china_tz = chrono::FixedOffset::east_opt(8 * 3600).unwrap();
         let china_time = chrono::Utc::now().with_timezone(&china_tz);
@@ -386,14 +772,16 @@ impl BiliScraper {
     }
 
     async fn req_wbi_keys(&self) -> anyhow::Result<(String, String)> {
-        let wbi = self
-            .client
-            .get("https://api.bilibili.com/x/web-interface/nav")
-            .send()
-            .await?
-            .json::<BiliResponse<NavData>>()
-            .await?
-            .data;
+        // the code is -101 if not logged in, but the keys are there anyway
+        let body = self
+            .send(
+                self.client
+                    .get("https://api.bilibili.com/x/web-interface/nav"),
+            )
+            .await?;
+        let wbi = serde_json::from_slice::<BiliResponse<NavData>>(&body)?
+            .data
+            .ok_or_else(|| anyhow!("[Bilibili] no wbi keys in nav response"))?;
 
         Ok((wbi.wbi_img.img_url, wbi.wbi_img.sub_url))
     }
@@ -425,6 +813,27 @@ impl BiliScraper {
             .collect::<String>()
     }
 
+    /// First page of the videos uploaded by the user, in `pubdate` or `click` order
+    async fn uploads(&self, mid: &ArtistId, order: &str) -> anyhow::Result<Vec<BiliVideo>> {
+        let params = vec![
+            ("mid", mid.to_string()),
+            ("ps", "30".to_string()),
+            ("pn", "1".to_string()),
+            ("order", order.to_string()),
+        ];
+        let (img_key, sub_key) = self.get_wbi_keys().await?;
+        let query = self.encode_wbi(params, img_key, sub_key);
+
+        Ok(self
+            .request::<BiliUploads>(self.client.get(format!(
+                "https://api.bilibili.com/x/space/wbi/arc/search?{}",
+                query
+            )))
+            .await?
+            .list
+            .vlist)
+    }
+
     pub fn encode_wbi(
         &self,
         mut params: Vec<(&str, String)>,
@@ -476,58 +885,77 @@ impl BiliScraper {
         }
     }
 
-    async fn bili_comprehensive_search(&self, keyword: String) -> anyhow::Result<Vec<ScrapeItem>> {
-        let params = vec![("keyword", keyword)];
-        info!("search param: {:?}", params);
+    async fn bili_comprehensive_search(
+        &self,
+        keyword: String,
+        page: u32,
+    ) -> anyhow::Result<SearchPage> {
+        info!("search: keyword: {}, page: {}", redact(&keyword), page);
+        let params = vec![("keyword", keyword), ("page", page.to_string())];
 
         let (img_key, sub_key) = self.get_wbi_keys().await?;
         let query = self.encode_wbi(params, img_key, sub_key);
-        info!("search query with wbi encoding: {}", query);
+        info!("search query with wbi encoding: {}", redact(&query));
 
-        Ok(self
-            .client
-            .get(format!(
+        let search = self
+            .request::<ComprehensiveSearch>(self.client.get(format!(
                 "https://api.bilibili.com/x/web-interface/wbi/search/all/v2?{}",
                 query
-            ))
-            .send()
-            .await?
-            .json::<BiliResponse<ComprehensiveSearch>>()
-            .await?
-            .data()?
-            .result
-            .into_iter()
-            .flat_map(|i| self.handle_search_item(i))
-            .collect())
+            )))
+            .await?;
+
+        Ok(SearchPage {
+            items: search
+                .result
+                .into_iter()
+                .flat_map(|i| self.handle_search_item(i))
+                .collect(),
+            next: next_page(page, search.num_pages),
+        })
     }
 
+    /// Pages by `page_size` if present, by the default of the search type otherwise
     async fn bili_type_search(
         &self,
         keyword: String,
         search_type: String,
-    ) -> anyhow::Result<Vec<ScrapeItem>> {
-        let params = vec![("search_type", search_type), ("keyword", keyword)];
-        info!("type search param: {:?}", params);
+        page: u32,
+        page_size: Option<usize>,
+    ) -> anyhow::Result<SearchPage> {
+        info!(
+            "type search: type: {}, keyword: {}, page: {}",
+            search_type,
+            redact(&keyword),
+            page
+        );
+        let mut params = vec![
+            ("search_type", search_type),
+            ("keyword", keyword),
+            ("page", page.to_string()),
+        ];
+        if let Some(size) = page_size {
+            params.push(("page_size", size.min(MAX_PAGE_SIZE).to_string()));
+        }
 
         let (img_key, sub_key) = self.get_wbi_keys().await?;
         let query = self.encode_wbi(params, img_key, sub_key);
-        info!("type search query with wbi encoding: {}", query);
+        info!("type search query with wbi encoding: {}", redact(&query));
 
-        Ok(self
-            .client
-            .get(format!(
+        let search = self
+            .request::<TypedSearch>(self.client.get(format!(
                 "https://api.bilibili.com/x/web-interface/wbi/search/type?{}",
                 query
-            ))
-            .send()
-            .await?
-            .json::<BiliResponse<TypedSearch>>()
-            .await?
-            .data()?
-            .result
-            .into_iter()
-            .filter_map(|i| self.handle_typed_search_item(i))
-            .collect())
+            )))
+            .await?;
+
+        Ok(SearchPage {
+            items: search
+                .result
+                .into_iter()
+                .filter_map(|i| self.handle_typed_search_item(i))
+                .collect(),
+            next: next_page(page, search.num_pages),
+        })
     }
 }
 
@@ -535,62 +963,159 @@ impl BiliScraper {
 impl Scraper for BiliScraper {
     async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
         Ok(self
-            .client
-            .get(format!(
+            .request::<BiliSuggest>(self.client.get(format!(
                 "https://s.search.bilibili.com/main/suggest?term={}",
                 keyword,
-            ))
-            .send()
+            )))
             .await?
-            .json::<BiliResponse<BiliSuggest>>()
-            .await?
-            .data()?
             .tag
             .into_iter()
             .map(|i| i.value)
             .collect())
     }
 
-    async fn search(&self, keyword: String, t: ScrapeType) -> Vec<ScrapeItem> {
-        let items = match t {
-            ScrapeType::All => self.bili_comprehensive_search(keyword).await,
-            ScrapeType::Playlist => self.bili_type_search(keyword, "video".to_string()).await,
-            ScrapeType::Artist => {
-                self.bili_type_search(keyword, "bili_user".to_string())
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        self.search_filtered(keyword, t, continuation, &SearchFilter::default())
+            .await
+    }
+
+    /// Typed searches page by `limit` of the filter. The comprehensive search has fixed page
+    /// sizes per type.
+    async fn search_filtered(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<SearchPage> {
+        let page = continuation
+            .and_then(|c| c.parse::<u32>().ok())
+            .unwrap_or(1);
+
+        match t {
+            ScrapeType::All => self.bili_comprehensive_search(keyword, page).await,
+            ScrapeType::Playlist => {
+                self.bili_type_search(keyword, "video".to_string(), page, filter.limit)
                     .await
             }
-            ScrapeType::Song => return vec![],
-            ScrapeType::Album => return vec![],
-        };
-
-        match items {
-            Ok(i) => i,
-            Err(e) => {
-                error!("comprehensive search failed: {}", e);
-                println!("comprehensive search failed: {}", e);
-                vec![]
+            ScrapeType::Artist => {
+                self.bili_type_search(keyword, "bili_user".to_string(), page, filter.limit)
+                    .await
             }
+            ScrapeType::Song => Ok(SearchPage::default()),
+            ScrapeType::Album | ScrapeType::Radio => Ok(SearchPage::default()),
         }
     }
 
-    async fn collection_detail(&self, id: String) -> anyhow::Result<SongCollection> {
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
         Ok(self
-            .client
-            .get("https://api.bilibili.com/x/web-interface/view")
-            .query(&[("bvid", &id)])
-            .send()
-            .await?
-            .json::<BiliResponse<BiliVideoDetail>>()
+            .request::<BiliVideoDetail>(
+                self.client
+                    .get("https://api.bilibili.com/x/web-interface/view")
+                    .query(&[("bvid", id.as_str())]),
+            )
             .await?
-            .data()?
             .into())
     }
 
-    async fn stream(&self, id: String) -> anyhow::Result<Vec<Stream>> {
-        let ids = id.split("::").collect::<Vec<_>>();
-        if ids.len() != 2 {
-            bail!("incorrect id: should be ${{bvid}}::${{cid}} but get {}", id);
-        }
+    async fn artist_releases(&self, id: ArtistId) -> anyhow::Result<Vec<ScrapeItem>> {
+        Ok(self
+            .uploads(&id, "pubdate")
+            .await?
+            .into_iter()
+            .map(|v| ScrapeItem::Playlist(v.into()))
+            .collect())
+    }
+
+    /// The most played uploads as playlists, like videos everywhere else. Bilibili has no top
+    /// tracks or albums of users.
+    async fn artist_detail(&self, id: ArtistId) -> anyhow::Result<ArtistDetail> {
+        let info = async {
+            let (img_key, sub_key) = self.get_wbi_keys().await?;
+            let query = self.encode_wbi(vec![("mid", id.to_string())], img_key, sub_key);
+            self.request::<BiliSpaceInfo>(self.client.get(format!(
+                "https://api.bilibili.com/x/space/wbi/acc/info?{}",
+                query
+            )))
+            .await
+        };
+        let (info, uploads) = futures::future::try_join(info, self.uploads(&id, "click")).await?;
+
+        Ok(ArtistDetail {
+            artist: info.into(),
+            songs: vec![],
+            albums: vec![],
+            playlists: uploads.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Highlights of the page, or else the tracklist of the description or the pinned reply of
+    /// single page videos, since both belong to the video
+    async fn chapters(&self, id: TrackId) -> anyhow::Result<Vec<Song>> {
+        let BiliTrackId { bvid, cid } = id.parse()?;
+        let detail = self.request::<BiliVideoDetail>(
+            self.client
+                .get("https://api.bilibili.com/x/web-interface/view")
+                .query(&[("bvid", bvid.as_str())]),
+        );
+        let player = async {
+            let (img_key, sub_key) = self.get_wbi_keys().await?;
+            let query = self.encode_wbi(
+                vec![("bvid", bvid.clone()), ("cid", cid.to_string())],
+                img_key,
+                sub_key,
+            );
+            self.request::<BiliPlayerInfo>(self.client.get(format!(
+                "https://api.bilibili.com/x/player/wbi/v2?{}",
+                query
+            )))
+            .await
+        };
+        let (detail, player) = futures::future::try_join(detail, player).await?;
+
+        let description = detail.desc.clone();
+        let aid = detail.aid;
+        let video = SongCollection::from(detail);
+        let single_page = video.songs.len() == 1;
+        let upload = video
+            .songs
+            .into_iter()
+            .find(|s| s.id == id)
+            .ok_or(anyhow!("page not found: {}", id.as_str()))?;
+        let chapters = match player.view_points.unwrap_or_default() {
+            points if !points.is_empty() => {
+                let mut chapters = points.into_iter().map(Into::into).collect::<Vec<_>>();
+                tracklist::split_artists(&mut chapters);
+                chapters
+            }
+            _ if single_page => {
+                let pinned = match aid {
+                    0 => None,
+                    aid => self.pinned_reply(aid).await.unwrap_or_else(|e| {
+                        warn!("[Bilibili] get pinned reply of {} failed: {}", bvid, e);
+                        None
+                    }),
+                };
+                tracklist::best_tracklist(
+                    [description.as_str()].into_iter().chain(pinned.as_deref()),
+                )
+            }
+            _ => vec![],
+        };
+        Ok(chapter::chapter_songs(&upload, chapters))
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        Ok(self.stream_trace(id).await?.streams)
+    }
+
+    async fn stream_trace(&self, id: TrackId) -> anyhow::Result<StreamTrace> {
+        let BiliTrackId { bvid, cid } = id.parse()?;
 
         // 16: DASH. 256: Dolby audio
         let fn_val = match self.enable_dolby {
@@ -598,55 +1123,103 @@ impl Scraper for BiliScraper {
             false => 16,
         };
 
+        info!(
+            "stream: bvid: {}, cid: {}, fnval: {}",
+            redact(&bvid),
+            redact(&cid.to_string()),
+            fn_val
+        );
         let params = vec![
-            ("bvid", ids[0].to_string()),
-            ("cid", ids[1].to_string()),
+            ("bvid", bvid),
+            ("cid", cid.to_string()),
             ("fnval", fn_val.to_string()),
         ];
-        info!("stream param: {:?}", params);
 
         let (img_key, sub_key) = self.get_wbi_keys().await?;
         let query = self.encode_wbi(params, img_key, sub_key);
-        info!("stream query with wbi encoding: {}", query);
+        info!("stream query with wbi encoding: {}", redact(&query));
 
-        let dash = self
-            .client
-            .get(format!(
+        let BiliStream { dash, volume } = self
+            .request(self.client.get(format!(
                 "https://api.bilibili.com/x/player/wbi/playurl?{}",
                 query
-            ))
-            .send()
-            .await?
-            .json::<BiliResponse<BiliStream>>()
-            .await?
-            .data()?
-            .dash;
+            )))
+            .await?;
+        let loudness = volume.map(Loudness::from);
+
+        let mut trace = StreamTrace::default();
+        let mut audio = vec![];
+        match dash.dolby.audio {
+            Some(dolby) => audio.extend(dolby),
+            None if !self.enable_dolby => trace.filtered("Dolby", "disabled by enable_dolby"),
+            None => trace.filtered("Dolby", "not offered for this video"),
+        }
 
-        let mut streams = vec![];
-        if let Some(audio) = dash.dolby.audio {
-            streams.extend(audio.into_iter().flat_map(Into::<Vec<Stream>>::into));
+        match dash.flac.filter(|f| !f.audio.is_empty()) {
+            Some(flac) => audio.extend(flac.audio),
+            None => trace.filtered(
+                "Hi-Res lossless",
+                "not offered: requires a VIP account or the video has no lossless audio",
+            ),
         }
+        audio.extend(dash.audio);
 
-        if let Some(flac) = dash.flac {
-            streams.extend(flac.audio.into_iter().flat_map(Into::<Vec<Stream>>::into));
+        let groups = futures::future::join_all(
+            audio
+                .into_iter()
+                .map(|a| self.rank_mirrors(Into::<Vec<Stream>>::into(a))),
+        )
+        .await;
+        for (streams, unreachable) in groups {
+            unreachable
+                .into_iter()
+                .for_each(|url| trace.note(format!("mirror probe failed: {}", url)));
+            streams.into_iter().for_each(|s| {
+                trace.found(Stream {
+                    loudness: loudness.clone(),
+                    ..s
+                })
+            });
         }
 
-        streams.extend(dash.audio.into_iter().flat_map(Into::<Vec<Stream>>::into));
+        Ok(trace)
+    }
 
-        Ok(streams)
+    /// The CDN refuses requests without the Referer of the site
+    fn stream_headers(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("Referer", "https://www.bilibili.com".to_string()),
+            ("User-Agent", DEFAULT_UA.to_string()),
+        ]
+    }
+
+    fn health(&self) -> Health {
+        match self.risk.active() {
+            Some((remaining, code)) => {
+                Health::degraded(format!("bilibili risk control {}", code), remaining)
+            }
+            None => Health::default(),
+        }
     }
 }
 
 #[cfg(test)]
 mod test {
+    use serde::de::IgnoredAny;
     use tracing::level_filters::LevelFilter;
 
     use crate::{
-        scraper::{ScrapeType, Scraper},
+        scraper::{
+            chapter::Chapter, unavailable::Unavailable, Loudness, ScrapeType, Scraper,
+            SongCollection, Stream,
+        },
         settings::BiliSettings,
     };
 
-    use super::BiliScraper;
+    use super::{
+        BiliDashAudio, BiliPlayerInfo, BiliReplies, BiliResponse, BiliScraper, BiliStream,
+        BiliVideoDetail, RiskControl, RISK_CONTROL_CODES, RISK_COOLDOWN,
+    };
 
     fn cli() -> BiliScraper {
         tracing_subscriber::fmt::fmt()
@@ -657,12 +1230,20 @@ mod test {
             )
             .init();
 
-        BiliScraper::try_from_setting(BiliSettings {
-            enabled: true,
-            cookie_path: ".cookie/bili.json".into(),
-            wbi_path: ".cookie/wbi.json".into(),
-            enable_dolby: false,
-        })
+        BiliScraper::try_from_setting(
+            BiliSettings {
+                enabled: true,
+                cookie_path: ".cookie/bili.json".into(),
+                wbi_path: ".cookie/wbi.json".into(),
+                enable_dolby: false,
+                probe_mirrors: false,
+                host_rewrites: vec![],
+                budget: Default::default(),
+                keyword_variants: vec![],
+                search_zones: vec![],
+            },
+            None,
+        )
         .unwrap()
         .unwrap()
     }
@@ -679,7 +1260,7 @@ mod test {
     async fn test_search_mix() {
         let cli = cli();
 
-        let resp = cli.search("早稻叽".into(), ScrapeType::All).await;
+        let resp = cli.search("早稻叽".into(), ScrapeType::All, None).await;
         println!("{:?}", resp);
     }
 
@@ -687,7 +1268,9 @@ mod test {
     async fn test_search_playlist() {
         let cli = cli();
 
-        let resp = cli.search("早稻叽".into(), ScrapeType::Playlist).await;
+        let resp = cli
+            .search("早稻叽".into(), ScrapeType::Playlist, None)
+            .await;
         println!("{:?}", resp);
     }
 
@@ -695,7 +1278,7 @@ mod test {
     async fn test_search_user() {
         let cli = cli();
 
-        let resp = cli.search("早稻叽".into(), ScrapeType::Artist).await;
+        let resp = cli.search("早稻叽".into(), ScrapeType::Artist, None).await;
         println!("{:?}", resp);
     }
 
@@ -703,10 +1286,7 @@ mod test {
     async fn test_playlist_detail() {
         let cli = cli();
 
-        let resp = cli
-            .collection_detail("BV1dZ4y1g7ag".to_string())
-            .await
-            .unwrap();
+        let resp = cli.collection_detail("BV1dZ4y1g7ag".into()).await.unwrap();
         println!("{:?}", resp);
     }
 
@@ -714,10 +1294,148 @@ mod test {
     async fn test_stream() {
         let cli = cli();
 
-        let resp = cli
-            .stream("BV1dZ4y1g7ag::266767355".to_string())
-            .await
-            .unwrap();
+        let resp = cli.stream("BV1dZ4y1g7ag::266767355".into()).await.unwrap();
         println!("{:?}", resp);
     }
+
+    #[test]
+    fn test_backup_url() {
+        let audio: Vec<BiliDashAudio> = serde_json::from_str(
+            r#"[
+                {"id": 30280, "base_url": "https://upos-sz-mirrorcos.bilivideo.com/a.m4s",
+                 "backup_url": ["https://upos-hz-mirrorakam.akamaized.net/a.m4s"],
+                 "bandwidth": 192000, "codecs": "mp4a.40.2"},
+                {"id": 30216, "base_url": "https://upos-sz-mirrorcos.bilivideo.com/b.m4s",
+                 "backup_url": null}
+            ]"#,
+        )
+        .unwrap();
+
+        let streams = audio
+            .into_iter()
+            .flat_map(Into::<Vec<Stream>>::into)
+            .collect::<Vec<_>>();
+        assert_eq!(streams.len(), 3);
+        assert!(!streams[0].mirror);
+        assert!(streams[1].mirror);
+        assert_eq!(streams[1].quality, "192k");
+        assert_eq!(streams[1].bitrate, Some(192000));
+        assert_eq!(streams[2].quality, "64k");
+    }
+
+    #[test]
+    fn test_volume() {
+        let stream: BiliStream = serde_json::from_str(
+            r#"{
+                "dash": {"audio": [], "dolby": {"type": 0, "audio": null}, "flac": null},
+                "volume": {"measured_i": -10.5, "measured_lra": 6.2, "measured_tp": 0.3,
+                           "measured_threshold": -20.9, "target_offset": 0.1, "target_i": -15,
+                           "target_tp": -2}
+            }"#,
+        )
+        .unwrap();
+
+        let loudness = stream.volume.map(Loudness::from).unwrap();
+        assert_eq!(loudness.integrated, -10.5);
+        assert_eq!(loudness.true_peak, Some(0.3));
+        assert_eq!(loudness.gain, Some(-4.5));
+    }
+
+    #[test]
+    fn test_view_points() {
+        let info: BiliPlayerInfo = serde_json::from_str(
+            r#"{
+                "view_points": [
+                    {"type": 2, "from": 0, "to": 252, "content": "春日影", "imgUrl": ""},
+                    {"type": 2, "from": 252, "to": 0, "content": "&amp;碧天伴走", "imgUrl": ""}
+                ]
+            }"#,
+        )
+        .unwrap();
+        let chapters = info
+            .view_points
+            .unwrap()
+            .into_iter()
+            .map(Chapter::from)
+            .collect::<Vec<_>>();
+        assert_eq!(chapters[0].end, Some(252));
+        assert_eq!(chapters[1].title, "&碧天伴走");
+        assert_eq!(chapters[1].end, None);
+
+        // pages without highlights have none at all
+        let info: BiliPlayerInfo = serde_json::from_str(r#"{"view_points": null}"#).unwrap();
+        assert!(info.view_points.is_none());
+    }
+
+    #[test]
+    fn test_pinned_reply() {
+        let replies: BiliReplies = serde_json::from_str(
+            r#"{
+                "upper": {
+                    "mid": 2,
+                    "top": {"rpid": 1, "content": {"message": "00:00 稻香 - 周杰伦&#10;"}}
+                },
+                "replies": []
+            }"#,
+        )
+        .unwrap();
+        let pinned = replies.upper.and_then(|u| u.top).unwrap();
+        assert!(pinned.content.message.starts_with("00:00 稻香"));
+
+        let replies: BiliReplies =
+            serde_json::from_str(r#"{"upper": {"mid": 2, "top": null}}"#).unwrap();
+        assert!(replies.upper.unwrap().top.is_none());
+    }
+
+    #[test]
+    fn test_risk_control() {
+        // the data of a risk control is the captcha voucher, not the requested data
+        let body = br#"{"code":-352,"message":"-352","data":{"v_voucher":"voucher_1"}}"#;
+        let resp = serde_json::from_slice::<BiliResponse<IgnoredAny>>(body).unwrap();
+        assert!(RISK_CONTROL_CODES.contains(&resp.code));
+
+        let risk = RiskControl::default();
+        assert!(risk.active().is_none());
+        assert!(risk.trigger(-352));
+        // a repeated detection only extends the cooldown
+        assert!(!risk.trigger(-412));
+        let (remaining, code) = risk.active().unwrap();
+        assert!(remaining <= RISK_COOLDOWN);
+        assert_eq!(code, -412);
+    }
+
+    #[test]
+    fn test_deleted() {
+        let body = r#"{"code":-404,"message":"啥都木有","data":null}"#;
+        let e = serde_json::from_str::<BiliResponse<BiliVideoDetail>>(body)
+            .unwrap()
+            .data()
+            .unwrap_err();
+        assert!(e.downcast_ref::<Unavailable>().is_some());
+    }
+
+    #[test]
+    fn test_unavailable_pages() {
+        let detail = |extra: &str| {
+            let body = format!(
+                r#"{{"code": 0, "data": {{"bvid": "BV1dZ4y1g7ag", "aid": 1, "pic": "http://i0.hdslb.com/1.jpg",
+                "title": "t", "desc": "", "owner": {{"mid": 1, "name": "up", "face": "http://i0.hdslb.com/2.jpg"}},
+                "pages": [{{"cid": 266767355, "part": "p1", "duration": 60}}]{}}}}}"#,
+                extra
+            );
+            let detail = serde_json::from_str::<BiliResponse<BiliVideoDetail>>(&body)
+                .unwrap()
+                .data()
+                .unwrap();
+            SongCollection::from(detail).songs.remove(0)
+        };
+        assert!(!detail(r#", "state": 0"#).unavailable);
+        let exclusive = detail(r#", "state": 0, "is_upower_exclusive": true"#);
+        assert!(exclusive.unavailable);
+        assert_eq!(
+            exclusive.unavailable_reason.as_deref(),
+            Some("for supporters of the uploader only")
+        );
+        assert!(detail(r#", "state": -4"#).unavailable);
+    }
 }
@@ -0,0 +1,166 @@
+use anyhow::{anyhow, bail};
+use regex::{Regex, RegexBuilder};
+
+use crate::settings::{Capabilities, ClientProfile};
+
+use super::{explain::StreamTrace, Stream};
+
+/// Capabilities of the clients by User-Agent, first match wins
+#[derive(Debug, Default)]
+pub struct ClientProfiles(Vec<(Regex, Capabilities)>);
+
+impl ClientProfiles {
+    pub fn try_from_setting(profiles: Vec<ClientProfile>) -> anyhow::Result<Self> {
+        profiles
+            .into_iter()
+            .map(|p| {
+                let user_agent = RegexBuilder::new(&p.user_agent)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| anyhow!("invalid user agent of client profile: {}", e))?;
+                Ok((user_agent, p.capabilities))
+            })
+            .collect::<anyhow::Result<_>>()
+            .map(Self)
+    }
+
+    pub fn matching(&self, user_agent: &str) -> Option<&Capabilities> {
+        self.0
+            .iter()
+            .find(|(r, _)| r.is_match(user_agent))
+            .map(|(_, c)| c)
+    }
+}
+
+/// Parse capabilities declared like `codecs=mp4a,flac; max-bitrate=320000`. Unknown keys are
+/// ignored, so that clients may declare more than this server knows about.
+pub fn parse_capabilities(declared: &str) -> anyhow::Result<Capabilities> {
+    let mut capabilities = Capabilities::default();
+    for (key, value) in declared
+        .split(';')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(|p| {
+            p.split_once('=')
+                .ok_or(anyhow!("expected key=value: {}", p))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?
+    {
+        match key.trim() {
+            "codecs" => {
+                capabilities.codecs = value
+                    .split(',')
+                    .map(|c| c.trim().to_lowercase())
+                    .filter(|c| !c.is_empty())
+                    .collect()
+            }
+            "max-bitrate" => match value.trim().parse() {
+                Ok(bitrate) => capabilities.max_bitrate = Some(bitrate),
+                Err(_) => bail!("invalid max-bitrate: {}", value),
+            },
+            _ => {}
+        }
+    }
+    Ok(capabilities)
+}
+
+/// Why the client cannot play the stream, if it cannot. Streams of unknown codec or bitrate are
+/// taken as playable.
+fn unplayable(stream: &Stream, capabilities: &Capabilities) -> Option<String> {
+    if let Some(codec) = &stream.codec {
+        let codec = codec.to_lowercase();
+        if !capabilities.codecs.is_empty()
+            && !capabilities
+                .codecs
+                .iter()
+                .any(|c| codec.starts_with(&c.to_lowercase()))
+        {
+            return Some(format!("codec {} not supported by the client", codec));
+        }
+    }
+    match (stream.bitrate, capabilities.max_bitrate) {
+        (Some(bitrate), Some(max)) if bitrate > max => Some(format!(
+            "bitrate {} above the client maximum of {}",
+            bitrate, max
+        )),
+        _ => None,
+    }
+}
+
+/// Drop the streams the client cannot play, keeping the order of the rest
+pub fn filter_streams(streams: &mut Vec<Stream>, capabilities: &Capabilities) {
+    streams.retain(|s| unplayable(s, capabilities).is_none());
+}
+
+/// Like `filter_streams`, noting why each stream was dropped
+pub fn filter_trace(trace: &mut StreamTrace, capabilities: &Capabilities) {
+    for stream in std::mem::take(&mut trace.streams) {
+        match unplayable(&stream, capabilities) {
+            Some(reason) => trace.filtered(stream.quality, reason),
+            None => trace.streams.push(stream),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        scraper::{explain::StreamTrace, Stream},
+        settings::{Capabilities, ClientProfile},
+    };
+
+    use super::{filter_streams, filter_trace, parse_capabilities, ClientProfiles};
+
+    fn stream(quality: &str, bitrate: Option<u64>, codec: Option<&str>) -> Stream {
+        Stream {
+            quality: quality.into(),
+            url: format!("https://upos/{}", quality),
+            bitrate,
+            codec: codec.map(Into::into),
+            mirror: false,
+            loudness: None,
+            preview: false,
+        }
+    }
+
+    #[test]
+    fn test_filter_streams() {
+        let ios = parse_capabilities("codecs=mp4a, FLAC; max-bitrate=1000000; hdr=1").unwrap();
+        assert_eq!(ios.codecs, vec!["mp4a", "flac"]);
+        assert_eq!(ios.max_bitrate, Some(1_000_000));
+        assert!(parse_capabilities("max-bitrate=high").is_err());
+        assert_eq!(parse_capabilities("").unwrap(), Capabilities::default());
+
+        let mut streams = vec![
+            stream("opus", Some(160_000), Some("opus")),
+            stream("192k", Some(192_000), Some("mp4a.40.2")),
+            stream("Hi-Res lossless", Some(3_000_000), Some("flac")),
+            stream("lossless", None, None),
+        ];
+        let mut trace = StreamTrace::from(streams.clone());
+        filter_streams(&mut streams, &ios);
+        let qualities = streams
+            .iter()
+            .map(|s| s.quality.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(qualities, vec!["192k", "lossless"]);
+
+        filter_trace(&mut trace, &ios);
+        assert_eq!(trace.streams.len(), 2);
+        assert_eq!(trace.steps.len(), 6);
+    }
+
+    #[test]
+    fn test_profiles() {
+        let profiles = ClientProfiles::try_from_setting(vec![ClientProfile {
+            user_agent: "iphone|ipad".into(),
+            capabilities: Capabilities {
+                codecs: vec!["mp4a".into()],
+                max_bitrate: None,
+            },
+        }])
+        .unwrap();
+        assert!(profiles.matching("Bragi/1.0 (iPhone; iOS 17.4)").is_some());
+        assert!(profiles.matching("Bragi/1.0 (Android 14)").is_none());
+    }
+}
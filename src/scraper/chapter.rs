@@ -0,0 +1,178 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::{tracklist, Artist, Clip, Song};
+
+lazy_static! {
+    /// `1:02:03`, `62:03` or `2:03`, optionally in brackets
+    static ref TIMESTAMP: Regex =
+        Regex::new(r"[\[(]?\b(?:(\d{1,2}):)?(\d{1,3}):([0-5]\d)\b[\])]?").unwrap();
+}
+
+/// Like YouTube, descriptions with fewer timestamps are not taken as chapters
+const MIN_CHAPTERS: usize = 3;
+
+/// Separators left around the title once the timestamp is taken out of the line
+const SEPARATORS: &[char] = &['-', '–', '—', '|', ':', '·', '•', '~', ' '];
+
+/// Chapter of an upload, in seconds from its start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chapter {
+    pub title: String,
+    /// artists named along with the title, the uploader performs otherwise
+    pub artists: Vec<String>,
+    pub start: u32,
+    /// start of the next chapter if absent
+    pub end: Option<u32>,
+}
+
+fn seconds(caps: &regex::Captures) -> u32 {
+    let part = |i| {
+        caps.get(i)
+            .and_then(|m| m.as_str().parse::<u32>().ok())
+            .unwrap_or_default()
+    };
+    part(1) * 3600 + part(2) * 60 + part(3)
+}
+
+/// Chapters listed in the description the way YouTube takes them: one per line, led or followed by
+/// its timestamp, the first at 0:00 and ascending. Lines with a range like `0:00 - 4:10` end the
+/// chapter there. Songs and artists of the titles are told apart by `tracklist::split_artists`.
+pub fn parse_chapters(description: &str) -> Vec<Chapter> {
+    let mut chapters: Vec<Chapter> = vec![];
+    for line in description.lines() {
+        let mut stamps = TIMESTAMP.captures_iter(line);
+        let Some(start) = stamps.next() else {
+            continue;
+        };
+        let end = stamps.next().map(|c| seconds(&c));
+        let start = seconds(&start);
+        let title = TIMESTAMP.replace_all(line, "");
+        let title = tracklist::strip_numbering(title.trim_matches(SEPARATORS));
+        let title = title.trim_matches(SEPARATORS);
+        if title.is_empty() || chapters.last().is_some_and(|c| c.start >= start) {
+            continue;
+        }
+        chapters.push(Chapter {
+            title: title.to_string(),
+            artists: vec![],
+            start,
+            end: end.filter(|e| *e > start),
+        });
+    }
+
+    match chapters.first() {
+        Some(first) if first.start == 0 && chapters.len() >= MIN_CHAPTERS => {
+            tracklist::split_artists(&mut chapters);
+            chapters
+        }
+        _ => vec![],
+    }
+}
+
+/// The chapters as songs of the upload, each ending where the next one starts unless told
+/// otherwise, the last one with the upload
+pub fn chapter_songs(upload: &Song, chapters: Vec<Chapter>) -> Vec<Song> {
+    let starts = chapters
+        .iter()
+        .skip(1)
+        .map(|c| Some(c.start))
+        .chain(Some(upload.duration))
+        .collect::<Vec<_>>();
+    chapters
+        .into_iter()
+        .zip(starts)
+        .map(|(chapter, next)| {
+            let end = chapter.end.or(next);
+            let artists = match chapter.artists.is_empty() {
+                true => upload.artists.clone(),
+                // not known to the provider by name, so without id
+                false => chapter
+                    .artists
+                    .into_iter()
+                    .map(|name| Artist {
+                        id: String::new().into(),
+                        name,
+                        description: None,
+                        avatar: None,
+                    })
+                    .collect(),
+            };
+            Song {
+                name: chapter.title,
+                artists,
+                duration: end.map(|e| e.saturating_sub(chapter.start)),
+                clip: Some(Clip {
+                    start: chapter.start,
+                    end,
+                }),
+                ..upload.clone()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::{Clip, Song};
+
+    use super::{chapter_songs, parse_chapters, Chapter};
+
+    #[test]
+    fn test_parse_chapters() {
+        let description = "YOASOBI ARENA TOUR 2023\n\
+            Setlist:\n\
+            00:00 Opening\n\
+            03:12 - アイドル\n\
+            7:45 | 夜に駆ける (extended)\n\
+            1:02:03 - 1:06:40 群青\n\
+            follow us on twitter: 12:00 tonight";
+        let chapters = parse_chapters(description);
+        let starts = chapters.iter().map(|c| c.start).collect::<Vec<_>>();
+        assert_eq!(starts, vec![0, 192, 465, 3723]);
+        assert_eq!(chapters[1].title, "アイドル");
+        assert_eq!(chapters[2].title, "夜に駆ける (extended)");
+        assert_eq!(chapters[3].title, "群青");
+        assert_eq!(chapters[3].end, Some(3723 + 277));
+
+        // not from the start
+        assert!(parse_chapters("1:00 a\n2:00 b\n3:00 c").is_empty());
+        assert!(parse_chapters("0:00 a\n2:00 b").is_empty());
+    }
+
+    #[test]
+    fn test_chapter_songs() {
+        let upload = Song {
+            id: "K_x2r8vJxZ4".into(),
+            name: "Live".into(),
+            artists: vec![],
+            cover: None,
+            duration: Some(600),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        };
+        let chapters = ["a", "b", "c"]
+            .into_iter()
+            .zip([0, 100, 300])
+            .map(|(title, start)| Chapter {
+                title: title.into(),
+                artists: vec![],
+                start,
+                end: None,
+            })
+            .collect();
+        let songs = chapter_songs(&upload, chapters);
+        assert_eq!(songs[1].id, upload.id);
+        assert_eq!(songs[1].duration, Some(200));
+        assert_eq!(
+            songs[2].clip,
+            Some(Clip {
+                start: 300,
+                end: Some(600)
+            })
+        );
+    }
+}
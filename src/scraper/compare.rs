@@ -0,0 +1,161 @@
+use serde::Serialize;
+
+use super::{Provider, Song, Stream};
+
+/// Songs off by more seconds than this from the asked duration are other versions, like live or
+/// extended ones
+pub(super) const DURATION_TOLERANCE: u32 = 10;
+
+/// The track on a provider, a row of the comparison. Stream fields are of the stream played by
+/// default, the first one after sorting.
+#[derive(Debug, Clone, Serialize)]
+pub struct Source {
+    pub provider: Provider,
+    /// song matching the track, absent if none was found
+    pub song: Option<Song>,
+    /// whether any stream was resolved
+    pub available: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+    /// integrated loudness in LUFS as measured upstream
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<f64>,
+    /// every quality resolved, best first
+    pub qualities: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Source {
+    pub fn missing(provider: Provider, reason: impl Into<String>) -> Self {
+        Self {
+            provider,
+            song: None,
+            available: false,
+            quality: None,
+            codec: None,
+            bitrate: None,
+            loudness: None,
+            qualities: vec![],
+            error: Some(reason.into()),
+        }
+    }
+
+    pub fn resolved(provider: Provider, song: Song, streams: anyhow::Result<Vec<Stream>>) -> Self {
+        let streams = match streams {
+            Ok(streams) => streams,
+            Err(e) => {
+                return Self {
+                    song: Some(song),
+                    ..Self::missing(provider, e.to_string())
+                }
+            }
+        };
+        let best = streams.first();
+        Self {
+            provider,
+            song: Some(song),
+            available: best.is_some(),
+            quality: best.map(|s| s.quality.clone()),
+            codec: best.and_then(|s| s.codec.clone()),
+            bitrate: best.and_then(|s| s.bitrate),
+            loudness: best.and_then(|s| s.loudness.as_ref()).map(|l| l.integrated),
+            qualities: streams.iter().map(|s| s.quality.clone()).collect(),
+            error: best.is_none().then(|| "no stream".to_string()),
+        }
+    }
+}
+
+/// The song standing for the track among the search results of a provider: the first available
+/// one, or the one closest to the duration if given
+pub fn best_match(songs: Vec<Song>, duration: Option<u32>) -> Option<Song> {
+    let mut songs = songs.into_iter().filter(|s| !s.unavailable);
+    match duration {
+        None => songs.next(),
+        Some(duration) => songs
+            .filter_map(|s| Some((s.duration?.abs_diff(duration), s)))
+            .filter(|(off, _)| *off <= DURATION_TOLERANCE)
+            .min_by_key(|(off, _)| *off)
+            .map(|(_, s)| s),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::anyhow;
+
+    use crate::scraper::{Loudness, Provider, Song, Stream};
+
+    use super::{best_match, Source};
+
+    fn song(id: &str, duration: Option<u32>, unavailable: bool) -> Song {
+        Song {
+            id: id.into(),
+            name: "Plastic Love".into(),
+            artists: vec![],
+            cover: None,
+            duration,
+            unavailable,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+
+    #[test]
+    fn test_best_match() {
+        let songs = vec![
+            song("1", Some(480), true),
+            song("2", Some(600), false),
+            song("3", Some(476), false),
+            song("4", None, false),
+        ];
+        assert_eq!(best_match(songs.clone(), None).unwrap().id.as_str(), "2");
+        assert_eq!(
+            best_match(songs.clone(), Some(480)).unwrap().id.as_str(),
+            "3"
+        );
+        assert!(best_match(songs, Some(300)).is_none());
+    }
+
+    #[test]
+    fn test_source() {
+        let stream = |quality: &str, bitrate| Stream {
+            quality: quality.into(),
+            url: "https://m701.music.126.net/1.flac".into(),
+            bitrate: Some(bitrate),
+            codec: Some("flac".into()),
+            mirror: false,
+            loudness: Some(Loudness {
+                integrated: -9.5,
+                range: None,
+                true_peak: None,
+                gain: None,
+            }),
+            preview: false,
+        };
+        let source = Source::resolved(
+            Provider::NetEase,
+            song("1", Some(480), false),
+            Ok(vec![stream("lossless", 900_000), stream("320k", 320_000)]),
+        );
+        assert!(source.available);
+        assert_eq!(source.bitrate, Some(900_000));
+        assert_eq!(source.loudness, Some(-9.5));
+        assert_eq!(source.qualities, vec!["lossless", "320k"]);
+
+        let source = Source::resolved(
+            Provider::Youtube,
+            song("1", None, false),
+            Err(anyhow!("video unavailable")),
+        );
+        assert!(!source.available);
+        assert!(source.song.is_some());
+        assert_eq!(source.error.as_deref(), Some("video unavailable"));
+    }
+}
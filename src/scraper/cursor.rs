@@ -0,0 +1,73 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Deserializer};
+
+use super::Provider;
+
+/// Opaque continuation token of a fan-out search. It records the provider specific continuation
+/// (page number, offset, continuation token, ...) of every provider which still has more results.
+/// Providers missing from the cursor are exhausted and skipped by subsequent calls.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Cursor(BTreeMap<Provider, String>);
+
+impl Cursor {
+    pub fn get(&self, provider: &Provider) -> Option<&String> {
+        self.0.get(provider)
+    }
+
+    pub fn insert(&mut self, provider: Provider, continuation: String) {
+        self.0.insert(provider, continuation);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&self.0).unwrap_or_default())
+    }
+
+    pub fn decode(s: &str) -> anyhow::Result<Self> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(s)
+            .map_err(|e| anyhow!("invalid cursor: {}", e))?;
+        Ok(Self(serde_json::from_slice(&bytes)?))
+    }
+}
+
+impl<'de> Deserialize<'de> for Cursor {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let s: String = Deserialize::deserialize(deserializer)?;
+        Self::decode(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::Provider;
+
+    use super::Cursor;
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let mut cursor = Cursor::default();
+        cursor.insert(Provider::Bilibili, "2".into());
+        cursor.insert(Provider::NetEase, "30".into());
+
+        let encoded = cursor.encode();
+        assert!(encoded
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(Cursor::decode(&encoded).unwrap(), cursor);
+    }
+
+    #[test]
+    fn test_cursor_invalid() {
+        assert!(Cursor::decode("not a cursor").is_err());
+    }
+}
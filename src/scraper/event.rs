@@ -0,0 +1,71 @@
+use super::{FanOut, Provider, ScrapeItem, ScrapeType, Stream};
+
+/// Hooks called by `ScraperManager`, for analytics, caching or notifications without forking.
+/// Handlers are called inline and must return quickly. Spawn a task for any slow work.
+pub trait EventHandler: Send + Sync {
+    /// A search finished with the merged results of all providers
+    fn on_search(&self, _keyword: &str, _t: &ScrapeType, _results: &FanOut<ScrapeItem>) {}
+
+    /// Streams of the song were resolved, possibly stale ones
+    fn on_stream_resolved(&self, _provider: &Provider, _id: &str, _streams: &[Stream]) {}
+
+    /// A call to the provider failed
+    fn on_provider_error(&self, _provider: &Provider, _error: &anyhow::Error) {}
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use crate::{
+        scraper::{fixture::FixtureScraper, FanOut, Provider, ScrapeItem, ScrapeType},
+        BragiBuilder,
+    };
+
+    use super::EventHandler;
+
+    #[derive(Default)]
+    struct Counter {
+        searches: AtomicUsize,
+        errors: AtomicUsize,
+    }
+
+    impl EventHandler for Arc<Counter> {
+        fn on_search(&self, _keyword: &str, _t: &ScrapeType, _results: &FanOut<ScrapeItem>) {
+            self.searches.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_provider_error(&self, provider: &Provider, _error: &anyhow::Error) {
+            assert_eq!(provider, &Provider::NetEase);
+            self.errors.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_events() {
+        let counter = Arc::new(Counter::default());
+        let broken = FixtureScraper::from_json(
+            r#"{"failures": {
+                "suggest": {"error": "down"},
+                "search": {"error": "down"},
+                "stream": {"error": "down"}
+            }}"#,
+        )
+        .unwrap();
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, broken)
+            .with_event_handler(counter.clone())
+            .build()
+            .await;
+
+        manager.suggest("taffy".into()).await;
+        manager.search("taffy".into(), ScrapeType::All, None).await;
+        assert!(manager.stream("1".into(), Provider::NetEase).await.is_err());
+
+        assert_eq!(counter.searches.load(Ordering::SeqCst), 1);
+        assert_eq!(counter.errors.load(Ordering::SeqCst), 3);
+    }
+}
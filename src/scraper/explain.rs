@@ -0,0 +1,87 @@
+use serde::Serialize;
+
+use super::Stream;
+
+/// One decision made while resolving the streams of a song
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "decision", rename_all = "lowercase")]
+pub enum TraceStep {
+    /// the quality tier is returned
+    Found { quality: String },
+    /// the quality tier is not returned and why, e.g. VIP required or video only format
+    Filtered { quality: String, reason: String },
+    /// anything else worth knowing, like a provider error or a host rewrite
+    Note { message: String },
+}
+
+/// Decision trace of a stream resolution, for debugging questions like "why is this track 64kbps"
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct StreamTrace {
+    pub steps: Vec<TraceStep>,
+    pub streams: Vec<Stream>,
+}
+
+impl StreamTrace {
+    pub fn found(&mut self, stream: Stream) {
+        self.steps.push(TraceStep::Found {
+            quality: stream.quality.clone(),
+        });
+        self.streams.push(stream);
+    }
+
+    pub fn filtered(&mut self, quality: impl Into<String>, reason: impl Into<String>) {
+        self.steps.push(TraceStep::Filtered {
+            quality: quality.into(),
+            reason: reason.into(),
+        });
+    }
+
+    pub fn note(&mut self, message: impl Into<String>) {
+        self.steps.push(TraceStep::Note {
+            message: message.into(),
+        });
+    }
+}
+
+impl From<Vec<Stream>> for StreamTrace {
+    fn from(streams: Vec<Stream>) -> Self {
+        let mut trace = Self::default();
+        streams.into_iter().for_each(|s| trace.found(s));
+        trace
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::scraper::Stream;
+
+    use super::StreamTrace;
+
+    #[test]
+    fn test_trace() {
+        let mut trace = StreamTrace::default();
+        trace.filtered("Hi-Res lossless", "requires a VIP account");
+        trace.found(Stream {
+            quality: "192k".into(),
+            url: "https://upos".into(),
+            bitrate: None,
+            codec: None,
+            mirror: false,
+            loudness: None,
+            preview: false,
+        });
+        trace.note("provider error: timed out");
+
+        assert_eq!(trace.streams.len(), 1);
+        assert_eq!(
+            serde_json::to_value(&trace.steps).unwrap(),
+            json!([
+                {"decision": "filtered", "quality": "Hi-Res lossless", "reason": "requires a VIP account"},
+                {"decision": "found", "quality": "192k"},
+                {"decision": "note", "message": "provider error: timed out"},
+            ])
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use parking_lot::RwLock;
+use tracing::info;
+
+use crate::{privacy::redact, util};
+
+use super::{Provider, ScrapeItem, SongCollection};
+
+/// Songs, playlists and albums saved to the library, persisted as json if `filename` is present.
+/// Search and collection results containing them are annotated as `saved` so that clients can
+/// render them without a lookup per item.
+#[derive(Debug, Default)]
+pub struct FavoriteStore {
+    filename: Option<String>,
+    ids: RwLock<BTreeMap<Provider, BTreeSet<String>>>,
+}
+
+impl FavoriteStore {
+    pub fn try_new(filename: Option<String>) -> anyhow::Result<Self> {
+        let Some(filename) = filename else {
+            return Ok(Self::default());
+        };
+
+        let ids: BTreeMap<Provider, BTreeSet<String>> = util::load_json(&filename)?;
+        info!(
+            "load favorites from {}: {} ids",
+            filename,
+            ids.values().map(BTreeSet::len).sum::<usize>()
+        );
+
+        Ok(Self {
+            filename: Some(filename),
+            ids: RwLock::new(ids),
+        })
+    }
+
+    pub fn contains(&self, provider: &Provider, id: &str) -> bool {
+        self.ids
+            .read()
+            .get(provider)
+            .map(|ids| ids.contains(id))
+            .unwrap_or_default()
+    }
+
+    /// All saved ids by provider
+    pub fn list(&self) -> BTreeMap<Provider, BTreeSet<String>> {
+        self.ids.read().clone()
+    }
+
+    /// Returns false if the id was already saved
+    pub fn insert(&self, provider: Provider, id: String) -> anyhow::Result<bool> {
+        let inserted =
+            util::update_json(self.filename.as_deref(), &mut *self.ids.write(), |ids| {
+                ids.entry(provider.clone()).or_default().insert(id.clone())
+            })?;
+        if inserted {
+            info!(
+                "save favorite: provider: {:?}, id: {}",
+                provider,
+                redact(&id)
+            );
+        }
+        Ok(inserted)
+    }
+
+    /// Save the ids at once. Returns the ids not saved before
+    pub fn extend(
+        &self,
+        provider: Provider,
+        new_ids: impl IntoIterator<Item = String>,
+    ) -> anyhow::Result<Vec<String>> {
+        let mut added = vec![];
+        util::update_json(self.filename.as_deref(), &mut *self.ids.write(), |ids| {
+            let saved = ids.entry(provider).or_default();
+            added = new_ids
+                .into_iter()
+                .filter(|id| saved.insert(id.clone()))
+                .collect();
+            !added.is_empty()
+        })?;
+        Ok(added)
+    }
+
+    /// The ids not saved yet, in the order given, without saving them
+    pub fn missing(
+        &self,
+        provider: &Provider,
+        new_ids: impl IntoIterator<Item = String>,
+    ) -> Vec<String> {
+        let ids = self.ids.read();
+        let saved = ids.get(provider);
+        let mut seen = BTreeSet::new();
+        new_ids
+            .into_iter()
+            .filter(|id| !saved.is_some_and(|s| s.contains(id)) && seen.insert(id.clone()))
+            .collect()
+    }
+
+    /// Returns false if the id was not saved
+    pub fn remove(&self, provider: &Provider, id: &str) -> anyhow::Result<bool> {
+        let removed = util::update_json(self.filename.as_deref(), &mut *self.ids.write(), |ids| {
+            let Some(saved) = ids.get_mut(provider) else {
+                return false;
+            };
+            if !saved.remove(id) {
+                return false;
+            }
+            if saved.is_empty() {
+                ids.remove(provider);
+            }
+            true
+        })?;
+        if removed {
+            info!(
+                "remove favorite: provider: {:?}, id: {}",
+                provider,
+                redact(id)
+            );
+        }
+        Ok(removed)
+    }
+
+    pub fn annotate(&self, provider: &Provider, item: &mut ScrapeItem) {
+        match item {
+            ScrapeItem::Song(s) => s.saved = self.contains(provider, &s.id),
+            ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => self.annotate_collection(provider, c),
+            ScrapeItem::Artist(_) | ScrapeItem::Radio(_) => {}
+        }
+    }
+
+    pub fn annotate_collection(&self, provider: &Provider, collection: &mut SongCollection) {
+        collection.saved = self.contains(provider, &collection.id);
+        for s in collection.songs.iter_mut() {
+            s.saved = self.contains(provider, &s.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        scraper::{fixture::FixtureScraper, Provider, ScrapeItem, Song},
+        BragiBuilder,
+    };
+
+    use super::FavoriteStore;
+
+    fn song(id: &str) -> Song {
+        Song {
+            id: id.into(),
+            name: "song".into(),
+            artists: vec![],
+            cover: None,
+            duration: None,
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+
+    #[test]
+    fn test_toggle() {
+        let filename = std::env::temp_dir()
+            .join(format!("bragi-favorites-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        let store = FavoriteStore::try_new(Some(filename.clone())).unwrap();
+        assert!(store
+            .insert(Provider::NetEase, "1901371647".into())
+            .unwrap());
+        assert!(!store
+            .insert(Provider::NetEase, "1901371647".into())
+            .unwrap());
+        assert!(store.insert(Provider::NetEase, "1".into()).unwrap());
+        assert!(store.remove(&Provider::NetEase, "1").unwrap());
+        assert!(!store.remove(&Provider::Youtube, "1").unwrap());
+        assert_eq!(
+            store.missing(
+                &Provider::NetEase,
+                ["1901371647".into(), "2".into(), "2".into()]
+            ),
+            ["2"]
+        );
+        assert_eq!(
+            store
+                .extend(Provider::NetEase, ["1901371647".into(), "2".into()])
+                .unwrap(),
+            ["2"]
+        );
+
+        let store = FavoriteStore::try_new(Some(filename.clone())).unwrap();
+        let mut saved = ScrapeItem::Song(song("1901371647"));
+        let mut other = ScrapeItem::Song(song("1"));
+        store.annotate(&Provider::NetEase, &mut saved);
+        store.annotate(&Provider::NetEase, &mut other);
+
+        assert!(matches!(saved, ScrapeItem::Song(s) if s.saved));
+        assert!(matches!(other, ScrapeItem::Song(s) if !s.saved));
+
+        // a half written library is refused rather than loaded empty and written over
+        std::fs::write(&filename, r#"{"netease": ["1901"#).unwrap();
+        assert!(FavoriteStore::try_new(Some(filename.clone())).is_err());
+        assert_eq!(
+            std::fs::read_to_string(&filename).unwrap(),
+            r#"{"netease": ["1901"#
+        );
+
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_import() {
+        let store = FavoriteStore::default();
+        store.insert(Provider::NetEase, "186016".into()).unwrap();
+        let liked = FixtureScraper::from_json(r#"{"liked": ["1901371647", "186016"]}"#).unwrap();
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, liked)
+            .with_favorite_store(store)
+            .build()
+            .await;
+
+        // reported, and left unsaved
+        assert_eq!(
+            manager
+                .import_favorites(Provider::NetEase, true)
+                .await
+                .unwrap(),
+            ["1901371647"]
+        );
+        assert!(!manager
+            .favorites()
+            .contains(&Provider::NetEase, "1901371647"));
+
+        assert_eq!(
+            manager
+                .import_favorites(Provider::NetEase, false)
+                .await
+                .unwrap(),
+            ["1901371647"]
+        );
+        assert!(manager.favorites().contains(&Provider::NetEase, "186016"));
+        assert!(manager
+            .favorites()
+            .contains(&Provider::NetEase, "1901371647"));
+        assert!(manager
+            .import_favorites(Provider::NetEase, true)
+            .await
+            .unwrap()
+            .is_empty());
+        assert!(manager
+            .import_favorites(Provider::Youtube, false)
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use regex::{Regex, RegexBuilder};
+
+use crate::settings::FilterSettings;
+
+use super::{Artist, Provider, ScrapeItem, Song, SongCollection};
+
+/// Drop unwanted results of every provider, like spam re-uploads, short clips or known bad
+/// channels
+#[derive(Debug, Default)]
+pub struct ResultFilter {
+    blocked_artists: BTreeMap<Provider, BTreeSet<String>>,
+    allowed_artists: BTreeMap<Provider, BTreeSet<String>>,
+    blocked_titles: Vec<Regex>,
+    min_duration: Option<u32>,
+}
+
+impl ResultFilter {
+    pub fn try_from_setting(setting: FilterSettings) -> anyhow::Result<Self> {
+        let blocked_titles = setting
+            .blocked_titles
+            .iter()
+            .map(|p| Ok(RegexBuilder::new(p).case_insensitive(true).build()?))
+            .collect::<anyhow::Result<_>>()?;
+        let collect = |ids: BTreeMap<Provider, Vec<String>>| {
+            ids.into_iter()
+                .map(|(p, ids)| (p, ids.into_iter().collect()))
+                .collect()
+        };
+        Ok(Self {
+            blocked_artists: collect(setting.blocked_artists),
+            allowed_artists: collect(setting.allowed_artists),
+            blocked_titles,
+            min_duration: setting.min_duration,
+        })
+    }
+
+    fn listed(list: &BTreeMap<Provider, BTreeSet<String>>, p: &Provider, a: &[Artist]) -> bool {
+        list.get(p)
+            .is_some_and(|ids| a.iter().any(|a| ids.contains(a.id.as_str())))
+    }
+
+    /// Allowed artists skip the title and duration checks, but a blocked co-artist still drops
+    /// the item
+    fn keep_titled(&self, provider: &Provider, artists: &[Artist], title: &str) -> bool {
+        if Self::listed(&self.blocked_artists, provider, artists) {
+            return false;
+        }
+        Self::listed(&self.allowed_artists, provider, artists)
+            || !self.blocked_titles.iter().any(|r| r.is_match(title))
+    }
+
+    pub fn keep_song(&self, provider: &Provider, song: &Song) -> bool {
+        if !self.keep_titled(provider, &song.artists, &song.name) {
+            return false;
+        }
+        match (self.min_duration, song.duration) {
+            (Some(min), Some(duration)) if duration < min => {
+                Self::listed(&self.allowed_artists, provider, &song.artists)
+            }
+            // unknown durations are kept
+            _ => true,
+        }
+    }
+
+    pub fn keep(&self, provider: &Provider, item: &ScrapeItem) -> bool {
+        match item {
+            ScrapeItem::Artist(a) => {
+                !Self::listed(&self.blocked_artists, provider, std::slice::from_ref(a))
+            }
+            ScrapeItem::Song(s) => self.keep_song(provider, s),
+            ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => self.keep_collection(provider, c),
+            ScrapeItem::Radio(s) => self.keep_titled(provider, &[], &s.name),
+        }
+    }
+
+    pub fn keep_collection(&self, provider: &Provider, collection: &SongCollection) -> bool {
+        self.keep_titled(provider, &collection.artists, &collection.name)
+    }
+
+    /// Drop the filtered songs of the collection. The collection itself was asked for by id and
+    /// is kept.
+    pub fn apply_collection(&self, provider: &Provider, collection: &mut SongCollection) {
+        collection.songs.retain(|s| self.keep_song(provider, s));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        scraper::{Artist, Provider, ScrapeItem, Song},
+        settings::FilterSettings,
+    };
+
+    use super::ResultFilter;
+
+    fn song(artist: &str, name: &str, duration: Option<u32>) -> ScrapeItem {
+        ScrapeItem::Song(Song {
+            id: "1".into(),
+            name: name.into(),
+            artists: vec![Artist {
+                id: artist.into(),
+                name: artist.into(),
+                description: None,
+                avatar: None,
+            }],
+            cover: None,
+            duration,
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        })
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = ResultFilter::try_from_setting(FilterSettings {
+            blocked_artists: BTreeMap::from([(Provider::Bilibili, vec!["666".into()])]),
+            allowed_artists: BTreeMap::from([(Provider::Bilibili, vec!["1".into()])]),
+            blocked_titles: vec!["搬运".into(), r"\bnightcore\b".into()],
+            min_duration: Some(30),
+        })
+        .unwrap();
+        let b = Provider::Bilibili;
+
+        assert!(filter.keep(&b, &song("2", "夜に駆ける", Some(261))));
+        assert!(!filter.keep(&b, &song("666", "夜に駆ける", Some(261))));
+        // blocked ids are per provider
+        assert!(filter.keep(&Provider::NetEase, &song("666", "夜に駆ける", Some(261))));
+        assert!(!filter.keep(&b, &song("2", "【搬运】夜に駆ける", Some(261))));
+        assert!(!filter.keep(&b, &song("2", "Idol (NIGHTCORE)", Some(261))));
+        assert!(!filter.keep(&b, &song("2", "夜に駆ける", Some(10))));
+        assert!(filter.keep(&b, &song("2", "夜に駆ける", None)));
+
+        // allowed artists skip the title and duration checks
+        assert!(filter.keep(&b, &song("1", "【搬运】夜に駆ける", Some(10))));
+    }
+
+    #[test]
+    fn test_invalid_regex() {
+        assert!(ResultFilter::try_from_setting(FilterSettings {
+            blocked_titles: vec!["(".into()],
+            ..Default::default()
+        })
+        .is_err());
+    }
+}
@@ -0,0 +1,475 @@
+use std::{collections::BTreeMap, fs::File};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use super::{
+    id::{ArtistId, CollectionId, TrackId},
+    lyrics::Lyrics,
+    Artist, ArtistDetail, ScrapeItem, ScrapeType, Scraper, SearchPage, Song, SongCollection,
+    Stream,
+};
+
+/// Search results of one page, like the providers return
+const PAGE_SIZE: usize = 10;
+const MAX_SUGGESTIONS: usize = 10;
+
+/// Content of a fixture file. Ids are raw provider ids.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct Fixture {
+    artists: Vec<Artist>,
+    songs: Vec<Song>,
+    playlists: Vec<SongCollection>,
+    albums: Vec<SongCollection>,
+    /// streams by track id
+    streams: BTreeMap<String, Vec<Stream>>,
+    /// ids of the liked songs
+    liked: Vec<String>,
+    /// LRC lyrics by track id
+    lyrics: BTreeMap<String, String>,
+    /// preview streams by track id
+    previews: BTreeMap<String, Vec<Stream>>,
+    /// failures by method name, like `search` or `stream`, to simulate a provider being down
+    failures: BTreeMap<String, Failure>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Failure {
+    /// the method returns the error
+    Error(String),
+    /// the method panics, like on an unwrap of an unexpected upstream response
+    Panic(String),
+}
+
+/// In-memory provider serving a json fixture, for developing clients with reproducible data and
+/// no network access. Every call returns the same result for the same arguments.
+pub struct FixtureScraper {
+    fixture: Fixture,
+}
+
+impl FixtureScraper {
+    pub fn try_from_file(path: &str) -> anyhow::Result<Self> {
+        let file = File::open(path).map_err(|e| anyhow!("open fixture {}: {}", path, e))?;
+        let fixture =
+            serde_json::from_reader(file).map_err(|e| anyhow!("parse fixture {}: {}", path, e))?;
+        Ok(Self { fixture })
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            fixture: serde_json::from_str(json)?,
+        })
+    }
+
+    /// Fail the method if the fixture says so
+    fn check(&self, method: &str) -> anyhow::Result<()> {
+        match self.fixture.failures.get(method) {
+            Some(Failure::Error(e)) => bail!("{}", e),
+            Some(Failure::Panic(message)) => panic!("{}", message),
+            None => Ok(()),
+        }
+    }
+
+    /// Items of the type in fixture order, artists first for `All`
+    fn items(&self, t: &ScrapeType) -> Vec<ScrapeItem> {
+        let artists = || self.fixture.artists.iter().cloned().map(ScrapeItem::Artist);
+        let songs = || self.fixture.songs.iter().cloned().map(ScrapeItem::Song);
+        let playlists = || {
+            self.fixture
+                .playlists
+                .iter()
+                .cloned()
+                .map(ScrapeItem::Playlist)
+        };
+        let albums = || self.fixture.albums.iter().cloned().map(ScrapeItem::Album);
+        match t {
+            ScrapeType::All => artists()
+                .chain(songs())
+                .chain(playlists())
+                .chain(albums())
+                .collect(),
+            ScrapeType::Artist => artists().collect(),
+            ScrapeType::Song => songs().collect(),
+            ScrapeType::Playlist => playlists().collect(),
+            ScrapeType::Album => albums().collect(),
+            ScrapeType::Radio => vec![],
+        }
+    }
+}
+
+fn name(item: &ScrapeItem) -> &str {
+    match item {
+        ScrapeItem::Artist(a) => &a.name,
+        ScrapeItem::Song(s) => &s.name,
+        ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => &c.name,
+        ScrapeItem::Radio(s) => &s.name,
+    }
+}
+
+fn contains(text: &str, keyword: &str) -> bool {
+    text.to_lowercase().contains(&keyword.to_lowercase())
+}
+
+fn artists(item: &ScrapeItem) -> &[Artist] {
+    match item {
+        ScrapeItem::Song(s) => &s.artists,
+        ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => &c.artists,
+        ScrapeItem::Artist(_) | ScrapeItem::Radio(_) => &[],
+    }
+}
+
+/// Every word of the keyword is in the name or the artists of the item, like a search of
+/// `name artist` finds the song
+fn matches(item: &ScrapeItem, keyword: &str) -> bool {
+    let text = std::iter::once(name(item))
+        .chain(artists(item).iter().map(|a| a.name.as_str()))
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    keyword
+        .to_lowercase()
+        .split_whitespace()
+        .all(|word| text.contains(word))
+}
+
+#[async_trait]
+impl Scraper for FixtureScraper {
+    async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
+        self.check("suggest")?;
+        let mut suggestions: Vec<String> = vec![];
+        for item in self.items(&ScrapeType::All) {
+            if contains(name(&item), &keyword) && !suggestions.iter().any(|s| s == name(&item)) {
+                suggestions.push(name(&item).to_string());
+            }
+        }
+        suggestions.truncate(MAX_SUGGESTIONS);
+        Ok(suggestions)
+    }
+
+    /// The continuation is the offset of the next page
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        self.check("search")?;
+        let offset = match continuation {
+            Some(c) => c
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid continuation: {}", c))?,
+            None => 0,
+        };
+        let found = self
+            .items(&t)
+            .into_iter()
+            .filter(|i| matches(i, &keyword))
+            .collect::<Vec<_>>();
+        let next = offset + PAGE_SIZE;
+        Ok(SearchPage {
+            items: found.iter().skip(offset).take(PAGE_SIZE).cloned().collect(),
+            next: (next < found.len()).then(|| next.to_string()),
+        })
+    }
+
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        self.check("collection_detail")?;
+        match self
+            .fixture
+            .playlists
+            .iter()
+            .chain(self.fixture.albums.iter())
+            .find(|c| c.id == id)
+        {
+            Some(collection) => Ok(collection.clone()),
+            None => bail!("collection not in fixture: {}", id),
+        }
+    }
+
+    async fn collection_version(&self, id: CollectionId) -> anyhow::Result<Option<String>> {
+        Ok(self.collection_detail(id).await?.version)
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        self.check("stream")?;
+        match self.fixture.streams.get(id.as_str()) {
+            Some(streams) => Ok(streams.clone()),
+            None => bail!("stream not in fixture: {}", id),
+        }
+    }
+
+    /// Empty for songs without previews in the fixture
+    async fn preview(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        self.check("preview")?;
+        Ok(self
+            .fixture
+            .previews
+            .get(id.as_str())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    async fn liked(&self) -> anyhow::Result<Vec<String>> {
+        self.check("liked")?;
+        Ok(self.fixture.liked.clone())
+    }
+
+    /// Songs crediting the artist, in fixture order
+    async fn artist_releases(&self, id: ArtistId) -> anyhow::Result<Vec<ScrapeItem>> {
+        self.check("artist_releases")?;
+        Ok(self
+            .fixture
+            .songs
+            .iter()
+            .filter(|s| s.artists.iter().any(|a| a.id == id))
+            .cloned()
+            .map(ScrapeItem::Song)
+            .collect())
+    }
+
+    /// Empty for songs without lyrics in the fixture
+    async fn lyrics(&self, id: TrackId) -> anyhow::Result<Lyrics> {
+        self.check("lyrics")?;
+        Ok(self
+            .fixture
+            .lyrics
+            .get(id.as_str())
+            .cloned()
+            .and_then(Lyrics::from_lrc)
+            .unwrap_or_default())
+    }
+
+    /// Every song and collection crediting the artist, in fixture order
+    async fn artist_detail(&self, id: ArtistId) -> anyhow::Result<ArtistDetail> {
+        self.check("artist_detail")?;
+        let Some(artist) = self.fixture.artists.iter().find(|a| a.id == id) else {
+            bail!("artist not in fixture: {}", id);
+        };
+        let credited = |artists: &[Artist]| artists.iter().any(|a| a.id == id);
+        let collections = |collections: &[SongCollection]| {
+            collections
+                .iter()
+                .filter(|c| credited(&c.artists))
+                .cloned()
+                .collect()
+        };
+        Ok(ArtistDetail {
+            artist: artist.clone(),
+            songs: self
+                .fixture
+                .songs
+                .iter()
+                .filter(|s| credited(&s.artists))
+                .cloned()
+                .collect(),
+            albums: collections(&self.fixture.albums),
+            playlists: collections(&self.fixture.playlists),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        scraper::{query::SearchFilter, ScrapeItem, ScrapeType, Scraper},
+        BragiBuilder, Provider,
+    };
+
+    use super::FixtureScraper;
+
+    fn scraper() -> FixtureScraper {
+        FixtureScraper::try_from_file("fixtures/netease.json").unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let scraper = scraper();
+        let page = scraper
+            .search("night".into(), ScrapeType::Song, None)
+            .await
+            .unwrap();
+        assert_eq!(page.items.len(), 10);
+        assert_eq!(page.next.as_deref(), Some("10"));
+
+        let last = scraper
+            .search("night".into(), ScrapeType::Song, page.next)
+            .await
+            .unwrap();
+        // matched by the artist as well
+        assert_eq!(last.items.len(), 3);
+        assert!(last.next.is_none());
+
+        let artists = scraper
+            .search("YOASOBI".into(), ScrapeType::Artist, None)
+            .await
+            .unwrap();
+        assert!(matches!(&artists.items[..], [ScrapeItem::Artist(a)] if a.name == "YOASOBI"));
+
+        assert_eq!(
+            scraper.suggest("night".into()).await.unwrap(),
+            scraper.suggest("NIGHT".into()).await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_manager() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, scraper())
+            .build()
+            .await;
+
+        let collection = manager
+            .collection_detail("1000".into(), Provider::NetEase)
+            .await
+            .unwrap();
+        assert_eq!(collection.songs.len(), 3);
+        assert_eq!(
+            manager
+                .collection_version("1000".into(), Provider::NetEase)
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("1")
+        );
+        assert!(manager
+            .collection_detail("1".into(), Provider::NetEase)
+            .await
+            .is_err());
+
+        let streams = manager
+            .stream("1001".into(), Provider::NetEase)
+            .await
+            .unwrap();
+        assert_eq!(streams[0].quality, "320k");
+        assert!(manager.stream("1".into(), Provider::NetEase).await.is_err());
+
+        let lyrics = manager
+            .lyrics("1001".into(), Provider::NetEase, None)
+            .await
+            .unwrap();
+        assert!(lyrics.synced.unwrap().contains("[00:00.50]"));
+        assert!(lyrics.plain.unwrap().starts_with("Headlights"));
+        assert!(!lyrics.fallback);
+        assert!(manager
+            .lyrics("1002".into(), Provider::NetEase, None)
+            .await
+            .unwrap()
+            .is_empty());
+
+        let detail = manager
+            .artist_detail("12345001".into(), Provider::NetEase)
+            .await
+            .unwrap();
+        assert_eq!(detail.artist.name, "YOASOBI");
+        assert!(!detail.songs.is_empty());
+        assert!(detail
+            .songs
+            .iter()
+            .all(|s| s.artists.iter().any(|a| a.name == "YOASOBI")));
+        assert!(manager
+            .artist_detail("1".into(), Provider::NetEase)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_deterministic_order() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::Youtube, scraper())
+            .with_scraper(Provider::NetEase, scraper())
+            .with_scraper(Provider::Bilibili, scraper())
+            .build()
+            .await;
+        let search = || async {
+            let results = manager.search("night".into(), ScrapeType::Song, None).await;
+            serde_json::to_string(&results.items).unwrap()
+        };
+        let first = search().await;
+        for _ in 0..5 {
+            assert_eq!(search().await, first);
+        }
+
+        // by provider, then in the order of the provider
+        let results = manager.search("night".into(), ScrapeType::Song, None).await;
+        let page = scraper()
+            .search("night".into(), ScrapeType::Song, None)
+            .await
+            .unwrap();
+        let providers = results
+            .items
+            .iter()
+            .map(|i| i.provider().clone())
+            .collect::<Vec<_>>();
+        assert!(providers.windows(2).all(|w| w[0] <= w[1]));
+        assert_eq!(providers[0], Provider::Bilibili);
+        let ids = |items: Vec<&ScrapeItem>| {
+            items
+                .into_iter()
+                .map(|i| match i {
+                    ScrapeItem::Song(s) => s.id.to_string(),
+                    _ => unreachable!(),
+                })
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ids(results
+                .items
+                .iter()
+                .filter(|i| i.provider() == &Provider::NetEase)
+                .map(|i| i.data())
+                .collect()),
+            ids(page.items.iter().collect())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_merge() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, scraper())
+            .with_scraper(Provider::Youtube, scraper())
+            .build()
+            .await;
+        let filter = SearchFilter {
+            merge: true,
+            ..Default::default()
+        };
+        let merged = manager
+            .search_filtered("night".into(), ScrapeType::Song, None, &filter)
+            .await;
+        let page = scraper()
+            .search("night".into(), ScrapeType::Song, None)
+            .await
+            .unwrap();
+        assert_eq!(merged.items.len(), page.items.len());
+        for (item, song) in merged.items.iter().zip(&page.items) {
+            assert_eq!(item.provider(), &Provider::NetEase);
+            assert!(
+                matches!((item.data(), song), (ScrapeItem::Song(a), ScrapeItem::Song(b)) if a.id == b.id)
+            );
+            assert_eq!(item.alternatives().len(), 1);
+            assert_eq!(item.alternatives()[0].provider, Provider::Youtube);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compare() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, scraper())
+            .build()
+            .await;
+
+        let sources = manager.compare("Night Drive".into(), Some(183)).await;
+        assert_eq!(sources.len(), 1);
+        assert_eq!(sources[0].song.as_ref().unwrap().id.as_str(), "1001");
+        assert!(sources[0].available);
+        assert_eq!(sources[0].bitrate, Some(320_000));
+        assert_eq!(sources[0].qualities, vec!["320k", "128k"]);
+
+        let sources = manager.compare("Night Drive".into(), Some(600)).await;
+        assert!(!sources[0].available);
+        assert_eq!(sources[0].error.as_deref(), Some("no matching song"));
+    }
+}
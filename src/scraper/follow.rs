@@ -0,0 +1,305 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, VecDeque},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{privacy::redact, settings::FollowSettings, util};
+
+use super::{id::ArtistId, Provider, ScrapeItem, ScraperManager};
+
+/// Releases kept in the feed, the oldest are dropped first
+const FEED_CAPACITY: usize = 500;
+
+/// A new upload or release of a followed artist
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Release {
+    pub provider: Provider,
+    pub artist: ArtistId,
+    pub item: ScrapeItem,
+    /// unix seconds when the release was found
+    pub found_at: u64,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+struct Follows {
+    /// ids of the releases returned by the last check of each followed artist. None until the
+    /// first check, which only takes note of the existing releases.
+    artists: BTreeMap<Provider, BTreeMap<String, Option<BTreeSet<String>>>>,
+    /// newest first
+    feed: VecDeque<Release>,
+}
+
+/// Artists followed for new releases and the feed of the releases found, persisted as json if
+/// `filename` is present
+#[derive(Debug, Default)]
+pub struct FollowStore {
+    filename: Option<String>,
+    follows: RwLock<Follows>,
+}
+
+impl FollowStore {
+    pub fn try_new(filename: Option<String>) -> anyhow::Result<Self> {
+        let Some(filename) = filename else {
+            return Ok(Self::default());
+        };
+
+        let follows: Follows = util::load_json(&filename)?;
+        info!(
+            "load follows from {}: {} artists, {} releases",
+            filename,
+            follows.artists.values().map(BTreeMap::len).sum::<usize>(),
+            follows.feed.len()
+        );
+
+        Ok(Self {
+            filename: Some(filename),
+            follows: RwLock::new(follows),
+        })
+    }
+
+    /// All followed artist ids by provider
+    pub fn list(&self) -> BTreeMap<Provider, BTreeSet<String>> {
+        self.follows
+            .read()
+            .artists
+            .iter()
+            .map(|(p, artists)| (p.clone(), artists.keys().cloned().collect()))
+            .collect()
+    }
+
+    /// Returns false if the artist was already followed
+    pub fn follow(&self, provider: Provider, id: String) -> anyhow::Result<bool> {
+        let followed = self.update(|follows| {
+            let artists = follows.artists.entry(provider.clone()).or_default();
+            if artists.contains_key(&id) {
+                return false;
+            }
+            artists.insert(id.clone(), None);
+            true
+        })?;
+        if followed {
+            info!(
+                "follow artist: provider: {:?}, id: {}",
+                provider,
+                redact(&id)
+            );
+        }
+        Ok(followed)
+    }
+
+    /// Returns false if the artist was not followed. Releases already in the feed are kept.
+    pub fn unfollow(&self, provider: &Provider, id: &str) -> anyhow::Result<bool> {
+        let unfollowed = self.update(|follows| {
+            let Some(artists) = follows.artists.get_mut(provider) else {
+                return false;
+            };
+            if artists.remove(id).is_none() {
+                return false;
+            }
+            if artists.is_empty() {
+                follows.artists.remove(provider);
+            }
+            true
+        })?;
+        if unfollowed {
+            info!(
+                "unfollow artist: provider: {:?}, id: {}",
+                provider,
+                redact(id)
+            );
+        }
+        Ok(unfollowed)
+    }
+
+    /// The latest releases, newest first
+    pub fn feed(&self, limit: usize) -> Vec<Release> {
+        self.follows
+            .read()
+            .feed
+            .iter()
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    /// Compare the current releases of the artist with the last check and put the new ones
+    /// into the feed. Returns the new releases.
+    pub fn record(
+        &self,
+        provider: &Provider,
+        artist: &str,
+        items: Vec<ScrapeItem>,
+    ) -> anyhow::Result<Vec<Release>> {
+        let mut releases = vec![];
+        self.update(|follows| {
+            // unfollowed during the check
+            let Some(seen) = follows
+                .artists
+                .get_mut(provider)
+                .and_then(|artists| artists.get_mut(artist))
+            else {
+                return false;
+            };
+
+            let ids = items.iter().map(|i| i.id().to_string()).collect();
+            releases = match seen.replace(ids) {
+                Some(seen) => {
+                    let found_at = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    items
+                        .into_iter()
+                        .filter(|i| !seen.contains(i.id()))
+                        .map(|item| Release {
+                            provider: provider.clone(),
+                            artist: artist.into(),
+                            item,
+                            found_at,
+                        })
+                        .collect()
+                }
+                None => vec![],
+            };
+
+            for release in releases.iter().rev() {
+                follows.feed.push_front(release.clone());
+            }
+            follows.feed.truncate(FEED_CAPACITY);
+            true
+        })?;
+        Ok(releases)
+    }
+
+    /// Change the follows by `f`, saved if it returns true
+    fn update(&self, f: impl FnOnce(&mut Follows) -> bool) -> anyhow::Result<bool> {
+        util::update_json(self.filename.as_deref(), &mut *self.follows.write(), f)
+    }
+}
+
+#[derive(Serialize)]
+struct Notification<'a> {
+    releases: &'a [Release],
+}
+
+/// Check the followed artists every `interval_minutes`, once a minute at most, and post the new
+/// releases to the webhooks as `{"releases": [...]}`. Runs until the process exits.
+pub async fn watch(manager: ScraperManager, setting: FollowSettings) {
+    let client = reqwest::Client::new();
+    let minutes = setting.interval_minutes.max(1);
+    let mut interval = tokio::time::interval(Duration::from_secs(minutes * 60));
+    loop {
+        interval.tick().await;
+        let releases = manager.check_releases().await;
+        if releases.is_empty() {
+            continue;
+        }
+        info!("new releases of followed artists: {}", releases.len());
+
+        let notification = Notification {
+            releases: &releases,
+        };
+        for webhook in &setting.webhooks {
+            match client
+                .post(webhook)
+                .json(&notification)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+            {
+                Ok(_) => {}
+                Err(e) => warn!("notify webhook {} failed: {}", webhook, e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        scraper::{fixture::FixtureScraper, Provider, ScrapeItem, Song},
+        BragiBuilder,
+    };
+
+    use super::FollowStore;
+
+    fn song(id: &str) -> ScrapeItem {
+        ScrapeItem::Song(Song {
+            id: id.into(),
+            name: "song".into(),
+            artists: vec![],
+            cover: None,
+            duration: None,
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        })
+    }
+
+    #[test]
+    fn test_record() {
+        let store = FollowStore::default();
+        let p = Provider::Youtube;
+        assert!(store.follow(p.clone(), "UC1".into()).unwrap());
+        assert!(!store.follow(p.clone(), "UC1".into()).unwrap());
+
+        // the first check only notes the existing releases
+        let new = store.record(&p, "UC1", vec![song("a"), song("b")]).unwrap();
+        assert!(new.is_empty());
+
+        let new = store
+            .record(&p, "UC1", vec![song("d"), song("c"), song("a")])
+            .unwrap();
+        let ids = new.iter().map(|r| r.item.id()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["d", "c"]);
+        assert_eq!(store.feed(1)[0].item.id(), "d");
+
+        // not followed
+        assert!(store.record(&p, "UC2", vec![song("e")]).unwrap().is_empty());
+        assert!(store.unfollow(&p, "UC1").unwrap());
+        assert!(store.list().is_empty());
+        assert_eq!(store.feed(10).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_check_releases() {
+        let releases = FixtureScraper::from_json(
+            r#"{"songs": [
+                {"id": "1", "name": "new", "artists": [{"id": "1", "name": "artist"}]},
+                {"id": "0", "name": "old", "artists": [{"id": "1", "name": "artist"}]}
+            ]}"#,
+        )
+        .unwrap();
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, releases)
+            .build()
+            .await;
+        assert!(manager
+            .follows()
+            .follow(Provider::NetEase, "1".into())
+            .unwrap());
+        // checked before the new song was released
+        assert!(manager
+            .follows()
+            .record(&Provider::NetEase, "1", vec![song("0")])
+            .unwrap()
+            .is_empty());
+        // invalid ids are rejected before any check
+        assert!(manager
+            .artist_releases("abc".into(), Provider::NetEase)
+            .await
+            .is_err());
+
+        let releases = manager.check_releases().await;
+        assert_eq!(releases.len(), 1);
+        assert_eq!(releases[0].item.id(), "1");
+        assert_eq!(releases[0].artist.as_str(), "1");
+        assert!(manager.check_releases().await.is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// Self-reported state of a provider, e.g. while cooling down from a Bilibili risk control
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Health {
+    /// calls are expected to fail or to be slowed down
+    pub degraded: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// seconds until the provider is expected to recover
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after: Option<u64>,
+}
+
+impl Health {
+    pub fn degraded(reason: String, retry_after: Duration) -> Self {
+        Self {
+            degraded: true,
+            reason: Some(reason),
+            retry_after: Some(retry_after.as_secs()),
+        }
+    }
+}
+
+/// Upstream refuses the calls for a while, e.g. Bilibili risk control. Retrying before
+/// `retry_after` makes it worse.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub reason: String,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "rate limited: {}, retry after {}s",
+            self.reason,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for RateLimited {}
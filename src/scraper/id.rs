@@ -0,0 +1,311 @@
+use std::{borrow::Cow, fmt, ops::Deref, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::Provider;
+
+lazy_static! {
+    static ref DIGITS: Regex = Regex::new(r"^\d+$").unwrap();
+    static ref BVID: Regex = Regex::new(r"^BV[0-9A-Za-z]{10}$").unwrap();
+    static ref BILI_TRACK: Regex = Regex::new(r"^BV[0-9A-Za-z]{10}::\d+$").unwrap();
+    static ref YOUTUBE_VIDEO: Regex = Regex::new(r"^[0-9A-Za-z_-]{11}$").unwrap();
+    static ref YOUTUBE_ID: Regex = Regex::new(r"^[0-9A-Za-z_-]+$").unwrap();
+    static ref SPOTIFY: Regex = Regex::new(r"^[0-9A-Za-z]{22}$").unwrap();
+    /// albums and playlists share the format, so albums are prefixed
+    static ref SPOTIFY_COLLECTION: Regex =
+        Regex::new(r"^(?:album:|playlist:)?[0-9A-Za-z]{22}$").unwrap();
+    /// relative path below the music directory
+    static ref LOCAL_PATH: Regex = Regex::new(r"^[^/]+(?:/[^/]+)*$").unwrap();
+    static ref ANY: Regex = Regex::new(r"^.+$").unwrap();
+    /// radio-browser station uuid
+    static ref UUID: Regex =
+        Regex::new(r"^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$").unwrap();
+}
+
+/// Prefix of opaque ids. Raw ids handed out never start with it, so the two are told apart.
+const OPAQUE_PREFIX: &str = "~";
+
+/// Whether the raw id goes into paths and query params as is, like the ids of NetEase, YouTube
+/// and Bilibili
+fn url_safe(id: &str) -> bool {
+    !id.is_empty()
+        && !id.starts_with(OPAQUE_PREFIX)
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':'))
+}
+
+/// The id handed to clients: raw if url safe, so that the ids of the providers keep their wire
+/// format, and otherwise wrapped into an opaque url safe envelope, e.g. paths of local files
+pub fn encode(id: &str) -> String {
+    match url_safe(id) {
+        true => id.to_string(),
+        false => format!("{}{}", OPAQUE_PREFIX, URL_SAFE_NO_PAD.encode(id)),
+    }
+}
+
+/// Unwrap an opaque id. Anything else is a raw id, kept as is.
+pub fn decode(id: &str) -> Cow<'_, str> {
+    id.strip_prefix(OPAQUE_PREFIX)
+        .and_then(|s| URL_SAFE_NO_PAD.decode(s).ok())
+        .and_then(|b| String::from_utf8(b).ok())
+        .map_or(Cow::Borrowed(id), Cow::Owned)
+}
+
+/// The id does not match the id format of the provider. Returned before any upstream request.
+#[derive(Debug)]
+pub struct InvalidId {
+    pub provider: Provider,
+    pub id: String,
+}
+
+impl fmt::Display for InvalidId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid {} id: {}", self.provider, self.id)
+    }
+}
+
+impl std::error::Error for InvalidId {}
+
+macro_rules! id_type {
+    ($(#[$doc:meta])* $name:ident) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        pub struct $name(String);
+
+        impl $name {
+            /// Validate an id from a client, opaque or raw, against the id format of the provider
+            pub fn parse(provider: &Provider, id: &str) -> Result<Self, InvalidId> {
+                let raw = decode(id);
+                match Self::pattern(provider).is_match(&raw) {
+                    true => Ok(Self(raw.into_owned())),
+                    false => Err(InvalidId {
+                        provider: provider.clone(),
+                        id: id.to_string(),
+                    }),
+                }
+            }
+
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+
+            pub fn into_inner(self) -> String {
+                self.0
+            }
+        }
+
+        /// Ids returned by the provider itself are trusted as is
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_string())
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+
+        /// Clients get the raw id unless it needs escaping
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(&encode(&self.0))
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let s: String = Deserialize::deserialize(deserializer)?;
+                Ok(Self(decode(&s).into_owned()))
+            }
+        }
+    };
+}
+
+id_type!(
+    /// Id of a song: a NetEase song id, a YouTube video id, `{bvid}::{cid}` of a Bilibili video
+    /// page or the path of a local file
+    TrackId
+);
+
+id_type!(
+    /// Id of a playlist or an album: a NetEase playlist or album id, a YouTube playlist id, a
+    /// Bilibili bvid or the directory of local files
+    CollectionId
+);
+
+id_type!(
+    /// Id of an artist: a NetEase artist or user id, a YouTube channel id, a Bilibili mid or the
+    /// name of a local artist
+    ArtistId
+);
+
+impl TrackId {
+    fn pattern(provider: &Provider) -> &'static Regex {
+        match provider {
+            Provider::Bilibili => &BILI_TRACK,
+            Provider::Local => &LOCAL_PATH,
+            Provider::NetEase => &DIGITS,
+            Provider::Radio => &UUID,
+            Provider::Spotify => &SPOTIFY,
+            Provider::Youtube => &YOUTUBE_VIDEO,
+        }
+    }
+}
+
+impl CollectionId {
+    fn pattern(provider: &Provider) -> &'static Regex {
+        match provider {
+            Provider::Bilibili => &BVID,
+            Provider::Local => &LOCAL_PATH,
+            Provider::NetEase => &DIGITS,
+            Provider::Radio => &UUID,
+            Provider::Spotify => &SPOTIFY_COLLECTION,
+            Provider::Youtube => &YOUTUBE_ID,
+        }
+    }
+}
+
+impl ArtistId {
+    fn pattern(provider: &Provider) -> &'static Regex {
+        match provider {
+            Provider::Bilibili | Provider::NetEase => &DIGITS,
+            // artist names
+            Provider::Local => &ANY,
+            Provider::Radio => &UUID,
+            Provider::Spotify => &SPOTIFY,
+            Provider::Youtube => &YOUTUBE_ID,
+        }
+    }
+}
+
+/// A page of a Bilibili video, the unit of audio on Bilibili. Encoded as `{bvid}::{cid}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BiliTrackId {
+    pub bvid: String,
+    pub cid: u64,
+}
+
+impl FromStr for BiliTrackId {
+    type Err = InvalidId;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidId {
+            provider: Provider::Bilibili,
+            id: s.to_string(),
+        };
+        let (bvid, cid) = s.split_once("::").ok_or_else(invalid)?;
+        if !BVID.is_match(bvid) {
+            return Err(invalid());
+        }
+        Ok(Self {
+            bvid: bvid.to_string(),
+            cid: cid.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+impl fmt::Display for BiliTrackId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}::{}", self.bvid, self.cid)
+    }
+}
+
+impl From<BiliTrackId> for TrackId {
+    fn from(val: BiliTrackId) -> Self {
+        Self(val.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::Provider;
+
+    use super::{decode, encode, BiliTrackId, CollectionId, TrackId};
+
+    #[test]
+    fn test_parse() {
+        assert!(TrackId::parse(&Provider::NetEase, "1901371647").is_ok());
+        assert!(TrackId::parse(&Provider::NetEase, "1901371647&limit=1").is_err());
+        assert!(TrackId::parse(&Provider::Youtube, "K_x2r8vJxZ4").is_ok());
+        assert!(TrackId::parse(&Provider::Youtube, "K_x2r8vJxZ").is_err());
+        assert!(TrackId::parse(&Provider::Bilibili, "BV1dZ4y1g7ag::266767355").is_ok());
+        assert!(TrackId::parse(&Provider::Bilibili, "BV1dZ4y1g7ag").is_err());
+        assert!(CollectionId::parse(&Provider::Bilibili, "BV1dZ4y1g7ag").is_ok());
+        assert!(
+            CollectionId::parse(&Provider::Youtube, "PLtrsXT0Azk1lh-F9RxHOlPBhpUcn-x96X").is_ok()
+        );
+        assert!(CollectionId::parse(&Provider::Spotify, "album:4aawyAB9vmqN3uQ7FjRGTy").is_ok());
+        assert!(CollectionId::parse(&Provider::Spotify, "37i9dQZF1DXcBWIGoYBM5M").is_ok());
+        assert!(CollectionId::parse(&Provider::Spotify, "track:4aawyAB9vmqN3uQ7FjRGTy").is_err());
+
+        let e = TrackId::parse(&Provider::NetEase, "abc").unwrap_err();
+        assert_eq!(e.to_string(), "invalid netease id: abc");
+    }
+
+    #[test]
+    fn test_bili_track() {
+        let id = "BV1dZ4y1g7ag::266767355".parse::<BiliTrackId>().unwrap();
+        assert_eq!(id.bvid, "BV1dZ4y1g7ag");
+        assert_eq!(id.cid, 266767355);
+        assert_eq!(TrackId::from(id).as_str(), "BV1dZ4y1g7ag::266767355");
+
+        assert!("BV1dZ4y1g7ag::".parse::<BiliTrackId>().is_err());
+        assert!("BV1dZ4y1g7ag::1::2".parse::<BiliTrackId>().is_err());
+        assert!("av170001::1".parse::<BiliTrackId>().is_err());
+    }
+
+    #[test]
+    fn test_opaque() {
+        // ids of the providers keep their wire format
+        assert_eq!(encode("BV1dZ4y1g7ag::266767355"), "BV1dZ4y1g7ag::266767355");
+        assert_eq!(encode("1901371647"), "1901371647");
+        assert_eq!(
+            encode("album:4aawyAB9vmqN3uQ7FjRGTy"),
+            "album:4aawyAB9vmqN3uQ7FjRGTy"
+        );
+
+        let raw = "YOASOBI/THE BOOK/01 夜に駆ける.flac";
+        let opaque = encode(raw);
+        assert!(opaque.starts_with('~'));
+        assert!(opaque[1..]
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode(&opaque), raw);
+        assert_eq!(decode(raw), raw);
+        assert_eq!(decode("~not base64!"), "~not base64!");
+        // would be taken for an opaque id
+        assert!(encode("~abc").starts_with("~fmFi"));
+
+        let id = TrackId::parse(&Provider::Local, &opaque).unwrap();
+        assert_eq!(id, TrackId::parse(&Provider::Local, raw).unwrap());
+        assert_eq!(id.as_str(), raw);
+
+        let json = serde_json::to_string(&id).unwrap();
+        assert_eq!(json, format!("\"{}\"", opaque));
+        assert_eq!(serde_json::from_str::<TrackId>(&json).unwrap(), id);
+        assert_eq!(
+            serde_json::from_str::<TrackId>(&format!("\"{}\"", raw)).unwrap(),
+            id
+        );
+    }
+}
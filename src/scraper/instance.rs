@@ -0,0 +1,281 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use parking_lot::Mutex;
+use serde::Serialize;
+use tracing::{info, warn};
+
+/// Weight of the latest call in the moving averages
+const SMOOTHING: f64 = 0.2;
+
+/// Failure of a call on an instance
+#[derive(Debug)]
+pub enum InstanceError {
+    /// the instance is unreachable or broken, another one may do
+    Instance(String),
+    /// answer of the upstream, the same on every instance, like a video being unavailable
+    Upstream(String),
+}
+
+/// Health of an upstream instance as seen from the calls on it
+#[derive(Debug, Clone, Serialize)]
+pub struct InstanceStats {
+    pub url: String,
+    pub successes: u64,
+    pub failures: u64,
+    /// moving average of the share of successful calls, so that old failures fade out
+    pub success_rate: f64,
+    /// moving average of successful calls
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<f64>,
+    /// failures since the last success
+    pub consecutive_failures: u32,
+    /// skipped by calls until a probe finds it working again
+    pub benched: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+}
+
+impl InstanceStats {
+    fn new(url: String) -> Self {
+        Self {
+            url,
+            successes: 0,
+            failures: 0,
+            success_rate: 1.0,
+            latency_ms: None,
+            consecutive_failures: 0,
+            benched: false,
+            last_error: None,
+        }
+    }
+
+    /// Preference among the instances: the success rate, less a tenth for every second of latency
+    fn score(&self) -> f64 {
+        self.success_rate / (1.0 + self.latency_ms.unwrap_or_default() / 10_000.0)
+    }
+}
+
+/// Instances serving the same upstream, like public Invidious instances. Calls go to the best
+/// scoring instance and move on to the next one if it fails. Instances failing `bench_after` times
+/// in a row are benched until a probe finds them working again.
+pub struct InstancePool<C> {
+    clients: Vec<C>,
+    stats: Mutex<Vec<InstanceStats>>,
+    bench_after: u32,
+}
+
+impl<C> InstancePool<C> {
+    pub fn new(instances: Vec<(String, C)>, bench_after: u32) -> Self {
+        let (urls, clients): (Vec<_>, Vec<_>) = instances.into_iter().unzip();
+        Self {
+            clients,
+            stats: Mutex::new(urls.into_iter().map(InstanceStats::new).collect()),
+            bench_after: bench_after.max(1),
+        }
+    }
+
+    pub fn stats(&self) -> Vec<InstanceStats> {
+        self.stats.lock().clone()
+    }
+
+    pub fn all_benched(&self) -> bool {
+        self.stats.lock().iter().all(|s| s.benched)
+    }
+
+    /// Indexes of the instances to try in turn: the ones not benched, best first. Every instance
+    /// in the configured order if all of them are benched, as a call failing anyway costs nothing.
+    fn order(&self) -> Vec<usize> {
+        let stats = self.stats.lock();
+        let mut order = (0..stats.len())
+            .filter(|i| !stats[*i].benched)
+            .collect::<Vec<_>>();
+        if order.is_empty() {
+            return (0..stats.len()).collect();
+        }
+        // stable, so the configured order breaks ties
+        order.sort_by(|a, b| stats[*b].score().total_cmp(&stats[*a].score()));
+        order
+    }
+
+    fn record(&self, i: usize, result: Result<Duration, &str>) {
+        let mut stats = self.stats.lock();
+        let s = &mut stats[i];
+        match result {
+            Ok(latency) => {
+                let latency = latency.as_secs_f64() * 1000.0;
+                s.successes += 1;
+                s.success_rate += SMOOTHING * (1.0 - s.success_rate);
+                s.latency_ms = Some(match s.latency_ms {
+                    Some(average) => average + SMOOTHING * (latency - average),
+                    None => latency,
+                });
+                s.consecutive_failures = 0;
+                if s.benched {
+                    info!("instance back: {}", s.url);
+                    s.benched = false;
+                }
+            }
+            Err(e) => {
+                s.failures += 1;
+                s.success_rate -= SMOOTHING * s.success_rate;
+                s.consecutive_failures += 1;
+                s.last_error = Some(e.to_string());
+                if !s.benched && s.consecutive_failures >= self.bench_after {
+                    warn!(
+                        "bench instance {} after {} failures: {}",
+                        s.url, s.consecutive_failures, e
+                    );
+                    s.benched = true;
+                }
+            }
+        }
+    }
+
+    /// Call the instances in turn until one of them succeeds or the upstream refuses the call
+    pub async fn call<'a, T, F, Fut>(&'a self, f: F) -> anyhow::Result<T>
+    where
+        F: Fn(&'a C) -> Fut,
+        Fut: Future<Output = Result<T, InstanceError>>,
+    {
+        let mut last_error = None;
+        for i in self.order() {
+            let start = Instant::now();
+            match f(&self.clients[i]).await {
+                Ok(v) => {
+                    self.record(i, Ok(start.elapsed()));
+                    return Ok(v);
+                }
+                // the instance works, the answer is just not the one hoped for
+                Err(InstanceError::Upstream(e)) => {
+                    self.record(i, Ok(start.elapsed()));
+                    return Err(anyhow!(e));
+                }
+                Err(InstanceError::Instance(e)) => {
+                    self.record(i, Err(&e));
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(anyhow!(
+            "every instance failed, last error: {}",
+            last_error.unwrap_or_else(|| "no instance".to_string())
+        ))
+    }
+
+    /// Call `probe` on every benched instance, bringing back the ones it succeeds on
+    pub async fn probe<'a, F, Fut>(&'a self, probe: F)
+    where
+        F: Fn(&'a C) -> Fut,
+        Fut: Future<Output = Result<(), InstanceError>>,
+    {
+        let benched = self
+            .stats
+            .lock()
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.benched)
+            .map(|(i, _)| i)
+            .collect::<Vec<_>>();
+        for i in benched {
+            let start = Instant::now();
+            match probe(&self.clients[i]).await {
+                Ok(_) => self.record(i, Ok(start.elapsed())),
+                Err(InstanceError::Instance(e) | InstanceError::Upstream(e)) => {
+                    self.record(i, Err(&e))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use super::{InstanceError, InstancePool};
+
+    fn pool() -> InstancePool<&'static str> {
+        InstancePool::new(
+            ["a", "b", "c"]
+                .map(|i| (format!("https://{}.example", i), i))
+                .into(),
+            2,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_call() {
+        let pool = pool();
+        let called = parking_lot::Mutex::new(vec![]);
+        let result = pool
+            .call(|c| {
+                called.lock().push(*c);
+                async move {
+                    match *c {
+                        "a" => Err(InstanceError::Instance("connection refused".into())),
+                        _ => Ok(*c),
+                    }
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), "b");
+        assert_eq!(*called.lock(), ["a", "b"]);
+        let stats = pool.stats();
+        assert_eq!((stats[0].failures, stats[1].successes), (1, 1));
+
+        // the upstream answers the same anywhere
+        called.lock().clear();
+        let result = pool
+            .call(|c| {
+                called.lock().push(*c);
+                async { Err::<(), _>(InstanceError::Upstream("video unavailable".into())) }
+            })
+            .await;
+        assert_eq!(result.unwrap_err().to_string(), "video unavailable");
+        assert_eq!(called.lock().len(), 1);
+
+        let result = pool
+            .call(|_| async { Err::<(), _>(InstanceError::Instance("timed out".into())) })
+            .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "every instance failed, last error: timed out"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bench() {
+        let pool = pool();
+        for _ in 0..2 {
+            pool.record(0, Err("502 bad gateway"));
+        }
+        pool.record(1, Ok(Duration::from_secs(5)));
+        pool.record(2, Ok(Duration::from_millis(200)));
+        assert!(pool.stats()[0].benched);
+        // the slower one last
+        assert_eq!(pool.order(), [2, 1]);
+
+        pool.probe(|c| async move {
+            match *c {
+                "a" => Ok(()),
+                _ => unreachable!("only benched instances are probed"),
+            }
+        })
+        .await;
+        let stats = pool.stats();
+        assert!(!stats[0].benched);
+        assert_eq!(stats[0].consecutive_failures, 0);
+        assert_eq!(stats[0].last_error.as_deref(), Some("502 bad gateway"));
+
+        for i in 0..3 {
+            pool.record(i, Err("down"));
+            pool.record(i, Err("down"));
+        }
+        assert!(pool.all_benched());
+        assert_eq!(pool.order(), [0, 1, 2]);
+    }
+}
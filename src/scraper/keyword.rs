@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+
+use tracing::info;
+
+use crate::settings::{KeywordSettings, KeywordVariant};
+
+/// Generate search keyword variants in different scripts, since providers index different ones.
+/// e.g. NetEase mostly indexes simplified chinese while YouTube titles are often traditional.
+#[derive(Debug, Default)]
+pub struct KeywordNormalizer {
+    s2t: HashMap<char, char>,
+    t2s: HashMap<char, char>,
+}
+
+impl KeywordNormalizer {
+    pub fn try_from_setting(setting: KeywordSettings) -> anyhow::Result<Self> {
+        let mut normalizer = Self::default();
+        if let Some(path) = setting.s2t_dict {
+            normalizer.s2t = load_opencc_chars(&std::fs::read_to_string(&path)?);
+            info!(
+                "load s2t dict from {}: {} chars",
+                path,
+                normalizer.s2t.len()
+            );
+        }
+        if let Some(path) = setting.t2s_dict {
+            normalizer.t2s = load_opencc_chars(&std::fs::read_to_string(&path)?);
+            info!(
+                "load t2s dict from {}: {} chars",
+                path,
+                normalizer.t2s.len()
+            );
+        }
+        Ok(normalizer)
+    }
+
+    /// The original keyword followed by its distinct variants
+    pub fn variants(&self, keyword: &str, wanted: &[KeywordVariant]) -> Vec<String> {
+        let mut variants = vec![keyword.to_string()];
+        for v in wanted {
+            let variant = match v {
+                KeywordVariant::Halfwidth => to_halfwidth(keyword),
+                KeywordVariant::Simplified => map_chars(keyword, &self.t2s),
+                KeywordVariant::Traditional => map_chars(keyword, &self.s2t),
+                KeywordVariant::Romaji => to_romaji(keyword),
+            };
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+        }
+        variants
+    }
+}
+
+/// OpenCC dictionary line format: `简\t簡 简` where the first candidate is the preferred one.
+/// Phrase entries are skipped since only characters are mapped.
+fn load_opencc_chars(content: &str) -> HashMap<char, char> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let (from, to) = line.split_once('\t')?;
+            let mut from = from.chars();
+            let mut to = to.split(' ').next()?.chars();
+            match (from.next(), from.next(), to.next(), to.next()) {
+                (Some(f), None, Some(t), None) => Some((f, t)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn map_chars(s: &str, dict: &HashMap<char, char>) -> String {
+    s.chars().map(|c| *dict.get(&c).unwrap_or(&c)).collect()
+}
+
+/// Fullwidth ASCII variants (U+FF01..U+FF5E) and ideographic space to their halfwidth form
+pub fn to_halfwidth(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '\u{3000}' => ' ',
+            '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+            _ => c,
+        })
+        .collect()
+}
+
+/// Katakana to hiragana so that a single romaji table covers both
+fn to_hiragana(c: char) -> char {
+    match c {
+        '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+const DIGRAPHS: [(&str, &str); 33] = [
+    ("きゃ", "kya"),
+    ("きゅ", "kyu"),
+    ("きょ", "kyo"),
+    ("しゃ", "sha"),
+    ("しゅ", "shu"),
+    ("しょ", "sho"),
+    ("ちゃ", "cha"),
+    ("ちゅ", "chu"),
+    ("ちょ", "cho"),
+    ("にゃ", "nya"),
+    ("にゅ", "nyu"),
+    ("にょ", "nyo"),
+    ("ひゃ", "hya"),
+    ("ひゅ", "hyu"),
+    ("ひょ", "hyo"),
+    ("みゃ", "mya"),
+    ("みゅ", "myu"),
+    ("みょ", "myo"),
+    ("りゃ", "rya"),
+    ("りゅ", "ryu"),
+    ("りょ", "ryo"),
+    ("ぎゃ", "gya"),
+    ("ぎゅ", "gyu"),
+    ("ぎょ", "gyo"),
+    ("じゃ", "ja"),
+    ("じゅ", "ju"),
+    ("じょ", "jo"),
+    ("びゃ", "bya"),
+    ("びゅ", "byu"),
+    ("びょ", "byo"),
+    ("ぴゃ", "pya"),
+    ("ぴゅ", "pyu"),
+    ("ぴょ", "pyo"),
+];
+
+fn kana_to_romaji(c: char) -> Option<&'static str> {
+    Some(match c {
+        'あ' => "a",
+        'い' => "i",
+        'う' => "u",
+        'え' => "e",
+        'お' => "o",
+        'か' => "ka",
+        'き' => "ki",
+        'く' => "ku",
+        'け' => "ke",
+        'こ' => "ko",
+        'さ' => "sa",
+        'し' => "shi",
+        'す' => "su",
+        'せ' => "se",
+        'そ' => "so",
+        'た' => "ta",
+        'ち' => "chi",
+        'つ' => "tsu",
+        'て' => "te",
+        'と' => "to",
+        'な' => "na",
+        'に' => "ni",
+        'ぬ' => "nu",
+        'ね' => "ne",
+        'の' => "no",
+        'は' => "ha",
+        'ひ' => "hi",
+        'ふ' => "fu",
+        'へ' => "he",
+        'ほ' => "ho",
+        'ま' => "ma",
+        'み' => "mi",
+        'む' => "mu",
+        'め' => "me",
+        'も' => "mo",
+        'や' => "ya",
+        'ゆ' => "yu",
+        'よ' => "yo",
+        'ら' => "ra",
+        'り' => "ri",
+        'る' => "ru",
+        'れ' => "re",
+        'ろ' => "ro",
+        'わ' => "wa",
+        'を' => "wo",
+        'ん' => "n",
+        'が' => "ga",
+        'ぎ' => "gi",
+        'ぐ' => "gu",
+        'げ' => "ge",
+        'ご' => "go",
+        'ざ' => "za",
+        'じ' => "ji",
+        'ず' => "zu",
+        'ぜ' => "ze",
+        'ぞ' => "zo",
+        'だ' => "da",
+        'ぢ' => "ji",
+        'づ' => "zu",
+        'で' => "de",
+        'ど' => "do",
+        'ば' => "ba",
+        'び' => "bi",
+        'ぶ' => "bu",
+        'べ' => "be",
+        'ぼ' => "bo",
+        'ぱ' => "pa",
+        'ぴ' => "pi",
+        'ぷ' => "pu",
+        'ぺ' => "pe",
+        'ぽ' => "po",
+        'ぁ' => "a",
+        'ぃ' => "i",
+        'ぅ' => "u",
+        'ぇ' => "e",
+        'ぉ' => "o",
+        'ゃ' => "ya",
+        'ゅ' => "yu",
+        'ょ' => "yo",
+        'ゔ' => "vu",
+        _ => return None,
+    })
+}
+
+/// Hepburn romanization of hiragana and katakana. Other characters are kept as is.
+pub fn to_romaji(s: &str) -> String {
+    let chars = s.chars().map(to_hiragana).collect::<Vec<_>>();
+    let mut romaji = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+
+        // sokuon: double the first consonant of the next syllable
+        if c == 'っ' {
+            if let Some(next) = chars.get(i + 1).and_then(|&n| kana_to_romaji(n)) {
+                romaji.push_str(&next[..1]);
+            }
+            i += 1;
+            continue;
+        }
+
+        // chōonpu: repeat the previous vowel
+        if c == 'ー' {
+            if let Some(last) = romaji.chars().last().filter(|l| "aiueo".contains(*l)) {
+                romaji.push(last);
+            }
+            i += 1;
+            continue;
+        }
+
+        if let Some(&next) = chars.get(i + 1) {
+            let pair = [c, next].iter().collect::<String>();
+            if let Some((_, r)) = DIGRAPHS.iter().find(|(k, _)| *k == pair) {
+                romaji.push_str(r);
+                i += 2;
+                continue;
+            }
+        }
+
+        match kana_to_romaji(c) {
+            Some(r) => romaji.push_str(r),
+            None => romaji.push(c),
+        }
+        i += 1;
+    }
+    romaji
+}
+
+#[cfg(test)]
+mod test {
+    use crate::settings::KeywordVariant;
+
+    use super::{load_opencc_chars, to_halfwidth, to_romaji, KeywordNormalizer};
+
+    #[test]
+    fn test_halfwidth() {
+        assert_eq!(to_halfwidth("ＹＯＡＳＯＢＩ　１２３！"), "YOASOBI 123!");
+        assert_eq!(to_halfwidth("夜に駆ける"), "夜に駆ける");
+    }
+
+    #[test]
+    fn test_romaji() {
+        assert_eq!(to_romaji("よるにかける"), "yorunikakeru");
+        assert_eq!(to_romaji("シャルル"), "sharuru");
+        assert_eq!(to_romaji("きっと"), "kitto");
+        assert_eq!(to_romaji("ラーメン"), "raamen");
+        assert_eq!(to_romaji("夜に駆ける"), "夜ni駆keru");
+    }
+
+    #[test]
+    fn test_variants() {
+        let dict = "简\t簡\n体\t體 体\n简体\t簡體\n";
+        let normalizer = KeywordNormalizer {
+            s2t: load_opencc_chars(dict),
+            t2s: Default::default(),
+        };
+        assert_eq!(normalizer.s2t.len(), 2);
+
+        assert_eq!(
+            normalizer.variants(
+                "简体",
+                &[
+                    KeywordVariant::Traditional,
+                    KeywordVariant::Simplified,
+                    KeywordVariant::Halfwidth
+                ]
+            ),
+            vec!["简体".to_string(), "簡體".to_string()]
+        );
+    }
+}
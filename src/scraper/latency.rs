@@ -0,0 +1,140 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::Duration,
+};
+
+use parking_lot::RwLock;
+use serde::Serialize;
+
+use crate::settings::TimeoutSettings;
+
+use super::Provider;
+
+/// Samples required before the timeout adapts. Providers with fewer samples get `max`.
+const MIN_SAMPLES: usize = 10;
+
+/// Rolling latency of each provider and the timeouts derived from it, so that a temporarily slow
+/// provider gets more slack while a hung one is cut quickly.
+#[derive(Debug, Default)]
+pub struct LatencyTracker {
+    setting: Option<TimeoutSettings>,
+    samples: RwLock<HashMap<Provider, VecDeque<Duration>>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub p50_ms: u128,
+    pub p95_ms: u128,
+    /// absent if adaptive timeouts are disabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u128>,
+}
+
+impl LatencyTracker {
+    /// Latencies are tracked even without timeouts
+    pub fn new(setting: Option<TimeoutSettings>) -> Self {
+        Self {
+            setting,
+            samples: Default::default(),
+        }
+    }
+
+    pub fn from_setting(setting: &TimeoutSettings) -> Self {
+        Self::new(setting.enabled.then(|| setting.clone()))
+    }
+
+    pub fn record(&self, provider: &Provider, latency: Duration) {
+        let window = self.setting.as_ref().map(|s| s.window).unwrap_or(100);
+        let mut samples = self.samples.write();
+        let samples = samples.entry(provider.clone()).or_default();
+        if samples.len() >= window {
+            samples.pop_front();
+        }
+        samples.push_back(latency);
+    }
+
+    /// The `p` (0 to 1) percentile of the recent latencies
+    fn percentile(&self, provider: &Provider, p: f64) -> Option<Duration> {
+        let samples = self.samples.read();
+        let mut sorted = samples.get(provider)?.iter().copied().collect::<Vec<_>>();
+        if sorted.is_empty() {
+            return None;
+        }
+        sorted.sort();
+        let index = ((sorted.len() - 1) as f64 * p).round() as usize;
+        Some(sorted[index])
+    }
+
+    /// p95 times the multiplier, bounded by min and max. None if disabled.
+    pub fn timeout(&self, provider: &Provider) -> Option<Duration> {
+        let setting = self.setting.as_ref()?;
+        let min = Duration::from_millis(setting.min_ms);
+        let max = Duration::from_millis(setting.max_ms);
+
+        let enough = self
+            .samples
+            .read()
+            .get(provider)
+            .is_some_and(|s| s.len() >= MIN_SAMPLES);
+        match self.percentile(provider, 0.95).filter(|_| enough) {
+            Some(p95) => Some(p95.mul_f64(setting.multiplier).clamp(min, max)),
+            None => Some(max),
+        }
+    }
+
+    pub fn stats(&self) -> HashMap<Provider, LatencyStats> {
+        let providers = self.samples.read().keys().cloned().collect::<Vec<_>>();
+        providers
+            .into_iter()
+            .map(|p| {
+                let stats = LatencyStats {
+                    samples: self.samples.read().get(&p).map(VecDeque::len).unwrap_or(0),
+                    p50_ms: self.percentile(&p, 0.5).unwrap_or_default().as_millis(),
+                    p95_ms: self.percentile(&p, 0.95).unwrap_or_default().as_millis(),
+                    timeout_ms: self.timeout(&p).map(|t| t.as_millis()),
+                };
+                (p, stats)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use crate::{scraper::Provider, settings::TimeoutSettings};
+
+    use super::LatencyTracker;
+
+    #[test]
+    fn test_timeout() {
+        let tracker = LatencyTracker::from_setting(&TimeoutSettings {
+            enabled: true,
+            multiplier: 2.0,
+            min_ms: 500,
+            max_ms: 8000,
+            window: 20,
+        });
+        let p = Provider::NetEase;
+        assert_eq!(tracker.timeout(&p), Some(Duration::from_millis(8000)));
+
+        (1..=20).for_each(|i| tracker.record(&p, Duration::from_millis(i * 100)));
+        let stats = tracker.stats().remove(&p).unwrap();
+        assert_eq!((stats.p50_ms, stats.p95_ms), (1100, 1900));
+        assert_eq!(tracker.timeout(&p), Some(Duration::from_millis(3800)));
+
+        // old samples leave the window
+        (0..20).for_each(|_| tracker.record(&p, Duration::from_millis(50)));
+        assert_eq!(tracker.timeout(&p), Some(Duration::from_millis(500)));
+
+        (0..20).for_each(|_| tracker.record(&p, Duration::from_secs(30)));
+        assert_eq!(tracker.timeout(&p), Some(Duration::from_millis(8000)));
+
+        let disabled = LatencyTracker::default();
+        disabled.record(&p, Duration::from_millis(100));
+        assert_eq!(disabled.timeout(&p), None);
+        assert_eq!(disabled.stats()[&p].p50_ms, 100);
+    }
+}
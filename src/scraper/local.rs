@@ -0,0 +1,517 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Weak},
+    time::Duration,
+};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use regex::Regex;
+use tracing::{error, info, warn};
+
+use crate::{settings::LocalSettings, util};
+
+use super::{
+    id::{self, ArtistId, CollectionId, TrackId},
+    keyword::to_halfwidth,
+    tag::read_tags,
+    Artist, ArtistDetail, ScrapeItem, ScrapeType, Scraper, SearchPage, Song, SongCollection,
+    Stream,
+};
+
+lazy_static! {
+    /// `01 - `, `1. ` or `01_` leading the file name
+    static ref TRACK_NUMBER: Regex = Regex::new(r"^(\d{1,3})(?:\s*[.)\-_]\s*|\s+)").unwrap();
+}
+
+/// Search results of one page
+const PAGE_SIZE: usize = 20;
+const MAX_SUGGESTIONS: usize = 10;
+
+/// Route serving the indexed files, the encoded id of the song as `id`
+pub const FILE_ROUTE: &str = "/api/v1/stream/local";
+
+/// Extensions of the files indexed, case insensitive, with the codec of their audio
+const AUDIO_EXTENSIONS: &[(&str, &str)] = &[
+    ("flac", "flac"),
+    ("mp3", "mp3"),
+    ("m4a", "mp4a"),
+    ("ogg", "vorbis"),
+    ("opus", "opus"),
+    ("wav", "pcm"),
+];
+
+/// Quality of the only stream of a file, served as is
+const QUALITY: &str = "original";
+
+/// A file of the music directory
+#[derive(Debug, Clone)]
+struct LocalTrack {
+    /// relative to the music directory, `/` separated. The id of the song
+    path: String,
+    /// directory of the file relative to the music directory, the id of its album. Empty for
+    /// files right in the music directory, which belong to no album.
+    folder: String,
+    /// the album tag, or else the name of the directory
+    album: String,
+    /// position on the album
+    track: Option<u32>,
+    codec: &'static str,
+    song: Song,
+}
+
+/// Files of a configured music directory, indexed by their tags or else their file names and
+/// directories. Every directory of files is an album. The files are served by the api at
+/// `FILE_ROUTE`, symbolic links are not followed.
+pub struct LocalScraper {
+    dir: PathBuf,
+    /// sorted by album, then position on the album
+    tracks: Arc<RwLock<Vec<LocalTrack>>>,
+}
+
+impl LocalScraper {
+    pub fn try_from_setting(setting: LocalSettings) -> anyhow::Result<Option<Self>> {
+        if !setting.enabled {
+            return Ok(None);
+        }
+        let dir = PathBuf::from(&setting.dir);
+        if !dir.is_dir() {
+            bail!("local music directory not found: {}", setting.dir);
+        }
+        let tracks = scan(&dir);
+        info!("indexed {} local files in {}", tracks.len(), setting.dir);
+        let scraper = Self {
+            dir,
+            tracks: Arc::new(RwLock::new(tracks)),
+        };
+        if setting.rescan_interval > 0 {
+            let (dir, tracks) = (scraper.dir.clone(), Arc::downgrade(&scraper.tracks));
+            let interval = Duration::from_secs(setting.rescan_interval);
+            util::task::supervise("local rescan", move || {
+                rescan(dir.clone(), tracks.clone(), interval)
+            });
+        }
+        Ok(Some(scraper))
+    }
+
+    /// Index the directory now, for tests
+    pub fn from_dir(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        Self {
+            tracks: Arc::new(RwLock::new(scan(&dir))),
+            dir,
+        }
+    }
+
+    fn albums(&self) -> Vec<SongCollection> {
+        let tracks = self.tracks.read();
+        let mut albums: Vec<SongCollection> = vec![];
+        for t in tracks.iter().filter(|t| !t.folder.is_empty()) {
+            match albums.last_mut() {
+                Some(album) if album.id.as_str() == t.folder => album.songs.push(t.song.clone()),
+                _ => albums.push(SongCollection {
+                    id: t.folder.clone().into(),
+                    name: t.album.clone(),
+                    artists: vec![],
+                    cover: None,
+                    description: None,
+                    songs: vec![t.song.clone()],
+                    version: None,
+                    unavailable: false,
+                    saved: false,
+                    stale: false,
+                }),
+            }
+        }
+        // the artists credited on every song of the album
+        for album in albums.iter_mut() {
+            album.artists = album.songs[0]
+                .artists
+                .iter()
+                .filter(|a| {
+                    album
+                        .songs
+                        .iter()
+                        .all(|s| s.artists.iter().any(|b| b.name == a.name))
+                })
+                .cloned()
+                .collect();
+        }
+        albums
+    }
+
+    /// Every artist credited, by name
+    fn artists(&self) -> Vec<Artist> {
+        self.tracks
+            .read()
+            .iter()
+            .flat_map(|t| t.song.artists.iter())
+            .map(|a| (a.name.clone(), a.clone()))
+            .collect::<BTreeMap<_, _>>()
+            .into_values()
+            .collect()
+    }
+
+    /// Items of the type, artists first for `All`. There are no playlists.
+    fn items(&self, t: &ScrapeType) -> Vec<ScrapeItem> {
+        let artists = || self.artists().into_iter().map(ScrapeItem::Artist);
+        let songs = || {
+            self.tracks
+                .read()
+                .iter()
+                .map(|t| ScrapeItem::Song(t.song.clone()))
+                .collect::<Vec<_>>()
+        };
+        let albums = || self.albums().into_iter().map(ScrapeItem::Album);
+        match t {
+            ScrapeType::All => artists().chain(songs()).chain(albums()).collect(),
+            ScrapeType::Artist => artists().collect(),
+            ScrapeType::Song => songs(),
+            ScrapeType::Album => albums().collect(),
+            ScrapeType::Playlist | ScrapeType::Radio => vec![],
+        }
+    }
+}
+
+/// Ignoring case and width
+fn normalize(s: &str) -> String {
+    to_halfwidth(s).to_lowercase()
+}
+
+fn name(item: &ScrapeItem) -> &str {
+    match item {
+        ScrapeItem::Artist(a) => &a.name,
+        ScrapeItem::Song(s) => &s.name,
+        ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => &c.name,
+        ScrapeItem::Radio(s) => &s.name,
+    }
+}
+
+/// Every term of the keyword is in the name or the artists of the item
+fn matches(item: &ScrapeItem, keyword: &str) -> bool {
+    let artists = match item {
+        ScrapeItem::Artist(_) | ScrapeItem::Radio(_) => &[][..],
+        ScrapeItem::Song(s) => &s.artists,
+        ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => &c.artists,
+    };
+    let text = std::iter::once(name(item))
+        .chain(artists.iter().map(|a| a.name.as_str()))
+        .map(normalize)
+        .collect::<Vec<_>>()
+        .join(" ");
+    normalize(keyword)
+        .split_whitespace()
+        .all(|term| text.contains(term))
+}
+
+/// Audio files below the directory, sorted by album and position
+fn scan(dir: &Path) -> Vec<LocalTrack> {
+    let mut tracks = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(current) = dirs.pop() {
+        let entries = match fs::read_dir(&current) {
+            Ok(entries) => entries,
+            Err(e) => {
+                warn!("read local directory {}: {}", current.display(), e);
+                continue;
+            }
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let hidden = entry.file_name().to_string_lossy().starts_with('.');
+            match entry.file_type() {
+                Ok(t) if t.is_dir() && !hidden => dirs.push(path),
+                Ok(t) if t.is_file() && !hidden => tracks.extend(track(dir, &path)),
+                _ => {}
+            }
+        }
+    }
+    tracks.sort_by(|a, b| (&a.folder, a.track, &a.path).cmp(&(&b.folder, b.track, &b.path)));
+    tracks
+}
+
+/// `01 - artist - title`, `01. title` or `title`
+fn parse_file_name(stem: &str) -> (Option<u32>, Vec<String>, String) {
+    let (track, name) = match TRACK_NUMBER.captures(stem) {
+        Some(c) if c[0].len() < stem.len() => (c[1].parse().ok(), &stem[c[0].len()..]),
+        _ => (None, stem),
+    };
+    match name.split_once(" - ") {
+        Some((artist, title)) => (
+            track,
+            vec![artist.trim().to_string()],
+            title.trim().to_string(),
+        ),
+        None => (track, vec![], name.trim().to_string()),
+    }
+}
+
+fn track(dir: &Path, path: &Path) -> Option<LocalTrack> {
+    let extension = path.extension()?.to_str()?.to_lowercase();
+    let (_, codec) = AUDIO_EXTENSIONS.iter().find(|(e, _)| *e == extension)?;
+    let relative = path
+        .strip_prefix(dir)
+        .ok()?
+        .iter()
+        .map(|c| c.to_str())
+        .collect::<Option<Vec<_>>>();
+    let Some(relative) = relative else {
+        warn!("skip local file of a name not in utf-8: {}", path.display());
+        return None;
+    };
+    let (folder, file) = relative.split_at(relative.len() - 1);
+    let stem = file[0].rsplit_once('.').map_or(file[0], |(stem, _)| stem);
+
+    let tags = read_tags(path).unwrap_or_else(|e| {
+        warn!("read tags of {}: {}", path.display(), e);
+        Default::default()
+    });
+    let (track, artists, title) = parse_file_name(stem);
+    let artists = match tags.artists.is_empty() {
+        true => artists,
+        false => tags.artists,
+    };
+    Some(LocalTrack {
+        path: relative.join("/"),
+        folder: folder.join("/"),
+        album: tags
+            .album
+            .unwrap_or_else(|| folder.last().unwrap_or(&"").to_string()),
+        track: tags.track.or(track),
+        codec,
+        song: Song {
+            id: relative.join("/").into(),
+            name: tags.title.unwrap_or(title),
+            artists: artists
+                .into_iter()
+                .map(|name| Artist {
+                    id: name.clone().into(),
+                    name,
+                    description: None,
+                    avatar: None,
+                })
+                .collect(),
+            cover: None,
+            duration: tags.duration,
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        },
+    })
+}
+
+/// Index the directory again every `interval` until the scraper is dropped
+async fn rescan(dir: PathBuf, tracks: Weak<RwLock<Vec<LocalTrack>>>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        if tracks.strong_count() == 0 {
+            return;
+        }
+        let scanned = match tokio::task::spawn_blocking({
+            let dir = dir.clone();
+            move || scan(&dir)
+        })
+        .await
+        {
+            Ok(scanned) => scanned,
+            Err(e) => {
+                error!("rescan local directory {} failed: {}", dir.display(), e);
+                continue;
+            }
+        };
+        let Some(tracks) = tracks.upgrade() else {
+            return;
+        };
+        let mut tracks = tracks.write();
+        if tracks.len() != scanned.len() {
+            info!(
+                "local files in {}: {} -> {}",
+                dir.display(),
+                tracks.len(),
+                scanned.len()
+            );
+        }
+        *tracks = scanned;
+    }
+}
+
+#[async_trait]
+impl Scraper for LocalScraper {
+    async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
+        let mut suggestions: Vec<String> = vec![];
+        for item in self.items(&ScrapeType::All) {
+            if matches(&item, &keyword) && !suggestions.iter().any(|s| s == name(&item)) {
+                suggestions.push(name(&item).to_string());
+            }
+        }
+        suggestions.truncate(MAX_SUGGESTIONS);
+        Ok(suggestions)
+    }
+
+    /// The continuation is the offset of the next page
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        let offset = match continuation {
+            Some(c) => c
+                .parse::<usize>()
+                .map_err(|_| anyhow::anyhow!("invalid continuation: {}", c))?,
+            None => 0,
+        };
+        let found = self
+            .items(&t)
+            .into_iter()
+            .filter(|i| matches(i, &keyword))
+            .collect::<Vec<_>>();
+        let next = offset + PAGE_SIZE;
+        Ok(SearchPage {
+            next: (next < found.len()).then(|| next.to_string()),
+            items: found.into_iter().skip(offset).take(PAGE_SIZE).collect(),
+        })
+    }
+
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        match self.albums().into_iter().find(|a| a.id == id) {
+            Some(album) => Ok(album),
+            None => bail!("no local album: {}", id),
+        }
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        let tracks = self.tracks.read();
+        let Some(track) = tracks.iter().find(|t| t.song.id == id) else {
+            bail!("no local file: {}", id);
+        };
+        Ok(vec![Stream {
+            quality: QUALITY.to_string(),
+            url: format!("{}?id={}", FILE_ROUTE, id::encode(&track.path)),
+            bitrate: None,
+            codec: Some(track.codec.to_string()),
+            mirror: false,
+            loudness: None,
+            preview: false,
+        }])
+    }
+
+    /// Songs and albums crediting the artist
+    async fn artist_detail(&self, id: ArtistId) -> anyhow::Result<ArtistDetail> {
+        let Some(artist) = self.artists().into_iter().find(|a| a.id == id) else {
+            bail!("no local artist: {}", id);
+        };
+        let credited = |artists: &[Artist]| artists.iter().any(|a| a.id == id);
+        Ok(ArtistDetail {
+            songs: self
+                .tracks
+                .read()
+                .iter()
+                .filter(|t| credited(&t.song.artists))
+                .map(|t| t.song.clone())
+                .collect(),
+            albums: self
+                .albums()
+                .into_iter()
+                .filter(|a| credited(&a.artists))
+                .collect(),
+            playlists: vec![],
+            artist,
+        })
+    }
+
+    /// Only indexed files, so that no id reaches outside the music directory
+    fn local_file(&self, id: &TrackId) -> Option<PathBuf> {
+        self.tracks
+            .read()
+            .iter()
+            .find(|t| t.song.id == *id)
+            .map(|t| self.dir.join(&t.path))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::scraper::{ScrapeItem, ScrapeType, Scraper};
+
+    use super::{parse_file_name, LocalScraper};
+
+    #[test]
+    fn test_parse_file_name() {
+        assert_eq!(
+            parse_file_name("03 - YOASOBI - 群青"),
+            (Some(3), vec!["YOASOBI".to_string()], "群青".to_string())
+        );
+        assert_eq!(
+            parse_file_name("1. Lemon"),
+            (Some(1), vec![], "Lemon".to_string())
+        );
+        assert_eq!(parse_file_name("1989"), (None, vec![], "1989".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_local() {
+        let dir = std::env::temp_dir().join(format!("bragi-local-{}", std::process::id()));
+        for (path, content) in [
+            ("THE BOOK/02 - YOASOBI - あの夢をなぞって.mp3", "ID"),
+            ("THE BOOK/01 - YOASOBI - 夜に駆ける.mp3", "ID"),
+            ("THE BOOK/cover.jpg", ""),
+            ("Singles/Lemon.flac", "fLa"),
+            ("loose.ogg", ""),
+            (".trash/Lemon.mp3", ""),
+        ] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        let scraper = LocalScraper::from_dir(&dir);
+
+        let songs = scraper
+            .search("yoasobi".into(), ScrapeType::Song, None)
+            .await
+            .unwrap();
+        let names = songs
+            .items
+            .iter()
+            .map(|i| match i {
+                ScrapeItem::Song(s) => s.name.as_str(),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["夜に駆ける", "あの夢をなぞって"]);
+
+        let albums = scraper
+            .search("".into(), ScrapeType::Album, None)
+            .await
+            .unwrap();
+        // files right in the directory belong to no album
+        assert_eq!(albums.items.len(), 2);
+        let album = scraper.collection_detail("THE BOOK".into()).await.unwrap();
+        assert_eq!(album.songs.len(), 2);
+        assert_eq!(album.artists[0].name, "YOASOBI");
+
+        let streams = scraper.stream("Singles/Lemon.flac".into()).await.unwrap();
+        assert_eq!(streams[0].codec.as_deref(), Some("flac"));
+        assert!(streams[0].url.starts_with("/api/v1/stream/local?id=~"));
+        assert_eq!(
+            scraper.local_file(&"loose.ogg".into()),
+            Some(dir.join("loose.ogg"))
+        );
+        assert!(scraper.local_file(&".trash/Lemon.mp3".into()).is_none());
+        assert!(scraper.local_file(&"../etc/passwd".into()).is_none());
+
+        assert_eq!(
+            scraper.suggest("lem".into()).await.unwrap(),
+            ["Lemon".to_string()]
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
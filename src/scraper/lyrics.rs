@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{privacy::redact, settings::LyricsSettings, util::body::LimitedBody};
+
+/// Durations of the fallback lookup may differ by this many seconds, e.g. a video with a longer
+/// intro than the album version
+const MAX_DURATION_DIFF: f64 = 3.0;
+
+/// Lyrics of a song. Time-synced lyrics are in the LRC format, one `[mm:ss.xx]` timestamp per line.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Lyrics {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub plain: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub synced: Option<String>,
+    /// time-synced translation, like the Chinese lyrics of NetEase
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub translation: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub instrumental: bool,
+    /// looked up by the title instead of served by the provider
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fallback: bool,
+}
+
+impl Lyrics {
+    /// Plain lyrics derived from the LRC. None if it is blank.
+    pub fn from_lrc(lrc: String) -> Option<Self> {
+        let plain = strip_timestamps(&lrc);
+        (!plain.is_empty()).then(|| Self {
+            plain: Some(plain),
+            synced: Some(lrc),
+            ..Default::default()
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.plain.is_none() && self.synced.is_none() && !self.instrumental
+    }
+}
+
+/// Text of the LRC without the timestamps. Tag lines like `[ar: artist]` are dropped.
+pub fn strip_timestamps(lrc: &str) -> String {
+    lrc.lines()
+        .filter_map(|line| {
+            let mut rest = line.trim();
+            let tagged = rest.starts_with('[');
+            while let Some(tag) = rest.strip_prefix('[') {
+                let (_, text) = tag.split_once(']')?;
+                rest = text.trim_start();
+            }
+            // lines of tags only
+            (!tagged || !rest.is_empty()).then_some(rest)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+/// Song looked up by the fallback
+#[derive(Debug, Clone, Deserialize)]
+pub struct LyricsQuery {
+    pub title: String,
+    pub artist: Option<String>,
+    /// seconds
+    pub duration: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LrclibRecord {
+    duration: Option<f64>,
+    #[serde(default)]
+    instrumental: bool,
+    plain_lyrics: Option<String>,
+    synced_lyrics: Option<String>,
+}
+
+/// Lookup of lyrics by title, artist and duration on an lrclib compatible api, for providers
+/// without lyrics of their own
+#[derive(Debug)]
+pub struct LyricsFallback {
+    instance: String,
+    client: reqwest::Client,
+}
+
+impl LyricsFallback {
+    pub fn new(instance: String, client: reqwest::Client) -> Self {
+        Self { instance, client }
+    }
+
+    pub fn from_setting(setting: &LyricsSettings) -> Option<Self> {
+        let instance = setting.lrclib.clone()?;
+        // lrclib asks clients to identify themselves
+        let client = reqwest::Client::builder()
+            .user_agent(concat!("bragi-core/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .ok()?;
+        Some(Self::new(instance, client))
+    }
+
+    /// The first result of about the same duration. None if nothing is found.
+    pub async fn lookup(&self, query: &LyricsQuery) -> anyhow::Result<Option<Lyrics>> {
+        info!("[Lyrics] look up lyrics of {}", redact(&query.title));
+        let mut params = vec![("track_name", query.title.clone())];
+        if let Some(artist) = &query.artist {
+            params.push(("artist_name", artist.clone()));
+        }
+        let records = self
+            .client
+            .get(format!("{}/api/search", self.instance))
+            .query(&params)
+            .send()
+            .await?
+            .error_for_status()?
+            .limited_json::<Vec<LrclibRecord>>()
+            .await?;
+
+        Ok(records
+            .into_iter()
+            .find(|r| match (query.duration, r.duration) {
+                (Some(expected), Some(actual)) => {
+                    (f64::from(expected) - actual).abs() <= MAX_DURATION_DIFF
+                }
+                _ => true,
+            })
+            .map(|r| Lyrics {
+                plain: r
+                    .plain_lyrics
+                    .or_else(|| r.synced_lyrics.as_deref().map(strip_timestamps)),
+                synced: r.synced_lyrics,
+                translation: None,
+                instrumental: r.instrumental,
+                fallback: true,
+            }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{strip_timestamps, Lyrics};
+
+    #[test]
+    fn test_strip_timestamps() {
+        let lrc = "[ar: YOASOBI]\n[ti: 夜に駆ける]\n[00:01.00]沈むように\n[00:05.10][01:05.10]溶けてゆくように\n\n[00:09.00]";
+        assert_eq!(strip_timestamps(lrc), "沈むように\n溶けてゆくように");
+
+        let lyrics = Lyrics::from_lrc(lrc.to_string()).unwrap();
+        assert_eq!(lyrics.synced.as_deref(), Some(lrc));
+        assert!(Lyrics::from_lrc("[by: someone]\n".into()).is_none());
+    }
+}
@@ -0,0 +1,158 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    alias::ArtistAliases, compare::DURATION_TOLERANCE, id::TrackId, keyword::to_halfwidth,
+    Provider, ScrapeItem, Song, WithProvider,
+};
+
+lazy_static! {
+    /// Decorations of titles like `(Official Video)`, `[MV]` or `【歌ってみた】`
+    static ref BRACKETED: Regex = Regex::new(r"\([^)]*\)|\[[^\]]*\]|【[^】]*】").unwrap();
+}
+
+/// Suffix of the channels YouTube generates for the releases of an artist
+const TOPIC_SUFFIX: &str = " - Topic";
+
+/// The same song on another provider
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Alternative {
+    pub provider: Provider,
+    pub id: TrackId,
+}
+
+/// Compared ignoring case, width, spacing and bracketed decorations
+fn normalize_title(title: &str) -> String {
+    let title = to_halfwidth(title).to_lowercase();
+    let stripped = BRACKETED.replace_all(&title, " ");
+    // the whole title may be in brackets
+    let title = match stripped.trim().is_empty() {
+        true => title.as_str(),
+        false => &stripped,
+    };
+    title.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Same title, an artist in common by any of their names, and durations within the tolerance.
+/// Songs of unknown duration are never taken as the same, a live or extended version may share
+/// the title.
+pub(super) fn same_song(a: &Song, b: &Song, aliases: &ArtistAliases) -> bool {
+    let close = matches!((a.duration, b.duration), (Some(x), Some(y)) if x.abs_diff(y) <= DURATION_TOLERANCE);
+    let artist = |name: &str| name.trim_end_matches(TOPIC_SUFFIX).to_string();
+    close
+        && normalize_title(&a.name) == normalize_title(&b.name)
+        && a.artists.iter().any(|x| {
+            b.artists
+                .iter()
+                .any(|y| aliases.same_artist(&artist(&x.name), &artist(&y.name)))
+        })
+}
+
+/// Fold the songs found on several providers into the first of them, which comes from the
+/// provider of the highest priority, with the others listed as its alternatives. A song takes at
+/// most one alternative of each provider, other versions on the same provider are kept apart.
+pub fn merge_songs(
+    items: Vec<WithProvider<ScrapeItem>>,
+    aliases: &ArtistAliases,
+) -> Vec<WithProvider<ScrapeItem>> {
+    let mut merged: Vec<WithProvider<ScrapeItem>> = vec![];
+    for item in items {
+        let ScrapeItem::Song(song) = &item.data else {
+            merged.push(item);
+            continue;
+        };
+        let same = merged.iter_mut().find(|m| {
+            let carried = m.provider == item.provider
+                || m.alternatives.iter().any(|a| a.provider == item.provider);
+            !carried && matches!(&m.data, ScrapeItem::Song(s) if same_song(s, song, aliases))
+        });
+        match same {
+            Some(m) => m.alternatives.push(Alternative {
+                provider: item.provider.clone(),
+                id: song.id.clone(),
+            }),
+            None => merged.push(item),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::{alias::ArtistAliases, Artist, Provider, ScrapeItem, Song, WithProvider};
+
+    use super::{merge_songs, normalize_title};
+
+    fn song(
+        provider: Provider,
+        id: &str,
+        name: &str,
+        artist: &str,
+        duration: u32,
+    ) -> WithProvider<ScrapeItem> {
+        WithProvider::new(
+            provider,
+            ScrapeItem::Song(Song {
+                id: id.into(),
+                name: name.into(),
+                artists: vec![Artist {
+                    id: "1".into(),
+                    name: artist.into(),
+                    description: None,
+                    avatar: None,
+                }],
+                cover: None,
+                duration: Some(duration),
+                unavailable: false,
+                unavailable_reason: None,
+                alternatives: vec![],
+                saved: false,
+                clip: None,
+            }),
+        )
+    }
+
+    #[test]
+    fn test_normalize_title() {
+        assert_eq!(normalize_title("Idol (Official Video)"), "idol");
+        assert_eq!(normalize_title("【MV】 アイドル"), "アイドル");
+        assert_eq!(normalize_title("Ｉｄｏｌ  [Live]"), "idol");
+        assert_eq!(normalize_title("(Intro)"), "(intro)");
+    }
+
+    #[test]
+    fn test_merge_songs() {
+        let aliases = ArtistAliases::default();
+        let items = vec![
+            song(Provider::NetEase, "1", "アイドル", "YOASOBI", 213),
+            song(Provider::NetEase, "2", "アイドル", "YOASOBI", 215),
+            song(
+                Provider::Youtube,
+                "ZRtdQ81jPUQ",
+                "アイドル [Official Music Video]",
+                "YOASOBI - Topic",
+                219,
+            ),
+            song(Provider::Bilibili, "BV1", "アイドル", "YOASOBI", 300),
+            song(
+                Provider::Bilibili,
+                "BV2",
+                "アイドル",
+                "ずっと真夜中でいいのに。",
+                214,
+            ),
+        ];
+        let merged = merge_songs(items, &aliases);
+        assert_eq!(merged.len(), 4);
+        let ids = |i: &WithProvider<ScrapeItem>| {
+            i.alternatives
+                .iter()
+                .map(|a| a.id.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&merged[0]), ["ZRtdQ81jPUQ"]);
+        // another version on the same provider, the live one and the cover stay apart
+        assert!(merged[1..].iter().all(|i| ids(i).is_empty()));
+    }
+}
@@ -1,20 +1,120 @@
+pub mod alias;
+pub mod analytics;
+#[cfg(feature = "bili")]
 pub mod bili;
+pub mod capability;
+pub mod chapter;
+pub mod compare;
+pub mod cursor;
+pub mod event;
+pub mod explain;
+pub mod favorite;
+pub mod filter;
+#[cfg(any(test, feature = "test-util"))]
+pub mod fixture;
+pub mod follow;
+pub mod health;
+pub mod id;
+pub mod instance;
+pub mod keyword;
+pub mod latency;
+#[cfg(feature = "local")]
+pub mod local;
+pub mod lyrics;
+pub mod merge;
+#[cfg(feature = "netease")]
 pub mod netease;
+pub mod priority;
+pub mod query;
+pub mod quota;
+#[cfg(feature = "radio")]
+pub mod radio;
+pub mod relax;
+pub mod rewrite;
+pub mod sort;
+#[cfg(feature = "spotify")]
+pub mod spotify;
+pub mod stale;
+#[cfg(feature = "local")]
+pub mod tag;
+pub mod tracklist;
+pub mod unavailable;
+#[cfg(feature = "youtube")]
 pub mod youtube;
+#[cfg(feature = "youtube")]
+pub mod youtube_api;
+#[cfg(feature = "youtube")]
+pub mod ytdlp;
 
-use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Debug,
+    future::Future,
+    panic::AssertUnwindSafe,
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
 
-use anyhow::anyhow;
+use anyhow::{anyhow, bail};
 use async_trait::async_trait;
+use futures::FutureExt;
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
-use tracing::{error, info};
+use tokio::sync::{OwnedSemaphorePermit, RwLock, Semaphore};
+use tracing::{error, info, warn};
 
-use crate::settings::Settings;
+use crate::{
+    builder::BragiBuilder,
+    privacy::{self, redact},
+    settings::{BudgetSettings, KeywordVariant, PrioritySettings, Settings, StreamSortSettings},
+    util,
+};
 
-use self::{bili::BiliScraper, netease::NeteaseScraper, youtube::YouTubeScraper};
+#[cfg(feature = "bili")]
+use self::bili::BiliScraper;
+#[cfg(feature = "test-util")]
+use self::fixture::FixtureScraper;
+#[cfg(feature = "local")]
+use self::local::LocalScraper;
+#[cfg(feature = "netease")]
+use self::netease::NeteaseScraper;
+#[cfg(feature = "radio")]
+use self::radio::RadioScraper;
+#[cfg(feature = "spotify")]
+use self::spotify::SpotifyScraper;
+#[cfg(feature = "youtube")]
+use self::youtube::YouTubeScraper;
+use self::{
+    alias::ArtistAliases,
+    analytics::SearchAnalytics,
+    compare::Source,
+    cursor::Cursor,
+    event::EventHandler,
+    explain::StreamTrace,
+    favorite::FavoriteStore,
+    filter::ResultFilter,
+    follow::{FollowStore, Release},
+    health::Health,
+    id::{ArtistId, CollectionId, TrackId},
+    instance::InstanceStats,
+    keyword::KeywordNormalizer,
+    latency::LatencyTracker,
+    lyrics::{Lyrics, LyricsFallback, LyricsQuery},
+    merge::{merge_songs, Alternative},
+    priority::sort_by_priority,
+    query::{Query, SearchFilter},
+    quota::QuotaTracker,
+    rewrite::HostRewriter,
+    sort::sort_streams,
+    stale::StaleCache,
+    unavailable::{MarkKind, Restricted, Restriction, Unavailable, UnavailableStore},
+};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Aliases of an `artist:` filter searched in addition to the name itself, each one an upstream
+/// call
+const MAX_ALIAS_VARIANTS: usize = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ScrapeType {
     All,
@@ -22,85 +122,404 @@ pub enum ScrapeType {
     Artist,
     Playlist,
     Album,
+    /// internet radio stations
+    Radio,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ScrapeItem {
     Artist(Artist),
     Song(Song),
     Playlist(SongCollection),
     Album(SongCollection),
+    Radio(Station),
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl ScrapeItem {
+    pub fn id(&self) -> &str {
+        match self {
+            ScrapeItem::Artist(a) => &a.id,
+            ScrapeItem::Song(s) => &s.id,
+            ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => &c.id,
+            ScrapeItem::Radio(s) => &s.id,
+        }
+    }
+
+    /// Same type and same id
+    pub fn same_as(&self, other: &ScrapeItem) -> bool {
+        std::mem::discriminant(self) == std::mem::discriminant(other) && self.id() == other.id()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Artist {
-    pub id: String,
+    pub id: ArtistId,
     pub name: String,
     pub description: Option<String>,
     pub avatar: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Song {
-    pub id: String,
+    pub id: TrackId,
     pub name: String,
     pub artists: Vec<Artist>,
     pub cover: Option<String>,
     pub duration: Option<u32>,
+    /// known as deleted or blocked upstream
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unavailable: bool,
+    /// why upstream greys the song out, like `removed upstream`, if it tells
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unavailable_reason: Option<String>,
+    /// the same song on other providers to play instead, for unavailable songs of collections
+    /// asked with `substitute=true`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alternatives: Vec<Alternative>,
+    /// saved to the library
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub saved: bool,
+    /// part of the upload played as this song, if it is a chapter of a longer one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clip: Option<Clip>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Time range of a chapter in seconds. Chapters share the id and the streams of their upload, the
+/// stream proxy plays the range only.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Clip {
+    pub start: u32,
+    /// end of the upload if absent
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub end: Option<u32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SongCollection {
-    pub id: String,
+    pub id: CollectionId,
     pub name: String,
     pub artists: Vec<Artist>,
     pub cover: Option<String>,
     pub description: Option<String>,
     pub songs: Vec<Song>,
+    /// upstream version marker of the collection. Exposed as ETag instead of in the body
+    #[serde(skip_serializing)]
+    pub version: Option<String>,
+    /// known as deleted or blocked upstream
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub unavailable: bool,
+    /// saved to the library
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub saved: bool,
+    /// last known result served while the provider is down
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
 }
 
-#[derive(Debug, Clone, Serialize)]
+/// Internet radio station, played by resolving its stream like a song
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Station {
+    pub id: TrackId,
+    pub name: String,
+    /// logo of the station
+    pub cover: Option<String>,
+    /// ISO 3166-1 alpha-2 code
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub country: Option<String>,
+    /// genres and languages, like `jazz` or `japanese`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub homepage: Option<String>,
+}
+
+/// Profile of an artist with their most popular works
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArtistDetail {
+    pub artist: Artist,
+    /// top tracks, most popular first
+    pub songs: Vec<Song>,
+    pub albums: Vec<SongCollection>,
+    pub playlists: Vec<SongCollection>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Stream {
     pub quality: String,
     pub url: String,
+    /// bits per second, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bitrate: Option<u64>,
+    /// codec like `mp4a.40.2`, `ec-3`, `flac` or `opus`, if known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// backup url of the same quality on another CDN host
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub mirror: bool,
+    /// loudness measured by the provider, for clients to normalize the volume
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub loudness: Option<Loudness>,
+    /// a sample of a song whose full stream is restricted, like the 30 second trial of NetEase
+    #[serde(
+        default,
+        rename = "is_preview",
+        skip_serializing_if = "std::ops::Not::not"
+    )]
+    pub preview: bool,
+}
+
+/// Loudness normalization metadata, like ReplayGain but measured by the provider
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Loudness {
+    /// integrated loudness in LUFS
+    pub integrated: f64,
+    /// loudness range in LU
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<f64>,
+    /// true peak in dBTP
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub true_peak: Option<f64>,
+    /// gain in dB reaching the target loudness of the provider
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain: Option<f64>,
+}
+
+/// One page of search results of a single provider
+#[derive(Debug, Default, Clone)]
+pub struct SearchPage {
+    pub items: Vec<ScrapeItem>,
+    /// provider specific continuation of the next page. None if there are no more results
+    pub next: Option<String>,
+}
+
+impl From<Vec<ScrapeItem>> for SearchPage {
+    fn from(items: Vec<ScrapeItem>) -> Self {
+        Self { items, next: None }
+    }
 }
 
 #[async_trait]
-pub trait Scraper {
+pub trait Scraper: Send + Sync {
     async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>>;
 
-    async fn search(&self, keyword: String, t: ScrapeType) -> Vec<ScrapeItem>;
+    /// Translate a query with field filters into the native keyword and search type of the
+    /// provider. Providers without field filters search all terms as plain keyword.
+    fn native_query(&self, query: &Query, t: ScrapeType) -> (String, ScrapeType) {
+        (query.keyword(), t)
+    }
+
+    /// `continuation` is the `next` of the previous page, None for the first page
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage>;
+
+    /// Search narrowed upstream by the filter as far as the provider supports it. The manager
+    /// filters the results anyway, so providers without such support search as usual.
+    async fn search_filtered(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+        _filter: &SearchFilter,
+    ) -> anyhow::Result<SearchPage> {
+        self.search(keyword, t, continuation).await
+    }
+
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection>;
+
+    /// Cheap lookup of the collection version marker without fetching all songs.
+    /// Returns None if the provider has no such marker.
+    async fn collection_version(&self, _id: CollectionId) -> anyhow::Result<Option<String>> {
+        Ok(None)
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>>;
+
+    /// Resolve the streams with the trace of the decisions made, like quality tiers filtered out.
+    /// Providers without any filtering report every stream as found.
+    async fn stream_trace(&self, id: TrackId) -> anyhow::Result<StreamTrace> {
+        self.stream(id).await.map(Into::into)
+    }
+
+    /// Samples of the song, for when its full streams are restricted. Empty if the provider
+    /// offers none.
+    async fn preview(&self, _id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        Ok(vec![])
+    }
+
+    /// Ids of the songs liked in the logged in provider account
+    async fn liked(&self) -> anyhow::Result<Vec<String>> {
+        Err(anyhow!("liked songs are not supported by the provider"))
+    }
+
+    /// Latest uploads or releases of the artist, newest first
+    async fn artist_releases(&self, _id: ArtistId) -> anyhow::Result<Vec<ScrapeItem>> {
+        Err(anyhow!("artist releases are not supported by the provider"))
+    }
+
+    /// Plain and time-synced lyrics of the song
+    async fn lyrics(&self, _id: TrackId) -> anyhow::Result<Lyrics> {
+        Err(anyhow!("lyrics are not supported by the provider"))
+    }
+
+    /// Chapters of a long upload like a concert as songs, empty if it has none
+    async fn chapters(&self, _id: TrackId) -> anyhow::Result<Vec<Song>> {
+        Err(anyhow!("chapters are not supported by the provider"))
+    }
+
+    /// Profile of the artist with their top tracks, albums and playlists
+    async fn artist_detail(&self, _id: ArtistId) -> anyhow::Result<ArtistDetail> {
+        Err(anyhow!("artist detail is not supported by the provider"))
+    }
+
+    /// Headers required by the hosts of the stream urls, like a Referer. Sent by the stream proxy.
+    fn stream_headers(&self) -> Vec<(&'static str, String)> {
+        vec![]
+    }
+
+    /// Current state of the provider, healthy unless it knows better
+    fn health(&self) -> Health {
+        Health::default()
+    }
 
-    async fn collection_detail(&self, id: String) -> anyhow::Result<SongCollection>;
+    /// Upstream instances called by the provider with their health, empty unless it has several
+    fn instances(&self) -> Vec<InstanceStats> {
+        vec![]
+    }
 
-    async fn stream(&self, id: String) -> anyhow::Result<Vec<Stream>>;
+    /// File on disk of the song, for providers serving local files
+    fn local_file(&self, _id: &TrackId) -> Option<PathBuf> {
+        None
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
 #[serde(rename_all = "lowercase")]
 pub enum Provider {
     Bilibili,
+    Local,
     NetEase,
+    Radio,
     Spotify,
     Youtube,
 }
 
+impl std::fmt::Display for Provider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Provider::Bilibili => "bilibili",
+            Provider::Local => "local",
+            Provider::NetEase => "netease",
+            Provider::Radio => "radio",
+            Provider::Spotify => "spotify",
+            Provider::Youtube => "youtube",
+        })
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WithProvider<T> {
     provider: Provider,
     data: T,
+    /// last known result served while the provider is down
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    stale: bool,
+    /// found by a relaxed keyword since the original one found nothing
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    relaxed_match: bool,
+    /// the same song on other providers, folded into this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alternatives: Vec<Alternative>,
 }
 
 impl<T> WithProvider<T> {
     fn new(provider: Provider, data: T) -> Self {
-        Self { provider, data }
+        Self {
+            provider,
+            data,
+            stale: false,
+            relaxed_match: false,
+            alternatives: vec![],
+        }
     }
+
+    pub fn provider(&self) -> &Provider {
+        &self.provider
+    }
+
+    pub fn data(&self) -> &T {
+        &self.data
+    }
+
+    pub fn into_data(self) -> T {
+        self.data
+    }
+
+    /// last known result served while the provider is down
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn is_relaxed_match(&self) -> bool {
+        self.relaxed_match
+    }
+
+    pub fn alternatives(&self) -> &[Alternative] {
+        &self.alternatives
+    }
+}
+
+/// How a provider fared in a fan-out, so that a provider being down can be told from it finding
+/// nothing
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase", tag = "status", content = "error")]
+pub enum ProviderStatus {
+    Ok,
+    /// failed with the error, its last known result served instead
+    Stale(String),
+    Throttled,
+    Failed(String),
+}
+
+/// Merged fan-out results. `throttled` lists the providers skipped because their concurrency budget
+/// was exhausted at the time of the call. `next` is the cursor of the next page, if any.
+/// `providers` has the status of every provider called, or skipped for its budget.
+#[derive(Debug, Clone)]
+pub struct FanOut<T> {
+    pub items: Vec<WithProvider<T>>,
+    pub throttled: Vec<Provider>,
+    pub next: Option<Cursor>,
+    pub providers: BTreeMap<Provider, ProviderStatus>,
 }
 
 #[derive(Default, Clone)]
 pub struct ScraperManager {
-    scrapers: Arc<RwLock<HashMap<Provider, Box<dyn Scraper>>>>,
+    /// ordered so that fan-out results and the providers listed with them come in the same order
+    /// on every call
+    scrapers: Arc<RwLock<BTreeMap<Provider, Box<dyn Scraper>>>>,
+    budgets: Arc<RwLock<HashMap<Provider, Arc<Semaphore>>>>,
+    normalizer: Arc<KeywordNormalizer>,
+    keyword_variants: Arc<RwLock<HashMap<Provider, Vec<KeywordVariant>>>>,
+    search_zones: Arc<RwLock<HashMap<Provider, Vec<ScrapeType>>>>,
+    aliases: Arc<ArtistAliases>,
+    relax_zero_result: bool,
+    host_rewrites: Arc<RwLock<HashMap<Provider, HostRewriter>>>,
+    unavailable: Arc<UnavailableStore>,
+    favorites: Arc<FavoriteStore>,
+    follows: Arc<FollowStore>,
+    filter: Arc<ResultFilter>,
+    stale: Option<Arc<StaleCache>>,
+    stream_sort: Arc<StreamSortSettings>,
+    priority: Arc<PrioritySettings>,
+    latency: Arc<LatencyTracker>,
+    quota: Arc<QuotaTracker>,
+    analytics: Option<Arc<SearchAnalytics>>,
+    lyrics_fallback: Option<Arc<LyricsFallback>>,
+    handlers: Arc<parking_lot::RwLock<Vec<Box<dyn EventHandler>>>>,
 }
 
 unsafe impl Send for ScraperManager {}
@@ -113,48 +532,638 @@ impl ScraperManager {
         scrapers.insert(provider, scraper);
     }
 
-    pub async fn suggest(&self, keyword: String) -> Vec<WithProvider<String>> {
-        futures::future::join_all(self.scrapers.read().await.iter().map(|(p, s)| {
+    /// Limit the number of in-flight upstream calls of the provider to `permits`, one at least
+    pub async fn set_budget(&mut self, provider: Provider, permits: usize) {
+        let permits = permits.max(1);
+        info!("set budget: provider: {:?}, permits: {}", provider, permits);
+        let mut budgets = self.budgets.write().await;
+        budgets.insert(provider, Arc::new(Semaphore::new(permits)));
+    }
+
+    pub fn set_keyword_normalizer(&mut self, normalizer: KeywordNormalizer) {
+        self.normalizer = Arc::new(normalizer);
+    }
+
+    pub fn set_artist_aliases(&mut self, aliases: ArtistAliases) {
+        self.aliases = Arc::new(aliases);
+    }
+
+    /// Names of the same artist across scripts and providers
+    pub fn aliases(&self) -> &ArtistAliases {
+        &self.aliases
+    }
+
+    /// Retry searches which found nothing with relaxed keywords
+    pub fn set_zero_result_relaxation(&mut self, enabled: bool) {
+        self.relax_zero_result = enabled;
+    }
+
+    pub fn set_unavailable_store(&mut self, store: UnavailableStore) {
+        self.unavailable = Arc::new(store);
+    }
+
+    pub fn set_favorite_store(&mut self, store: FavoriteStore) {
+        self.favorites = Arc::new(store);
+    }
+
+    pub fn set_follow_store(&mut self, store: FollowStore) {
+        self.follows = Arc::new(store);
+    }
+
+    /// Followed artists and the feed of their new releases
+    pub fn follows(&self) -> &FollowStore {
+        &self.follows
+    }
+
+    pub fn set_result_filter(&mut self, filter: ResultFilter) {
+        self.filter = Arc::new(filter);
+    }
+
+    /// Songs and collections saved to the library
+    pub fn favorites(&self) -> &FavoriteStore {
+        &self.favorites
+    }
+
+    /// Save the songs liked in the provider account to the favorites. Nothing is removed on either
+    /// side, since a song missing on one side may as well be newly liked as unliked on the other.
+    /// Returns the ids newly saved, or the ones that would be without saving them on a dry run.
+    pub async fn import_favorites(
+        &self,
+        provider: Provider,
+        dry_run: bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let _permit = self.acquire(&provider).await;
+        let liked = self
+            .scrapers
+            .read()
+            .await
+            .get(&provider)
+            .map(|s| self.timed(&provider, s.liked()))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await
+            .inspect_err(|e| {
+                error!("liked songs failed: provider: {:?}: {}", provider, e);
+                self.emit(|h| h.on_provider_error(&provider, e));
+            })?;
+
+        if dry_run {
+            let missing = self.favorites.missing(&provider, liked);
+            info!(
+                "import favorites: provider: {:?}, dry run, would import: {}",
+                provider,
+                missing.len()
+            );
+            return Ok(missing);
+        }
+        let imported = self.favorites.extend(provider.clone(), liked)?;
+        info!(
+            "import favorites: provider: {:?}, imported: {}",
+            provider,
+            imported.len()
+        );
+        Ok(imported)
+    }
+
+    /// Fall back on the last known results of providers when they fail
+    pub fn set_stale_cache(&mut self, cache: StaleCache) {
+        self.stale = Some(Arc::new(cache));
+    }
+
+    /// Order streams of all providers by the policy before returning them
+    pub fn set_stream_sort(&mut self, policy: StreamSortSettings) {
+        self.stream_sort = Arc::new(policy);
+    }
+
+    pub fn set_provider_priority(&mut self, policy: PrioritySettings) {
+        self.priority = Arc::new(policy);
+    }
+
+    /// Derive the timeouts of provider calls from their recent latency
+    pub fn set_latency_tracker(&mut self, tracker: LatencyTracker) {
+        self.latency = Arc::new(tracker);
+    }
+
+    pub fn latency(&self) -> &LatencyTracker {
+        &self.latency
+    }
+
+    /// Count the provider calls against daily quotas
+    pub fn set_quota_tracker(&mut self, tracker: QuotaTracker) {
+        self.quota = Arc::new(tracker);
+    }
+
+    pub fn quota(&self) -> &QuotaTracker {
+        &self.quota
+    }
+
+    /// Self-reported state of each provider
+    pub async fn health(&self) -> BTreeMap<Provider, Health> {
+        self.scrapers
+            .read()
+            .await
+            .iter()
+            .map(|(p, s)| (p.clone(), s.health()))
+            .collect()
+    }
+
+    /// Upstream instances of the providers calling several, like the Invidious instances of
+    /// YouTube
+    pub async fn instances(&self) -> BTreeMap<Provider, Vec<InstanceStats>> {
+        self.scrapers
+            .read()
+            .await
+            .iter()
+            .map(|(p, s)| (p.clone(), s.instances()))
+            .filter(|(_, instances)| !instances.is_empty())
+            .collect()
+    }
+
+    /// File of the song on disk, None if the provider has not indexed it
+    pub async fn local_file(&self, id: &str) -> anyhow::Result<Option<PathBuf>> {
+        let provider = Provider::Local;
+        let tid = TrackId::parse(&provider, id)?;
+        self.scrapers
+            .read()
+            .await
+            .get(&provider)
+            .map(|s| s.local_file(&tid))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))
+    }
+
+    /// Run the provider call within its adaptive timeout and record its latency. Timed out calls
+    /// are recorded at the timeout, so that a slowed down provider raises it up to the maximum.
+    /// A panic of the provider is turned into an error of this call only. Calls beyond the
+    /// daily quota of the provider are refused.
+    async fn timed<T>(
+        &self,
+        provider: &Provider,
+        call: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        self.quota.spend(provider)?;
+        let call = AssertUnwindSafe(call).catch_unwind().map(|result| {
+            result.unwrap_or_else(|panic| {
+                let message = panic
+                    .downcast_ref::<&str>()
+                    .map(ToString::to_string)
+                    .or_else(|| panic.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                Err(anyhow!("provider panicked: {}", message))
+            })
+        });
+        let start = Instant::now();
+        let result = match self.latency.timeout(provider) {
+            Some(timeout) => match tokio::time::timeout(timeout, call).await {
+                Ok(result) => result,
+                Err(_) => {
+                    self.latency.record(provider, timeout);
+                    bail!("timed out after {}ms", timeout.as_millis());
+                }
+            },
+            None => call.await,
+        };
+        self.latency.record(provider, start.elapsed());
+        result
+    }
+
+    pub fn add_event_handler(&mut self, handler: Box<dyn EventHandler>) {
+        self.handlers.write().push(handler);
+    }
+
+    /// Count the searches for operators, as an event handler
+    pub fn set_search_analytics(&mut self, analytics: SearchAnalytics) {
+        let analytics = Arc::new(analytics);
+        self.analytics = Some(analytics.clone());
+        self.add_event_handler(Box::new(analytics));
+    }
+
+    /// Look up lyrics by title where the provider has none
+    pub fn set_lyrics_fallback(&mut self, fallback: LyricsFallback) {
+        self.lyrics_fallback = Some(Arc::new(fallback));
+    }
+
+    /// None if search analytics are disabled
+    pub fn analytics(&self) -> Option<&SearchAnalytics> {
+        self.analytics.as_deref()
+    }
+
+    fn emit(&self, f: impl Fn(&dyn EventHandler)) {
+        self.handlers.read().iter().for_each(|h| f(h.as_ref()));
+    }
+
+    /// Report the error to event handlers and remember the id if upstream reports it as
+    /// unavailable. Content fetched is no longer remembered as unavailable.
+    fn track_error<T>(
+        &self,
+        provider: &Provider,
+        id: &str,
+        result: anyhow::Result<T>,
+    ) -> anyhow::Result<T> {
+        match &result {
+            Ok(_) => self.unavailable.remove(provider, id),
+            Err(e) => {
+                self.emit(|h| h.on_provider_error(provider, e));
+                let region_locked = e
+                    .downcast_ref::<Restricted>()
+                    .is_some_and(|r| r.restriction == Restriction::RegionLocked);
+                let kind = match e.downcast_ref::<Unavailable>() {
+                    Some(_) => Some(MarkKind::Deleted),
+                    None => region_locked.then_some(MarkKind::RegionLocked),
+                };
+                if let Some(kind) = kind {
+                    self.unavailable
+                        .insert(provider.clone(), id.to_string(), kind);
+                }
+            }
+        }
+        result
+    }
+
+    /// Search the provider with the variants of the keyword besides the original one
+    pub async fn set_keyword_variants(
+        &mut self,
+        provider: Provider,
+        variants: Vec<KeywordVariant>,
+    ) {
+        info!(
+            "set keyword variants: provider: {:?}, variants: {:?}",
+            provider, variants
+        );
+        let mut keyword_variants = self.keyword_variants.write().await;
+        keyword_variants.insert(provider, variants);
+    }
+
+    /// Search these types of the provider for `All` instead of the provider default, e.g. only
+    /// songs and playlists for music-focused deployments. Empty keeps the provider default.
+    pub async fn set_search_zones(&mut self, provider: Provider, zones: Vec<ScrapeType>) {
+        info!(
+            "set search zones: provider: {:?}, zones: {:?}",
+            provider, zones
+        );
+        let mut search_zones = self.search_zones.write().await;
+        search_zones.insert(provider, zones);
+    }
+
+    /// Search every zone as a typed search. Zones share the continuation, which is the page or
+    /// offset for every provider, and exhausted zones return nothing for later pages.
+    /// Every zone waits for a permit of the provider budget, like any other upstream call.
+    async fn search_zones(
+        &self,
+        provider: &Provider,
+        scraper: &dyn Scraper,
+        keyword: String,
+        zones: &[ScrapeType],
+        continuation: Option<String>,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<SearchPage> {
+        let mut page = SearchPage::default();
+        let mut last_err = None;
+        for result in futures::future::join_all(zones.iter().map(|z| async {
+            let _permit = self.acquire(provider).await;
+            self.timed(
+                provider,
+                scraper.search_filtered(keyword.clone(), z.clone(), continuation.clone(), filter),
+            )
+            .await
+        }))
+        .await
+        {
+            match result {
+                Ok(p) => {
+                    page.items.extend(p.items);
+                    page.next = page.next.or(p.next);
+                }
+                Err(e) => {
+                    warn!("zone search failed: provider: {:?}: {}", provider, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+
+        // partial results are fine as long as one of the zones succeeded
+        match (page.items.is_empty(), last_err) {
+            (true, Some(e)) => Err(e),
+            _ => Ok(page),
+        }
+    }
+
+    /// Rewrite the hosts of stream urls returned by the provider
+    pub async fn set_host_rewrites(&mut self, provider: Provider, rewriter: HostRewriter) {
+        info!(
+            "set host rewrites: provider: {:?}, rewrites: {:?}",
+            provider, rewriter
+        );
+        let mut host_rewrites = self.host_rewrites.write().await;
+        host_rewrites.insert(provider, rewriter);
+    }
+
+    /// Try to take a permit of the provider without waiting.
+    /// Returns Err(()) if the budget is exhausted and Ok(None) if the provider is unbounded.
+    async fn try_acquire(&self, provider: &Provider) -> Result<Option<OwnedSemaphorePermit>, ()> {
+        match self.budgets.read().await.get(provider) {
+            Some(s) => s.clone().try_acquire_owned().map(Some).map_err(|_| ()),
+            None => Ok(None),
+        }
+    }
+
+    /// Wait for a permit of the provider. Used by single provider calls which have no other
+    /// provider to fall back on.
+    async fn acquire(&self, provider: &Provider) -> Option<OwnedSemaphorePermit> {
+        let budget = self.budgets.read().await.get(provider).cloned();
+        match budget {
+            Some(s) => s.acquire_owned().await.ok(),
+            None => None,
+        }
+    }
+
+    pub async fn suggest(&self, keyword: String) -> FanOut<String> {
+        self.suggest_from(keyword, &[]).await
+    }
+
+    /// Suggestions of the listed providers only, of all of them if none is listed
+    pub async fn suggest_from(&self, keyword: String, allowed: &[Provider]) -> FanOut<String> {
+        let scrapers = self.scrapers.read().await;
+
+        let mut throttled = vec![];
+        let mut providers = BTreeMap::new();
+        let mut tasks = vec![];
+        for (p, s) in scrapers.iter().filter(|(p, _)| is_allowed(allowed, p)) {
+            let permit = match self.try_acquire(p).await {
+                Ok(permit) => permit,
+                Err(_) => {
+                    warn!("suggest throttled: provider: {:?}", p);
+                    throttled.push(p.clone());
+                    providers.insert(p.clone(), ProviderStatus::Throttled);
+                    continue;
+                }
+            };
             let keyword = keyword.clone();
-            async move {
-                s.suggest(keyword).await.map(|ss| {
+            tasks.push(async move {
+                let _permit = permit;
+                let suggestions = self.timed(p, s.suggest(keyword)).await.map(|ss| {
                     ss.into_iter()
                         .map(|s| WithProvider::new(p.clone(), s))
                         .collect::<Vec<_>>()
-                })
-            }
-        }))
-        .await
-        .into_iter()
-        .filter_map(
-            |v: Result<Vec<WithProvider<String>>, anyhow::Error>| match v {
-                Ok(v) => Some(v),
+                });
+                (p, suggestions)
+            });
+        }
+
+        let mut items = futures::future::join_all(tasks)
+            .await
+            .into_iter()
+            .filter_map(|(p, v)| match v {
+                Ok(v) => {
+                    providers.insert(p.clone(), ProviderStatus::Ok);
+                    Some(v)
+                }
                 Err(e) => {
-                    error!("suggest failed: {}", e);
+                    error!("suggest failed: provider: {:?}: {}", p, e);
+                    self.emit(|h| h.on_provider_error(p, &e));
+                    providers.insert(p.clone(), ProviderStatus::Failed(e.to_string()));
                     None
                 }
-            },
-        )
-        .flatten()
-        .collect()
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+        sort_by_priority(&mut items, &self.priority, &ScrapeType::All);
+
+        FanOut {
+            items,
+            throttled,
+            next: None,
+            providers,
+        }
     }
 
-    pub async fn search(&self, keyword: String, t: ScrapeType) -> Vec<WithProvider<ScrapeItem>> {
-        futures::future::join_all(self.scrapers.read().await.iter().map(|(p, s)| {
-            let keyword = keyword.clone();
-            let t = t.clone();
-            async move {
-                s.search(keyword, t)
-                    .await
+    /// Search all providers. With a cursor, only the providers which still have more results are
+    /// searched, each continuing from its own position.
+    pub async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        cursor: Option<Cursor>,
+    ) -> FanOut<ScrapeItem> {
+        self.search_filtered(keyword, t, cursor, &SearchFilter::default())
+            .await
+    }
+
+    /// Search with the results narrowed by the filter, e.g. by duration.
+    /// If the first page finds nothing, relaxed keywords are tried in turn if enabled. Results of
+    /// a relaxed keyword are marked and have no next page, since the cursor would continue the
+    /// original keyword.
+    pub async fn search_filtered(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        cursor: Option<Cursor>,
+        filter: &SearchFilter,
+    ) -> FanOut<ScrapeItem> {
+        let first_page = cursor.is_none();
+        let mut results = self
+            .search_providers(keyword.clone(), t.clone(), cursor, filter)
+            .await;
+
+        if results.items.is_empty() && first_page && self.relax_zero_result {
+            for relaxed in relax::relaxations(&keyword) {
+                let mut relaxed_results = self
+                    .search_providers(relaxed.clone(), t.clone(), None, filter)
+                    .await;
+                if relaxed_results.items.is_empty() {
+                    continue;
+                }
+                info!(
+                    "relaxed search: {} -> {}",
+                    redact(&keyword),
+                    redact(&relaxed)
+                );
+                relaxed_results
+                    .items
+                    .iter_mut()
+                    .for_each(|i| i.relaxed_match = true);
+                relaxed_results.next = None;
+                results = relaxed_results;
+                break;
+            }
+        }
+
+        self.emit(|h| h.on_search(&keyword, &t, &results));
+        results
+    }
+
+    async fn search_providers(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        cursor: Option<Cursor>,
+        filter: &SearchFilter,
+    ) -> FanOut<ScrapeItem> {
+        let scrapers = self.scrapers.read().await;
+        let keyword_variants = self.keyword_variants.read().await;
+        let search_zones = self.search_zones.read().await;
+        let query = Query::parse(&keyword);
+
+        let mut throttled = vec![];
+        let mut providers = BTreeMap::new();
+        let mut next = Cursor::default();
+        let mut tasks = vec![];
+        for (p, s) in scrapers
+            .iter()
+            .filter(|(p, _)| is_allowed(&filter.providers, p))
+        {
+            let continuation = match &cursor {
+                Some(cursor) => match cursor.get(p) {
+                    Some(c) => Some(c.clone()),
+                    // the provider is exhausted
+                    None => continue,
+                },
+                None => None,
+            };
+
+            // Only checked here, every upstream call of the search takes its own permit since a
+            // search may call the provider for several keywords and zones
+            match self.try_acquire(p).await {
+                Ok(_) => {}
+                Err(_) => {
+                    warn!("search throttled: provider: {:?}", p);
+                    throttled.push(p.clone());
+                    providers.insert(p.clone(), ProviderStatus::Throttled);
+                    // keep the position so that the page can be retried with the next cursor
+                    if let Some(c) = continuation {
+                        next.insert(p.clone(), c);
+                    }
+                    continue;
+                }
+            }
+            // other names of the filtered artist are searched like keyword variants
+            let alias_keywords = match (&continuation, &query.artist) {
+                (None, Some(artist)) => self
+                    .aliases
+                    .aliases(artist)
                     .into_iter()
-                    .map(|s| WithProvider::new(p.clone(), s))
-                    .collect::<Vec<_>>()
+                    .take(MAX_ALIAS_VARIANTS)
+                    .map(|alias| {
+                        let query = Query {
+                            artist: Some(alias),
+                            ..query.clone()
+                        };
+                        s.native_query(&query, t.clone()).0
+                    })
+                    .collect(),
+                _ => vec![],
+            };
+            let (keyword, t) = match query.has_filters() {
+                true => s.native_query(&query, t.clone()),
+                false => (keyword.clone(), t.clone()),
+            };
+            let zones = match (&t, search_zones.get(p)) {
+                (ScrapeType::All, Some(zones)) if !zones.is_empty() => zones.clone(),
+                _ => vec![t.clone()],
+            };
+
+            let stale_key = format!(
+                "{:?}:{:?}:{}:{}",
+                t,
+                filter,
+                continuation.clone().unwrap_or_default(),
+                keyword
+            );
+
+            // Variants are only searched for the first page. Following pages continue the
+            // original keyword.
+            let mut keywords = match continuation {
+                Some(_) => vec![keyword],
+                None => self.normalizer.variants(
+                    &keyword,
+                    keyword_variants
+                        .get(p)
+                        .map(Vec::as_slice)
+                        .unwrap_or_default(),
+                ),
+            };
+            for k in alias_keywords {
+                if !keywords.contains(&k) {
+                    keywords.push(k);
+                }
             }
-        }))
-        .await
-        .into_iter()
-        .flatten()
-        .collect()
+            tasks.push(async move {
+                let mut pages = futures::future::join_all(keywords.into_iter().map(|k| {
+                    self.search_zones(p, s.as_ref(), k, &zones, continuation.clone(), filter)
+                }))
+                .await
+                .into_iter();
+
+                // only the original keyword decides whether the provider failed
+                let page = pages.next().unwrap_or_else(|| Ok(SearchPage::default()));
+                let page = page.map(|mut page| {
+                    for item in pages.filter_map(Result::ok).flat_map(|p| p.items) {
+                        if !page.items.iter().any(|i| i.same_as(&item)) {
+                            page.items.push(item);
+                        }
+                    }
+                    page
+                });
+                (p.clone(), stale_key, page)
+            });
+        }
+
+        let mut items = vec![];
+        for (p, stale_key, page) in futures::future::join_all(tasks).await {
+            let (page, stale) = match (page, &self.stale) {
+                (Ok(page), Some(cache)) => {
+                    cache.put_search(p.clone(), stale_key, page.clone());
+                    (page, false)
+                }
+                (Ok(page), None) => (page, false),
+                (Err(e), cache) => {
+                    error!("search failed: provider: {:?}: {}", p, e);
+                    self.emit(|h| h.on_provider_error(&p, &e));
+                    match cache.as_ref().and_then(|c| c.search(p.clone(), stale_key)) {
+                        Some(page) => {
+                            warn!("serve stale search: provider: {:?}", p);
+                            providers.insert(p.clone(), ProviderStatus::Stale(e.to_string()));
+                            (page, true)
+                        }
+                        None => {
+                            providers.insert(p.clone(), ProviderStatus::Failed(e.to_string()));
+                            continue;
+                        }
+                    }
+                }
+            };
+            providers.entry(p.clone()).or_insert(ProviderStatus::Ok);
+
+            if let Some(c) = page.next {
+                next.insert(p.clone(), c);
+            }
+            let kept = page
+                .items
+                .into_iter()
+                .filter(|i| self.filter.keep(&p, i) && filter.keep(i));
+            items.extend(kept.map(|mut i| {
+                self.unavailable.annotate(&p, &mut i);
+                self.favorites.annotate(&p, &mut i);
+                WithProvider {
+                    stale,
+                    ..WithProvider::new(p.clone(), i)
+                }
+            }));
+        }
+
+        sort_by_priority(&mut items, &self.priority, &t);
+        if filter.merge {
+            items = merge_songs(items, &self.aliases);
+        }
+        // results of the filtered artist first, by whatever name the provider knows the artist
+        if let Some(artist) = &query.artist {
+            items.sort_by_key(|i| !self.aliases.matches(artist, i.data()));
+        }
+
+        FanOut {
+            items,
+            throttled,
+            next: (!next.is_empty()).then_some(next),
+            providers,
+        }
     }
 
     pub async fn collection_detail(
@@ -162,52 +1171,993 @@ impl ScraperManager {
         id: String,
         provider: Provider,
     ) -> anyhow::Result<SongCollection> {
-        self.scrapers
+        let cid = CollectionId::parse(&provider, &id)?;
+        // the raw id, also when the client sent the opaque one
+        let id = cid.to_string();
+        let _permit = self.acquire(&provider).await;
+        let result = self
+            .scrapers
             .read()
             .await
             .get(&provider)
-            .map(|s| s.collection_detail(id))
+            .map(|s| self.timed(&provider, s.collection_detail(cid)))
             .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await;
+
+        let mut collection = match (self.track_error(&provider, &id, result), &self.stale) {
+            (Ok(collection), Some(cache)) => {
+                cache.put_collection(provider.clone(), id, collection.clone());
+                collection
+            }
+            (Ok(collection), None) => collection,
+            (Err(e), cache) => match cache
+                .as_ref()
+                .filter(|_| e.downcast_ref::<Unavailable>().is_none())
+                .and_then(|c| c.collection(provider.clone(), id))
+            {
+                Some(collection) => {
+                    warn!("serve stale collection: provider: {:?}: {}", provider, e);
+                    collection
+                }
+                None => return Err(e),
+            },
+        };
+        self.filter.apply_collection(&provider, &mut collection);
+        self.unavailable
+            .annotate_collection(&provider, &mut collection);
+        self.favorites
+            .annotate_collection(&provider, &mut collection);
+        Ok(collection)
+    }
+
+    pub async fn collection_version(
+        &self,
+        id: String,
+        provider: Provider,
+    ) -> anyhow::Result<Option<String>> {
+        let cid = CollectionId::parse(&provider, &id)?;
+        let id = cid.to_string();
+        let _permit = self.acquire(&provider).await;
+        let result = self
+            .scrapers
+            .read()
             .await
+            .get(&provider)
+            .map(|s| self.timed(&provider, s.collection_version(cid)))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await;
+        self.track_error(&provider, &id, result)
     }
 
-    pub async fn stream(&self, id: String, provider: Provider) -> anyhow::Result<Vec<Stream>> {
+    /// Latest uploads or releases of the artist, newest first
+    pub async fn artist_releases(
+        &self,
+        id: String,
+        provider: Provider,
+    ) -> anyhow::Result<Vec<ScrapeItem>> {
+        let aid = ArtistId::parse(&provider, &id)?;
+        let _permit = self.acquire(&provider).await;
+        let mut items = self
+            .scrapers
+            .read()
+            .await
+            .get(&provider)
+            .map(|s| self.timed(&provider, s.artist_releases(aid)))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await
+            .inspect_err(|e| self.emit(|h| h.on_provider_error(&provider, e)))?;
+        items.retain(|item| self.filter.keep(&provider, item));
+        for item in items.iter_mut() {
+            self.unavailable.annotate(&provider, item);
+            self.favorites.annotate(&provider, item);
+        }
+        Ok(items)
+    }
+
+    pub async fn artist_detail(
+        &self,
+        id: String,
+        provider: Provider,
+    ) -> anyhow::Result<ArtistDetail> {
+        let aid = ArtistId::parse(&provider, &id)?;
+        let _permit = self.acquire(&provider).await;
+        let mut detail = self
+            .scrapers
+            .read()
+            .await
+            .get(&provider)
+            .map(|s| self.timed(&provider, s.artist_detail(aid)))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await
+            .inspect_err(|e| self.emit(|h| h.on_provider_error(&provider, e)))?;
+        detail.songs.retain(|s| self.filter.keep_song(&provider, s));
+        for s in detail.songs.iter_mut() {
+            s.unavailable |= self.unavailable.contains(&provider, &s.id);
+            s.saved = self.favorites.contains(&provider, &s.id);
+        }
+        for collections in [&mut detail.albums, &mut detail.playlists] {
+            collections.retain(|c| self.filter.keep_collection(&provider, c));
+            for c in collections.iter_mut() {
+                self.unavailable.annotate_collection(&provider, c);
+                self.favorites.annotate_collection(&provider, c);
+            }
+        }
+        Ok(detail)
+    }
+
+    /// Chapters of the upload as songs sharing its id
+    pub async fn chapters(&self, id: String, provider: Provider) -> anyhow::Result<Vec<Song>> {
+        let tid = TrackId::parse(&provider, &id)?;
+        let _permit = self.acquire(&provider).await;
+        let result = self
+            .scrapers
+            .read()
+            .await
+            .get(&provider)
+            .map(|s| self.timed(&provider, s.chapters(tid)))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await;
+        self.track_error(&provider, &id, result)
+    }
+
+    /// Headers required by the hosts of the stream urls of the provider
+    pub async fn stream_headers(&self, provider: &Provider) -> Vec<(&'static str, String)> {
         self.scrapers
+            .read()
+            .await
+            .get(provider)
+            .map(|s| s.stream_headers())
+            .unwrap_or_default()
+    }
+
+    /// Lyrics of the provider, or else those looked up by the `query` if a fallback is configured.
+    /// Empty if neither has any.
+    pub async fn lyrics(
+        &self,
+        id: String,
+        provider: Provider,
+        query: Option<LyricsQuery>,
+    ) -> anyhow::Result<Lyrics> {
+        let tid = TrackId::parse(&provider, &id)?;
+        let result = {
+            let _permit = self.acquire(&provider).await;
+            self.scrapers
+                .read()
+                .await
+                .get(&provider)
+                .map(|s| self.timed(&provider, s.lyrics(tid)))
+                .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+                .await
+                .inspect_err(|e| self.emit(|h| h.on_provider_error(&provider, e)))
+        };
+        if result.as_ref().is_ok_and(|l| !l.is_empty()) {
+            return result;
+        }
+
+        let (Some(fallback), Some(query)) = (&self.lyrics_fallback, &query) else {
+            return result;
+        };
+        match fallback.lookup(query).await {
+            Ok(Some(lyrics)) => Ok(lyrics),
+            Ok(None) => result,
+            Err(e) => {
+                warn!("look up lyrics failed: {}", e);
+                result
+            }
+        }
+    }
+
+    /// Check every followed artist for new releases, one after another to go easy on the
+    /// providers. Artists failing to be checked, or of providers short of quota, are retried on
+    /// the next check.
+    pub async fn check_releases(&self) -> Vec<Release> {
+        let mut releases = vec![];
+        for (provider, artists) in self.follows.list() {
+            for artist in artists {
+                if !self.quota.allows_background(&provider) {
+                    break;
+                }
+                let found = match self.artist_releases(artist.clone(), provider.clone()).await {
+                    Ok(items) => self.follows.record(&provider, &artist, items),
+                    Err(e) => Err(e),
+                };
+                match found {
+                    Ok(found) => releases.extend(found),
+                    Err(e) => warn!(
+                        "check releases failed: provider: {:?}, artist: {}: {}",
+                        provider,
+                        redact(&artist),
+                        e
+                    ),
+                }
+            }
+        }
+        releases
+    }
+
+    pub async fn stream(&self, id: String, provider: Provider) -> anyhow::Result<Vec<Stream>> {
+        let tid = TrackId::parse(&provider, &id)?;
+        let id = tid.to_string();
+        let _permit = self.acquire(&provider).await;
+        let result = self
+            .scrapers
+            .read()
+            .await
+            .get(&provider)
+            .map(|s| self.timed(&provider, s.stream(tid)))
+            .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await;
+
+        // no stale fallback: stream urls expire, Bilibili and NetEase ones carry their deadline
+        let mut streams = self.track_error(&provider, &id, result)?;
+        if let Some(rewriter) = self.host_rewrites.read().await.get(&provider) {
+            rewriter.apply(&mut streams);
+        }
+        sort_streams(&mut streams, &self.stream_sort);
+        self.emit(|h| h.on_stream_resolved(&provider, &id, &streams));
+        Ok(streams)
+    }
+
+    /// Samples of the song marked as previews, for clients offering to listen to a sample of
+    /// locked content
+    pub async fn preview(&self, id: String, provider: Provider) -> anyhow::Result<Vec<Stream>> {
+        let tid = TrackId::parse(&provider, &id)?;
+        let _permit = self.acquire(&provider).await;
+        let result = self
+            .scrapers
             .read()
             .await
             .get(&provider)
-            .map(|s| s.stream(id))
+            .map(|s| self.timed(&provider, s.preview(tid)))
             .ok_or(anyhow!("unsupported provider: {:?}", provider))?
+            .await;
+        let mut streams = self.track_error(&provider, &id, result)?;
+        streams.iter_mut().for_each(|s| s.preview = true);
+        if let Some(rewriter) = self.host_rewrites.read().await.get(&provider) {
+            rewriter.apply(&mut streams);
+        }
+        sort_streams(&mut streams, &self.stream_sort);
+        Ok(streams)
+    }
+
+    /// Dry run of the stream resolution returning the decision trace. Nothing is cached or
+    /// remembered and provider errors are reported in the trace instead.
+    pub async fn explain_stream(&self, id: String, provider: Provider) -> StreamTrace {
+        let mut trace = StreamTrace::default();
+        let tid = match TrackId::parse(&provider, &id) {
+            Ok(tid) => tid,
+            Err(e) => {
+                trace.note(format!("not requested upstream: {}", e));
+                return trace;
+            }
+        };
+        let id = tid.to_string();
+
+        if self.unavailable.contains(&provider, &id) {
+            trace.note("remembered as unavailable upstream");
+        }
+        if let Some(budget) = self.budgets.read().await.get(&provider) {
+            if budget.available_permits() == 0 {
+                trace.note("concurrency budget exhausted: waiting for a permit");
+            }
+        }
+
+        let _permit = self.acquire(&provider).await;
+        let result = match self.scrapers.read().await.get(&provider) {
+            Some(s) => self.timed(&provider, s.stream_trace(tid)).await,
+            None => Err(anyhow!("unsupported provider: {:?}", provider)),
+        };
+
+        match result {
+            Ok(t) => {
+                trace.steps.extend(t.steps);
+                trace.streams = t.streams;
+            }
+            Err(e) => trace.note(format!("provider error: {}", e)),
+        }
+
+        if let Some(rewriter) = self.host_rewrites.read().await.get(&provider) {
+            for rewrite in rewriter.apply(&mut trace.streams) {
+                trace.note(format!("host rewritten: {}", rewrite));
+            }
+        }
+        if sort_streams(&mut trace.streams, &self.stream_sort) {
+            trace.note("streams reordered by the stream_sort policy");
+        }
+        trace
+    }
+
+    /// The track, searched by its name and artists, on every provider side by side with the
+    /// streams of the song matching it best, in the priority order of songs. Providers throttled
+    /// or without a match are listed too, with the reason.
+    pub async fn compare(&self, keyword: String, duration: Option<u32>) -> Vec<Source> {
+        let FanOut {
+            items, throttled, ..
+        } = self.search(keyword, ScrapeType::Song, None).await;
+        let mut songs: BTreeMap<Provider, Vec<Song>> = self
+            .scrapers
+            .read()
+            .await
+            .keys()
+            .map(|p| (p.clone(), vec![]))
+            .collect();
+        for item in items {
+            if let (Some(songs), ScrapeItem::Song(song)) =
+                (songs.get_mut(&item.provider), item.data)
+            {
+                songs.push(song);
+            }
+        }
+
+        let throttled = &throttled;
+        let sources = songs.into_iter().map(|(provider, songs)| async move {
+            if throttled.contains(&provider) {
+                return Source::missing(provider, "throttled");
+            }
+            match compare::best_match(songs, duration) {
+                Some(song) => {
+                    let streams = self.stream(song.id.to_string(), provider.clone()).await;
+                    Source::resolved(provider, song, streams)
+                }
+                None => Source::missing(provider, "no matching song"),
+            }
+        });
+        let mut sources = futures::future::join_all(sources).await;
+        sources
+            .sort_by_cached_key(|s| priority::rank(&self.priority, &ScrapeType::Song, &s.provider));
+        sources
+    }
+
+    /// The song on the other providers, for unavailable songs to be played elsewhere
+    pub async fn substitutes(&self, song: &Song, provider: &Provider) -> Vec<Alternative> {
+        let others: Vec<Provider> = self
+            .scrapers
+            .read()
             .await
+            .keys()
+            .filter(|p| *p != provider)
+            .cloned()
+            .collect();
+        self.find_song(song, others).await
+    }
+
+    /// The song on every provider, like a song known by its name, artists and duration only
+    pub async fn find(&self, song: &Song) -> Vec<Alternative> {
+        let providers: Vec<Provider> = self.scrapers.read().await.keys().cloned().collect();
+        self.find_song(song, providers).await
+    }
+
+    /// The song on the providers, searched by its name and first artist and matched like merged
+    /// search results are. At most one of each provider, in the priority order of songs.
+    async fn find_song(&self, song: &Song, providers: Vec<Provider>) -> Vec<Alternative> {
+        if providers.is_empty() {
+            return vec![];
+        }
+        let keyword = match song.artists.first() {
+            Some(artist) => format!("{} {}", song.name, artist.name),
+            None => song.name.clone(),
+        };
+        let filter = SearchFilter {
+            providers,
+            ..Default::default()
+        };
+        // not a search of the user, kept out of the search events
+        let found = self
+            .search_providers(keyword, ScrapeType::Song, None, &filter)
+            .await;
+
+        let mut alternatives: Vec<Alternative> = vec![];
+        for item in found.items {
+            let ScrapeItem::Song(s) = &item.data else {
+                continue;
+            };
+            if s.unavailable
+                || alternatives.iter().any(|a| a.provider == item.provider)
+                || !merge::same_song(song, s, &self.aliases)
+            {
+                continue;
+            }
+            alternatives.push(Alternative {
+                provider: item.provider.clone(),
+                id: s.id.clone(),
+            });
+        }
+        alternatives
     }
 
     pub async fn try_from_settings(settings: &Settings) -> anyhow::Result<Self> {
-        let mut manager = Self::default();
+        if settings.application.privacy_mode {
+            info!("privacy mode: keywords and ids are hashed in logs and analytics");
+            privacy::enable();
+        }
+        util::body::set_limit(settings.limits.upstream_body);
+        // the ids found unavailable tell what was requested
+        let unavailable_path = match settings.application.privacy_mode {
+            true => None,
+            false => settings.application.unavailable_path.clone(),
+        };
+
+        let mut builder = BragiBuilder::new()
+            .with_zero_result_relaxation(settings.keyword.relax_zero_result)
+            .with_keyword_normalizer(KeywordNormalizer::try_from_setting(
+                settings.keyword.clone(),
+            )?)
+            .with_artist_aliases(ArtistAliases::try_from_setting(&settings.application)?)
+            .with_unavailable_store(UnavailableStore::try_new(unavailable_path)?)
+            .with_favorite_store(FavoriteStore::try_new(
+                settings.application.favorites_path.clone(),
+            )?)
+            .with_follow_store(FollowStore::try_new(
+                settings.application.follows_path.clone(),
+            )?)
+            .with_result_filter(ResultFilter::try_from_setting(settings.filter.clone())?);
+        if let Some(cache) = StaleCache::from_setting(&settings.stale) {
+            builder = builder.with_cache(cache);
+        }
+        builder = builder
+            .with_stream_sort(settings.stream_sort.clone())
+            .with_provider_priority(settings.priority.clone())
+            .with_latency_tracker(LatencyTracker::from_setting(&settings.timeout))
+            .with_quota_tracker(QuotaTracker::new(settings.quota.clone()));
+        if let Some(analytics) = SearchAnalytics::from_setting(&settings.analytics) {
+            builder = builder.with_search_analytics(analytics);
+        }
+        if let Some(fallback) = LyricsFallback::from_setting(&settings.lyrics) {
+            builder = builder.with_lyrics_fallback(fallback);
+        }
+
+        // stays empty if every provider is compiled out
+        #[allow(unused_mut)]
+        let mut budgets = vec![];
 
         if let Some(cfg) = &settings.youtube {
+            #[cfg(feature = "youtube")]
             if let Some(scraper) = YouTubeScraper::try_from_setting(cfg.clone())? {
-                manager
-                    .add_scraper(Provider::Youtube, Box::new(scraper))
-                    .await;
+                builder = builder
+                    .with_scraper(Provider::Youtube, scraper)
+                    .with_keyword_variants(Provider::Youtube, cfg.keyword_variants.clone())
+                    .with_search_zones(Provider::Youtube, cfg.search_zones.clone());
+                budgets.push((Provider::Youtube, cfg.budget.clone()));
             }
+            #[cfg(not(feature = "youtube"))]
+            compiled_out(Provider::Youtube, cfg.enabled);
         }
 
         if let Some(cfg) = &settings.netease {
-            if let Some(scraper) = NeteaseScraper::try_from_setting(cfg.clone())? {
-                manager
-                    .add_scraper(Provider::NetEase, Box::new(scraper))
-                    .await;
+            #[cfg(feature = "netease")]
+            if let Some(scraper) =
+                NeteaseScraper::try_from_setting(cfg.clone(), settings.application.outbound_family)?
+            {
+                builder = builder
+                    .with_scraper(Provider::NetEase, scraper)
+                    .with_keyword_variants(Provider::NetEase, cfg.keyword_variants.clone())
+                    .with_search_zones(Provider::NetEase, cfg.search_zones.clone())
+                    .with_host_rewrites(
+                        Provider::NetEase,
+                        HostRewriter::try_from_setting(cfg.host_rewrites.clone())?,
+                    );
+                budgets.push((Provider::NetEase, cfg.budget.clone()));
             }
+            #[cfg(not(feature = "netease"))]
+            compiled_out(Provider::NetEase, cfg.enabled);
         }
 
         if let Some(cfg) = &settings.bilibili {
-            if let Some(scraper) = BiliScraper::try_from_setting(cfg.clone())? {
-                manager
-                    .add_scraper(Provider::Bilibili, Box::new(scraper))
-                    .await;
+            #[cfg(feature = "bili")]
+            if let Some(scraper) =
+                BiliScraper::try_from_setting(cfg.clone(), settings.application.outbound_family)?
+            {
+                builder = builder
+                    .with_scraper(Provider::Bilibili, scraper)
+                    .with_keyword_variants(Provider::Bilibili, cfg.keyword_variants.clone())
+                    .with_search_zones(Provider::Bilibili, cfg.search_zones.clone())
+                    .with_host_rewrites(
+                        Provider::Bilibili,
+                        HostRewriter::try_from_setting(cfg.host_rewrites.clone())?,
+                    );
+                budgets.push((Provider::Bilibili, cfg.budget.clone()));
             }
+            #[cfg(not(feature = "bili"))]
+            compiled_out(Provider::Bilibili, cfg.enabled);
         }
 
-        Ok(manager)
+        if let Some(cfg) = &settings.local {
+            #[cfg(feature = "local")]
+            if let Some(scraper) = LocalScraper::try_from_setting(cfg.clone())? {
+                builder = builder.with_scraper(Provider::Local, scraper);
+            }
+            #[cfg(not(feature = "local"))]
+            compiled_out(Provider::Local, cfg.enabled);
+        }
+
+        if let Some(cfg) = &settings.radio {
+            #[cfg(feature = "radio")]
+            if let Some(scraper) =
+                RadioScraper::try_from_setting(cfg.clone(), settings.application.outbound_family)?
+            {
+                builder = builder.with_scraper(Provider::Radio, scraper);
+                budgets.push((Provider::Radio, cfg.budget.clone()));
+            }
+            #[cfg(not(feature = "radio"))]
+            compiled_out(Provider::Radio, cfg.enabled);
+        }
+
+        if let Some(cfg) = &settings.spotify {
+            #[cfg(feature = "spotify")]
+            if let Some(scraper) =
+                SpotifyScraper::try_from_setting(cfg.clone(), settings.application.outbound_family)?
+            {
+                builder = builder.with_scraper(Provider::Spotify, scraper);
+                budgets.push((Provider::Spotify, cfg.budget.clone()));
+            }
+            #[cfg(not(feature = "spotify"))]
+            compiled_out(Provider::Spotify, cfg.enabled);
+        }
+
+        // replaces the scraper of the provider if it is configured as well
+        if let Some(cfg) = settings.fixtures.as_ref().filter(|f| f.enabled) {
+            #[cfg(feature = "test-util")]
+            for (provider, path) in &cfg.files {
+                warn!("serve provider {:?} from fixture: {}", provider, path);
+                builder =
+                    builder.with_scraper(provider.clone(), FixtureScraper::try_from_file(path)?);
+            }
+            #[cfg(not(feature = "test-util"))]
+            warn!(
+                "fixtures of {:?} are enabled but compiled out. Rebuild with the test-util cargo feature to use them",
+                cfg.files.keys().collect::<Vec<_>>()
+            );
+        }
+
+        for (provider, permits) in split_budgets(settings.application.max_concurrency, budgets)? {
+            builder = builder.with_budget(provider, permits);
+        }
+
+        Ok(builder.build().await)
+    }
+}
+
+/// Whether the provider is among the allowed ones, all of them are if none is listed
+fn is_allowed(allowed: &[Provider], provider: &Provider) -> bool {
+    allowed.is_empty() || allowed.contains(provider)
+}
+
+/// The provider is configured but its cargo feature is disabled
+#[allow(dead_code)]
+fn compiled_out(provider: Provider, enabled: bool) {
+    if enabled {
+        warn!(
+            "provider {:?} is enabled but compiled out. Rebuild with its cargo feature to use it",
+            provider
+        );
+    }
+}
+
+/// Split the shared concurrency among providers. Providers with an explicit concurrency keep it,
+/// and the rest of the total is split among the others by weight. Providers are unbounded if
+/// neither is configured. Every budget allows one call at least, a budget of none would wait
+/// forever, and the budgets never add up to more than the total.
+fn split_budgets(
+    max_concurrency: Option<usize>,
+    budgets: Vec<(Provider, BudgetSettings)>,
+) -> anyhow::Result<Vec<(Provider, usize)>> {
+    let pinned: usize = budgets
+        .iter()
+        .filter_map(|(_, b)| b.concurrency)
+        .map(|c| c.max(1))
+        .sum();
+    let shared = budgets
+        .iter()
+        .filter(|(_, b)| b.concurrency.is_none())
+        .collect::<Vec<_>>();
+    let total_weight: usize = shared.iter().map(|(_, b)| b.weight).sum();
+
+    // one permit for every provider sharing the total, the rest by weight
+    let rest = match max_concurrency {
+        Some(max) if max < pinned + shared.len() => bail!(
+            "max_concurrency {} is below the {} calls the providers need at least: {} of fixed concurrencies and one for each of the {} others",
+            max,
+            pinned + shared.len(),
+            pinned,
+            shared.len()
+        ),
+        Some(max) => Some(max - pinned - shared.len()),
+        None => None,
+    };
+
+    Ok(budgets
+        .into_iter()
+        .filter_map(|(p, b)| match (b.concurrency, rest) {
+            (Some(c), _) => Some((p, c.max(1))),
+            (None, Some(rest)) => Some((p, 1 + rest * b.weight / total_weight.max(1))),
+            (None, None) => None,
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::{settings::BudgetSettings, BragiBuilder};
+
+    use super::{
+        alias::ArtistAliases, fixture::FixtureScraper, query::SearchFilter, split_budgets, Artist,
+        Provider, ProviderStatus, ScrapeItem, ScrapeType, Song,
+    };
+
+    fn fixture(json: serde_json::Value) -> FixtureScraper {
+        FixtureScraper::from_json(&json.to_string()).unwrap()
+    }
+
+    fn song(id: &str, duration: u32) -> Song {
+        Song {
+            id: id.into(),
+            name: "Plastic Love".into(),
+            artists: vec![Artist {
+                id: "1".into(),
+                name: "竹内まりや".into(),
+                description: None,
+                avatar: None,
+            }],
+            cover: None,
+            duration: Some(duration),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+
+    /// The song and its live version
+    fn versions() -> FixtureScraper {
+        fixture(json!({ "songs": [song("live", 612), song("original", 480)] }))
+    }
+
+    #[tokio::test]
+    async fn test_substitutes() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, versions())
+            .with_scraper(Provider::Youtube, versions())
+            .build()
+            .await;
+
+        let unavailable = Song {
+            unavailable: true,
+            ..song("1", 482)
+        };
+        let alternatives = manager.substitutes(&unavailable, &Provider::NetEase).await;
+        assert_eq!(alternatives.len(), 1);
+        assert_eq!(alternatives[0].provider, Provider::Youtube);
+        assert_eq!(alternatives[0].id.as_str(), "original");
+        assert_eq!(manager.find(&unavailable).await.len(), 2);
+
+        // no other provider
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, versions())
+            .build()
+            .await;
+        assert!(manager
+            .substitutes(&unavailable, &Provider::NetEase)
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_artist_aliases() {
+        let aliases = ArtistAliases::default();
+        aliases.learn(vec!["米津玄師".into(), "Kenshi Yonezu".into()]);
+        let scraper = fixture(json!({
+            "artists": [{ "id": "1", "name": "米津玄師" }, { "id": "2", "name": "Kenshi Yonezu" }],
+            "songs": [{
+                "id": "3",
+                "name": "Lemon (米津玄師 / Kenshi Yonezu cover)",
+                "artists": [{ "id": "4", "name": "cover channel" }],
+            }],
+        }));
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, scraper)
+            .with_artist_aliases(aliases)
+            .build()
+            .await;
+
+        let results = manager
+            .search("artist:米津玄師".into(), ScrapeType::All, None)
+            .await;
+        let names = results
+            .items
+            .iter()
+            .map(|i| i.data().id().to_string())
+            .collect::<Vec<_>>();
+        // the alias is searched too, and results of the artist come first
+        assert_eq!(names, vec!["1", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn test_relax_zero_result() {
+        // more than a page of songs, so that the first search has a next page
+        let songs = (0..11)
+            .map(|i| json!({ "id": i.to_string(), "name": "Idol", "artists": [] }))
+            .collect::<Vec<_>>();
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, fixture(json!({ "songs": songs })))
+            .with_zero_result_relaxation(true)
+            .build()
+            .await;
+
+        let results = manager.search("idol".into(), ScrapeType::All, None).await;
+        assert!(!results.items[0].is_relaxed_match());
+        assert!(results.next.is_some());
+
+        let results = manager
+            .search(
+                "YOASOBI - Idol (feat. someone)".into(),
+                ScrapeType::All,
+                None,
+            )
+            .await;
+        assert_eq!(results.items.len(), 10);
+        assert!(results.items.iter().all(|i| i.is_relaxed_match()));
+        assert!(results.next.is_none());
+
+        let results = manager.search("taffy".into(), ScrapeType::All, None).await;
+        assert!(results.items.is_empty());
+    }
+
+    /// An artist, a playlist and an album named `taffy`, and `songs` songs named alike
+    fn taffy(songs: usize) -> FixtureScraper {
+        let collection =
+            |id: &str| json!({ "id": id, "name": "taffy", "artists": [], "songs": [] });
+        fixture(json!({
+            "artists": [{ "id": "1", "name": "taffy" }],
+            "songs": (0..songs)
+                .map(|i| json!({ "id": (10 + i).to_string(), "name": "taffy", "artists": [] }))
+                .collect::<Vec<_>>(),
+            "playlists": [collection("2")],
+            "albums": [collection("3")],
+            "previews": {
+                "10": [{ "quality": "preview(128000)", "url": "https://m801.music.126.net/10.mp3" }],
+            },
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_search_zones() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, taffy(11))
+            .with_scraper(Provider::Bilibili, taffy(1))
+            .with_search_zones(
+                Provider::NetEase,
+                vec![ScrapeType::Song, ScrapeType::Artist, ScrapeType::Playlist],
+            )
+            // the zones take the permit in turn
+            .with_budget(Provider::NetEase, 1)
+            .build()
+            .await;
+
+        let results = manager.search("taffy".into(), ScrapeType::All, None).await;
+        let mut searched = results
+            .items
+            .iter()
+            .map(|i| {
+                let kind = match i.data() {
+                    ScrapeItem::Artist(_) => "artist",
+                    ScrapeItem::Song(_) => "song",
+                    ScrapeItem::Playlist(_) => "playlist",
+                    ScrapeItem::Album(_) => "album",
+                    ScrapeItem::Radio(_) => "radio",
+                };
+                (i.provider().clone(), kind)
+            })
+            .collect::<Vec<_>>();
+        searched.sort();
+        searched.dedup();
+        assert_eq!(
+            searched,
+            vec![
+                (Provider::Bilibili, "album"),
+                (Provider::Bilibili, "artist"),
+                (Provider::Bilibili, "playlist"),
+                (Provider::Bilibili, "song"),
+                (Provider::NetEase, "artist"),
+                (Provider::NetEase, "playlist"),
+                (Provider::NetEase, "song"),
+            ]
+        );
+        // the next page of the song zone
+        assert_eq!(
+            results.next.unwrap().get(&Provider::NetEase),
+            Some(&"10".to_string())
+        );
+
+        // zones only apply to `All`
+        let results = manager
+            .search("taffy".into(), ScrapeType::Album, None)
+            .await;
+        assert_eq!(results.items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_panic_isolation() {
+        let panicky = fixture(json!({
+            "failures": {
+                "suggest": { "panic": "unexpected suggest response" },
+                "search": { "panic": "unexpected search response" },
+                "stream": { "panic": "unexpected stream response" },
+            },
+        }));
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::Bilibili, panicky)
+            .with_scraper(Provider::NetEase, taffy(1))
+            .build()
+            .await;
+
+        let suggestions = manager.suggest("taffy".into()).await;
+        assert_eq!(suggestions.items.len(), 1);
+        assert_eq!(suggestions.items[0].provider(), &Provider::NetEase);
+        assert!(matches!(
+            suggestions.providers[&Provider::Bilibili],
+            ProviderStatus::Failed(_)
+        ));
+
+        let results = manager.search("taffy".into(), ScrapeType::All, None).await;
+        assert!(results
+            .items
+            .iter()
+            .all(|i| i.provider() == &Provider::NetEase));
+        assert_eq!(
+            results.providers[&Provider::Bilibili],
+            ProviderStatus::Failed("provider panicked: unexpected search response".into())
+        );
+        assert_eq!(results.providers[&Provider::NetEase], ProviderStatus::Ok);
+        let e = manager
+            .stream("BV1dZ4y1g7ag::1".into(), Provider::Bilibili)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "provider panicked: unexpected stream response"
+        );
+
+        // rejected before calling the provider
+        let e = manager
+            .stream("1".into(), Provider::Bilibili)
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "invalid bilibili id: 1");
+    }
+
+    #[tokio::test]
+    async fn test_preview() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::NetEase, taffy(1))
+            .build()
+            .await;
+
+        assert!(manager
+            .stream("10".into(), Provider::NetEase)
+            .await
+            .is_err());
+        let previews = manager
+            .preview("10".into(), Provider::NetEase)
+            .await
+            .unwrap();
+        assert_eq!(previews.len(), 1);
+        assert!(previews[0].preview);
+        assert_eq!(
+            serde_json::to_value(&previews[0]).unwrap()["is_preview"],
+            true
+        );
+
+        // songs without previews have none
+        assert!(manager
+            .preview("11".into(), Provider::NetEase)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_allowed_providers() {
+        let manager = BragiBuilder::new()
+            .with_scraper(Provider::Bilibili, taffy(1))
+            .with_scraper(Provider::NetEase, taffy(1))
+            .build()
+            .await;
+
+        let suggestions = manager
+            .suggest_from("taffy".into(), &[Provider::NetEase])
+            .await;
+        assert_eq!(suggestions.items.len(), 1);
+        assert_eq!(suggestions.items[0].provider(), &Provider::NetEase);
+        assert_eq!(manager.suggest("taffy".into()).await.items.len(), 2);
+
+        let filter = SearchFilter {
+            providers: vec![Provider::Bilibili, Provider::Youtube],
+            ..Default::default()
+        };
+        let results = manager
+            .search_filtered("taffy".into(), ScrapeType::All, None, &filter)
+            .await;
+        assert_eq!(
+            results.providers.into_keys().collect::<Vec<_>>(),
+            [Provider::Bilibili]
+        );
+    }
+
+    fn budget(weight: usize, concurrency: Option<usize>) -> BudgetSettings {
+        BudgetSettings {
+            weight,
+            concurrency,
+        }
+    }
+
+    #[test]
+    fn test_split_budgets() {
+        let budgets = split_budgets(
+            Some(12),
+            vec![
+                (Provider::Bilibili, budget(1, Some(2))),
+                (Provider::NetEase, budget(2, None)),
+                (Provider::Youtube, budget(1, None)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            budgets,
+            vec![
+                (Provider::Bilibili, 2),
+                (Provider::NetEase, 6),
+                (Provider::Youtube, 3)
+            ]
+        );
+        assert!(budgets.iter().map(|(_, permits)| permits).sum::<usize>() <= 12);
+
+        // exactly enough for one call each
+        let budgets = split_budgets(
+            Some(3),
+            vec![
+                (Provider::Bilibili, budget(1, Some(1))),
+                (Provider::NetEase, budget(5, None)),
+                (Provider::Youtube, budget(0, None)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            budgets,
+            vec![
+                (Provider::Bilibili, 1),
+                (Provider::NetEase, 1),
+                (Provider::Youtube, 1)
+            ]
+        );
+
+        // a total below the fixed concurrencies is refused rather than exceeded
+        assert!(split_budgets(
+            Some(2),
+            vec![
+                (Provider::Bilibili, budget(1, Some(2))),
+                (Provider::NetEase, budget(1, None)),
+            ],
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_split_budgets_unbounded() {
+        let budgets = split_budgets(
+            None,
+            vec![
+                (Provider::Bilibili, budget(1, Some(2))),
+                (Provider::NetEase, budget(1, None)),
+            ],
+        )
+        .unwrap();
+        assert_eq!(budgets, vec![(Provider::Bilibili, 2)]);
+
+        // no permit at all would block every call
+        let budgets = split_budgets(None, vec![(Provider::Bilibili, budget(1, Some(0)))]).unwrap();
+        assert_eq!(budgets, vec![(Provider::Bilibili, 1)]);
     }
 }
@@ -1,16 +1,39 @@
-use std::{format, sync::Arc};
+use std::{collections::HashMap, format, future::Future, sync::Arc};
 
 use anyhow::bail;
 use async_trait::async_trait;
-use serde::{Deserialize, Deserializer};
-use tracing::{error, info};
+use serde::{de::IgnoredAny, Deserialize, Deserializer};
+use tracing::{error, info, warn};
 
 use crate::{
-    settings::NeteaseSettings,
-    util::{self, cookie::PersistCookieStore},
+    privacy::redact,
+    settings::{IpFamily, NeteaseSettings},
+    util::{
+        self,
+        body::LimitedBody,
+        cookie::PersistCookieStore,
+        text::{deserialize_optional_text, deserialize_text},
+    },
 };
 
-use super::{Artist, ScrapeItem, ScrapeType, Scraper, Song, SongCollection, Stream};
+use super::{
+    explain::StreamTrace,
+    id::{ArtistId, CollectionId, TrackId},
+    lyrics::Lyrics,
+    query::{Query, SearchFilter},
+    unavailable::{Restricted, Restriction},
+    Artist, ArtistDetail, ScrapeItem, ScrapeType, Scraper, SearchPage, Song, SongCollection,
+    Stream,
+};
+
+/// page size of cloud search
+const SEARCH_LIMIT: usize = 30;
+
+/// Address in mainland China sent along, so that songs are not blocked by region
+const REAL_IP: &str = "116.25.146.177";
+
+/// Host of the NetEase api called directly by the native endpoints
+const NATIVE_HOST: &str = "https://music.163.com";
 
 /// cover pic id to pic url
 fn deserialize_pic_id<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
@@ -57,17 +80,18 @@ impl<T> NeteaseResponseResult<T> {
 struct NeteaseAccount {
     #[serde(alias = "userId", alias = "id")]
     user_id: i64,
-    #[serde(alias = "userName")]
+    #[serde(alias = "userName", deserialize_with = "deserialize_text")]
     nickname: String,
     #[serde(rename = "avatarUrl")]
     avatar_url: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_optional_text")]
     description: Option<String>,
 }
 
 impl From<NeteaseAccount> for Artist {
     fn from(val: NeteaseAccount) -> Self {
         Artist {
-            id: val.user_id.to_string(),
+            id: val.user_id.to_string().into(),
             name: val.nickname,
             description: val.description,
             avatar: val.avatar_url,
@@ -78,6 +102,7 @@ impl From<NeteaseAccount> for Artist {
 #[derive(Debug, Deserialize)]
 struct NeteaseArtist {
     id: i64,
+    #[serde(deserialize_with = "deserialize_text")]
     name: String,
     #[serde(rename = "picUrl")]
     pic_url: Option<String>,
@@ -88,10 +113,10 @@ struct NeteaseArtist {
 impl From<NeteaseArtist> for Artist {
     fn from(val: NeteaseArtist) -> Self {
         Artist {
-            id: val.id.to_string(),
+            id: val.id.to_string().into(),
             name: val.name,
             description: None,
-            avatar: val.pic_url.or(val.back_image_url).map(Into::into),
+            avatar: val.pic_url.or(val.back_image_url),
         }
     }
 }
@@ -99,6 +124,7 @@ impl From<NeteaseArtist> for Artist {
 #[derive(Debug, Deserialize)]
 struct NeteaseSong {
     id: i64,
+    #[serde(deserialize_with = "deserialize_text")]
     name: String,
     duration: Option<u32>, // unit ms
     #[serde(alias = "ar", default)]
@@ -110,6 +136,7 @@ struct NeteaseSong {
 #[derive(Debug, Deserialize)]
 struct NeteaseAlbum {
     id: i64,
+    #[serde(deserialize_with = "deserialize_text")]
     name: String,
     #[serde(rename = "picUrl")]
     pic_url: Option<String>,
@@ -121,12 +148,16 @@ struct NeteaseAlbum {
 impl From<NeteaseAlbum> for SongCollection {
     fn from(value: NeteaseAlbum) -> Self {
         Self {
-            id: value.id.to_string(),
+            id: value.id.to_string().into(),
             name: value.name,
             artists: vec![value.artist.into()],
             cover: value.pic_url,
             description: None,
             songs: vec![],
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
         }
     }
 }
@@ -134,7 +165,7 @@ impl From<NeteaseAlbum> for SongCollection {
 impl From<NeteaseSong> for Song {
     fn from(val: NeteaseSong) -> Self {
         Song {
-            id: val.id.to_string(),
+            id: val.id.to_string().into(),
             name: val.name,
             // Choose album image as default cover. Otherwise, choose the first artist image as back cover.
             cover: val
@@ -143,6 +174,11 @@ impl From<NeteaseSong> for Song {
                 .or(val.artists.first().and_then(|a| a.pic_url.clone())),
             artists: val.artists.into_iter().map(Into::into).collect(),
             duration: val.duration.map(|v| v / 1000),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
         }
     }
 }
@@ -150,22 +186,28 @@ impl From<NeteaseSong> for Song {
 #[derive(Debug, Deserialize)]
 struct NeteasePlaylist {
     id: i64,
+    #[serde(deserialize_with = "deserialize_text")]
     name: String,
     #[serde(rename = "coverImgUrl")]
     cover_url: Option<String>,
     creator: NeteaseAccount,
+    #[serde(default, deserialize_with = "deserialize_optional_text")]
     description: Option<String>,
 }
 
 impl From<NeteasePlaylist> for SongCollection {
     fn from(val: NeteasePlaylist) -> Self {
         SongCollection {
-            id: val.id.to_string(),
+            id: val.id.to_string().into(),
             name: val.name,
             artists: vec![val.creator.into()],
-            cover: val.cover_url.map(Into::into),
+            cover: val.cover_url,
             description: val.description,
             songs: vec![],
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
         }
     }
 }
@@ -174,16 +216,42 @@ impl From<NeteasePlaylist> for SongCollection {
 struct NeteaseSearchSuggest {
     #[serde(default)]
     artists: Vec<NeteaseArtist>,
+    /// missing if nothing is found
+    #[serde(default)]
     songs: Vec<NeteaseSong>,
 }
 
-#[derive(Debug, Deserialize)]
-#[serde(untagged)]
-enum NeteaseSearch {
-    Album { albums: Vec<NeteaseAlbum> },
-    Playlist { playlists: Vec<NeteasePlaylist> },
-    Song { songs: Vec<NeteaseSong> },
-    Artist { artists: Vec<NeteaseArtist> },
+/// Only the field of the searched type is present. All of them are missing if nothing is found.
+#[derive(Debug, Default, Deserialize)]
+struct NeteaseSearch {
+    #[serde(default)]
+    songs: Vec<NeteaseSong>,
+    #[serde(default)]
+    artists: Vec<NeteaseArtist>,
+    #[serde(default)]
+    playlists: Vec<NeteasePlaylist>,
+    #[serde(default)]
+    albums: Vec<NeteaseAlbum>,
+}
+
+impl From<NeteaseSearch> for Vec<ScrapeItem> {
+    fn from(val: NeteaseSearch) -> Self {
+        val.songs
+            .into_iter()
+            .map(|s| ScrapeItem::Song(s.into()))
+            .chain(
+                val.artists
+                    .into_iter()
+                    .map(|a| ScrapeItem::Artist(a.into())),
+            )
+            .chain(
+                val.playlists
+                    .into_iter()
+                    .map(|p| ScrapeItem::Playlist(p.into())),
+            )
+            .chain(val.albums.into_iter().map(|a| ScrapeItem::Album(a.into())))
+            .collect()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -197,6 +265,9 @@ struct NeteasePlaylistDetail {
     basic_info: NeteasePlaylist,
     #[serde(rename = "trackIds")]
     track_ids: Vec<NeteasePlaylistTrackID>,
+    /// changed every time tracks are added, removed or reordered
+    #[serde(rename = "trackUpdateTime")]
+    track_update_time: Option<i64>,
 }
 #[derive(Debug, Deserialize)]
 struct NeteasePlaylistTrackID {
@@ -206,97 +277,535 @@ struct NeteasePlaylistTrackID {
 #[derive(Debug, Deserialize)]
 struct NeteaseSongDetail {
     songs: Vec<NeteaseSong>,
+    /// what the server may play of each song
+    #[serde(default)]
+    privileges: Vec<NeteasePrivilege>,
+}
+
+impl NeteaseSongDetail {
+    /// Songs greyed out like the apps of NetEase do, by their privileges
+    fn into_songs(self) -> Vec<Song> {
+        let reasons: HashMap<i64, &'static str> = self
+            .privileges
+            .iter()
+            .filter_map(|p| Some((p.id, p.unavailable_reason()?)))
+            .collect();
+        self.songs
+            .into_iter()
+            .map(|s| {
+                let reason = reasons.get(&s.id).map(|r| r.to_string());
+                Song {
+                    unavailable: reason.is_some(),
+                    unavailable_reason: reason,
+                    ..s.into()
+                }
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteasePrivilege {
+    id: i64,
+    /// negative once taken down
+    #[serde(default)]
+    st: i32,
+    /// highest bitrate playable, 0 if none
+    #[serde(default)]
+    pl: u64,
+    /// 1 for VIP songs, 4 for songs of paid albums, 0 and 8 for free ones
+    #[serde(default)]
+    fee: i32,
+}
+
+impl NeteasePrivilege {
+    /// VIP and paid songs are not greyed out, they have a trial and play with an entitled account
+    fn unavailable_reason(&self) -> Option<&'static str> {
+        if self.st < 0 {
+            Some("removed upstream")
+        } else if self.pl == 0 && !matches!(self.fee, 1 | 4) {
+            Some("no copyright")
+        } else {
+            None
+        }
+    }
 }
 
 #[allow(dead_code)]
 #[derive(Debug, Deserialize)]
 struct NeteaseSongDownload {
     url: Option<String>,
-    #[serde(rename = "br")]
+    #[serde(default, rename = "br")]
     bitrate: u64,
+    /// file type like `flac` or `mp3`
+    #[serde(rename = "type")]
+    format: Option<String>,
+    /// 404 if blocked by copyright
+    #[serde(default)]
+    code: i32,
+    /// 1 for VIP songs, 4 for songs of paid albums, 0 and 8 for free ones
+    #[serde(default)]
+    fee: i32,
+}
+
+/// VIP and paid songs need an account entitled to them, songs without a url otherwise are blocked
+/// by copyright where the server is
+fn restriction(download: Option<&NeteaseSongDownload>, id: &TrackId) -> Restricted {
+    let restriction = match download {
+        Some(d) if d.fee == 1 || d.fee == 4 => Restriction::AuthRequired,
+        Some(d) if d.code == 404 => Restriction::RegionLocked,
+        // vip songs come without any data to anonymous accounts
+        None => Restriction::AuthRequired,
+        Some(_) => Restriction::RegionLocked,
+    };
+    Restricted {
+        restriction,
+        reason: format!("no download url of netease song {}", id),
+    }
+}
+
+/// Playing url of a song, a trial snippet of restricted songs if `free_trial_info` is present
+#[derive(Debug, Deserialize)]
+struct NeteaseSongUrl {
+    url: Option<String>,
+    #[serde(default, rename = "br")]
+    bitrate: u64,
+    #[serde(rename = "type")]
+    format: Option<String>,
+    #[serde(rename = "freeTrialInfo")]
+    free_trial_info: Option<IgnoredAny>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseUserAccount {
+    /// null if the cookie is not logged in
+    account: Option<NeteaseUserAccountID>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseUserAccountID {
+    id: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseLikeList {
+    ids: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseArtistAlbums {
+    #[serde(rename = "hotAlbums")]
+    hot_albums: Vec<NeteaseAlbum>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseArtistDetail {
+    artist: NeteaseArtistProfile,
+    #[serde(rename = "hotSongs", default)]
+    hot_songs: Vec<NeteaseSong>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseArtistProfile {
+    #[serde(flatten)]
+    artist: NeteaseArtist,
+    #[serde(
+        rename = "briefDesc",
+        default,
+        deserialize_with = "deserialize_optional_text"
+    )]
+    description: Option<String>,
+}
+
+impl From<NeteaseArtistProfile> for Artist {
+    fn from(val: NeteaseArtistProfile) -> Self {
+        Artist {
+            description: val.description,
+            ..val.artist.into()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseLyric {
+    lrc: Option<NeteaseLyricText>,
+    /// translation
+    tlyric: Option<NeteaseLyricText>,
+    #[serde(default)]
+    nolyric: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct NeteaseLyricText {
+    #[serde(default)]
+    lyric: String,
+}
+
+impl From<NeteaseLyric> for Lyrics {
+    fn from(val: NeteaseLyric) -> Self {
+        let text =
+            |t: Option<NeteaseLyricText>| t.map(|t| t.lyric).filter(|l| !l.trim().is_empty());
+        let mut lyrics = text(val.lrc).and_then(Lyrics::from_lrc).unwrap_or_default();
+        lyrics.translation = text(val.tlyric);
+        lyrics.instrumental = val.nolyric;
+        lyrics
+    }
 }
 
 #[derive(Debug)]
 pub struct NeteaseScraper {
     instance: String,
     client: reqwest::Client,
+    /// search, suggestions, playlists and stream urls are called on NetEase directly
+    native: bool,
 }
 
 impl NeteaseScraper {
     pub fn new(instance: String, client: reqwest::Client) -> Self {
-        Self { instance, client }
+        Self {
+            instance,
+            client,
+            native: false,
+        }
     }
 
-    pub fn try_from_setting(setting: NeteaseSettings) -> anyhow::Result<Option<Self>> {
+    pub fn try_from_setting(
+        setting: NeteaseSettings,
+        outbound_family: Option<IpFamily>,
+    ) -> anyhow::Result<Option<Self>> {
         if setting.enabled {
+            if setting.instance.is_empty() && !setting.native {
+                bail!("[Netease] either an instance or the native endpoints are required");
+            }
             util::ensure_file(&setting.cookie_path)?;
 
             let jar = PersistCookieStore::try_new(setting.cookie_path)?;
             return Ok(Some(Self {
                 instance: setting.instance,
-                client: reqwest::Client::builder()
+                client: util::client_builder(outbound_family)
                     .cookie_provider(Arc::new(jar))
                     .build()
                     .unwrap(),
+                native: setting.native,
             }));
         }
 
         Ok(None)
     }
 
-    async fn cloud_search(&self, keyword: String, t: ScrapeType) -> anyhow::Result<NeteaseSearch> {
+    /// Call NetEase directly if the native endpoints are enabled. The instance is called if that
+    /// fails, or is the only one called otherwise.
+    async fn native_first<T>(
+        &self,
+        native: impl Future<Output = anyhow::Result<T>>,
+        instance: impl Future<Output = anyhow::Result<T>>,
+    ) -> anyhow::Result<T> {
+        if !self.native {
+            return instance.await;
+        }
+        match native.await {
+            Ok(v) => Ok(v),
+            Err(e) if self.instance.is_empty() => Err(e),
+            Err(e) => {
+                warn!(
+                    "[Netease] native call failed, fall back to the instance: {}",
+                    e
+                );
+                instance.await
+            }
+        }
+    }
+
+    /// POST the form to the plain api of NetEase, which needs no encryption unlike the web api
+    fn native_post<F: serde::Serialize + ?Sized>(
+        &self,
+        path: &str,
+        form: &F,
+    ) -> reqwest::RequestBuilder {
+        self.client
+            .post(format!("{}{}", NATIVE_HOST, path))
+            .header("Referer", NATIVE_HOST)
+            .header("X-Real-IP", REAL_IP)
+            .form(form)
+    }
+
+    async fn cloud_search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<NeteaseSearch> {
         let t_str = match t {
-            // ScrapeType::All => "1018",
-            // All has some bugs now
+            // the comprehensive type 1018 returns differently shaped blocks without paging.
+            // Therefore, All is fanned out to every type by `search` instead
             ScrapeType::All | ScrapeType::Song => "1",
             ScrapeType::Album => "10",
             ScrapeType::Artist => "100",
             ScrapeType::Playlist => "1000",
+            ScrapeType::Radio => bail!("no radio stations on netease"),
         };
+        let (limit, offset) = (limit.to_string(), offset.to_string());
 
-        self.client
-            .get(format!("{}/search", self.instance))
-            .query(&[
-                ("keywords", keyword.as_str()),
-                ("type", t_str),
-                ("realIP", "116.25.146.177"),
-            ])
+        let native = async {
+            self.native_post(
+                "/api/search/get",
+                &[
+                    ("s", keyword.as_str()),
+                    ("type", t_str),
+                    ("limit", &limit),
+                    ("offset", &offset),
+                ],
+            )
             .send()
             .await?
-            .json::<NeteaseResponseResult<NeteaseSearch>>()
+            .limited_json::<NeteaseResponseResult<NeteaseSearch>>()
             .await?
             .data()
+        };
+        let instance = async {
+            self.client
+                .get(format!("{}/search", self.instance))
+                .query(&[
+                    ("keywords", keyword.as_str()),
+                    ("type", t_str),
+                    ("limit", &limit),
+                    ("offset", &offset),
+                    ("realIP", REAL_IP),
+                ])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponseResult<NeteaseSearch>>()
+                .await?
+                .data()
+        };
+        self.native_first(native, instance).await
     }
 
-    async fn batch_songs(&self, ids: Vec<String>) -> anyhow::Result<Vec<NeteaseSong>> {
+    async fn playlist_detail(&self, id: CollectionId) -> anyhow::Result<NeteasePlaylistDetail> {
+        let native = async {
+            // every track id is listed whatever the number of tracks detailed
+            self.native_post(
+                "/api/v6/playlist/detail",
+                &[("id", id.as_str()), ("n", "0"), ("s", "8")],
+            )
+            .send()
+            .await?
+            .limited_json::<NeteaseResponse<NeteasePlaylistDetailResp>>()
+            .await?
+            .data()
+        };
+        let instance = async {
+            self.client
+                .get(format!("{}/playlist/detail", self.instance))
+                .query(&[("id", id.as_str()), ("realIP", REAL_IP)])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponse<NeteasePlaylistDetailResp>>()
+                .await?
+                .data()
+        };
+        Ok(self.native_first(native, instance).await?.playlist)
+    }
+
+    async fn artist_albums(&self, id: &ArtistId) -> anyhow::Result<Vec<NeteaseAlbum>> {
         Ok(self
             .client
-            .get(format!("{}/song/detail", self.instance))
-            .query(&[
-                ("ids", ids.join(",")),
-                ("realIP", "116.25.146.177".to_string()),
-            ])
+            .get(format!("{}/artist/album", self.instance))
+            .query(&[("id", id.as_str()), ("limit", &SEARCH_LIMIT.to_string())])
             .send()
             .await?
-            .json::<NeteaseResponse<NeteaseSongDetail>>()
+            .limited_json::<NeteaseResponse<NeteaseArtistAlbums>>()
             .await?
             .data()?
-            .songs)
+            .hot_albums)
+    }
+
+    async fn batch_songs(&self, ids: Vec<String>) -> anyhow::Result<Vec<Song>> {
+        let native = async {
+            let c = serde_json::to_string(
+                &ids.iter()
+                    .map(|id| serde_json::json!({ "id": id }))
+                    .collect::<Vec<_>>(),
+            )?;
+            self.native_post("/api/v3/song/detail", &[("c", c)])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponse<NeteaseSongDetail>>()
+                .await?
+                .data()
+        };
+        let instance = async {
+            self.client
+                .get(format!("{}/song/detail", self.instance))
+                .query(&[("ids", ids.join(",")), ("realIP", REAL_IP.to_string())])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponse<NeteaseSongDetail>>()
+                .await?
+                .data()
+        };
+        Ok(self.native_first(native, instance).await?.into_songs())
+    }
+
+    /// None if NetEase tells nothing of the song, like for VIP songs to anonymous accounts
+    async fn song_download(&self, id: &TrackId) -> anyhow::Result<Option<NeteaseSongDownload>> {
+        let native = async {
+            self.native_post(
+                "/api/song/enhance/download/url",
+                &[("id", id.as_str()), ("br", "999000")],
+            )
+            .send()
+            .await?
+            .limited_json::<NeteaseResponseResult<Option<NeteaseSongDownload>>>()
+            .await?
+            .data()
+        };
+        let instance = async {
+            self.client
+                .get(format!("{}/song/download/url", self.instance))
+                .query(&[("id", id.as_str()), ("realIP", REAL_IP)])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponseResult<Option<NeteaseSongDownload>>>()
+                .await?
+                .data()
+        };
+        self.native_first(native, instance).await
+    }
+
+    /// Playing url of the standard quality, the trial snippet of songs restricted to VIP
+    async fn song_url(&self, id: &TrackId) -> anyhow::Result<Option<NeteaseSongUrl>> {
+        let native = async {
+            self.native_post(
+                "/api/song/enhance/player/url/v1",
+                &[
+                    ("ids", format!("[{}]", id.as_str()).as_str()),
+                    ("level", "standard"),
+                    ("encodeType", "mp3"),
+                ],
+            )
+            .send()
+            .await?
+            .limited_json::<NeteaseResponseResult<Vec<NeteaseSongUrl>>>()
+            .await?
+            .data()
+        };
+        let instance = async {
+            self.client
+                .get(format!("{}/song/url/v1", self.instance))
+                .query(&[
+                    ("id", id.as_str()),
+                    ("level", "standard"),
+                    ("realIP", REAL_IP),
+                ])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponseResult<Vec<NeteaseSongUrl>>>()
+                .await?
+                .data()
+        };
+        Ok(self
+            .native_first(native, instance)
+            .await?
+            .into_iter()
+            .next())
+    }
+
+    /// Streams of the song, and why it is restricted if no download url is given. The trial
+    /// snippet of restricted songs is left to `preview`.
+    async fn streams(&self, id: &TrackId) -> anyhow::Result<(StreamTrace, Option<Restricted>)> {
+        let download = self.song_download(id).await?;
+
+        let mut trace = StreamTrace::default();
+        if let Some(NeteaseSongDownload {
+            url: Some(url),
+            bitrate,
+            format,
+            ..
+        }) = download
+        {
+            trace.found(Stream {
+                url,
+                quality: format!("lossless({})", bitrate),
+                bitrate: Some(bitrate),
+                codec: format.map(|f| f.to_lowercase()),
+                mirror: false,
+                loudness: None,
+                preview: false,
+            });
+            return Ok((trace, None));
+        }
+
+        let restricted = restriction(download.as_ref(), id);
+        trace.filtered("lossless".to_string(), restricted.to_string());
+        match self.song_url(id).await {
+            Ok(Some(NeteaseSongUrl {
+                url: Some(_),
+                bitrate,
+                free_trial_info: Some(_),
+                ..
+            })) => trace.filtered(
+                format!("preview({})", bitrate),
+                "trial of a restricted song, a preview",
+            ),
+            Ok(Some(NeteaseSongUrl {
+                url: Some(url),
+                bitrate,
+                format,
+                ..
+            })) => {
+                trace.found(Stream {
+                    url,
+                    quality: format!("standard({})", bitrate),
+                    bitrate: Some(bitrate),
+                    codec: format.map(|f| f.to_lowercase()),
+                    mirror: false,
+                    loudness: None,
+                    preview: false,
+                });
+            }
+            Ok(_) => trace.filtered("preview".to_string(), "no trial of the song"),
+            Err(e) => warn!("[Netease] get song url of {} failed: {}", redact(id), e),
+        }
+        Ok((trace, Some(restricted)))
     }
 }
 
 #[async_trait]
 impl Scraper for NeteaseScraper {
+    /// NetEase has no field filters. Narrow down the search type instead if the query only asks
+    /// for an artist or an album.
+    fn native_query(&self, query: &Query, t: ScrapeType) -> (String, ScrapeType) {
+        match (t, &query.artist, &query.title, &query.album) {
+            (ScrapeType::All, Some(artist), None, None) if query.text.is_empty() => {
+                (artist.clone(), ScrapeType::Artist)
+            }
+            (ScrapeType::All, _, None, Some(_)) => (query.keyword(), ScrapeType::Album),
+            (t, _, _, _) => (query.keyword(), t),
+        }
+    }
+
     async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
-        let data = self
-            .client
-            .get(format!("{}/search/suggest", self.instance))
-            .query(&[("keywords", keyword.as_str()), ("realIP", "116.25.146.177")])
-            .send()
-            .await?
-            .json::<NeteaseResponseResult<NeteaseSearchSuggest>>()
-            .await?
-            .data()?;
+        let native = async {
+            self.native_post("/api/search/suggest/web", &[("s", keyword.as_str())])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponseResult<NeteaseSearchSuggest>>()
+                .await?
+                .data()
+        };
+        let instance = async {
+            self.client
+                .get(format!("{}/search/suggest", self.instance))
+                .query(&[("keywords", keyword.as_str()), ("realIP", REAL_IP)])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponseResult<NeteaseSearchSuggest>>()
+                .await?
+                .data()
+        };
+        let data = self.native_first(native, instance).await?;
 
         Ok(data
             .artists
@@ -306,45 +815,85 @@ impl Scraper for NeteaseScraper {
             .collect())
     }
 
-    async fn search(&self, keyword: String, t: ScrapeType) -> Vec<ScrapeItem> {
-        info!("[Netease] search {} with type {:?}", keyword, t);
-        match self.cloud_search(keyword, t).await {
-            Err(e) => {
-                error!("cloud search failed: {}", e);
-                vec![]
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        self.search_filtered(keyword, t, continuation, &SearchFilter::default())
+            .await
+    }
+
+    /// Pages by `limit` of the filter
+    async fn search_filtered(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<SearchPage> {
+        let limit = filter.limit.unwrap_or(SEARCH_LIMIT);
+        // continuation of netease search is the offset of the next page
+        let offset = continuation
+            .and_then(|c| c.parse::<usize>().ok())
+            .unwrap_or_default();
+
+        info!(
+            "[Netease] search {} with type {:?} from offset {}",
+            redact(&keyword),
+            t,
+            offset
+        );
+
+        let types = match t {
+            ScrapeType::All => vec![
+                ScrapeType::Song,
+                ScrapeType::Artist,
+                ScrapeType::Playlist,
+                ScrapeType::Album,
+            ],
+            ScrapeType::Radio => vec![],
+            t => vec![t],
+        };
+
+        let mut items = vec![];
+        let mut has_more = false;
+        let mut last_err = None;
+        for res in futures::future::join_all(
+            types
+                .into_iter()
+                .map(|t| self.cloud_search(keyword.clone(), t, offset, limit)),
+        )
+        .await
+        {
+            match res {
+                Ok(res) => {
+                    let res: Vec<ScrapeItem> = res.into();
+                    has_more |= res.len() >= limit;
+                    items.extend(res);
+                }
+                Err(e) => {
+                    error!("cloud search failed: {}", e);
+                    last_err = Some(e);
+                }
             }
-            Ok(res) => match res {
-                NeteaseSearch::Song { songs } => songs
-                    .into_iter()
-                    .map(|s| ScrapeItem::Song(s.into()))
-                    .collect(),
-                NeteaseSearch::Playlist { playlists } => playlists
-                    .into_iter()
-                    .map(|p| ScrapeItem::Playlist(p.into()))
-                    .collect(),
-                NeteaseSearch::Artist { artists } => artists
-                    .into_iter()
-                    .map(|a| ScrapeItem::Artist(a.into()))
-                    .collect(),
-                NeteaseSearch::Album { albums } => albums
-                    .into_iter()
-                    .map(|a| ScrapeItem::Album(a.into()))
-                    .collect(),
-            },
         }
+
+        // partial results are fine as long as one of the types succeeded
+        if let (true, Some(e)) = (items.is_empty(), last_err) {
+            return Err(e);
+        }
+
+        Ok(SearchPage {
+            items,
+            // every type shares the same offset. Exhausted types return nothing for later pages
+            next: has_more.then(|| (offset + limit).to_string()),
+        })
     }
 
-    async fn collection_detail(&self, id: String) -> anyhow::Result<SongCollection> {
-        let playlist = self
-            .client
-            .get(format!("{}/playlist/detail", self.instance))
-            .query(&[("id", id.as_str()), ("realIP", "116.25.146.177")])
-            .send()
-            .await?
-            .json::<NeteaseResponse<NeteasePlaylistDetailResp>>()
-            .await?
-            .data()?
-            .playlist;
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        let playlist = self.playlist_detail(id).await?;
 
         let songs = self
             .batch_songs(
@@ -357,41 +906,146 @@ impl Scraper for NeteaseScraper {
             .await?;
 
         Ok(SongCollection {
-            id: playlist.basic_info.id.to_string(),
+            id: playlist.basic_info.id.to_string().into(),
             name: playlist.basic_info.name,
             artists: vec![playlist.basic_info.creator.into()],
-            cover: playlist.basic_info.cover_url.map(Into::into),
+            cover: playlist.basic_info.cover_url,
             description: playlist.basic_info.description,
-            songs: songs.into_iter().map(Into::into).collect(),
+            songs,
+            version: playlist.track_update_time.map(|t| t.to_string()),
+            unavailable: false,
+            saved: false,
+            stale: false,
         })
     }
 
-    async fn stream(&self, id: String) -> anyhow::Result<Vec<Stream>> {
-        let resp = self
+    async fn collection_version(&self, id: CollectionId) -> anyhow::Result<Option<String>> {
+        Ok(self
+            .playlist_detail(id)
+            .await?
+            .track_update_time
+            .map(|t| t.to_string()))
+    }
+
+    async fn liked(&self) -> anyhow::Result<Vec<String>> {
+        let Some(account) = self
             .client
-            .get(format!("{}/song/download/url", self.instance))
-            .query(&[("id", id.as_str()), ("realIP", "116.25.146.177")])
+            .get(format!("{}/user/account", self.instance))
             .send()
             .await?
-            .json::<NeteaseResponseResult<NeteaseSongDownload>>()
+            .limited_json::<NeteaseResponse<NeteaseUserAccount>>()
             .await?
-            .data()?;
+            .data()?
+            .account
+        else {
+            bail!("[Netease] liked songs: not logged in");
+        };
 
-        match resp.url {
-            Some(url) => Ok(vec![Stream {
-                url,
-                quality: format!("lossless({})", resp.bitrate),
-            }]),
-            None => bail!(r#"{{"message": "now download url present"}}"#),
+        Ok(self
+            .client
+            .get(format!("{}/likelist", self.instance))
+            .query(&[("uid", account.id)])
+            .send()
+            .await?
+            .limited_json::<NeteaseResponse<NeteaseLikeList>>()
+            .await?
+            .data()?
+            .ids
+            .into_iter()
+            .map(|id| id.to_string())
+            .collect())
+    }
+
+    async fn artist_releases(&self, id: ArtistId) -> anyhow::Result<Vec<ScrapeItem>> {
+        Ok(self
+            .artist_albums(&id)
+            .await?
+            .into_iter()
+            .map(|a| ScrapeItem::Album(a.into()))
+            .collect())
+    }
+
+    /// NetEase has no playlists of artists
+    async fn artist_detail(&self, id: ArtistId) -> anyhow::Result<ArtistDetail> {
+        let detail = async {
+            self.client
+                .get(format!("{}/artists", self.instance))
+                .query(&[("id", id.as_str()), ("realIP", REAL_IP)])
+                .send()
+                .await?
+                .limited_json::<NeteaseResponse<NeteaseArtistDetail>>()
+                .await?
+                .data()
+        };
+        let (detail, albums) = futures::future::try_join(detail, self.artist_albums(&id)).await?;
+
+        Ok(ArtistDetail {
+            artist: detail.artist.into(),
+            songs: detail.hot_songs.into_iter().map(Into::into).collect(),
+            albums: albums.into_iter().map(Into::into).collect(),
+            playlists: vec![],
+        })
+    }
+
+    async fn lyrics(&self, id: TrackId) -> anyhow::Result<Lyrics> {
+        Ok(self
+            .client
+            .get(format!("{}/lyric", self.instance))
+            .query(&[("id", id.as_str()), ("realIP", REAL_IP)])
+            .send()
+            .await?
+            .limited_json::<NeteaseResponse<NeteaseLyric>>()
+            .await?
+            .data()?
+            .into())
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        match self.streams(&id).await? {
+            (trace, Some(restricted)) if trace.streams.is_empty() => Err(restricted.into()),
+            (trace, _) => Ok(trace.streams),
         }
     }
+
+    async fn stream_trace(&self, id: TrackId) -> anyhow::Result<StreamTrace> {
+        Ok(self.streams(&id).await?.0)
+    }
+
+    /// The 30 second trial NetEase offers of VIP songs
+    async fn preview(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        let trial = match self.song_url(&id).await? {
+            Some(NeteaseSongUrl {
+                url: Some(url),
+                bitrate,
+                format,
+                free_trial_info: Some(_),
+            }) => Stream {
+                url,
+                quality: format!("preview({})", bitrate),
+                bitrate: Some(bitrate),
+                codec: format.map(|f| f.to_lowercase()),
+                mirror: false,
+                loudness: None,
+                preview: true,
+            },
+            _ => return Ok(vec![]),
+        };
+        Ok(vec![trial])
+    }
 }
 
 #[cfg(test)]
 mod test {
     use crate::scraper::{ScrapeType, Scraper};
 
-    use super::NeteaseScraper;
+    use anyhow::anyhow;
+
+    use super::{
+        restriction, Artist, Lyrics, NeteaseArtistDetail, NeteaseLyric, NeteaseResponse,
+        NeteaseResponseResult, NeteaseScraper, NeteaseSearch, NeteaseSearchSuggest,
+        NeteaseSongDetail, NeteaseSongDownload, NeteaseSongUrl, Restriction, ScrapeItem,
+        SEARCH_LIMIT,
+    };
 
     fn cli() -> NeteaseScraper {
         NeteaseScraper::new(
@@ -404,11 +1058,167 @@ mod test {
     async fn test_nsearch() {
         let cli = cli();
         let resp = cli
-            .cloud_search("早稻叽".to_string(), ScrapeType::Playlist)
+            .cloud_search("早稻叽".to_string(), ScrapeType::Playlist, 0, SEARCH_LIMIT)
             .await;
         println!("{:?}", resp);
     }
 
+    #[test]
+    fn test_search_parse() {
+        let empty = serde_json::from_str::<NeteaseResponseResult<NeteaseSearch>>(
+            r#"{"code": 200, "result": {"songCount": 0}}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap();
+        assert!(Vec::<ScrapeItem>::from(empty).is_empty());
+
+        let artists = serde_json::from_str::<NeteaseResponseResult<NeteaseSearch>>(
+            r#"{"code": 200, "result": {"artistCount": 1, "artists": [{"id": 1, "name": "早稻叽"}]}}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap();
+        let items = Vec::<ScrapeItem>::from(artists);
+        assert!(matches!(items.as_slice(), [ScrapeItem::Artist(a)] if a.name == "早稻叽"));
+    }
+
+    #[test]
+    fn test_artist_detail_parse() {
+        let detail = serde_json::from_str::<NeteaseResponse<NeteaseArtistDetail>>(
+            r#"{"code": 200, "artist": {"id": 1, "name": "早稻叽", "picUrl": "https://p1.music.126.net/1.jpg", "briefDesc": "bio"}, "hotSongs": [{"id": 2, "name": "song", "ar": [{"id": 1, "name": "早稻叽"}]}]}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap();
+        assert_eq!(detail.hot_songs.len(), 1);
+        let artist = Artist::from(detail.artist);
+        assert_eq!(artist.description.as_deref(), Some("bio"));
+        assert_eq!(
+            artist.avatar.as_deref(),
+            Some("https://p1.music.126.net/1.jpg")
+        );
+    }
+
+    #[test]
+    fn test_lyric_parse() {
+        let lyrics: Lyrics = serde_json::from_str::<NeteaseResponse<NeteaseLyric>>(
+            r#"{"code": 200, "lrc": {"version": 1, "lyric": "[00:00.00] 作词 : Ayase\n[00:01.00]沈むように"}, "tlyric": {"version": 0, "lyric": ""}}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap()
+        .into();
+        assert_eq!(lyrics.plain.as_deref(), Some("作词 : Ayase\n沈むように"));
+        assert!(lyrics.translation.is_none());
+
+        let instrumental: Lyrics = serde_json::from_str::<NeteaseResponse<NeteaseLyric>>(
+            r#"{"code": 200, "nolyric": true}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap()
+        .into();
+        assert!(instrumental.instrumental && instrumental.synced.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_native_first() {
+        let call = |scraper: NeteaseScraper| async move {
+            scraper
+                .native_first(async { Err::<&str, _>(anyhow!("native failed")) }, async {
+                    Ok("instance")
+                })
+                .await
+                .map_err(|e| e.to_string())
+        };
+        let native = NeteaseScraper {
+            native: true,
+            ..cli()
+        };
+        assert_eq!(call(native).await, Ok("instance"));
+        let native_only = NeteaseScraper {
+            native: true,
+            instance: String::new(),
+            ..cli()
+        };
+        assert_eq!(call(native_only).await, Err("native failed".to_string()));
+
+        let native = NeteaseScraper {
+            native: true,
+            ..cli()
+        };
+        let resp = native
+            .native_first(async { Ok("native") }, async {
+                unreachable!("the instance is only called if the native call fails")
+            })
+            .await;
+        assert_eq!(resp.unwrap(), "native");
+    }
+
+    #[test]
+    fn test_native_parse() {
+        let suggest = serde_json::from_str::<NeteaseResponseResult<NeteaseSearchSuggest>>(
+            r#"{"result": {}, "code": 200}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap();
+        assert!(suggest.songs.is_empty() && suggest.artists.is_empty());
+
+        let download = serde_json::from_str::<NeteaseResponseResult<Option<NeteaseSongDownload>>>(
+            r#"{"data": {"id": 1866231828, "url": "http://m701.music.126.net/1.flac", "br": 999000, "size": 1024, "type": "flac"}, "code": 200}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap()
+        .unwrap();
+        assert_eq!(download.bitrate, 999000);
+        assert_eq!(download.format.as_deref(), Some("flac"));
+    }
+
+    #[test]
+    fn test_restriction() {
+        let id = "1866231828".into();
+        let download = |data| {
+            serde_json::from_str::<NeteaseResponseResult<Option<NeteaseSongDownload>>>(data)
+                .unwrap()
+                .data()
+                .unwrap()
+        };
+
+        let vip = download(
+            r#"{"data": {"id": 1866231828, "url": null, "br": 0, "size": 0, "code": -110, "fee": 1}, "code": 200}"#,
+        );
+        assert_eq!(
+            restriction(vip.as_ref(), &id).restriction,
+            Restriction::AuthRequired
+        );
+        let blocked = download(
+            r#"{"data": {"id": 1866231828, "url": null, "br": 0, "code": 404, "fee": 0}, "code": 200}"#,
+        );
+        assert_eq!(
+            restriction(blocked.as_ref(), &id).restriction,
+            Restriction::RegionLocked
+        );
+        let nothing = download(r#"{"data": null, "code": 200}"#);
+        assert!(nothing.is_none());
+        assert_eq!(
+            restriction(nothing.as_ref(), &id).restriction,
+            Restriction::AuthRequired
+        );
+
+        let trial = serde_json::from_str::<NeteaseResponseResult<Vec<NeteaseSongUrl>>>(
+            r#"{"data": [{"id": 1866231828, "url": "http://m801.music.126.net/1.mp3", "br": 128000, "type": "mp3",
+                "fee": 1, "freeTrialInfo": {"start": 0, "end": 30}}], "code": 200}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap();
+        assert!(trial[0].free_trial_info.is_some());
+        assert_eq!(trial[0].bitrate, 128000);
+    }
+
     #[tokio::test]
     async fn test_suggest() {
         let cli = cli();
@@ -419,24 +1229,52 @@ mod test {
     #[tokio::test]
     async fn test_search() {
         let cli = cli();
-        let search = cli.search("早稻叽".to_string(), ScrapeType::All).await;
+        let search = cli
+            .search("早稻叽".to_string(), ScrapeType::All, None)
+            .await;
         println!("{:?}", search);
     }
 
     #[tokio::test]
     async fn test_playlist() {
         let cli = cli();
-        let search = cli
-            .collection_detail("4934616945".to_string())
-            .await
-            .unwrap();
+        let search = cli.collection_detail("4934616945".into()).await.unwrap();
         println!("{:?}", search);
     }
 
     #[tokio::test]
     async fn test_stream() {
         let cli = cli();
-        let search = cli.stream("1866231828".to_string()).await.unwrap();
+        let search = cli.stream("1866231828".into()).await.unwrap();
         println!("{:?}", search);
     }
+
+    #[test]
+    fn test_privileges() {
+        let detail = serde_json::from_str::<NeteaseResponse<NeteaseSongDetail>>(
+            r#"{"songs": [
+                {"id": 1, "name": "free", "ar": []},
+                {"id": 2, "name": "removed", "ar": []},
+                {"id": 3, "name": "vip", "ar": []},
+                {"id": 4, "name": "no copyright", "ar": []}
+            ], "privileges": [
+                {"id": 1, "st": 0, "pl": 128000, "fee": 8},
+                {"id": 2, "st": -200, "pl": 0, "fee": 0},
+                {"id": 3, "st": 0, "pl": 0, "fee": 1},
+                {"id": 4, "st": 0, "pl": 0, "fee": 0}
+            ], "code": 200}"#,
+        )
+        .unwrap()
+        .data()
+        .unwrap();
+        let songs = detail.into_songs();
+        assert!(!songs[0].unavailable);
+        assert_eq!(
+            songs[1].unavailable_reason.as_deref(),
+            Some("removed upstream")
+        );
+        assert!(!songs[2].unavailable);
+        assert!(songs[3].unavailable);
+        assert_eq!(songs[3].unavailable_reason.as_deref(), Some("no copyright"));
+    }
 }
@@ -0,0 +1,80 @@
+use crate::settings::PrioritySettings;
+
+use super::{Provider, ScrapeType, WithProvider};
+
+/// Providers first to last for the zone, the global order unless the zone has its own
+fn order<'a>(policy: &'a PrioritySettings, zone: &ScrapeType) -> &'a [Provider] {
+    policy
+        .zones
+        .get(zone)
+        .filter(|providers| !providers.is_empty())
+        .unwrap_or(&policy.providers)
+}
+
+/// Sort key of the provider: its position in the order of the zone, unlisted providers after
+/// the listed ones in name order
+pub fn rank(
+    policy: &PrioritySettings,
+    zone: &ScrapeType,
+    provider: &Provider,
+) -> (usize, Provider) {
+    let order = order(policy, zone);
+    let position = order
+        .iter()
+        .position(|p| p == provider)
+        .unwrap_or(order.len());
+    (position, provider.clone())
+}
+
+/// Stable sort of merged results by the priority of their provider, so the results of a provider
+/// keep their order
+pub fn sort_by_priority<T>(
+    items: &mut [WithProvider<T>],
+    policy: &PrioritySettings,
+    zone: &ScrapeType,
+) {
+    items.sort_by_cached_key(|i| rank(policy, zone, i.provider()));
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::{
+        scraper::{Provider, ScrapeType, WithProvider},
+        settings::PrioritySettings,
+    };
+
+    use super::sort_by_priority;
+
+    #[test]
+    fn test_sort_by_priority() {
+        let policy = PrioritySettings {
+            providers: vec![Provider::Youtube, Provider::NetEase],
+            zones: BTreeMap::from([(ScrapeType::Artist, vec![Provider::Bilibili])]),
+        };
+        let items = || {
+            [
+                (Provider::NetEase, 1),
+                (Provider::Bilibili, 2),
+                (Provider::Youtube, 3),
+                (Provider::NetEase, 4),
+                (Provider::Spotify, 5),
+            ]
+            .map(|(p, i)| WithProvider::new(p, i))
+        };
+        let sorted = |zone| {
+            let mut items = items();
+            sort_by_priority(&mut items, &policy, &zone);
+            items.map(|i| *i.data())
+        };
+
+        // unlisted bilibili and spotify in name order
+        assert_eq!(sorted(ScrapeType::Song), [3, 1, 4, 2, 5]);
+        assert_eq!(sorted(ScrapeType::Artist), [2, 1, 4, 5, 3]);
+
+        let mut items = items();
+        sort_by_priority(&mut items, &PrioritySettings::default(), &ScrapeType::All);
+        assert_eq!(items.map(|i| *i.data()), [2, 1, 4, 5, 3]);
+    }
+}
@@ -0,0 +1,176 @@
+use super::{Provider, ScrapeItem, Song};
+
+/// Search keyword with optional field filters, like: `artist:"YOASOBI" title:夜に駆ける live`.
+/// Values containing spaces must be quoted. Unknown fields are kept as free text.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Query {
+    pub text: String,
+    pub artist: Option<String>,
+    pub title: Option<String>,
+    pub album: Option<String>,
+}
+
+impl Query {
+    pub fn parse(s: &str) -> Self {
+        let mut query = Self::default();
+        let mut text = vec![];
+
+        let mut rest = s.trim_start();
+        while !rest.is_empty() {
+            let (token, remain) = next_token(rest);
+            rest = remain.trim_start();
+
+            let field = token.split_once(':').and_then(|(k, v)| {
+                let v = v.trim_matches('"');
+                match k {
+                    "artist" => Some(&mut query.artist),
+                    "title" => Some(&mut query.title),
+                    "album" => Some(&mut query.album),
+                    _ => None,
+                }
+                .filter(|_| !v.is_empty())
+                .map(|f| (f, v))
+            });
+
+            match field {
+                Some((f, v)) => *f = Some(v.to_string()),
+                None => text.push(token),
+            }
+        }
+
+        query.text = text.join(" ");
+        query
+    }
+
+    pub fn has_filters(&self) -> bool {
+        self.artist.is_some() || self.title.is_some() || self.album.is_some()
+    }
+
+    /// Plain keyword of all terms, used by providers without field filters
+    pub fn keyword(&self) -> String {
+        [&self.title, &self.artist, &self.album]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(Some(self.text.as_str()).filter(|t| !t.is_empty()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// Filters of search results which are not part of the keyword. The manager applies them to the
+/// results of every provider, providers able to filter upstream narrow the search as well.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SearchFilter {
+    /// seconds, inclusive
+    pub min_duration: Option<u32>,
+    /// seconds, inclusive
+    pub max_duration: Option<u32>,
+    /// drop short-form videos like YouTube Shorts. Only applied by providers which can tell
+    pub exclude_shorts: bool,
+    /// drop live and upcoming broadcasts. Only applied by providers which can tell
+    pub exclude_live: bool,
+    /// results per page of each provider. Only applied by providers which can page by size
+    /// upstream, the others keep their fixed page size
+    pub limit: Option<usize>,
+    /// providers searched, all of them if empty
+    pub providers: Vec<Provider>,
+    /// fold the same song found on several providers into one item
+    pub merge: bool,
+}
+
+impl SearchFilter {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Songs of unknown duration and items other than songs are kept
+    pub fn keep(&self, item: &ScrapeItem) -> bool {
+        let duration = match item {
+            ScrapeItem::Song(Song {
+                duration: Some(d), ..
+            }) => *d,
+            _ => return true,
+        };
+        self.min_duration.is_none_or(|min| duration >= min)
+            && self.max_duration.is_none_or(|max| duration <= max)
+    }
+}
+
+/// Split the next whitespace separated token, keeping quoted parts together
+fn next_token(s: &str) -> (&str, &str) {
+    let mut quoted = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return (&s[..i], &s[i..]),
+            _ => {}
+        }
+    }
+    (s, "")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::{ScrapeItem, Song};
+
+    use super::{Query, SearchFilter};
+
+    #[test]
+    fn test_parse() {
+        let query = Query::parse(r#"artist:"Aimer Official" title:残響散歌 live foo:bar"#);
+        assert_eq!(
+            query,
+            Query {
+                text: "live foo:bar".into(),
+                artist: Some("Aimer Official".into()),
+                title: Some("残響散歌".into()),
+                album: None,
+            }
+        );
+        assert_eq!(query.keyword(), "残響散歌 Aimer Official live foo:bar");
+    }
+
+    #[test]
+    fn test_parse_plain() {
+        let query = Query::parse("  早稻叽  \"taffy\" ");
+        assert!(!query.has_filters());
+        assert_eq!(query.keyword(), r#"早稻叽 "taffy""#);
+    }
+
+    #[test]
+    fn test_parse_empty_field() {
+        let query = Query::parse("artist: title:\"\"");
+        assert!(!query.has_filters());
+        assert_eq!(query.text, r#"artist: title:"""#);
+    }
+
+    #[test]
+    fn test_search_filter() {
+        let song = |duration| {
+            ScrapeItem::Song(Song {
+                id: "1".into(),
+                name: "夜に駆ける".into(),
+                artists: vec![],
+                cover: None,
+                duration,
+                unavailable: false,
+                unavailable_reason: None,
+                alternatives: vec![],
+                saved: false,
+                clip: None,
+            })
+        };
+        let filter = SearchFilter {
+            min_duration: Some(60),
+            max_duration: Some(600),
+            ..Default::default()
+        };
+        assert!(filter.keep(&song(Some(261))));
+        assert!(filter.keep(&song(Some(60))));
+        assert!(!filter.keep(&song(Some(15))));
+        assert!(!filter.keep(&song(Some(3 * 3600))));
+        assert!(filter.keep(&song(None)));
+        assert!(SearchFilter::default().keep(&song(Some(15))));
+    }
+}
@@ -0,0 +1,177 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use tracing::warn;
+
+use crate::settings::QuotaSettings;
+
+use super::{health::RateLimited, Provider};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// Daily provider calls counted against the configured quotas, resetting at midnight UTC. Calls
+/// are counted per provider call of the manager, which may take more than one upstream request.
+/// Providers without a quota are counted but never limited.
+#[derive(Debug, Default)]
+pub struct QuotaTracker {
+    setting: QuotaSettings,
+    /// calls of the current day by provider
+    used: Mutex<HashMap<Provider, (u64, u64)>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct QuotaUsage {
+    pub used: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remaining: Option<u64>,
+    /// seconds until the counts reset
+    pub resets_in: u64,
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+impl QuotaTracker {
+    pub fn new(setting: QuotaSettings) -> Self {
+        Self {
+            setting,
+            used: Default::default(),
+        }
+    }
+
+    /// Calls made today
+    fn used_at(&self, provider: &Provider, now: u64) -> u64 {
+        match self.used.lock().get(provider) {
+            Some((day, used)) if *day == now / DAY => *used,
+            _ => 0,
+        }
+    }
+
+    /// Count a call of the provider, refused once the quota of the day is exhausted
+    pub fn spend(&self, provider: &Provider) -> Result<(), RateLimited> {
+        self.spend_at(provider, now())
+    }
+
+    fn spend_at(&self, provider: &Provider, now: u64) -> Result<(), RateLimited> {
+        let quota = self.setting.daily.get(provider);
+        let mut used = self.used.lock();
+        let (day, count) = used.entry(provider.clone()).or_insert((now / DAY, 0));
+        if *day != now / DAY {
+            *day = now / DAY;
+            *count = 0;
+        }
+        if quota.is_some_and(|quota| *count >= *quota) {
+            return Err(RateLimited {
+                reason: format!("daily quota of {:?} exhausted", provider),
+                retry_after: Duration::from_secs(DAY - now % DAY),
+            });
+        }
+        *count += 1;
+        Ok(())
+    }
+
+    /// Whether background work like release checks may call the provider, which it may not once
+    /// the quota is down to the `reserve_percent` kept for the clients
+    pub fn allows_background(&self, provider: &Provider) -> bool {
+        self.allows_background_at(provider, now())
+    }
+
+    fn allows_background_at(&self, provider: &Provider, now: u64) -> bool {
+        let Some(quota) = self.setting.daily.get(provider) else {
+            return true;
+        };
+        let reserve = quota * self.setting.reserve_percent.min(100) / 100;
+        let allowed = self.used_at(provider, now) + reserve < *quota;
+        if !allowed {
+            warn!(
+                "quota nearly exhausted, pause background work: {:?}",
+                provider
+            );
+        }
+        allowed
+    }
+
+    /// Usage of the providers called today or with a quota
+    pub fn report(&self) -> HashMap<Provider, QuotaUsage> {
+        self.report_at(now())
+    }
+
+    fn report_at(&self, now: u64) -> HashMap<Provider, QuotaUsage> {
+        let providers = self
+            .used
+            .lock()
+            .keys()
+            .chain(self.setting.daily.keys())
+            .cloned()
+            .collect::<Vec<_>>();
+        providers
+            .into_iter()
+            .map(|p| {
+                let used = self.used_at(&p, now);
+                let quota = self.setting.daily.get(&p).copied();
+                let usage = QuotaUsage {
+                    used,
+                    quota,
+                    remaining: quota.map(|q| q.saturating_sub(used)),
+                    resets_in: DAY - now % DAY,
+                };
+                (p, usage)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use crate::{scraper::Provider, settings::QuotaSettings};
+
+    use super::{QuotaTracker, DAY};
+
+    #[test]
+    fn test_quota() {
+        let quota = QuotaTracker::new(QuotaSettings {
+            daily: BTreeMap::from([(Provider::Youtube, 10)]),
+            reserve_percent: 20,
+        });
+        let yt = Provider::Youtube;
+        let noon = 100 * DAY + DAY / 2;
+
+        for _ in 0..8 {
+            assert!(quota.allows_background_at(&yt, noon));
+            quota.spend_at(&yt, noon).unwrap();
+        }
+        // the rest is kept for the clients
+        assert!(!quota.allows_background_at(&yt, noon));
+        quota.spend_at(&yt, noon).unwrap();
+        quota.spend_at(&yt, noon).unwrap();
+        let e = quota.spend_at(&yt, noon).unwrap_err();
+        assert_eq!(e.retry_after.as_secs(), DAY / 2);
+
+        let report = quota.report_at(noon);
+        assert_eq!(report[&yt].remaining, Some(0));
+
+        // reset on the next day
+        assert!(quota.allows_background_at(&yt, noon + DAY));
+        quota.spend_at(&yt, noon + DAY).unwrap();
+        assert_eq!(quota.report_at(noon + DAY)[&yt].used, 1);
+
+        // no quota
+        for _ in 0..20 {
+            quota.spend_at(&Provider::NetEase, noon).unwrap();
+        }
+        assert!(quota.allows_background_at(&Provider::NetEase, noon));
+        assert_eq!(quota.report_at(noon)[&Provider::NetEase].quota, None);
+    }
+}
@@ -0,0 +1,285 @@
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Deserializer};
+use tracing::info;
+
+use crate::{
+    privacy::redact,
+    settings::{IpFamily, RadioSettings},
+    util::{self, body::LimitedBody},
+};
+
+use super::{
+    id::{CollectionId, TrackId},
+    ScrapeItem, ScrapeType, Scraper, SearchPage, SongCollection, Station, Stream,
+};
+
+/// Stations of one page
+const PAGE_SIZE: usize = 20;
+const MAX_SUGGESTIONS: usize = 10;
+
+/// Codecs as named by radio-browser, to the names of the other providers
+const CODECS: &[(&str, &str)] = &[
+    ("MP3", "mp3"),
+    ("AAC", "mp4a.40.2"),
+    ("AAC+", "mp4a.40.5"),
+    ("OGG", "vorbis"),
+    ("OPUS", "opus"),
+    ("FLAC", "flac"),
+];
+
+#[derive(Debug, Deserialize)]
+struct RadioStation {
+    stationuuid: String,
+    name: String,
+    url: String,
+    /// the stream behind playlist files like m3u or pls, empty if the url is the stream already
+    #[serde(default)]
+    url_resolved: String,
+    #[serde(default)]
+    homepage: String,
+    #[serde(default)]
+    favicon: String,
+    /// comma separated
+    #[serde(default)]
+    tags: String,
+    #[serde(default)]
+    countrycode: String,
+    #[serde(default)]
+    codec: String,
+    /// kbps, 0 if unknown
+    #[serde(default)]
+    bitrate: u64,
+    /// the last check of the station by radio-browser succeeded
+    #[serde(default, deserialize_with = "deserialize_flag")]
+    lastcheckok: bool,
+}
+
+/// Flags are 0 or 1
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    Ok(u8::deserialize(deserializer)? != 0)
+}
+
+fn non_empty(s: String) -> Option<String> {
+    let s = s.trim().to_string();
+    (!s.is_empty()).then_some(s)
+}
+
+impl From<RadioStation> for Station {
+    fn from(s: RadioStation) -> Self {
+        Self {
+            id: s.stationuuid.into(),
+            name: s.name.trim().to_string(),
+            cover: non_empty(s.favicon),
+            country: non_empty(s.countrycode),
+            tags: s
+                .tags
+                .split(',')
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(str::to_string)
+                .collect(),
+            homepage: non_empty(s.homepage),
+        }
+    }
+}
+
+impl From<RadioStation> for Stream {
+    fn from(s: RadioStation) -> Self {
+        let codec = CODECS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(s.codec.trim()))
+            .map(|(_, codec)| codec.to_string());
+        Self {
+            quality: match s.bitrate {
+                0 => "live".to_string(),
+                kbps => format!("{}k", kbps),
+            },
+            url: non_empty(s.url_resolved).unwrap_or(s.url),
+            bitrate: (s.bitrate > 0).then_some(s.bitrate * 1000),
+            codec,
+            mirror: false,
+            loudness: None,
+            preview: false,
+        }
+    }
+}
+
+/// Internet radio stations of radio-browser.info, searched by name. Stations are found for
+/// `Radio` and `All` searches only, and their stream is resolved like the stream of a song.
+pub struct RadioScraper {
+    instance: String,
+    client: reqwest::Client,
+}
+
+impl RadioScraper {
+    pub fn try_from_setting(
+        setting: RadioSettings,
+        outbound_family: Option<IpFamily>,
+    ) -> anyhow::Result<Option<Self>> {
+        if !setting.enabled {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            instance: setting.instance.trim_end_matches('/').to_string(),
+            // radio-browser asks clients to identify themselves
+            client: util::client_builder(outbound_family)
+                .user_agent(concat!("bragi-core/", env!("CARGO_PKG_VERSION")))
+                .build()?,
+        }))
+    }
+
+    /// Working stations matching the name, most played first
+    async fn stations(
+        &self,
+        name: &str,
+        offset: usize,
+        limit: usize,
+    ) -> anyhow::Result<Vec<RadioStation>> {
+        self.client
+            .get(format!("{}/json/stations/search", self.instance))
+            .query(&[
+                ("name", name),
+                ("offset", &offset.to_string()),
+                ("limit", &limit.to_string()),
+                ("hidebroken", "true"),
+                ("order", "clickcount"),
+                ("reverse", "true"),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .limited_json()
+            .await
+    }
+}
+
+#[async_trait]
+impl Scraper for RadioScraper {
+    async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
+        let mut suggestions: Vec<String> = vec![];
+        for station in self.stations(&keyword, 0, MAX_SUGGESTIONS).await? {
+            let name = station.name.trim();
+            if !suggestions.iter().any(|s| s == name) {
+                suggestions.push(name.to_string());
+            }
+        }
+        Ok(suggestions)
+    }
+
+    /// The continuation is the offset of the next page
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        if !matches!(t, ScrapeType::All | ScrapeType::Radio) {
+            return Ok(SearchPage::default());
+        }
+        let offset = match continuation {
+            Some(c) => c
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid continuation: {}", c))?,
+            None => 0,
+        };
+        info!("[Radio] search {} from offset {}", redact(&keyword), offset);
+
+        let stations = self.stations(&keyword, offset, PAGE_SIZE).await?;
+        let next = offset + PAGE_SIZE;
+        Ok(SearchPage {
+            next: (stations.len() >= PAGE_SIZE).then(|| next.to_string()),
+            items: stations
+                .into_iter()
+                .map(|s| ScrapeItem::Radio(s.into()))
+                .collect(),
+        })
+    }
+
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        bail!("no collections of radio stations: {}", id)
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        let stations: Vec<RadioStation> = self
+            .client
+            .get(format!("{}/json/stations/byuuid/{}", self.instance, id))
+            .send()
+            .await?
+            .error_for_status()?
+            .limited_json()
+            .await?;
+        let Some(station) = stations.into_iter().next() else {
+            bail!("radio station not found: {}", id);
+        };
+        if !station.lastcheckok {
+            info!("[Radio] station {} failed its last check", id);
+        }
+        Ok(vec![station.into()])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::{Station, Stream};
+
+    use super::RadioStation;
+
+    #[test]
+    fn test_parse() {
+        let stations: Vec<RadioStation> = serde_json::from_str(
+            r#"[{
+                "changeuuid": "610cafba-71d8-40fc-bf68-1456ec973b9d",
+                "stationuuid": "960e57c5-0601-11e8-ae97-52543be04c81",
+                "name": " Jazz Radio ",
+                "url": "http://jazzradio.ice.infomaniak.ch/jazzradio-high.m3u",
+                "url_resolved": "http://jazzradio.ice.infomaniak.ch/jazzradio-high.aac",
+                "homepage": "https://www.jazzradio.fr/",
+                "favicon": "",
+                "tags": "jazz, smooth jazz,",
+                "countrycode": "FR",
+                "codec": "AAC+",
+                "bitrate": 64,
+                "hls": 0,
+                "lastcheckok": 1
+            }, {
+                "stationuuid": "9617a958-0601-11e8-ae97-52543be04c81",
+                "name": "Unknown",
+                "url": "http://example.com/stream",
+                "codec": "UNKNOWN",
+                "bitrate": 0,
+                "lastcheckok": 0
+            }]"#,
+        )
+        .unwrap();
+        let mut stations = stations.into_iter();
+
+        let station = stations.next().unwrap();
+        assert!(station.lastcheckok);
+        let stream = Stream::from(station);
+        assert_eq!(stream.quality, "64k");
+        assert_eq!(stream.bitrate, Some(64000));
+        assert_eq!(stream.codec.as_deref(), Some("mp4a.40.5"));
+        assert!(stream.url.ends_with(".aac"));
+
+        let station = stations.next().unwrap();
+        assert!(!station.lastcheckok);
+        let name = station.name.clone();
+        let stream = Stream::from(station);
+        assert_eq!((stream.quality.as_str(), stream.codec), ("live", None));
+        assert_eq!(stream.url, "http://example.com/stream");
+        assert_eq!(name, "Unknown");
+
+        let station: Station = serde_json::from_str::<Vec<RadioStation>>(
+            r#"[{"stationuuid": "960e57c5-0601-11e8-ae97-52543be04c81", "name": "Jazz Radio",
+                "url": "http://example.com", "tags": "jazz, smooth jazz,", "countrycode": "FR"}]"#,
+        )
+        .unwrap()
+        .pop()
+        .unwrap()
+        .into();
+        assert_eq!(station.tags, ["jazz", "smooth jazz"]);
+        assert_eq!(station.country.as_deref(), Some("FR"));
+        assert!(station.cover.is_none());
+    }
+}
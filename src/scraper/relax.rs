@@ -0,0 +1,84 @@
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::keyword::{to_halfwidth, to_romaji};
+
+lazy_static! {
+    static ref BRACKETED_FEAT: Regex =
+        Regex::new(r"(?i)[(\[（【]\s*(feat\.?|ft\.|featuring)\s[^)\]）】]*[)\]）】]").unwrap();
+    static ref FEAT: Regex = Regex::new(r"(?i)\s(feat\.?|ft\.|featuring)\s.*$").unwrap();
+    static ref BRACKETS: Regex = Regex::new(r"[(\[（【][^)\]）】]*[)\]）】]").unwrap();
+    static ref ARTIST_FIELD: Regex = Regex::new(r#"\bartist:("[^"]*"|\S+)"#).unwrap();
+}
+
+/// Featured artists, bracketed or trailing a part of `{artist} - {title}`
+fn strip_feat(keyword: &str) -> String {
+    let keyword = BRACKETED_FEAT.replace_all(keyword, "");
+    keyword
+        .split(" - ")
+        .map(|part| FEAT.replace(part, ""))
+        .collect::<Vec<_>>()
+        .join(" - ")
+}
+
+/// The `artist:` filter, or else the artist of `{artist} - {title}` as taggers name files
+fn drop_artist(keyword: &str) -> String {
+    match ARTIST_FIELD.is_match(keyword) {
+        true => ARTIST_FIELD.replace_all(keyword, "").into_owned(),
+        false => keyword
+            .rsplit_once(" - ")
+            .map_or(keyword, |(_, title)| title)
+            .to_string(),
+    }
+}
+
+/// Looser keywords to retry a search which found nothing with, loosest last: without featured
+/// artists, without bracketed parts, without the artist and transliterated. Each step builds on
+/// the previous one and steps changing nothing are skipped.
+pub fn relaxations(keyword: &str) -> Vec<String> {
+    let normalize = |k: &str| k.split_whitespace().collect::<Vec<_>>().join(" ");
+    let original = normalize(keyword);
+
+    let stripped = strip_feat(&original);
+    let unbracketed = BRACKETS.replace_all(&stripped, "").into_owned();
+    let title = drop_artist(&unbracketed);
+    let transliterated = to_romaji(&to_halfwidth(&title));
+
+    let mut relaxed: Vec<String> = vec![];
+    for k in [stripped, unbracketed, title, transliterated] {
+        let k = normalize(&k);
+        if !k.is_empty() && k != original && !relaxed.contains(&k) {
+            relaxed.push(k);
+        }
+    }
+    relaxed
+}
+
+#[cfg(test)]
+mod test {
+    use super::relaxations;
+
+    #[test]
+    fn test_relaxations() {
+        assert_eq!(
+            relaxations("YOASOBI - アイドル (feat. someone) [Official Video]"),
+            vec![
+                "YOASOBI - アイドル [Official Video]",
+                "YOASOBI - アイドル",
+                "アイドル",
+                "aidoru",
+            ]
+        );
+        assert_eq!(
+            relaxations("Ado ft. someone - うっせぇわ"),
+            vec!["Ado - うっせぇわ", "うっせぇわ", "usseewa"]
+        );
+        assert_eq!(
+            relaxations(r#"artist:"Kenshi Yonezu" title:Lemon 【MV】"#),
+            vec![r#"artist:"Kenshi Yonezu" title:Lemon"#, "title:Lemon"]
+        );
+        // nothing to relax
+        assert!(relaxations("taffy").is_empty());
+        assert!(relaxations("(feat. someone)").is_empty());
+    }
+}
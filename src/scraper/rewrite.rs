@@ -0,0 +1,96 @@
+use regex::Regex;
+use reqwest::Url;
+
+use crate::settings::HostRewrite;
+
+use super::Stream;
+
+/// Rewrite the host of stream urls, since certain CDN hosts are blocked or slow for some ISPs
+#[derive(Debug, Default)]
+pub struct HostRewriter {
+    rules: Vec<(Regex, String)>,
+}
+
+impl HostRewriter {
+    pub fn try_from_setting(rules: Vec<HostRewrite>) -> anyhow::Result<Self> {
+        let rules = rules
+            .into_iter()
+            .map(|r| {
+                let pattern = regex::escape(&r.from).replace(r"\*", ".*");
+                Ok((Regex::new(&format!("^{}$", pattern))?, r.to))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// The url with its host replaced by the first matching rule, if any
+    pub fn rewrite(&self, url: &str) -> Option<String> {
+        let mut url = Url::parse(url).ok()?;
+        let to = self
+            .rules
+            .iter()
+            .find(|(from, _)| url.host_str().is_some_and(|h| from.is_match(h)))
+            .map(|(_, to)| to)?;
+        url.set_host(Some(to)).ok()?;
+        Some(url.into())
+    }
+
+    /// Rewrite the streams in place. Returns the rewrites as `from -> to` hosts
+    pub fn apply(&self, streams: &mut [Stream]) -> Vec<String> {
+        let mut rewrites = vec![];
+        for s in streams.iter_mut() {
+            if let Some(url) = self.rewrite(&s.url) {
+                rewrites.push(format!("{} -> {}", host(&s.url), host(&url)));
+                s.url = url;
+            }
+        }
+        rewrites
+    }
+}
+
+fn host(url: &str) -> String {
+    Url::parse(url)
+        .ok()
+        .and_then(|u| u.host_str().map(Into::into))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::settings::HostRewrite;
+
+    use super::HostRewriter;
+
+    #[test]
+    fn test_rewrite() {
+        let rewriter = HostRewriter::try_from_setting(vec![
+            HostRewrite {
+                from: "upos-sz-mirror*.bilivideo.com".into(),
+                to: "upos-sz-mirrorcos.bilivideo.com".into(),
+            },
+            HostRewrite {
+                from: "m7.music.126.net".into(),
+                to: "m8.music.126.net".into(),
+            },
+        ])
+        .unwrap();
+
+        assert_eq!(
+            rewriter
+                .rewrite("https://upos-sz-mirrorhw.bilivideo.com/upgcxcode/a.m4s?deadline=1")
+                .as_deref(),
+            Some("https://upos-sz-mirrorcos.bilivideo.com/upgcxcode/a.m4s?deadline=1")
+        );
+        assert_eq!(
+            rewriter
+                .rewrite("http://m7.music.126.net/20240101/a.flac")
+                .as_deref(),
+            Some("http://m8.music.126.net/20240101/a.flac")
+        );
+        assert_eq!(
+            rewriter.rewrite("http://m701.music.126.net/20240101/a.flac"),
+            None
+        );
+        assert_eq!(rewriter.rewrite("not a url"), None);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::settings::{BitrateOrder, StreamSortSettings};
+
+use super::Stream;
+
+const LOSSLESS_CODECS: [&str; 3] = ["flac", "alac", "wav"];
+const DOLBY_CODECS: [&str; 2] = ["ec-3", "ac-3"];
+
+fn is_lossless(stream: &Stream) -> bool {
+    match &stream.codec {
+        Some(codec) => LOSSLESS_CODECS.iter().any(|c| codec.starts_with(c)),
+        None => stream.quality.to_lowercase().contains("lossless"),
+    }
+}
+
+fn is_dolby(stream: &Stream) -> bool {
+    match &stream.codec {
+        Some(codec) => DOLBY_CODECS.iter().any(|c| codec.starts_with(c)),
+        None => stream.quality == "Dolby",
+    }
+}
+
+/// Stable sort of the streams by the policy. Returns whether the order changed.
+pub fn sort_streams(streams: &mut [Stream], policy: &StreamSortSettings) -> bool {
+    let before: Vec<String> = streams.iter().map(|s| s.url.clone()).collect();
+
+    streams.sort_by_cached_key(|s| {
+        let codec = s.codec.as_deref().unwrap_or_default().to_lowercase();
+        (
+            policy.dolby_last && is_dolby(s),
+            !(policy.prefer_lossless && is_lossless(s)),
+            policy
+                .prefer_codecs
+                .iter()
+                .position(|c| !codec.is_empty() && codec.starts_with(&c.to_lowercase()))
+                .unwrap_or(policy.prefer_codecs.len()),
+            // unknown bitrates last in both orders
+            match (policy.bitrate, s.bitrate) {
+                (None, _) => (false, 0),
+                (Some(_), None) => (true, 0),
+                (Some(BitrateOrder::Highest), Some(b)) => (false, -i128::from(b)),
+                (Some(BitrateOrder::Smallest), Some(b)) => (false, i128::from(b)),
+            },
+        )
+    });
+
+    streams.iter().map(|s| &s.url).ne(before.iter())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        scraper::Stream,
+        settings::{BitrateOrder, StreamSortSettings},
+    };
+
+    use super::sort_streams;
+
+    fn stream(quality: &str, bitrate: Option<u64>, codec: Option<&str>) -> Stream {
+        Stream {
+            quality: quality.into(),
+            url: format!("https://upos/{}", quality),
+            bitrate,
+            codec: codec.map(Into::into),
+            mirror: false,
+            loudness: None,
+            preview: false,
+        }
+    }
+
+    fn qualities(streams: &[Stream]) -> Vec<&str> {
+        streams.iter().map(|s| s.quality.as_str()).collect()
+    }
+
+    #[test]
+    fn test_sort_streams() {
+        let mut streams = vec![
+            stream("Dolby", Some(448_000), Some("ec-3")),
+            stream("64k", Some(64_000), Some("mp4a.40.2")),
+            stream("opus", None, Some("opus")),
+            stream("Hi-Res lossless", Some(1_000_000), Some("fLaC")),
+            stream("192k", Some(192_000), Some("mp4a.40.2")),
+        ];
+
+        assert!(!sort_streams(&mut streams, &StreamSortSettings::default()));
+        assert_eq!(qualities(&streams)[0], "Dolby");
+
+        let mut policy = StreamSortSettings {
+            bitrate: Some(BitrateOrder::Smallest),
+            ..Default::default()
+        };
+        assert!(sort_streams(&mut streams, &policy));
+        assert_eq!(
+            qualities(&streams),
+            ["64k", "192k", "Dolby", "Hi-Res lossless", "opus"]
+        );
+
+        policy.bitrate = Some(BitrateOrder::Highest);
+        policy.dolby_last = true;
+        policy.prefer_lossless = true;
+        sort_streams(&mut streams, &policy);
+        assert_eq!(
+            qualities(&streams),
+            ["Hi-Res lossless", "192k", "64k", "opus", "Dolby"]
+        );
+
+        policy.prefer_lossless = false;
+        policy.prefer_codecs = vec!["opus".into(), "MP4A".into()];
+        sort_streams(&mut streams, &policy);
+        assert_eq!(
+            qualities(&streams),
+            ["opus", "192k", "64k", "Hi-Res lossless", "Dolby"]
+        );
+    }
+}
@@ -0,0 +1,595 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail};
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize};
+use tracing::info;
+
+use crate::{
+    privacy::redact,
+    settings::{IpFamily, SpotifySettings},
+    util::{self, body::LimitedBody},
+};
+
+use super::{
+    health::RateLimited,
+    id::{CollectionId, TrackId},
+    unavailable::{Restricted, Restriction},
+    Artist, ScrapeItem, ScrapeType, Scraper, SearchPage, Song, SongCollection, Stream,
+};
+
+const API: &str = "https://api.spotify.com/v1";
+const TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+/// Items of each type on one page
+const PAGE_SIZE: usize = 20;
+const MAX_SUGGESTIONS: usize = 10;
+/// Pages of the tracks of an album or a playlist fetched at most, 100 tracks each
+const MAX_TRACK_PAGES: usize = 20;
+/// Renew the access token this long before it expires
+const TOKEN_MARGIN: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    access_token: String,
+    /// seconds
+    expires_in: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct Image {
+    url: String,
+    width: Option<u32>,
+}
+
+fn cover(images: Vec<Image>) -> Option<String> {
+    images
+        .into_iter()
+        .max_by_key(|i| i.width.unwrap_or_default())
+        .map(|i| i.url)
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleArtist {
+    /// absent for local files of playlists
+    id: Option<String>,
+    name: String,
+}
+
+impl From<SimpleArtist> for Artist {
+    fn from(a: SimpleArtist) -> Self {
+        Self {
+            id: a.id.unwrap_or_default().into(),
+            name: a.name,
+            description: None,
+            avatar: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct FullArtist {
+    id: String,
+    name: String,
+    #[serde(default)]
+    images: Vec<Image>,
+    #[serde(default)]
+    genres: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SimpleAlbum {
+    id: String,
+    name: String,
+    #[serde(default)]
+    artists: Vec<SimpleArtist>,
+    #[serde(default)]
+    images: Vec<Image>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Track {
+    /// absent for local files of playlists
+    id: Option<String>,
+    name: String,
+    duration_ms: u64,
+    #[serde(default)]
+    artists: Vec<SimpleArtist>,
+    /// absent for the tracks of an album
+    album: Option<SimpleAlbum>,
+    /// 30 second sample, absent for many tracks
+    preview_url: Option<String>,
+}
+
+impl Track {
+    /// The cover of the album of the track, or else the given one
+    fn into_song(self, album_cover: Option<&String>) -> Option<Song> {
+        Some(Song {
+            id: self.id?.into(),
+            name: self.name,
+            artists: self.artists.into_iter().map(Into::into).collect(),
+            cover: self
+                .album
+                .and_then(|a| cover(a.images))
+                .or_else(|| album_cover.cloned()),
+            duration: Some((self.duration_ms / 1000) as u32),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Owner {
+    id: String,
+    display_name: Option<String>,
+}
+
+impl From<Owner> for Artist {
+    fn from(o: Owner) -> Self {
+        Self {
+            name: o.display_name.unwrap_or_else(|| o.id.clone()),
+            id: o.id.into(),
+            description: None,
+            avatar: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct SimplePlaylist {
+    id: String,
+    name: String,
+    description: Option<String>,
+    owner: Owner,
+    images: Option<Vec<Image>>,
+}
+
+/// Items are null where the object is gone, like deleted playlists in search results
+#[derive(Debug, Deserialize)]
+struct Paging<T> {
+    #[serde(default = "Vec::new")]
+    items: Vec<Option<T>>,
+    /// url of the next page
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    tracks: Option<Paging<Track>>,
+    albums: Option<Paging<SimpleAlbum>>,
+    artists: Option<Paging<FullArtist>>,
+    playlists: Option<Paging<SimplePlaylist>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Album {
+    id: String,
+    name: String,
+    #[serde(default)]
+    artists: Vec<SimpleArtist>,
+    #[serde(default)]
+    images: Vec<Image>,
+    tracks: Paging<Track>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlaylistItem {
+    track: Option<Track>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Playlist {
+    id: String,
+    name: String,
+    description: Option<String>,
+    owner: Owner,
+    images: Option<Vec<Image>>,
+    snapshot_id: String,
+    tracks: Paging<PlaylistItem>,
+}
+
+/// Albums and playlists share the id format, `album:` tells albums apart. Playlists may be
+/// prefixed with `playlist:`.
+enum Collection<'a> {
+    Album(&'a str),
+    Playlist(&'a str),
+}
+
+impl<'a> Collection<'a> {
+    fn parse(id: &'a str) -> Self {
+        match id.strip_prefix("album:") {
+            Some(id) => Self::Album(id),
+            None => Self::Playlist(id.strip_prefix("playlist:").unwrap_or(id)),
+        }
+    }
+}
+
+/// Catalog of Spotify through its Web API, with the client credentials of a registered app.
+/// Spotify streams full tracks to its own clients only, the 30 second samples are previews.
+pub struct SpotifyScraper {
+    client_id: String,
+    client_secret: String,
+    market: String,
+    client: reqwest::Client,
+    /// access token with the time it expires
+    token: tokio::sync::Mutex<Option<(String, Instant)>>,
+}
+
+impl SpotifyScraper {
+    pub fn try_from_setting(
+        setting: SpotifySettings,
+        outbound_family: Option<IpFamily>,
+    ) -> anyhow::Result<Option<Self>> {
+        if !setting.enabled {
+            return Ok(None);
+        }
+        if setting.client_id.is_empty() || setting.client_secret.is_empty() {
+            bail!("spotify needs the client_id and the client_secret of an app");
+        }
+        Ok(Some(Self {
+            client_id: setting.client_id,
+            client_secret: setting.client_secret,
+            market: setting.market,
+            client: util::client_builder(outbound_family).build()?,
+            token: Default::default(),
+        }))
+    }
+
+    /// Access token of the app, renewed shortly before it expires
+    async fn token(&self) -> anyhow::Result<String> {
+        let mut token = self.token.lock().await;
+        if let Some((access_token, expires)) = token.as_ref() {
+            if Instant::now() + TOKEN_MARGIN < *expires {
+                return Ok(access_token.clone());
+            }
+        }
+        let resp = self
+            .client
+            .post(TOKEN_URL)
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .form(&[("grant_type", "client_credentials")])
+            .send()
+            .await?
+            .error_for_status()?
+            .limited_json::<Token>()
+            .await?;
+        info!("[Spotify] renewed access token");
+        let expires = Instant::now() + Duration::from_secs(resp.expires_in);
+        *token = Some((resp.access_token.clone(), expires));
+        Ok(resp.access_token)
+    }
+
+    /// GET the path of the api, or the full url of a next page
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        let url = match path.starts_with("https://") {
+            true => path.to_string(),
+            false => format!("{}{}", API, path),
+        };
+        let resp = self
+            .client
+            .get(url)
+            .bearer_auth(self.token().await?)
+            .query(query)
+            .send()
+            .await?;
+        if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(60);
+            return Err(RateLimited {
+                reason: "spotify 429".to_string(),
+                retry_after: Duration::from_secs(retry_after),
+            }
+            .into());
+        }
+        resp.error_for_status()?.limited_json().await
+    }
+
+    /// The items of the paging and of its next pages, up to `MAX_TRACK_PAGES` pages
+    async fn all_items<T: DeserializeOwned>(&self, mut page: Paging<T>) -> anyhow::Result<Vec<T>> {
+        let mut items = vec![];
+        for _ in 0..MAX_TRACK_PAGES {
+            items.extend(page.items.into_iter().flatten());
+            let Some(next) = page.next else {
+                break;
+            };
+            page = self.get(&next, &[]).await?;
+        }
+        Ok(items)
+    }
+}
+
+#[async_trait]
+impl Scraper for SpotifyScraper {
+    async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
+        let limit = MAX_SUGGESTIONS.to_string();
+        let resp: SearchResponse = self
+            .get(
+                "/search",
+                &[
+                    ("q", &keyword),
+                    ("type", "track"),
+                    ("limit", &limit),
+                    ("market", &self.market),
+                ],
+            )
+            .await?;
+        let mut suggestions: Vec<String> = vec![];
+        for track in resp.tracks.into_iter().flat_map(|t| t.items).flatten() {
+            if !suggestions.contains(&track.name) {
+                suggestions.push(track.name);
+            }
+        }
+        Ok(suggestions)
+    }
+
+    /// The continuation is the offset of the next page
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        let types = match t {
+            ScrapeType::Radio => return Ok(SearchPage::default()),
+            ScrapeType::All => "track,album,artist,playlist",
+            ScrapeType::Song => "track",
+            ScrapeType::Album => "album",
+            ScrapeType::Artist => "artist",
+            ScrapeType::Playlist => "playlist",
+        };
+        let offset = match continuation {
+            Some(c) => c
+                .parse::<usize>()
+                .map_err(|_| anyhow!("invalid continuation: {}", c))?,
+            None => 0,
+        };
+        info!(
+            "[Spotify] search {} from offset {}",
+            redact(&keyword),
+            offset
+        );
+
+        let resp: SearchResponse = self
+            .get(
+                "/search",
+                &[
+                    ("q", &keyword),
+                    ("type", types),
+                    ("limit", &PAGE_SIZE.to_string()),
+                    ("offset", &offset.to_string()),
+                    ("market", &self.market),
+                ],
+            )
+            .await?;
+
+        let mut more = false;
+        let mut items = vec![];
+        if let Some(tracks) = resp.tracks {
+            more |= tracks.next.is_some();
+            items.extend(
+                tracks
+                    .items
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|t| t.into_song(None))
+                    .map(ScrapeItem::Song),
+            );
+        }
+        if let Some(albums) = resp.albums {
+            more |= albums.next.is_some();
+            items.extend(albums.items.into_iter().flatten().map(|a| {
+                ScrapeItem::Album(SongCollection {
+                    id: format!("album:{}", a.id).into(),
+                    name: a.name,
+                    artists: a.artists.into_iter().map(Into::into).collect(),
+                    cover: cover(a.images),
+                    description: None,
+                    songs: vec![],
+                    version: None,
+                    unavailable: false,
+                    saved: false,
+                    stale: false,
+                })
+            }));
+        }
+        if let Some(artists) = resp.artists {
+            more |= artists.next.is_some();
+            items.extend(artists.items.into_iter().flatten().map(|a| {
+                ScrapeItem::Artist(Artist {
+                    id: a.id.into(),
+                    name: a.name,
+                    description: (!a.genres.is_empty()).then(|| a.genres.join(", ")),
+                    avatar: cover(a.images),
+                })
+            }));
+        }
+        if let Some(playlists) = resp.playlists {
+            more |= playlists.next.is_some();
+            items.extend(playlists.items.into_iter().flatten().map(|p| {
+                ScrapeItem::Playlist(SongCollection {
+                    id: format!("playlist:{}", p.id).into(),
+                    name: p.name,
+                    artists: vec![p.owner.into()],
+                    cover: p.images.and_then(cover),
+                    description: p
+                        .description
+                        .map(|d| util::text::clean(&d))
+                        .filter(|d| !d.is_empty()),
+                    songs: vec![],
+                    version: None,
+                    unavailable: false,
+                    saved: false,
+                    stale: false,
+                })
+            }));
+        }
+
+        Ok(SearchPage {
+            next: more.then(|| (offset + PAGE_SIZE).to_string()),
+            items,
+        })
+    }
+
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        let market = [("market", self.market.as_str())];
+        match Collection::parse(&id) {
+            Collection::Album(album_id) => {
+                let album: Album = self.get(&format!("/albums/{}", album_id), &market).await?;
+                let album_cover = cover(album.images);
+                let tracks = self.all_items(album.tracks).await?;
+                Ok(SongCollection {
+                    id: format!("album:{}", album.id).into(),
+                    name: album.name,
+                    artists: album.artists.into_iter().map(Into::into).collect(),
+                    songs: tracks
+                        .into_iter()
+                        .filter_map(|t| t.into_song(album_cover.as_ref()))
+                        .collect(),
+                    cover: album_cover,
+                    description: None,
+                    version: None,
+                    unavailable: false,
+                    saved: false,
+                    stale: false,
+                })
+            }
+            Collection::Playlist(playlist_id) => {
+                let playlist: Playlist = self
+                    .get(&format!("/playlists/{}", playlist_id), &market)
+                    .await?;
+                let items = self.all_items(playlist.tracks).await?;
+                Ok(SongCollection {
+                    id: format!("playlist:{}", playlist.id).into(),
+                    name: playlist.name,
+                    artists: vec![playlist.owner.into()],
+                    cover: playlist.images.and_then(cover),
+                    description: playlist
+                        .description
+                        .map(|d| util::text::clean(&d))
+                        .filter(|d| !d.is_empty()),
+                    songs: items
+                        .into_iter()
+                        .filter_map(|i| i.track?.into_song(None))
+                        .collect(),
+                    version: Some(playlist.snapshot_id),
+                    unavailable: false,
+                    saved: false,
+                    stale: false,
+                })
+            }
+        }
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        Err(Restricted {
+            restriction: Restriction::AuthRequired,
+            reason: format!("spotify streams {} to its own clients only", id),
+        }
+        .into())
+    }
+
+    /// The 30 second sample of the track, which Spotify offers for some tracks only
+    async fn preview(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        let track: Track = self
+            .get(&format!("/tracks/{}", id), &[("market", &self.market)])
+            .await?;
+        Ok(track
+            .preview_url
+            .map(|url| Stream {
+                quality: "preview".to_string(),
+                url,
+                bitrate: None,
+                codec: Some("mp3".to_string()),
+                mirror: false,
+                loudness: None,
+                preview: true,
+            })
+            .into_iter()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Collection, Paging, Playlist, PlaylistItem, SearchResponse};
+
+    #[test]
+    fn test_collection() {
+        assert!(matches!(
+            Collection::parse("album:4aawyAB9vmqN3uQ7FjRGTy"),
+            Collection::Album("4aawyAB9vmqN3uQ7FjRGTy")
+        ));
+        assert!(matches!(
+            Collection::parse("playlist:37i9dQZF1DXcBWIGoYBM5M"),
+            Collection::Playlist("37i9dQZF1DXcBWIGoYBM5M")
+        ));
+        assert!(matches!(
+            Collection::parse("37i9dQZF1DXcBWIGoYBM5M"),
+            Collection::Playlist("37i9dQZF1DXcBWIGoYBM5M")
+        ));
+    }
+
+    #[test]
+    fn test_parse() {
+        let resp: SearchResponse = serde_json::from_str(
+            r#"{
+                "tracks": {"items": [{
+                    "id": "11dFghVXANMlKmJXsNCbNl",
+                    "name": "Cut To The Feeling",
+                    "duration_ms": 207959,
+                    "artists": [{"id": "6sFIWsNpZYqfjUpaCgueju", "name": "Carly Rae Jepsen"}],
+                    "album": {"id": "0tGPJ0bkWOUmH7MEOR77qc", "name": "Cut To The Feeling", "images": [
+                        {"url": "https://i.scdn.co/image/640", "width": 640, "height": 640},
+                        {"url": "https://i.scdn.co/image/300", "width": 300, "height": 300}
+                    ]},
+                    "preview_url": null
+                }], "next": "https://api.spotify.com/v1/search?offset=20"},
+                "playlists": {"items": [null], "next": null}
+            }"#,
+        )
+        .unwrap();
+        let track = resp.tracks.unwrap().items.pop().flatten().unwrap();
+        assert!(track.preview_url.is_none());
+        let song = track.into_song(None).unwrap();
+        assert_eq!(song.duration, Some(207));
+        assert_eq!(song.cover.as_deref(), Some("https://i.scdn.co/image/640"));
+        assert_eq!(song.artists[0].name, "Carly Rae Jepsen");
+        assert_eq!(resp.playlists.unwrap().items.len(), 1);
+
+        let playlist: Playlist = serde_json::from_str(
+            r#"{
+                "id": "37i9dQZF1DXcBWIGoYBM5M",
+                "name": "Today's Top Hits",
+                "description": "<a href=\"spotify:user:spotify\">Spotify</a> picks",
+                "owner": {"id": "spotify", "display_name": "Spotify"},
+                "images": null,
+                "snapshot_id": "MTY5NjAw",
+                "tracks": {"items": [{"track": null}, {"track": {"id": null, "name": "local file",
+                    "duration_ms": 1000, "artists": [{"id": null, "name": "me"}]}}], "next": null}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(playlist.snapshot_id, "MTY5NjAw");
+        let items: Paging<PlaylistItem> = playlist.tracks;
+        // neither a removed track nor a local file is a song
+        assert!(items
+            .items
+            .into_iter()
+            .flatten()
+            .filter_map(|i| i.track?.into_song(None))
+            .next()
+            .is_none());
+    }
+}
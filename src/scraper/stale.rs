@@ -0,0 +1,139 @@
+use std::collections::{HashMap, VecDeque};
+
+use parking_lot::Mutex;
+
+use crate::settings::StaleSettings;
+
+use super::{Provider, SearchPage, SongCollection};
+
+type Key = (Provider, String);
+
+/// Bounded map evicting the least recently stored entry
+#[derive(Debug)]
+struct Entries<T> {
+    values: HashMap<Key, T>,
+    order: VecDeque<Key>,
+}
+
+impl<T> Default for Entries<T> {
+    fn default() -> Self {
+        Self {
+            values: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+}
+
+impl<T: Clone> Entries<T> {
+    fn put(&mut self, capacity: usize, key: Key, value: T) {
+        if self.values.insert(key.clone(), value).is_some() {
+            self.order.retain(|k| k != &key);
+        }
+        self.order.push_back(key);
+
+        while self.order.len() > capacity {
+            if let Some(k) = self.order.pop_front() {
+                self.values.remove(&k);
+            }
+        }
+    }
+
+    fn get(&self, key: &Key) -> Option<T> {
+        self.values.get(key).cloned()
+    }
+}
+
+/// Last known results of every provider. Served marked as `stale` when the provider fails, so
+/// that playlists stay browsable during upstream outages. Streams are left out since their urls
+/// expire, a stale one would look valid and fail to play.
+#[derive(Debug)]
+pub struct StaleCache {
+    /// max entries of each kind
+    capacity: usize,
+    searches: Mutex<Entries<SearchPage>>,
+    collections: Mutex<Entries<SongCollection>>,
+}
+
+impl StaleCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            searches: Default::default(),
+            collections: Default::default(),
+        }
+    }
+
+    pub fn from_setting(setting: &StaleSettings) -> Option<Self> {
+        setting.enabled.then(|| Self::new(setting.capacity))
+    }
+
+    pub fn put_search(&self, provider: Provider, key: String, page: SearchPage) {
+        self.searches
+            .lock()
+            .put(self.capacity, (provider, key), page);
+    }
+
+    pub fn search(&self, provider: Provider, key: String) -> Option<SearchPage> {
+        self.searches.lock().get(&(provider, key))
+    }
+
+    pub fn put_collection(&self, provider: Provider, id: String, collection: SongCollection) {
+        self.collections
+            .lock()
+            .put(self.capacity, (provider, id), collection);
+    }
+
+    pub fn collection(&self, provider: Provider, id: String) -> Option<SongCollection> {
+        self.collections
+            .lock()
+            .get(&(provider, id))
+            .map(|c| SongCollection { stale: true, ..c })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::{Provider, SongCollection};
+
+    use super::StaleCache;
+
+    fn collection(id: &str) -> SongCollection {
+        SongCollection {
+            id: id.into(),
+            name: "playlist".into(),
+            artists: vec![],
+            cover: None,
+            description: None,
+            songs: vec![],
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
+        }
+    }
+
+    #[test]
+    fn test_stale_collection() {
+        let cache = StaleCache::new(2);
+        cache.put_collection(Provider::NetEase, "1".into(), collection("1"));
+
+        let collection = cache.collection(Provider::NetEase, "1".into()).unwrap();
+        assert!(collection.stale);
+        assert_eq!(collection.id.as_str(), "1");
+        assert!(cache.collection(Provider::Youtube, "1".into()).is_none());
+    }
+
+    #[test]
+    fn test_eviction() {
+        let cache = StaleCache::new(2);
+        cache.put_collection(Provider::NetEase, "1".into(), collection("1"));
+        cache.put_collection(Provider::NetEase, "2".into(), collection("2"));
+        // refresh 1 so that 2 becomes the oldest
+        cache.put_collection(Provider::NetEase, "1".into(), collection("1"));
+        cache.put_collection(Provider::NetEase, "3".into(), collection("3"));
+
+        assert!(cache.collection(Provider::NetEase, "1".into()).is_some());
+        assert!(cache.collection(Provider::NetEase, "2".into()).is_none());
+        assert!(cache.collection(Provider::NetEase, "3".into()).is_some());
+    }
+}
@@ -0,0 +1,358 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Largest metadata read from a file. Bigger FLAC blocks are skipped and bigger ID3v2 tags are
+/// read up to it, what is that large is mostly embedded pictures.
+const MAX_METADATA_SIZE: usize = 1 << 20;
+
+/// FLAC metadata block types
+const STREAMINFO: u8 = 0;
+const VORBIS_COMMENT: u8 = 4;
+
+/// Tags of an audio file, as far as they are set
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Tags {
+    pub title: Option<String>,
+    pub artists: Vec<String>,
+    pub album: Option<String>,
+    /// position on the album
+    pub track: Option<u32>,
+    /// seconds
+    pub duration: Option<u32>,
+}
+
+/// Tags of a FLAC file or of a file with an ID3v2 tag like mp3. Empty for other formats.
+pub fn read_tags(path: &Path) -> io::Result<Tags> {
+    read(&mut BufReader::new(File::open(path)?))
+}
+
+fn read(reader: &mut (impl Read + Seek)) -> io::Result<Tags> {
+    let mut magic = [0; 4];
+    if let Err(e) = reader.read_exact(&mut magic) {
+        return match e.kind() {
+            io::ErrorKind::UnexpectedEof => Ok(Tags::default()),
+            _ => Err(e),
+        };
+    }
+    match magic {
+        [b'f', b'L', b'a', b'C'] => read_flac(reader),
+        [b'I', b'D', b'3', version] => read_id3(reader, version),
+        _ => Ok(Tags::default()),
+    }
+}
+
+/// The metadata blocks following the `fLaC` marker
+fn read_flac(reader: &mut (impl Read + Seek)) -> io::Result<Tags> {
+    let mut tags = Tags::default();
+    loop {
+        let mut header = [0; 4];
+        reader.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        match header[0] & 0x7f {
+            t @ (STREAMINFO | VORBIS_COMMENT) if length <= MAX_METADATA_SIZE => {
+                let mut block = vec![0; length];
+                reader.read_exact(&mut block)?;
+                match t {
+                    STREAMINFO => tags.duration = streaminfo_duration(&block),
+                    _ => vorbis_comments(&block, &mut tags),
+                }
+            }
+            _ => {
+                reader.seek(SeekFrom::Current(length as i64))?;
+            }
+        }
+        // last block
+        if header[0] & 0x80 != 0 {
+            return Ok(tags);
+        }
+    }
+}
+
+/// Total samples over the sample rate, both packed in the STREAMINFO block
+fn streaminfo_duration(block: &[u8]) -> Option<u32> {
+    let b = block.get(10..18)?;
+    let rate = (b[0] as u64) << 12 | (b[1] as u64) << 4 | (b[2] as u64) >> 4;
+    let samples =
+        ((b[3] & 0x0f) as u64) << 32 | u32::from_be_bytes([b[4], b[5], b[6], b[7]]) as u64;
+    (rate > 0 && samples > 0).then(|| ((samples + rate / 2) / rate) as u32)
+}
+
+/// `KEY=value` comments of the VORBIS_COMMENT block, lengths little endian
+fn vorbis_comments(block: &[u8], tags: &mut Tags) {
+    let mut pos = 0;
+    // vendor, then the number of comments
+    let Some(count) = next_field(block, &mut pos).and_then(|_| next_u32(block, &mut pos)) else {
+        return;
+    };
+    for _ in 0..count {
+        let Some(comment) = next_field(block, &mut pos) else {
+            return;
+        };
+        let comment = String::from_utf8_lossy(comment);
+        let Some((key, value)) = comment.split_once('=') else {
+            continue;
+        };
+        let value = value.trim();
+        if value.is_empty() {
+            continue;
+        }
+        match key.to_ascii_uppercase().as_str() {
+            "TITLE" => tags.title = Some(value.to_string()),
+            "ARTIST" => tags.artists.push(value.to_string()),
+            "ALBUM" => tags.album = Some(value.to_string()),
+            "TRACKNUMBER" => tags.track = track_number(value),
+            _ => {}
+        }
+    }
+}
+
+fn next_u32(block: &[u8], pos: &mut usize) -> Option<u32> {
+    let value = u32::from_le_bytes(block.get(*pos..*pos + 4)?.try_into().ok()?);
+    *pos += 4;
+    Some(value)
+}
+
+/// Bytes prefixed by their length
+fn next_field<'a>(block: &'a [u8], pos: &mut usize) -> Option<&'a [u8]> {
+    let length = next_u32(block, pos)? as usize;
+    let field = block.get(*pos..*pos + length)?;
+    *pos += length;
+    Some(field)
+}
+
+/// `3` or `3/12`
+fn track_number(s: &str) -> Option<u32> {
+    s.split('/').next()?.trim().parse().ok()
+}
+
+/// 7 bits of every byte, the high one always clear
+fn syncsafe(b: &[u8]) -> usize {
+    b.iter().fold(0, |size, b| size << 7 | (b & 0x7f) as usize)
+}
+
+/// Text frames of an ID3v2.3 or ID3v2.4 tag, after the `ID3` marker and the major version
+fn read_id3(reader: &mut impl Read, version: u8) -> io::Result<Tags> {
+    let mut header = [0; 6];
+    reader.read_exact(&mut header)?;
+    let (flags, size) = (header[1], syncsafe(&header[2..6]));
+    // unsynchronised tags are rare enough not to undo it
+    if !matches!(version, 3 | 4) || flags & 0x80 != 0 {
+        return Ok(Tags::default());
+    }
+    let mut tag = vec![];
+    reader
+        .take(size.min(MAX_METADATA_SIZE) as u64)
+        .read_to_end(&mut tag)?;
+
+    let mut pos = 0;
+    // extended header, its size excludes itself in v2.3
+    if flags & 0x40 != 0 {
+        pos = match (version, tag.get(0..4)) {
+            (3, Some(b)) => 4 + u32::from_be_bytes(b.try_into().unwrap()) as usize,
+            (_, Some(b)) => syncsafe(b),
+            (_, None) => return Ok(Tags::default()),
+        };
+    }
+
+    let mut tags = Tags::default();
+    while let Some(frame) = tag.get(pos..pos + 10) {
+        // padding
+        if frame[0] == 0 {
+            break;
+        }
+        let size = match version {
+            3 => u32::from_be_bytes(frame[4..8].try_into().unwrap()) as usize,
+            _ => syncsafe(&frame[4..8]),
+        };
+        let Some(data) = tag.get(pos + 10..pos + 10 + size) else {
+            break;
+        };
+        let text = || decode_text(data);
+        match &frame[0..4] {
+            b"TIT2" => tags.title = text().into_iter().next(),
+            b"TPE1" => tags.artists = text(),
+            b"TALB" => tags.album = text().into_iter().next(),
+            b"TRCK" => tags.track = text().first().and_then(|t| track_number(t)),
+            b"TLEN" => {
+                tags.duration = text()
+                    .first()
+                    .and_then(|ms| ms.parse::<u32>().ok())
+                    .map(|ms| (ms + 500) / 1000)
+            }
+            _ => {}
+        }
+        pos += 10 + size;
+    }
+    Ok(tags)
+}
+
+/// Values of a text frame, several of them separated by nul characters in v2.4
+fn decode_text(data: &[u8]) -> Vec<String> {
+    let Some((encoding, data)) = data.split_first() else {
+        return vec![];
+    };
+    let text = match encoding {
+        0 => data.iter().map(|b| *b as char).collect(),
+        1 | 2 => {
+            let mut big_endian = *encoding == 2;
+            let units = data
+                .chunks_exact(2)
+                .filter_map(|b| {
+                    // byte order mark of each value
+                    match (b[0], b[1]) {
+                        (0xff, 0xfe) => big_endian = false,
+                        (0xfe, 0xff) => big_endian = true,
+                        _ if big_endian => return Some(u16::from_be_bytes([b[0], b[1]])),
+                        _ => return Some(u16::from_le_bytes([b[0], b[1]])),
+                    }
+                    None
+                })
+                .collect::<Vec<_>>();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(data).to_string(),
+    };
+    text.split('\0')
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{read, Tags};
+
+    fn flac(comments: &[&str], samples: u64) -> Vec<u8> {
+        let mut streaminfo = vec![0; 34];
+        // 44100 Hz
+        streaminfo[10..13].copy_from_slice(&[0x0a, 0xc4, 0x40]);
+        streaminfo[13] |= (samples >> 32) as u8 & 0x0f;
+        streaminfo[14..18].copy_from_slice(&(samples as u32).to_be_bytes());
+
+        let mut comment = vec![];
+        comment.extend(3u32.to_le_bytes());
+        comment.extend(b"ref");
+        comment.extend((comments.len() as u32).to_le_bytes());
+        for c in comments {
+            comment.extend((c.len() as u32).to_le_bytes());
+            comment.extend(c.as_bytes());
+        }
+
+        let mut file = b"fLaC".to_vec();
+        file.push(0);
+        file.extend(&(streaminfo.len() as u32).to_be_bytes()[1..]);
+        file.extend(streaminfo);
+        // a picture, skipped
+        file.push(6);
+        file.extend(&[0, 0, 3, 1, 2, 3]);
+        file.push(0x80 | 4);
+        file.extend(&(comment.len() as u32).to_be_bytes()[1..]);
+        file.extend(comment);
+        file
+    }
+
+    fn id3(version: u8, frames: &[(&[u8; 4], Vec<u8>)]) -> Vec<u8> {
+        let mut body = vec![];
+        for (id, data) in frames {
+            body.extend(*id);
+            let size = data.len() as u32;
+            match version {
+                3 => body.extend(size.to_be_bytes()),
+                _ => body.extend([
+                    (size >> 21) as u8,
+                    (size >> 14) as u8 & 0x7f,
+                    (size >> 7) as u8 & 0x7f,
+                    size as u8 & 0x7f,
+                ]),
+            }
+            body.extend([0, 0]);
+            body.extend(data);
+        }
+        // padding
+        body.extend([0; 16]);
+        let size = body.len() as u32;
+        let mut file = vec![b'I', b'D', b'3', version, 0, 0];
+        file.extend([
+            (size >> 21) as u8,
+            (size >> 14) as u8 & 0x7f,
+            (size >> 7) as u8 & 0x7f,
+            size as u8 & 0x7f,
+        ]);
+        file.extend(body);
+        // the first mp3 frame
+        file.extend([0xff, 0xfb, 0x90, 0x64]);
+        file
+    }
+
+    fn utf16(s: &str) -> Vec<u8> {
+        let mut data = vec![1, 0xff, 0xfe];
+        data.extend(s.encode_utf16().flat_map(u16::to_le_bytes));
+        data
+    }
+
+    #[test]
+    fn test_flac() {
+        let file = flac(
+            &[
+                "TITLE=夜に駆ける",
+                "artist=YOASOBI",
+                "ARTIST=Ayase",
+                "ALBUM=THE BOOK",
+                "TRACKNUMBER=1/9",
+                "COMMENT",
+            ],
+            44100 * 261,
+        );
+        assert_eq!(
+            read(&mut Cursor::new(file)).unwrap(),
+            Tags {
+                title: Some("夜に駆ける".into()),
+                artists: vec!["YOASOBI".into(), "Ayase".into()],
+                album: Some("THE BOOK".into()),
+                track: Some(1),
+                duration: Some(261),
+            }
+        );
+    }
+
+    #[test]
+    fn test_id3() {
+        let file = id3(
+            4,
+            &[
+                (b"TIT2", b"\x03Plastic Love".to_vec()),
+                (b"TPE1", b"\x03Mariya Takeuchi\0Tatsuro Yamashita".to_vec()),
+                (b"TRCK", b"\x003".to_vec()),
+                (b"TLEN", b"\x03479600".to_vec()),
+            ],
+        );
+        let tags = read(&mut Cursor::new(file)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Plastic Love"));
+        assert_eq!(tags.artists, ["Mariya Takeuchi", "Tatsuro Yamashita"]);
+        assert_eq!((tags.track, tags.duration), (Some(3), Some(480)));
+
+        let file = id3(
+            3,
+            &[
+                (b"TIT2", utf16("真夜中のドア")),
+                (b"TALB", b"\x00Pocket Park".to_vec()),
+            ],
+        );
+        let tags = read(&mut Cursor::new(file)).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("真夜中のドア"));
+        assert_eq!(tags.album.as_deref(), Some("Pocket Park"));
+    }
+
+    #[test]
+    fn test_untagged() {
+        for file in [b"OggS\0\0".to_vec(), b"ID".to_vec(), vec![]] {
+            assert_eq!(read(&mut Cursor::new(file)).unwrap(), Tags::default());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashSet;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use super::chapter::{parse_chapters, Chapter};
+
+lazy_static! {
+    /// `01.`, `1)`, `#1`, `No.1` or `Track 1:` leading the title
+    static ref NUMBERING: Regex =
+        Regex::new(r"^(?:(?i:track|no\.?)\s*\d{1,3}\s*[.):：]?|#\d{1,3}|\d{1,3}\s*[.)、])\s*").unwrap();
+}
+
+/// Separators of the song and its artists, the first found in a title splits it. `by` always
+/// leads the artists.
+const SEPARATORS: &[&str] = &[" — ", " – ", " - ", " / ", " by "];
+
+/// Separators of several artists of a song
+const ARTIST_SEPARATORS: &[&str] = &["、", " & ", ", ", " feat. ", " ft. ", " × "];
+
+/// Quotes wrapping the titles of songs, common on Bilibili
+const QUOTES: &[(char, char)] = &[
+    ('《', '》'),
+    ('「', '」'),
+    ('『', '』'),
+    ('“', '”'),
+    ('"', '"'),
+];
+
+/// The title without the numbering of the track
+pub fn strip_numbering(title: &str) -> &str {
+    match NUMBERING.find(title) {
+        Some(m) if m.end() < title.len() => &title[m.end()..],
+        _ => title,
+    }
+}
+
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    QUOTES
+        .iter()
+        .find_map(|(open, close)| s.strip_prefix(*open)?.strip_suffix(*close))
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .unwrap_or(s)
+}
+
+fn split_names(artists: &str) -> Vec<String> {
+    let mut names = vec![artists.to_string()];
+    for separator in ARTIST_SEPARATORS {
+        names = names
+            .iter()
+            .flat_map(|n| n.split(separator))
+            .map(|n| unquote(n).to_string())
+            .filter(|n| !n.is_empty())
+            .collect();
+    }
+    names
+}
+
+/// Both sides of the first separator, and whether the right one is the artist for sure
+fn split_title(title: &str) -> Option<(&str, &str, bool)> {
+    SEPARATORS.iter().find_map(|separator| {
+        let (left, right) = title.split_once(separator)?;
+        let (left, right) = (left.trim(), right.trim());
+        (!left.is_empty() && !right.is_empty()).then_some((left, right, *separator == " by "))
+    })
+}
+
+/// Split the titles of the tracklist into songs and artists. Titles like `song — artist` are
+/// listed as often as `artist - song`, so the side repeating across the list is taken as the
+/// artist, the right one if neither does. Nothing is split unless most titles have a separator,
+/// so that a title like `Intro - Live` in a list of plain titles stays as is.
+pub fn split_artists(chapters: &mut [Chapter]) {
+    let splits = chapters
+        .iter()
+        .map(|c| split_title(&c.title))
+        .collect::<Vec<_>>();
+    if splits.iter().flatten().count() * 2 <= chapters.len() {
+        return;
+    }
+    let (lefts, rights): (HashSet<_>, HashSet<_>) = splits
+        .iter()
+        .flatten()
+        .filter(|(_, _, by)| !by)
+        .map(|(left, right, _)| (*left, *right))
+        .unzip();
+    let artist_left = lefts.len() < rights.len();
+
+    let splits = splits
+        .into_iter()
+        .map(|s| s.map(|(left, right, by)| (left.to_string(), right.to_string(), by)))
+        .collect::<Vec<_>>();
+    for (chapter, split) in chapters.iter_mut().zip(splits) {
+        let Some((left, right, by)) = split else {
+            continue;
+        };
+        let (song, artists) = match artist_left && !by {
+            true => (right, left),
+            false => (left, right),
+        };
+        chapter.title = unquote(&song).to_string();
+        chapter.artists = split_names(&artists);
+    }
+}
+
+/// The tracklist listing the most chapters among the texts, like the description and the pinned
+/// comments of an upload. The earlier text wins a tie.
+pub fn best_tracklist<'a>(texts: impl IntoIterator<Item = &'a str>) -> Vec<Chapter> {
+    texts
+        .into_iter()
+        .map(parse_chapters)
+        .fold(vec![], |best, chapters| match chapters.len() > best.len() {
+            true => chapters,
+            false => best,
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{best_tracklist, strip_numbering};
+
+    fn titles_and_artists(text: &str) -> Vec<(String, Vec<String>)> {
+        best_tracklist([text])
+            .into_iter()
+            .map(|c| (c.title, c.artists))
+            .collect()
+    }
+
+    #[test]
+    fn test_strip_numbering() {
+        assert_eq!(strip_numbering("01. 夜に駆ける"), "夜に駆ける");
+        assert_eq!(strip_numbering("#3 Lemon"), "Lemon");
+        assert_eq!(strip_numbering("Track 12: Outro"), "Outro");
+        assert_eq!(strip_numbering("No.5 群青"), "群青");
+        assert_eq!(strip_numbering("99 Luftballons"), "99 Luftballons");
+        assert_eq!(strip_numbering("1989"), "1989");
+    }
+
+    #[test]
+    fn test_song_then_artist() {
+        let tracks = titles_and_artists(
+            "City pop mix\n\
+            00:00 Plastic Love — Mariya Takeuchi\n\
+            04:50 真夜中のドア — 松原みき\n\
+            10:12 Stay With Me — 松原みき\n\
+            15:30 Sparkle — Tatsuro Yamashita",
+        );
+        assert_eq!(tracks[0].0, "Plastic Love");
+        assert_eq!(tracks[0].1, vec!["Mariya Takeuchi"]);
+        assert_eq!(tracks[2].0, "Stay With Me");
+        assert_eq!(tracks[2].1, vec!["松原みき"]);
+    }
+
+    #[test]
+    fn test_artist_then_song() {
+        // the artist repeats on the left
+        let tracks = titles_and_artists(
+            "1. [00:00] YOASOBI - 夜に駆ける\n\
+            2. [04:21] YOASOBI - 群青\n\
+            3. [08:30] Aimer & milet - 「Kataomoi」\n\
+            4. [13:02] YOASOBI - アイドル",
+        );
+        assert_eq!(tracks.len(), 4);
+        assert_eq!(tracks[1].0, "群青");
+        assert_eq!(tracks[2].0, "Kataomoi");
+        assert_eq!(tracks[2].1, vec!["Aimer", "milet"]);
+    }
+
+    #[test]
+    fn test_bilibili_comment() {
+        let tracks = titles_and_artists(
+            "歌单：\n\
+            00:00 《稻香》周杰伦\n\
+            03:45 《晴天》 by 周杰伦\n\
+            08:10 《海阔天空》 by Beyond\n\
+            13:30 《后来》 by 刘若英",
+        );
+        // the first line has no separator
+        assert_eq!(tracks[0].0, "《稻香》周杰伦");
+        assert!(tracks[0].1.is_empty());
+        assert_eq!(tracks[2].0, "海阔天空");
+        assert_eq!(tracks[2].1, vec!["Beyond"]);
+    }
+
+    #[test]
+    fn test_plain_titles() {
+        let tracks = titles_and_artists(
+            "0:00 Opening\n\
+            3:00 Intro - Live\n\
+            6:00 Encore\n\
+            9:00 Ending",
+        );
+        assert_eq!(tracks[1].0, "Intro - Live");
+        assert!(tracks.iter().all(|(_, artists)| artists.is_empty()));
+    }
+
+    #[test]
+    fn test_best_tracklist() {
+        let description = "0:00 a\n1:00 b\n2:00 c";
+        let comment = "0:00 a\n1:00 b\n2:00 c\n3:00 d";
+        assert_eq!(best_tracklist([description, comment]).len(), 4);
+        assert_eq!(best_tracklist([description, "no timestamps"]).len(), 3);
+        assert!(best_tracklist(["", "0:00 a"]).is_empty());
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::{privacy::redact, util};
+
+use super::{Provider, ScrapeItem, SongCollection};
+
+/// Upstream reports the content as deleted or blocked, e.g. Bilibili -404 or NetEase copyright.
+/// Scrapers return it so that the id is remembered as unavailable.
+#[derive(Debug)]
+pub struct Unavailable(pub String);
+
+impl std::fmt::Display for Unavailable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "content unavailable: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unavailable {}
+
+/// Why upstream refuses to serve the content
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Restriction {
+    /// for accounts entitled to it only, e.g. NetEase VIP songs
+    AuthRequired,
+    /// blocked by copyright where the server is
+    RegionLocked,
+}
+
+/// Upstream has the content but does not serve it to the account or the region of the server.
+/// Region locked ids are remembered like unavailable ones, ids another account may play are not.
+#[derive(Debug)]
+pub struct Restricted {
+    pub restriction: Restriction,
+    pub reason: String,
+}
+
+impl std::fmt::Display for Restricted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.restriction {
+            Restriction::AuthRequired => write!(f, "authentication required: {}", self.reason),
+            Restriction::RegionLocked => write!(f, "region locked: {}", self.reason),
+        }
+    }
+}
+
+impl std::error::Error for Restricted {}
+
+/// Marks of deleted content are checked again after this, in case it comes back upstream
+const DELETED_TTL: Duration = Duration::from_secs(30 * 24 * 3600);
+/// Region locks may only apply to the region or proxy of one request, or be lifted
+const REGION_LOCKED_TTL: Duration = Duration::from_secs(24 * 3600);
+
+/// Why an id is remembered as unavailable, which decides how long the mark lasts
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkKind {
+    Deleted,
+    RegionLocked,
+}
+
+impl MarkKind {
+    fn ttl(self) -> Duration {
+        match self {
+            MarkKind::Deleted => DELETED_TTL,
+            MarkKind::RegionLocked => REGION_LOCKED_TTL,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Mark {
+    kind: MarkKind,
+    /// unix seconds
+    marked_at: u64,
+}
+
+impl Mark {
+    fn live(&self, now: u64) -> bool {
+        now < self.marked_at.saturating_add(self.kind.ttl().as_secs())
+    }
+}
+
+/// Ids of a provider in the file, a plain list before marks expired
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredMarks {
+    Marks(BTreeMap<String, Mark>),
+    Ids(BTreeSet<String>),
+}
+
+type Marks = BTreeMap<Provider, BTreeMap<String, Mark>>;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// IDs known as unavailable upstream, persisted as json if `filename` is present.
+/// Search and collection results containing them are annotated so that clients can grey them out
+/// instead of failing at play time. Marks expire, region locks sooner than deletions, and are
+/// cleared once the content is fetched again.
+#[derive(Debug, Default)]
+pub struct UnavailableStore {
+    filename: Option<String>,
+    marks: RwLock<Marks>,
+}
+
+impl UnavailableStore {
+    pub fn try_new(filename: Option<String>) -> anyhow::Result<Self> {
+        let Some(filename) = filename else {
+            return Ok(Self::default());
+        };
+
+        let stored: BTreeMap<Provider, StoredMarks> = util::load_json(&filename)?;
+        let marked_at = now();
+        let marks: Marks = stored
+            .into_iter()
+            .map(|(provider, marks)| {
+                let marks = match marks {
+                    StoredMarks::Marks(marks) => marks,
+                    StoredMarks::Ids(ids) => ids
+                        .into_iter()
+                        .map(|id| {
+                            let kind = MarkKind::Deleted;
+                            (id, Mark { kind, marked_at })
+                        })
+                        .collect(),
+                };
+                (provider, marks)
+            })
+            .collect();
+        info!(
+            "load unavailable ids from {}: {} ids",
+            filename,
+            marks.values().map(BTreeMap::len).sum::<usize>()
+        );
+
+        Ok(Self {
+            filename: Some(filename),
+            marks: RwLock::new(marks),
+        })
+    }
+
+    pub fn contains(&self, provider: &Provider, id: &str) -> bool {
+        self.contains_at(provider, id, now())
+    }
+
+    fn contains_at(&self, provider: &Provider, id: &str, now: u64) -> bool {
+        self.marks
+            .read()
+            .get(provider)
+            .and_then(|marks| marks.get(id))
+            .is_some_and(|m| m.live(now))
+    }
+
+    pub fn insert(&self, provider: Provider, id: String, kind: MarkKind) {
+        self.insert_at(provider, id, kind, now());
+    }
+
+    /// Expired marks are dropped along
+    fn insert_at(&self, provider: Provider, id: String, kind: MarkKind, now: u64) {
+        // a failed save is logged, the id is marked again by the next failure
+        let marked = self.update(|all| {
+            all.values_mut()
+                .for_each(|marks| marks.retain(|_, m| m.live(now)));
+            all.retain(|_, marks| !marks.is_empty());
+            let marks = all.entry(provider.clone()).or_default();
+            if marks.get(&id).is_some_and(|m| m.kind == kind) {
+                return false;
+            }
+            marks.insert(
+                id.clone(),
+                Mark {
+                    kind,
+                    marked_at: now,
+                },
+            );
+            true
+        });
+        if let Ok(true) = marked {
+            info!(
+                "mark unavailable: provider: {:?}, id: {}, {:?}",
+                provider,
+                redact(&id),
+                kind
+            );
+        }
+    }
+
+    /// Clear the mark of content fetched again
+    pub fn remove(&self, provider: &Provider, id: &str) {
+        let marked = self
+            .marks
+            .read()
+            .get(provider)
+            .is_some_and(|marks| marks.contains_key(id));
+        if !marked {
+            return;
+        }
+        let cleared = self.update(|all| {
+            let Some(marks) = all.get_mut(provider) else {
+                return false;
+            };
+            let removed = marks.remove(id).is_some();
+            if marks.is_empty() {
+                all.remove(provider);
+            }
+            removed
+        });
+        if let Ok(true) = cleared {
+            info!(
+                "available again: provider: {:?}, id: {}",
+                provider,
+                redact(id)
+            );
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Marks) -> bool) -> anyhow::Result<bool> {
+        util::update_json(self.filename.as_deref(), &mut *self.marks.write(), f)
+    }
+
+    pub fn annotate(&self, provider: &Provider, item: &mut ScrapeItem) {
+        match item {
+            ScrapeItem::Song(s) => s.unavailable |= self.contains(provider, &s.id),
+            ScrapeItem::Playlist(c) | ScrapeItem::Album(c) => self.annotate_collection(provider, c),
+            ScrapeItem::Artist(_) | ScrapeItem::Radio(_) => {}
+        }
+    }
+
+    pub fn annotate_collection(&self, provider: &Provider, collection: &mut SongCollection) {
+        collection.unavailable = self.contains(provider, &collection.id);
+        for s in collection.songs.iter_mut() {
+            s.unavailable |= self.contains(provider, &s.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::scraper::{Provider, ScrapeItem, Song};
+
+    use super::{now, MarkKind, UnavailableStore};
+
+    fn song(id: &str) -> Song {
+        Song {
+            id: id.into(),
+            name: "song".into(),
+            artists: vec![],
+            cover: None,
+            duration: None,
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+
+    #[test]
+    fn test_annotate() {
+        let store = UnavailableStore::default();
+        store.insert(
+            Provider::Bilibili,
+            "BV1xx411c7mD::1".into(),
+            MarkKind::Deleted,
+        );
+
+        let mut deleted = ScrapeItem::Song(song("BV1xx411c7mD::1"));
+        let mut other = ScrapeItem::Song(song("BV1xx411c7mD::1"));
+        store.annotate(&Provider::Bilibili, &mut deleted);
+        store.annotate(&Provider::NetEase, &mut other);
+
+        assert!(matches!(deleted, ScrapeItem::Song(s) if s.unavailable));
+        assert!(matches!(other, ScrapeItem::Song(s) if !s.unavailable));
+
+        // greyed out by upstream, whatever the store knows
+        let mut grey = ScrapeItem::Song(Song {
+            unavailable: true,
+            ..song("1901371647")
+        });
+        store.annotate(&Provider::NetEase, &mut grey);
+        assert!(matches!(grey, ScrapeItem::Song(s) if s.unavailable));
+    }
+
+    #[test]
+    fn test_persist() {
+        let filename = std::env::temp_dir()
+            .join(format!("bragi-unavailable-{}.json", std::process::id()))
+            .to_string_lossy()
+            .to_string();
+
+        let store = UnavailableStore::try_new(Some(filename.clone())).unwrap();
+        store.insert(Provider::NetEase, "1901371647".into(), MarkKind::Deleted);
+
+        let store = UnavailableStore::try_new(Some(filename.clone())).unwrap();
+        assert!(store.contains(&Provider::NetEase, "1901371647"));
+        assert!(!store.contains(&Provider::NetEase, "1"));
+
+        // the plain lists of ids before marks expired
+        std::fs::write(&filename, r#"{"netease": ["186016"]}"#).unwrap();
+        let store = UnavailableStore::try_new(Some(filename.clone())).unwrap();
+        assert!(store.contains(&Provider::NetEase, "186016"));
+
+        std::fs::remove_file(filename).unwrap();
+    }
+
+    #[test]
+    fn test_expire() {
+        let store = UnavailableStore::default();
+        let day = 24 * 3600;
+        let now = now();
+        store.insert_at(
+            Provider::NetEase,
+            "1".into(),
+            MarkKind::RegionLocked,
+            now - 2 * day,
+        );
+        store.insert_at(
+            Provider::NetEase,
+            "2".into(),
+            MarkKind::Deleted,
+            now - 2 * day,
+        );
+        store.insert_at(
+            Provider::NetEase,
+            "3".into(),
+            MarkKind::Deleted,
+            now - 40 * day,
+        );
+        assert!(!store.contains(&Provider::NetEase, "1"));
+        assert!(store.contains(&Provider::NetEase, "2"));
+        assert!(!store.contains(&Provider::NetEase, "3"));
+
+        // fetched again
+        store.remove(&Provider::NetEase, "2");
+        assert!(!store.contains(&Provider::NetEase, "2"));
+
+        // expired marks are dropped by the next one
+        store.insert(Provider::NetEase, "4".into(), MarkKind::Deleted);
+        assert_eq!(store.marks.read()[&Provider::NetEase].len(), 1);
+    }
+}
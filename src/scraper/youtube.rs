@@ -1,10 +1,33 @@
-use anyhow::anyhow;
-use html_escape::decode_html_entities;
-use invidious::ClientAsyncTrait;
+use std::{collections::HashSet, sync::Weak, time::Duration};
 
-use crate::settings::YouTubeSettings;
+use invidious::{ClientAsync, ClientAsyncTrait, InvidiousError};
 
-use super::*;
+use crate::{settings::YouTubeSettings, util};
+
+use super::{
+    chapter,
+    explain::StreamTrace,
+    health::Health,
+    instance::{InstanceError, InstancePool, InstanceStats},
+    query::{Query, SearchFilter},
+    tracklist,
+    youtube_api::DataApi,
+    ytdlp::YtDlp,
+    *,
+};
+
+/// The first video uploaded to YouTube, streamed to probe whether an instance works again
+const PROBE_VIDEO: &str = "jNQXAC9IVRw";
+
+impl From<InvidiousError> for InstanceError {
+    fn from(e: InvidiousError) -> Self {
+        match e {
+            // the instance reached YouTube, which refused
+            InvidiousError::ApiError { .. } => InstanceError::Upstream(e.to_string()),
+            _ => InstanceError::Instance(e.to_string()),
+        }
+    }
+}
 
 fn thumbnails_to_cover(thumbnails: Vec<invidious::CommonThumbnail>) -> Option<String> {
     thumbnails
@@ -13,6 +36,13 @@ fn thumbnails_to_cover(thumbnails: Vec<invidious::CommonThumbnail>) -> Option<St
         .map(|t| t.url)
 }
 
+/// Invidious sometimes returns videos without thumbnails. Fall back to the standard thumbnail
+/// url of the video, which always exists.
+fn video_cover(id: &str, thumbnails: Vec<invidious::CommonThumbnail>) -> Option<String> {
+    thumbnails_to_cover(thumbnails)
+        .or_else(|| Some(format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", id)))
+}
+
 fn images_to_cover(thumbnails: Vec<invidious::CommonImage>) -> Option<String> {
     thumbnails
         .into_iter()
@@ -22,45 +52,183 @@ fn images_to_cover(thumbnails: Vec<invidious::CommonImage>) -> Option<String> {
 
 fn artists(id: String, name: String, avatar: Option<String>) -> Vec<Artist> {
     vec![Artist {
-        id,
-        name,
+        id: id.into(),
+        name: util::text::clean(&name),
         description: None,
         avatar,
     }]
 }
 
-#[derive(Default)]
 pub struct YouTubeScraper {
-    client: invidious::ClientAsync,
+    pool: Arc<InstancePool<ClientAsync>>,
+    probe_interval: Duration,
+    exclude_shorts: bool,
+    exclude_live: bool,
+    /// metadata from the Data API instead of the instances, if a key is configured
+    api: Option<DataApi>,
+    /// extractor of the streams the instances fail to give
+    ytdlp: Option<YtDlp>,
+}
+
+impl Default for YouTubeScraper {
+    fn default() -> Self {
+        Self::new(ClientAsync::default())
+    }
 }
 
 impl YouTubeScraper {
-    pub fn new(client: invidious::ClientAsync) -> Self {
-        Self { client }
+    pub fn new(client: ClientAsync) -> Self {
+        Self {
+            pool: Arc::new(InstancePool::new(
+                vec![(client.instance.clone(), client)],
+                1,
+            )),
+            probe_interval: Duration::from_secs(300),
+            exclude_shorts: false,
+            exclude_live: false,
+            api: None,
+            ytdlp: None,
+        }
     }
 
+    /// Probing of the benched instances runs in the background as long as the scraper exists
     pub fn try_from_setting(setting: YouTubeSettings) -> anyhow::Result<Option<Self>> {
         if setting.enabled {
-            return Ok(Some(Self {
-                client: invidious::ClientAsync::new(
-                    setting.instance,
-                    invidious::MethodAsync::Reqwest,
-                ),
-            }));
+            let mut urls = setting.instance.into_vec();
+            if !setting.fallback_instances.is_empty() {
+                warn!("[YouTube] fallback_instances is deprecated, list them in instance");
+                urls.extend(setting.fallback_instances);
+            }
+            let mut seen = HashSet::new();
+            urls.retain(|url| !url.trim().is_empty() && seen.insert(url.clone()));
+            if urls.is_empty() {
+                anyhow::bail!("no invidious instance configured");
+            }
+            let instances = urls
+                .into_iter()
+                .map(|i| {
+                    let client = ClientAsync::new(i.clone(), invidious::MethodAsync::Reqwest);
+                    (i, client)
+                })
+                .collect();
+            let scraper = Self {
+                pool: Arc::new(InstancePool::new(instances, setting.bench_after)),
+                probe_interval: Duration::from_secs(setting.probe_interval.max(1)),
+                exclude_shorts: setting.exclude_shorts,
+                exclude_live: setting.exclude_live,
+                api: setting
+                    .api_key
+                    .filter(|k| !k.trim().is_empty())
+                    .map(|k| DataApi::new(k.trim().to_string())),
+                ytdlp: setting.ytdlp.map(YtDlp::new),
+            };
+            let (pool, interval) = (Arc::downgrade(&scraper.pool), scraper.probe_interval);
+            util::task::supervise("youtube instance probe", move || {
+                probe_instances(pool.clone(), interval)
+            });
+            return Ok(Some(scraper));
         }
 
         Ok(None)
     }
+
+    /// Shorts and broadcasts are excluded if either the provider options or the search ask so
+    fn keep(&self, item: &invidious::hidden::SearchItem, filter: &SearchFilter) -> bool {
+        let invidious::hidden::SearchItem::Video(v) = item else {
+            return true;
+        };
+        if (self.exclude_live || filter.exclude_live) && (v.live || v.upcoming) {
+            return false;
+        }
+        !((self.exclude_shorts || filter.exclude_shorts) && is_short(v))
+    }
+}
+
+/// The video with its audio streams. Instances which fail to extract them, e.g. once YouTube
+/// blocks them, return the video without any. Broadcasts and premium videos have none anyway.
+async fn extract(client: &ClientAsync, id: &str) -> Result<invidious::video::Video, InstanceError> {
+    let video = client.video(id, None).await?;
+    let extracted = video
+        .adaptive_formats
+        .iter()
+        .any(|f| !f.audio_quality.is_empty());
+    match extracted || video.live || video.upcoming || video.premium {
+        true => Ok(video),
+        false => Err(InstanceError::Instance(
+            "no audio stream extracted".to_string(),
+        )),
+    }
+}
+
+fn formats_trace(video: invidious::video::Video) -> StreamTrace {
+    let mut trace = StreamTrace::default();
+    for format in video.adaptive_formats {
+        match format.audio_quality.is_empty() {
+            true => trace.filtered(
+                format!("{}({})", format.quality, format.r#type),
+                "video only format",
+            ),
+            false => trace.found(format.into()),
+        }
+    }
+    trace
+}
+
+/// Probe the benched instances every interval until the scraper is dropped
+async fn probe_instances(pool: Weak<InstancePool<ClientAsync>>, interval: Duration) {
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        pool.probe(|c| async move { extract(c, PROBE_VIDEO).await.map(|_| ()) })
+            .await;
+    }
+}
+
+/// Invidious does not flag Shorts. Videos up to a minute are taken as Shorts, while broadcasts
+/// report no length at all.
+pub(super) const SHORTS_MAX_LENGTH: u32 = 60;
+
+fn is_short(video: &invidious::CommonVideo) -> bool {
+    video.length > 0 && video.length <= SHORTS_MAX_LENGTH
 }
 
 impl From<invidious::CommonVideo> for Song {
     fn from(val: invidious::CommonVideo) -> Self {
         Song {
-            id: val.id,
-            name: decode_html_entities(&val.title).to_string(),
+            cover: video_cover(&val.id, val.thumbnails),
+            id: val.id.into(),
+            name: util::text::clean(&val.title),
             artists: artists(val.author_id, val.author, None),
-            cover: thumbnails_to_cover(val.thumbnails),
             duration: Some(val.length),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
+        }
+    }
+}
+
+impl From<&invidious::video::Video> for Song {
+    fn from(val: &invidious::video::Video) -> Self {
+        Self {
+            cover: video_cover(&val.id, val.thumbnails.clone()),
+            id: val.id.clone().into(),
+            name: util::text::clean(&val.title),
+            artists: artists(
+                val.author_id.clone(),
+                val.author.clone(),
+                images_to_cover(val.author_thumbnails.clone()),
+            ),
+            duration: Some(val.length),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
         }
     }
 }
@@ -68,11 +236,16 @@ impl From<invidious::CommonVideo> for Song {
 impl From<invidious::hidden::PlaylistItem> for Song {
     fn from(val: invidious::hidden::PlaylistItem) -> Self {
         Self {
-            id: val.id,
-            name: decode_html_entities(&val.title).to_string(),
+            cover: video_cover(&val.id, val.thumbnails),
+            id: val.id.into(),
+            name: util::text::clean(&val.title),
             artists: artists(val.author_id, val.author, None),
-            cover: thumbnails_to_cover(val.thumbnails),
             duration: Some(val.length),
+            unavailable: false,
+            unavailable_reason: None,
+            alternatives: vec![],
+            saved: false,
+            clip: None,
         }
     }
 }
@@ -81,22 +254,31 @@ impl From<invidious::CommonPlaylist> for SongCollection {
     fn from(val: invidious::CommonPlaylist) -> Self {
         let artists = artists(val.author_id, val.author, None);
         Self {
-            id: val.id,
-            name: decode_html_entities(&val.title).to_string(),
+            id: val.id.into(),
+            name: util::text::clean(&val.title),
             cover: Some(val.thumbnail),
             description: None,
             songs: val
                 .videos
                 .into_iter()
                 .map(|v| Song {
-                    id: v.id,
-                    name: v.title,
+                    cover: video_cover(&v.id, v.thumbnails),
+                    id: v.id.into(),
+                    name: util::text::clean(&v.title),
                     artists: artists.clone(),
-                    cover: thumbnails_to_cover(v.thumbnails),
                     duration: Some(v.length),
+                    unavailable: false,
+                    unavailable_reason: None,
+                    alternatives: vec![],
+                    saved: false,
+                    clip: None,
                 })
                 .collect(),
             artists,
+            version: None,
+            unavailable: false,
+            saved: false,
+            stale: false,
         }
     }
 }
@@ -104,16 +286,20 @@ impl From<invidious::CommonPlaylist> for SongCollection {
 impl From<invidious::universal::Playlist> for SongCollection {
     fn from(val: invidious::universal::Playlist) -> Self {
         Self {
-            id: val.id,
-            name: decode_html_entities(&val.title).to_string(),
+            id: val.id.into(),
+            name: util::text::clean(&val.title),
             artists: artists(
                 val.author_id,
                 val.author,
                 images_to_cover(val.author_thumbnails),
             ),
             cover: Some(val.thumbnail),
-            description: Some(val.description),
+            description: Some(util::text::clean(&val.description)),
             songs: val.videos.into_iter().map(Into::into).collect(),
+            version: Some(val.updated.to_string()),
+            unavailable: false,
+            saved: false,
+            stale: false,
         }
     }
 }
@@ -121,9 +307,20 @@ impl From<invidious::universal::Playlist> for SongCollection {
 impl From<invidious::CommonChannel> for Artist {
     fn from(val: invidious::CommonChannel) -> Self {
         Self {
-            id: val.id,
-            name: decode_html_entities(&val.name).to_string(),
-            description: Some(val.description),
+            id: val.id.into(),
+            name: util::text::clean(&val.name),
+            description: Some(util::text::clean(&val.description)),
+            avatar: images_to_cover(val.thumbnails),
+        }
+    }
+}
+
+impl From<invidious::channel::Channel> for Artist {
+    fn from(val: invidious::channel::Channel) -> Self {
+        Self {
+            id: val.id.into(),
+            name: util::text::clean(&val.name),
+            description: Some(util::text::clean(&val.description)),
             avatar: images_to_cover(val.thumbnails),
         }
     }
@@ -144,65 +341,262 @@ impl From<invidious::hidden::AdaptiveFormat> for Stream {
         Self {
             quality: format!("{}({})", val.audio_quality, val.bitrate),
             url: val.url,
+            bitrate: val.bitrate.parse().ok(),
+            codec: Some(val.encoding).filter(|e| !e.is_empty()),
+            mirror: false,
+            loudness: None,
+            preview: false,
         }
     }
 }
 
+/// Duration filter of videos of invidious and the Data API: short is under 4 minutes, medium 4 to 20 minutes and long
+/// over 20 minutes. Only a range within a single bucket can be narrowed upstream.
+pub(super) fn duration_bucket(filter: &SearchFilter) -> Option<&'static str> {
+    match (filter.min_duration.unwrap_or(0), filter.max_duration) {
+        (_, Some(max)) if max <= 4 * 60 => Some("short"),
+        (min, Some(max)) if min >= 4 * 60 && max <= 20 * 60 => Some("medium"),
+        (min, _) if min >= 20 * 60 => Some("long"),
+        _ => None,
+    }
+}
+
 #[async_trait]
 impl Scraper for YouTubeScraper {
+    /// YouTube has no field filters but honors quoted terms as exact matches
+    fn native_query(&self, query: &Query, t: ScrapeType) -> (String, ScrapeType) {
+        let keyword = [&query.title, &query.artist, &query.album]
+            .into_iter()
+            .flatten()
+            .map(|f| format!(r#""{}""#, f))
+            .chain(Some(query.text.clone()).filter(|t| !t.is_empty()))
+            .collect::<Vec<_>>()
+            .join(" ");
+        (keyword, t)
+    }
+
+    /// From the instances even with a Data API key, the api has no suggestions
     async fn suggest(&self, keyword: String) -> anyhow::Result<Vec<String>> {
-        self.client
-            .search_suggestions(Some(&format!("q={keyword}")))
+        let query = format!("q={keyword}");
+        self.pool
+            .call(|c| async { Ok(c.search_suggestions(Some(&query)).await?) })
             .await
             .map(|v| {
                 v.suggestions
                     .into_iter()
-                    .map(|s| decode_html_entities(&s).to_string())
+                    .map(|s| util::text::clean(&s))
                     .collect()
             })
-            .map_err(|e| anyhow!("{}", e))
     }
 
-    async fn search(&self, keyword: String, t: ScrapeType) -> Vec<ScrapeItem> {
+    async fn search(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+    ) -> anyhow::Result<SearchPage> {
+        self.search_filtered(keyword, t, continuation, &SearchFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        keyword: String,
+        t: ScrapeType,
+        continuation: Option<String>,
+        filter: &SearchFilter,
+    ) -> anyhow::Result<SearchPage> {
+        // continuation of invidious search is the page number, starting from 1, the one of the
+        // Data API a page token, never a number
+        let page = continuation.as_ref().and_then(|c| c.parse::<u32>().ok());
+        if let (Some(api), None) = (&self.api, page) {
+            let result = api
+                .search(
+                    &keyword,
+                    &t,
+                    continuation.as_deref(),
+                    filter,
+                    self.exclude_shorts || filter.exclude_shorts,
+                    self.exclude_live || filter.exclude_live,
+                )
+                .await;
+            match (result, &continuation) {
+                (Ok(page), _) => return Ok(page),
+                // the instances take over fresh searches, their pages differ from the api's
+                (Err(e), None) => warn!("[YouTube] data api search failed: {}", e),
+                (Err(e), Some(_)) => return Err(e),
+            }
+        }
+        let page = page.unwrap_or(1);
+
         let query_type = match t {
             // Album is not supported by YouTube
-            ScrapeType::Album => return vec![],
+            ScrapeType::Album | ScrapeType::Radio => return Ok(SearchPage::default()),
             ScrapeType::All => "all",
             ScrapeType::Song => "video",
             ScrapeType::Artist => "channel",
             ScrapeType::Playlist => "playlist",
         };
 
-        self.client
-            .search(Some(&format!("q={keyword}&type={query_type}")))
-            .await
-            .map(|v| v.items)
+        let mut query = format!("q={keyword}&type={query_type}&page={page}");
+        // the duration only applies to videos
+        if let (ScrapeType::Song, Some(duration)) = (&t, duration_bucket(filter)) {
+            query.push_str(&format!("&duration={duration}"));
+        }
+
+        let items = self
+            .pool
+            .call(|c| async { Ok(c.search(Some(&query)).await?) })
+            .await?
+            .items
             .into_iter()
-            .flatten()
+            .filter(|i| self.keep(i, filter))
             .map(Into::<ScrapeItem>::into)
-            .collect()
+            .collect::<Vec<_>>();
+
+        Ok(SearchPage {
+            // invidious doesn't tell the total. Keep paging until a page comes back empty
+            next: (!items.is_empty()).then(|| (page + 1).to_string()),
+            items,
+        })
     }
 
-    async fn collection_detail(&self, id: String) -> anyhow::Result<SongCollection> {
-        self.client
-            .playlist(&id, None)
+    async fn collection_detail(&self, id: CollectionId) -> anyhow::Result<SongCollection> {
+        if let Some(api) = &self.api {
+            match api.playlist(&id).await {
+                Ok(collection) => return Ok(collection),
+                Err(e) => warn!("[YouTube] data api playlist {} failed: {}", id.as_str(), e),
+            }
+        }
+        self.pool
+            .call(|c| async { Ok(c.playlist(&id, None).await?) })
             .await
             .map(Into::into)
-            .map_err(|e| anyhow!("{}", e))
     }
 
-    async fn stream(&self, id: String) -> anyhow::Result<Vec<Stream>> {
-        self.client
-            .video(&id, None)
-            .await
-            .map(|v| {
-                v.adaptive_formats
-                    .into_iter()
-                    .filter(|i| !i.audio_quality.is_empty())
-                    .map(Into::into)
-                    .collect()
+    /// Latest uploads of the channel, without Shorts if they are excluded
+    async fn artist_releases(&self, id: ArtistId) -> anyhow::Result<Vec<ScrapeItem>> {
+        Ok(self
+            .pool
+            .call(|c| async { Ok(c.channel_videos(&id, None).await?) })
+            .await?
+            .videos
+            .into_iter()
+            .filter(|v| !(self.exclude_shorts && is_short(v)))
+            .map(|v| ScrapeItem::Song(v.into()))
+            .collect())
+    }
+
+    /// Popular videos of the channel as top tracks. YouTube has no albums of channels.
+    async fn artist_detail(&self, id: ArtistId) -> anyhow::Result<ArtistDetail> {
+        let (channel, videos, playlists) = self
+            .pool
+            .call(|c| async {
+                Ok(futures::future::try_join3(
+                    c.channel(&id, None),
+                    c.channel_videos(&id, Some("sort_by=popular")),
+                    c.channel_playlists(&id, None),
+                )
+                .await?)
             })
-            .map_err(|e| anyhow!("{}", e))
+            .await?;
+
+        Ok(ArtistDetail {
+            artist: channel.into(),
+            songs: videos
+                .videos
+                .into_iter()
+                .filter(|v| !(self.exclude_shorts && is_short(v)))
+                .map(Into::into)
+                .collect(),
+            albums: vec![],
+            playlists: playlists.playlists.into_iter().map(Into::into).collect(),
+        })
+    }
+
+    /// Invidious does not expose the chapters, which YouTube takes from the description as well.
+    /// Mixes often list their tracks in a pinned comment instead, the first of the top comments,
+    /// so it and the other comments of the uploader on the first page are tried too.
+    async fn chapters(&self, id: TrackId) -> anyhow::Result<Vec<Song>> {
+        let video = self.pool.call(|c| async { Ok(c.video(&id, None).await?) });
+        let comments = async {
+            match self
+                .pool
+                .call(|c| async { Ok(c.comments(&id, None).await?) })
+                .await
+            {
+                Ok(comments) => comments.comments,
+                Err(e) => {
+                    warn!("[YouTube] get comments of {} failed: {}", id.as_str(), e);
+                    vec![]
+                }
+            }
+        };
+        let (video, comments) = futures::join!(video, comments);
+        let video = video?;
+        let pinned = comments
+            .iter()
+            .enumerate()
+            .filter(|(i, c)| *i == 0 || c.channel_owner || c.author_id == video.author_id)
+            .map(|(_, c)| c.content.as_str());
+        let chapters =
+            tracklist::best_tracklist(std::iter::once(video.description.as_str()).chain(pinned));
+        Ok(chapter::chapter_songs(&(&video).into(), chapters))
+    }
+
+    async fn stream(&self, id: TrackId) -> anyhow::Result<Vec<Stream>> {
+        Ok(self.stream_trace(id).await?.streams)
+    }
+
+    /// Streams of the instances, or of yt-dlp if configured and the best stream of the instances
+    /// fails validation
+    async fn stream_trace(&self, id: TrackId) -> anyhow::Result<StreamTrace> {
+        let video = self.pool.call(|c| extract(c, &id)).await;
+        let Some(ytdlp) = &self.ytdlp else {
+            return Ok(formats_trace(video?));
+        };
+
+        let (mut trace, broken) = match video {
+            Ok(video) => {
+                let trace = formats_trace(video);
+                // the other urls of the same video are as broken as the best one
+                let best = trace.streams.iter().max_by_key(|s| s.bitrate.unwrap_or(0));
+                let broken = match best {
+                    Some(s) => ytdlp.validate(&s.url).await.err(),
+                    None => Some(anyhow!("no audio stream")),
+                };
+                (trace, broken)
+            }
+            Err(e) => (StreamTrace::default(), Some(e)),
+        };
+        let Some(broken) = broken else {
+            return Ok(trace);
+        };
+
+        warn!(
+            "[YouTube] streams of {} from the instances failed, extract with yt-dlp: {}",
+            id.as_str(),
+            broken
+        );
+        let streams = ytdlp.streams(&id).await?;
+        trace.note(format!("instances failed: {}, extracted by yt-dlp", broken));
+        trace.streams.clear();
+        streams.into_iter().for_each(|s| trace.found(s));
+        Ok(trace)
+    }
+
+    fn health(&self) -> Health {
+        match self.pool.all_benched() {
+            true => Health::degraded(
+                "every invidious instance failed".to_string(),
+                self.probe_interval,
+            ),
+            false => Health::default(),
+        }
+    }
+
+    fn instances(&self) -> Vec<InstanceStats> {
+        self.pool.stats()
     }
 }
 
@@ -210,6 +604,107 @@ impl Scraper for YouTubeScraper {
 mod test {
     use super::*;
 
+    #[test]
+    fn test_video_cover_fallback() {
+        assert_eq!(
+            video_cover("K_x2r8vJxZ4", vec![]),
+            Some("https://i.ytimg.com/vi/K_x2r8vJxZ4/hqdefault.jpg".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_instances() {
+        let setting = |instance| {
+            serde_json::from_value::<YouTubeSettings>(serde_json::json!({
+                "enabled": true,
+                "instance": instance,
+            }))
+            .unwrap()
+        };
+        let urls = |setting| {
+            YouTubeScraper::try_from_setting(setting)
+                .unwrap()
+                .unwrap()
+                .pool
+                .stats()
+                .into_iter()
+                .map(|s| s.url)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(urls(setting(serde_json::json!("https://a"))), ["https://a"]);
+        assert_eq!(
+            urls(setting(serde_json::json!([
+                "https://a",
+                "https://b",
+                "https://a"
+            ]))),
+            ["https://a", "https://b"]
+        );
+        assert!(YouTubeScraper::try_from_setting(setting(serde_json::json!([]))).is_err());
+
+        // the deprecated key still adds to the list
+        let mut deprecated = setting(serde_json::json!(["https://a", "https://b"]));
+        deprecated.fallback_instances = vec!["https://b".to_string(), "https://c".to_string()];
+        assert_eq!(urls(deprecated), ["https://a", "https://b", "https://c"]);
+        let mut deprecated = setting(serde_json::json!([]));
+        deprecated.fallback_instances = vec!["https://c".to_string()];
+        assert_eq!(urls(deprecated), ["https://c"]);
+
+        let mut keyed = setting(serde_json::json!("https://a"));
+        keyed.api_key = Some(" ".to_string());
+        let scraper = YouTubeScraper::try_from_setting(keyed.clone())
+            .unwrap()
+            .unwrap();
+        assert!(scraper.api.is_none());
+        keyed.api_key = Some("AIza".to_string());
+        let scraper = YouTubeScraper::try_from_setting(keyed).unwrap().unwrap();
+        assert!(scraper.api.is_some());
+    }
+
+    #[test]
+    fn test_exclude() {
+        let scraper = YouTubeScraper {
+            exclude_shorts: true,
+            ..Default::default()
+        };
+        let video = |length, live| {
+            invidious::hidden::SearchItem::Video(invidious::CommonVideo {
+                length,
+                live,
+                ..Default::default()
+            })
+        };
+        let filter = SearchFilter::default();
+        assert!(scraper.keep(&video(261, false), &filter));
+        assert!(!scraper.keep(&video(30, false), &filter));
+        assert!(scraper.keep(&video(0, true), &filter));
+
+        let filter = SearchFilter {
+            exclude_live: true,
+            ..Default::default()
+        };
+        assert!(!scraper.keep(&video(0, true), &filter));
+        assert!(scraper.keep(&video(261, false), &filter));
+    }
+
+    #[test]
+    fn test_duration_bucket() {
+        let filter = |min_duration, max_duration| SearchFilter {
+            min_duration,
+            max_duration,
+            ..Default::default()
+        };
+        assert_eq!(duration_bucket(&filter(None, Some(60))), Some("short"));
+        assert_eq!(
+            duration_bucket(&filter(Some(300), Some(600))),
+            Some("medium")
+        );
+        assert_eq!(duration_bucket(&filter(Some(3600), None)), Some("long"));
+        // spans several buckets
+        assert_eq!(duration_bucket(&filter(Some(60), Some(600))), None);
+        assert_eq!(duration_bucket(&filter(None, None)), None);
+    }
+
     #[tokio::test]
     async fn test_suggest() {
         let scraper = YouTubeScraper::default();
@@ -221,8 +716,10 @@ mod test {
     async fn test_search() {
         let scraper = YouTubeScraper::default();
         scraper
-            .search("早稻叽".into(), ScrapeType::All)
+            .search("早稻叽".into(), ScrapeType::All, None)
             .await
+            .unwrap()
+            .items
             .into_iter()
             .for_each(|i| println!("Search Item: {:?}", i));
     }
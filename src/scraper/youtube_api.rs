@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::util::{self, body::LimitedBody};
+
+use super::{
+    query::SearchFilter,
+    youtube::{duration_bucket, SHORTS_MAX_LENGTH},
+    Artist, ScrapeItem, ScrapeType, SearchPage, Song, SongCollection,
+};
+
+const ENDPOINT: &str = "https://www.googleapis.com/youtube/v3";
+/// Largest page the Data API returns
+const MAX_RESULTS: usize = 50;
+/// Pages of a playlist fetched at most, 1000 songs. Every page costs a unit of the quota.
+const MAX_PLAYLIST_PAGES: usize = 20;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ApiList<T> {
+    #[serde(default = "Vec::new")]
+    items: Vec<T>,
+    next_page_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorBody {
+    error: ApiError,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiError {
+    code: u16,
+    message: String,
+    #[serde(default)]
+    errors: Vec<ApiErrorReason>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApiErrorReason {
+    #[serde(default)]
+    reason: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Thumbnail {
+    url: String,
+    #[serde(default)]
+    width: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Snippet {
+    #[serde(default)]
+    title: String,
+    #[serde(default)]
+    description: String,
+    #[serde(default)]
+    channel_id: String,
+    #[serde(default)]
+    channel_title: String,
+    /// the uploader of a playlist item, absent for deleted and private videos
+    video_owner_channel_id: Option<String>,
+    #[serde(default)]
+    video_owner_channel_title: String,
+    #[serde(default)]
+    thumbnails: HashMap<String, Thumbnail>,
+    /// `none`, `live` or `upcoming`
+    #[serde(default)]
+    live_broadcast_content: String,
+}
+
+impl Snippet {
+    fn cover(&mut self) -> Option<String> {
+        self.thumbnails
+            .drain()
+            .map(|(_, t)| t)
+            .max_by_key(|t| t.width)
+            .map(|t| t.url)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SearchResultId {
+    kind: String,
+    video_id: Option<String>,
+    channel_id: Option<String>,
+    playlist_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResult {
+    id: SearchResultId,
+    #[serde(default)]
+    snippet: Snippet,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ContentDetails {
+    /// ISO 8601 duration like `PT4M13S`
+    #[serde(default)]
+    duration: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Video {
+    id: String,
+    content_details: ContentDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct Playlist {
+    id: String,
+    #[serde(default)]
+    etag: String,
+    #[serde(default)]
+    snippet: Snippet,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistItemDetails {
+    video_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PlaylistItem {
+    #[serde(default)]
+    snippet: Snippet,
+    content_details: PlaylistItemDetails,
+}
+
+/// Seconds of an ISO 8601 duration like `PT1H2M3S`, None for `P0D` of live broadcasts or
+/// anything else unexpected
+fn parse_duration(duration: &str) -> Option<u32> {
+    let mut seconds = 0;
+    let mut number = 0;
+    let mut in_time = false;
+    for c in duration.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number = number * 10 + c.to_digit(10)?,
+            'T' => in_time = true,
+            'D' if !in_time => seconds += number * 86400,
+            'H' if in_time => seconds += number * 3600,
+            'M' if in_time => seconds += number * 60,
+            'S' if in_time => seconds += number,
+            _ => return None,
+        }
+        if !c.is_ascii_digit() {
+            number = 0;
+        }
+    }
+    (seconds > 0).then_some(seconds)
+}
+
+fn artists(id: String, name: String) -> Vec<Artist> {
+    vec![Artist {
+        id: id.into(),
+        name: util::text::clean(&name),
+        description: None,
+        avatar: None,
+    }]
+}
+
+/// Metadata of YouTube from the official Data API v3 with an api key, which is steadier than the
+/// invidious instances. Every call costs quota of the key: a search 100 units, a list 1 unit.
+pub struct DataApi {
+    endpoint: String,
+    key: String,
+    client: reqwest::Client,
+}
+
+impl DataApi {
+    pub fn new(key: String) -> Self {
+        Self {
+            endpoint: ENDPOINT.to_string(),
+            key,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        resource: &str,
+        query: &[(&str, &str)],
+    ) -> anyhow::Result<T> {
+        let resp = self
+            .client
+            .get(format!("{}/{}", self.endpoint, resource))
+            .query(query)
+            .query(&[("key", self.key.as_str())])
+            .send()
+            .await?;
+        if resp.status().is_success() {
+            return resp.limited_json().await;
+        }
+        let status = resp.status();
+        match resp.limited_json::<ApiErrorBody>().await {
+            Ok(ApiErrorBody { error }) => {
+                let reason = error.errors.first().map(|e| e.reason.as_str());
+                bail!(
+                    "youtube data api {} ({}): {}",
+                    error.code,
+                    reason.unwrap_or("unknown"),
+                    error.message
+                )
+            }
+            Err(_) => bail!("youtube data api {}", status),
+        }
+    }
+
+    /// Durations of the videos, by id. Search results and playlist items lack them.
+    async fn durations(&self, ids: &[String]) -> anyhow::Result<HashMap<String, u32>> {
+        let mut durations = HashMap::new();
+        for ids in ids.chunks(MAX_RESULTS) {
+            let videos: ApiList<Video> = self
+                .get(
+                    "videos",
+                    &[("part", "contentDetails"), ("id", &ids.join(","))],
+                )
+                .await?;
+            durations.extend(
+                videos
+                    .items
+                    .into_iter()
+                    .filter_map(|v| parse_duration(&v.content_details.duration).map(|d| (v.id, d))),
+            );
+        }
+        Ok(durations)
+    }
+
+    /// A page of the search. The continuation is the page token of the Data API.
+    pub async fn search(
+        &self,
+        keyword: &str,
+        t: &ScrapeType,
+        page_token: Option<&str>,
+        filter: &SearchFilter,
+        exclude_shorts: bool,
+        exclude_live: bool,
+    ) -> anyhow::Result<SearchPage> {
+        let kind = match t {
+            ScrapeType::Album | ScrapeType::Radio => return Ok(SearchPage::default()),
+            ScrapeType::All => "video,channel,playlist",
+            ScrapeType::Song => "video",
+            ScrapeType::Artist => "channel",
+            ScrapeType::Playlist => "playlist",
+        };
+        let max_results = filter.limit.unwrap_or(20).min(MAX_RESULTS).to_string();
+        let mut query = vec![
+            ("part", "snippet"),
+            ("q", keyword),
+            ("type", kind),
+            ("maxResults", max_results.as_str()),
+        ];
+        if let Some(page_token) = page_token {
+            query.push(("pageToken", page_token));
+        }
+        // the duration only applies to videos
+        if let (ScrapeType::Song, Some(duration)) = (t, duration_bucket(filter)) {
+            query.push(("videoDuration", duration));
+        }
+        let results: ApiList<SearchResult> = self.get("search", &query).await?;
+
+        let video_ids = results
+            .items
+            .iter()
+            .filter_map(|r| r.id.video_id.clone())
+            .collect::<Vec<_>>();
+        let durations = match video_ids.is_empty() {
+            true => HashMap::new(),
+            false => self.durations(&video_ids).await?,
+        };
+
+        let items = results
+            .items
+            .into_iter()
+            .filter_map(|mut r| {
+                let cover = r.snippet.cover();
+                let name = util::text::clean(&r.snippet.title);
+                match r.id.kind.as_str() {
+                    "youtube#video" => {
+                        let id = r.id.video_id?;
+                        let live = r.snippet.live_broadcast_content != "none";
+                        let duration = durations.get(&id).copied();
+                        if (exclude_live && live)
+                            || (exclude_shorts && duration.is_some_and(|d| d <= SHORTS_MAX_LENGTH))
+                        {
+                            return None;
+                        }
+                        Some(ScrapeItem::Song(Song {
+                            cover: cover.or_else(|| {
+                                Some(format!("https://i.ytimg.com/vi/{}/hqdefault.jpg", id))
+                            }),
+                            id: id.into(),
+                            name,
+                            artists: artists(r.snippet.channel_id, r.snippet.channel_title),
+                            duration,
+                            unavailable: false,
+                            unavailable_reason: None,
+                            alternatives: vec![],
+                            saved: false,
+                            clip: None,
+                        }))
+                    }
+                    "youtube#playlist" => Some(ScrapeItem::Playlist(SongCollection {
+                        id: r.id.playlist_id?.into(),
+                        name,
+                        artists: artists(r.snippet.channel_id, r.snippet.channel_title),
+                        cover,
+                        description: None,
+                        songs: vec![],
+                        version: None,
+                        unavailable: false,
+                        saved: false,
+                        stale: false,
+                    })),
+                    "youtube#channel" => Some(ScrapeItem::Artist(Artist {
+                        id: r.id.channel_id?.into(),
+                        name,
+                        description: Some(util::text::clean(&r.snippet.description)),
+                        avatar: cover,
+                    })),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        Ok(SearchPage {
+            next: results.next_page_token,
+            items,
+        })
+    }
+
+    /// The playlist with its songs, up to `MAX_PLAYLIST_PAGES` pages of them
+    pub async fn playlist(&self, id: &str) -> anyhow::Result<SongCollection> {
+        let playlists: ApiList<Playlist> = self
+            .get("playlists", &[("part", "snippet"), ("id", id)])
+            .await?;
+        let mut playlist = playlists
+            .items
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("playlist not found: {}", id))?;
+
+        let mut items: Vec<PlaylistItem> = vec![];
+        let mut page_token = None;
+        for _ in 0..MAX_PLAYLIST_PAGES {
+            let max_results = MAX_RESULTS.to_string();
+            let mut query = vec![
+                ("part", "snippet,contentDetails"),
+                ("playlistId", id),
+                ("maxResults", max_results.as_str()),
+            ];
+            if let Some(page_token) = page_token.as_deref() {
+                query.push(("pageToken", page_token));
+            }
+            let page: ApiList<PlaylistItem> = self.get("playlistItems", &query).await?;
+            items.extend(page.items);
+            page_token = page.next_page_token;
+            if page_token.is_none() {
+                break;
+            }
+        }
+
+        let video_ids = items
+            .iter()
+            .map(|i| i.content_details.video_id.clone())
+            .collect::<Vec<_>>();
+        let durations = self.durations(&video_ids).await?;
+        let songs = items
+            .into_iter()
+            .map(|mut i| {
+                let id = i.content_details.video_id;
+                // deleted and private videos have no uploader
+                let unavailable = i.snippet.video_owner_channel_id.is_none();
+                Song {
+                    cover: i.snippet.cover(),
+                    duration: durations.get(&id).copied(),
+                    id: id.into(),
+                    name: util::text::clean(&i.snippet.title),
+                    artists: artists(
+                        i.snippet.video_owner_channel_id.unwrap_or_default(),
+                        i.snippet.video_owner_channel_title,
+                    ),
+                    unavailable,
+                    unavailable_reason: None,
+                    alternatives: vec![],
+                    saved: false,
+                    clip: None,
+                }
+            })
+            .collect();
+
+        Ok(SongCollection {
+            cover: playlist.snippet.cover(),
+            id: playlist.id.into(),
+            name: util::text::clean(&playlist.snippet.title),
+            artists: artists(playlist.snippet.channel_id, playlist.snippet.channel_title),
+            description: Some(util::text::clean(&playlist.snippet.description)),
+            songs,
+            version: Some(playlist.etag).filter(|e| !e.is_empty()),
+            unavailable: false,
+            saved: false,
+            stale: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_duration, ApiErrorBody, ApiList, PlaylistItem, SearchResult};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("PT4M13S"), Some(253));
+        assert_eq!(parse_duration("PT1H2M3S"), Some(3723));
+        assert_eq!(parse_duration("PT45S"), Some(45));
+        assert_eq!(parse_duration("P1DT1S"), Some(86401));
+        // live broadcasts
+        assert_eq!(parse_duration("P0D"), None);
+        assert_eq!(parse_duration("4:13"), None);
+    }
+
+    #[test]
+    fn test_parse() {
+        let results: ApiList<SearchResult> = serde_json::from_str(
+            r#"{
+                "kind": "youtube#searchListResponse",
+                "nextPageToken": "CBQQAA",
+                "items": [{
+                    "kind": "youtube#searchResult",
+                    "id": {"kind": "youtube#video", "videoId": "K_x2r8vJxZ4"},
+                    "snippet": {
+                        "channelId": "UCvpredjG93ifbCP1Y77JyFA",
+                        "title": "Tom &amp; Jerry",
+                        "thumbnails": {
+                            "default": {"url": "https://i.ytimg.com/vi/K_x2r8vJxZ4/default.jpg", "width": 120},
+                            "high": {"url": "https://i.ytimg.com/vi/K_x2r8vJxZ4/hqdefault.jpg", "width": 480}
+                        },
+                        "channelTitle": "YOASOBI",
+                        "liveBroadcastContent": "none"
+                    }
+                }, {
+                    "kind": "youtube#searchResult",
+                    "id": {"kind": "youtube#channel", "channelId": "UCvpredjG93ifbCP1Y77JyFA"},
+                    "snippet": {"title": "YOASOBI", "channelTitle": "YOASOBI"}
+                }]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(results.next_page_token.as_deref(), Some("CBQQAA"));
+        let mut video = results.items.into_iter().next().unwrap();
+        assert_eq!(video.id.video_id.as_deref(), Some("K_x2r8vJxZ4"));
+        assert_eq!(
+            video.snippet.cover().as_deref(),
+            Some("https://i.ytimg.com/vi/K_x2r8vJxZ4/hqdefault.jpg")
+        );
+
+        let items: ApiList<PlaylistItem> = serde_json::from_str(
+            r#"{"items": [{
+                "snippet": {"title": "Deleted video", "thumbnails": {}},
+                "contentDetails": {"videoId": "jNQXAC9IVRw"}
+            }]}"#,
+        )
+        .unwrap();
+        assert!(items.next_page_token.is_none());
+        assert!(items.items[0].snippet.video_owner_channel_id.is_none());
+
+        let error: ApiErrorBody = serde_json::from_str(
+            r#"{"error": {"code": 403, "message": "The request cannot be completed because you have exceeded your quota.",
+                "errors": [{"message": "...", "domain": "youtube.quota", "reason": "quotaExceeded"}]}}"#,
+        )
+        .unwrap();
+        assert_eq!(error.error.code, 403);
+        assert_eq!(error.error.errors[0].reason, "quotaExceeded");
+    }
+}
@@ -0,0 +1,206 @@
+use std::{process::Stdio, time::Duration};
+
+use anyhow::{anyhow, bail};
+use reqwest::header::RANGE;
+use serde::Deserialize;
+use tokio::{io::AsyncReadExt, process::Command};
+use tracing::info;
+
+use super::Stream;
+
+/// yt-dlp resolves the player of YouTube itself, which takes a few seconds
+const EXTRACT_TIMEOUT: Duration = Duration::from_secs(30);
+const VALIDATE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Bytes of the json output read at most, a video with all its formats is a few hundred KiB
+const MAX_OUTPUT: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+struct YtDlpVideo {
+    #[serde(default)]
+    formats: Vec<YtDlpFormat>,
+}
+
+#[derive(Debug, Deserialize)]
+struct YtDlpFormat {
+    url: Option<String>,
+    /// `none` for formats without audio
+    #[serde(default)]
+    acodec: Option<String>,
+    /// `none` for audio only formats
+    #[serde(default)]
+    vcodec: Option<String>,
+    /// average bitrate of the audio in kbps
+    abr: Option<f64>,
+    /// like `low` or `medium`
+    format_note: Option<String>,
+    /// protocol of the url, streams of `https` only are played by url
+    protocol: Option<String>,
+}
+
+impl YtDlpFormat {
+    fn audio_only(&self) -> bool {
+        let none = |codec: &Option<String>| codec.as_deref().is_none_or(|c| c == "none");
+        !none(&self.acodec) && none(&self.vcodec)
+    }
+}
+
+/// Audio only formats of the output, in the order yt-dlp ranks them, worst first
+fn parse(output: &[u8]) -> anyhow::Result<Vec<Stream>> {
+    let video: YtDlpVideo = serde_json::from_slice(output)?;
+    Ok(video
+        .formats
+        .into_iter()
+        .filter(|f| f.audio_only() && f.protocol.as_deref().is_none_or(|p| p == "https"))
+        .filter_map(|f| {
+            let bitrate = f.abr.map(|abr| (abr * 1000.0) as u64);
+            Some(Stream {
+                quality: format!(
+                    "{}({})",
+                    f.format_note.as_deref().unwrap_or("unknown"),
+                    bitrate.unwrap_or_default()
+                ),
+                url: f.url?,
+                bitrate,
+                codec: f.acodec,
+                mirror: false,
+                loudness: None,
+                preview: false,
+            })
+        })
+        .collect())
+}
+
+/// Extracts the streams of videos by running yt-dlp, for when the invidious instances hand out
+/// throttled or broken urls
+pub struct YtDlp {
+    program: String,
+    client: reqwest::Client,
+}
+
+impl YtDlp {
+    pub fn new(program: String) -> Self {
+        info!("[YouTube] fall back to {} for streams", program);
+        Self {
+            program,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Whether the url serves its first byte. Broken urls of the instances are refused with 403.
+    pub async fn validate(&self, url: &str) -> anyhow::Result<()> {
+        let resp = self
+            .client
+            .get(url)
+            .header(RANGE, "bytes=0-0")
+            .timeout(VALIDATE_TIMEOUT)
+            .send()
+            .await?;
+        match resp.status().is_success() {
+            true => Ok(()),
+            false => bail!("stream url refused with {}", resp.status()),
+        }
+    }
+
+    /// Audio streams of the video. yt-dlp is killed if it takes longer than `EXTRACT_TIMEOUT`.
+    pub async fn streams(&self, id: &str) -> anyhow::Result<Vec<Stream>> {
+        let mut child = Command::new(&self.program)
+            .args(["-J", "--no-warnings", "--no-playlist", "--"])
+            .arg(format!("https://www.youtube.com/watch?v={}", id))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let Some(stdout) = child.stdout.take() else {
+            bail!("stdout of yt-dlp not available");
+        };
+
+        let extract = async {
+            let mut output = vec![];
+            stdout.take(MAX_OUTPUT).read_to_end(&mut output).await?;
+            let status = child.wait().await?;
+            match status.success() {
+                true => parse(&output),
+                false => bail!("yt-dlp failed with {}", status),
+            }
+        };
+        tokio::time::timeout(EXTRACT_TIMEOUT, extract)
+            .await
+            .map_err(|_| anyhow!("yt-dlp timed out after {}s", EXTRACT_TIMEOUT.as_secs()))?
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::os::unix::fs::PermissionsExt;
+
+    use actix_web::{web, App, HttpResponse, HttpServer};
+
+    use super::{parse, YtDlp};
+
+    const OUTPUT: &str = r#"{"id": "K_x2r8vJxZ4", "formats": [
+        {"format_id": "sb0", "url": "https://i.ytimg.com/sb/1.jpg", "acodec": "none", "vcodec": "none", "protocol": "mhtml"},
+        {"format_id": "249", "url": "https://rr1.googlevideo.com/249", "acodec": "opus", "vcodec": "none",
+         "abr": 50.2, "format_note": "low", "protocol": "https"},
+        {"format_id": "140", "url": "https://rr1.googlevideo.com/140", "acodec": "mp4a.40.2", "vcodec": "none",
+         "abr": 129.5, "format_note": "medium", "protocol": "https"},
+        {"format_id": "233", "url": "https://manifest.googlevideo.com/233.m3u8", "acodec": "unknown", "vcodec": "none",
+         "protocol": "m3u8_native"},
+        {"format_id": "18", "url": "https://rr1.googlevideo.com/18", "acodec": "mp4a.40.2", "vcodec": "avc1.42001E",
+         "protocol": "https"}
+    ]}"#;
+
+    #[test]
+    fn test_parse() {
+        let streams = parse(OUTPUT.as_bytes()).unwrap();
+        assert_eq!(streams.len(), 2);
+        assert_eq!(streams[0].quality, "low(50200)");
+        assert_eq!(streams[1].quality, "medium(129500)");
+        assert_eq!(streams[1].codec.as_deref(), Some("mp4a.40.2"));
+        assert_eq!(streams[1].bitrate, Some(129500));
+    }
+
+    #[tokio::test]
+    async fn test_streams() {
+        // stands in for yt-dlp, printing the json of the video
+        let program = std::env::temp_dir().join(format!("bragi-ytdlp-{}", std::process::id()));
+        std::fs::write(
+            &program,
+            format!("#!/bin/sh\ncat <<'EOF'\n{}\nEOF\n", OUTPUT),
+        )
+        .unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let ytdlp = YtDlp::new(program.to_string_lossy().to_string());
+        assert_eq!(ytdlp.streams("K_x2r8vJxZ4").await.unwrap().len(), 2);
+
+        std::fs::write(&program, "#!/bin/sh\nexit 1\n").unwrap();
+        assert!(ytdlp.streams("K_x2r8vJxZ4").await.is_err());
+        std::fs::remove_file(program).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_validate() {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route(
+                    "/ok",
+                    web::get().to(|| async { HttpResponse::PartialContent().body("0") }),
+                )
+                .route(
+                    "/throttled",
+                    web::get().to(|| async { HttpResponse::Forbidden().finish() }),
+                )
+        })
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let ytdlp = YtDlp::new("yt-dlp".into());
+        assert!(ytdlp.validate(&format!("http://{}/ok", addr)).await.is_ok());
+        assert!(ytdlp
+            .validate(&format!("http://{}/throttled", addr))
+            .await
+            .is_err());
+    }
+}
@@ -1,15 +1,574 @@
-use std::collections::HashSet;
+use std::{
+    collections::{BTreeMap, HashSet},
+    net::SocketAddr,
+};
 
 use anyhow::bail;
 use config::{Config, Environment, File};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use crate::scraper::{Provider, ScrapeType};
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct ApplicationSettings {
+    #[serde(default = "default_host")]
     pub host: String,
+    #[serde(default = "default_port")]
     pub port: u16,
+    /// socket addresses to listen on, like `["0.0.0.0:6000", "[::]:6000"]`. Takes priority over
+    /// `host` and `port`. IPv6 addresses only accept IPv6 so that both families can be listed.
+    #[serde(default)]
+    pub listen: Vec<SocketAddr>,
+    /// address family of outbound connections to providers. Both families are tried (happy
+    /// eyeballs) if absent. The invidious client of YouTube always uses both.
+    pub outbound_family: Option<IpFamily>,
+
+    /// bearer tokens of the api, open to anyone if empty
+    pub tokens: Tokens,
+    /// paths under `/api/v1` readable without a token, like `/health`. A trailing `*` covers the
+    /// paths below as well.
+    #[serde(default)]
+    pub auth_exempt: Vec<String>,
+    /// seconds the stream urls handed out stay valid without a token, for players which cannot
+    /// send one. Signed with a key of the process, so a restart invalidates them too
+    #[serde(default = "default_stream_url_ttl")]
+    pub stream_url_ttl: u64,
+
+    /// total number of in-flight upstream calls of all providers. Fixed concurrencies of providers
+    /// are taken out first, the rest is shared by the others according to their weights
+    pub max_concurrency: Option<usize>,
+
+    /// json file remembering ids deleted or blocked upstream. Kept in memory only if absent
+    pub unavailable_path: Option<String>,
+
+    /// json file of songs and collections saved to the library. Kept in memory only if absent
+    pub favorites_path: Option<String>,
+
+    /// json file of artist name aliases, extended by learned matches. Kept in memory only if
+    /// absent
+    pub aliases_path: Option<String>,
+    /// MusicBrainz artist json dump, one artist per line, seeding the aliases on startup
+    pub musicbrainz_aliases: Option<String>,
+
+    /// json lines file of admin operations served at `/api/v1/admin/audit`. The latest ones are
+    /// kept in memory only if absent
+    pub audit_path: Option<String>,
+
+    /// json file of the devices registered at `/api/v1/devices`. Kept in memory only if absent
+    pub devices_path: Option<String>,
+
+    /// directory of the offline bundles built at `/api/v1/library/bundles`. A directory in the
+    /// system temporary directory if absent
+    pub bundle_dir: Option<String>,
+    /// bytes per second each token downloads bundle archives and local files at, shared by its
+    /// concurrent downloads. Unlimited if absent
+    pub download_rate: Option<u64>,
+
+    /// hash search keywords and ids in logs and analytics, leave client addresses and query
+    /// strings out of the access log and keep the ids requested upstream in memory only
+    #[serde(default)]
+    pub privacy_mode: bool,
+
+    /// json file of the followed artists and the feed of their releases. Kept in memory only if
+    /// absent
+    pub follows_path: Option<String>,
+
+    /// naming of the fields of json responses. Fields of requests are accepted in either case
+    #[serde(default)]
+    pub json_case: JsonCase,
+
+    /// label qualities and providers of json responses and translate error messages to the
+    /// language of `Accept-Language`: chinese or japanese. English and streamed responses are
+    /// left as they are
+    #[serde(default)]
+    pub localize: bool,
+}
+
+fn default_host() -> String {
+    "0.0.0.0".into()
+}
+
+fn default_port() -> u16 {
+    6000
+}
+
+fn default_stream_url_ttl() -> u64 {
+    4 * 60 * 60
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    Ipv4,
+    Ipv6,
+}
+
+/// Naming convention of the fields of json responses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JsonCase {
+    /// like `max_bitrate`, the names of the fields as declared
+    #[default]
+    Snake,
+    /// like `maxBitrate`, for clients generated from a camelCase schema
+    Camel,
+}
+
+/// Access levels of api tokens, each including the ones below
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// searching, resolving streams and reading the library
+    Readonly,
+    /// changing the library, creating rooms and recognizing clips
+    User,
+    /// the admin endpoints
+    Admin,
+}
+
+/// Api tokens, either by role like `{ admin = [...], user = [...] }` or a plain list, whose
+/// tokens are all admins
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Tokens {
+    Admin(HashSet<String>),
+    Roles(BTreeMap<Role, HashSet<String>>),
+}
+
+impl Tokens {
+    pub fn is_empty(&self) -> bool {
+        match self {
+            Tokens::Admin(tokens) => tokens.is_empty(),
+            Tokens::Roles(roles) => roles.values().all(HashSet::is_empty),
+        }
+    }
+
+    /// The highest role of the token, None if unknown
+    pub fn role(&self, token: &str) -> Option<Role> {
+        match self {
+            Tokens::Admin(tokens) => tokens.contains(token).then_some(Role::Admin),
+            Tokens::Roles(roles) => roles
+                .iter()
+                .rev()
+                .find(|(_, tokens)| tokens.contains(token))
+                .map(|(role, _)| *role),
+        }
+    }
+}
+
+/// Per-provider fan-out budget. If `concurrency` is present, it takes priority over the weighted
+/// share of `application.max_concurrency` and counts against its total.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BudgetSettings {
+    #[serde(default = "default_weight")]
+    pub weight: usize,
+    pub concurrency: Option<usize>,
+}
+
+fn default_weight() -> usize {
+    1
+}
+
+impl Default for BudgetSettings {
+    fn default() -> Self {
+        Self {
+            weight: default_weight(),
+            concurrency: None,
+        }
+    }
+}
+
+/// Keyword variants searched in addition to the original keyword
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeywordVariant {
+    /// fullwidth ASCII characters to halfwidth
+    Halfwidth,
+    /// traditional chinese to simplified chinese. Requires `keyword.t2s_dict`
+    Simplified,
+    /// simplified chinese to traditional chinese. Requires `keyword.s2t_dict`
+    Traditional,
+    /// hiragana and katakana to hepburn romaji
+    Romaji,
+}
+
+/// OpenCC character dictionaries (STCharacters.txt / TSCharacters.txt) used for chinese variants
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct KeywordSettings {
+    pub s2t_dict: Option<String>,
+    pub t2s_dict: Option<String>,
+    /// retry searches which found nothing with relaxed keywords, marked as `relaxed_match`
+    #[serde(default)]
+    pub relax_zero_result: bool,
+}
+
+/// Serve the last known results of a provider, marked as stale, when it fails
+#[derive(Debug, Clone, Deserialize)]
+pub struct StaleSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// max cached entries of each kind: search pages and collections. Streams are not kept since
+    /// their urls expire
+    #[serde(default = "default_stale_capacity")]
+    pub capacity: usize,
+}
+
+fn default_stale_capacity() -> usize {
+    1024
+}
+
+impl Default for StaleSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: default_stale_capacity(),
+        }
+    }
+}
+
+/// Per-provider timeouts adapting to the recent latency of the provider: p95 times `multiplier`,
+/// bounded by `min_ms` and `max_ms`. Providers without enough samples yet get `max_ms`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TimeoutSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_timeout_multiplier")]
+    pub multiplier: f64,
+    #[serde(default = "default_timeout_min_ms")]
+    pub min_ms: u64,
+    #[serde(default = "default_timeout_max_ms")]
+    pub max_ms: u64,
+    /// number of recent calls of each provider the percentiles are computed from
+    #[serde(default = "default_timeout_window")]
+    pub window: usize,
+}
+
+fn default_timeout_multiplier() -> f64 {
+    2.0
+}
+
+fn default_timeout_min_ms() -> u64 {
+    1000
+}
+
+fn default_timeout_max_ms() -> u64 {
+    10000
+}
+
+fn default_timeout_window() -> usize {
+    100
+}
+
+impl TimeoutSettings {
+    /// Bounds the timeout can be clamped into
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.min_ms > self.max_ms {
+            bail!(
+                "timeout: min_ms {} is above max_ms {}",
+                self.min_ms,
+                self.max_ms
+            );
+        }
+        if !self.multiplier.is_finite() || self.multiplier < 0.0 {
+            bail!(
+                "timeout: multiplier {} is not a positive number",
+                self.multiplier
+            );
+        }
+        Ok(())
+    }
+}
+
+impl Default for TimeoutSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            multiplier: default_timeout_multiplier(),
+            min_ms: default_timeout_min_ms(),
+            max_ms: default_timeout_max_ms(),
+            window: default_timeout_window(),
+        }
+    }
+}
+
+/// Aggregated search counters served at `/api/v1/admin/analytics`. Only keywords and counts are
+/// kept, nothing about the clients.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AnalyticsSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// keywords listed in each top list
+    #[serde(default = "default_analytics_top")]
+    pub top: usize,
+    /// keywords searched fewer times are left out of the report, so that rare and possibly
+    /// personal queries are not exposed
+    #[serde(default = "default_analytics_min_count")]
+    pub min_count: u64,
+    /// distinct keywords counted, bounding the memory. Further keywords only count as searches.
+    #[serde(default = "default_analytics_max_keywords")]
+    pub max_keywords: usize,
+}
+
+fn default_analytics_top() -> usize {
+    20
+}
+
+fn default_analytics_min_count() -> u64 {
+    3
+}
+
+fn default_analytics_max_keywords() -> usize {
+    10000
+}
+
+impl Default for AnalyticsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top: default_analytics_top(),
+            min_count: default_analytics_min_count(),
+            max_keywords: default_analytics_max_keywords(),
+        }
+    }
+}
+
+/// Daily call quotas of the providers, e.g. the politeness budget of a public Invidious instance.
+/// Calls beyond the quota fail until midnight UTC.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QuotaSettings {
+    /// calls per day by provider. Providers absent are not limited
+    #[serde(default)]
+    pub daily: BTreeMap<Provider, u64>,
+    /// share of each quota kept for the clients: background work like release checks pauses
+    /// once no more than this is left
+    #[serde(default)]
+    pub reserve_percent: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LyricsSettings {
+    /// lrclib compatible api, like `https://lrclib.net`, looked up by title, artist and duration
+    /// for songs without lyrics of their provider. No fallback if absent
+    pub lrclib: Option<String>,
+}
+
+/// Converting streams with ffmpeg for clients which cannot play the original format
+#[derive(Debug, Clone, Deserialize)]
+pub struct TranscodeSettings {
+    /// ffmpeg executable, like `ffmpeg` on the PATH. Transcoding is disabled if absent
+    pub ffmpeg: Option<String>,
+    /// ffmpeg processes running at once, further conversions wait for one to finish
+    #[serde(default = "default_transcode_concurrency")]
+    pub max_concurrency: usize,
+    /// kbps of the lossy formats
+    #[serde(default = "default_transcode_bitrate")]
+    pub bitrate: u32,
+}
+
+fn default_transcode_concurrency() -> usize {
+    2
+}
 
-    pub tokens: HashSet<String>,
+fn default_transcode_bitrate() -> u32 {
+    192
+}
+
+impl Default for TranscodeSettings {
+    fn default() -> Self {
+        Self {
+            ffmpeg: None,
+            max_concurrency: default_transcode_concurrency(),
+            bitrate: default_transcode_bitrate(),
+        }
+    }
+}
+
+/// Audio of the stream proxy and offline bundles kept on disk, so that songs played again are
+/// neither fetched nor converted again. Disabled if `dir` is absent, and in privacy mode since the
+/// files tell what was played.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CacheSettings {
+    pub dir: Option<String>,
+    /// bytes on disk at most, the least recently played songs are removed beyond it
+    #[serde(default = "default_cache_size")]
+    pub max_size: u64,
+}
+
+fn default_cache_size() -> u64 {
+    1024 * 1024 * 1024
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_size: default_cache_size(),
+        }
+    }
+}
+
+/// Recognizing songs from audio clips by their Chromaprint fingerprint, looked up on AcoustID.
+/// Disabled unless both `fpcalc` and `acoustid_key` are present.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RecognizeSettings {
+    /// fpcalc executable of Chromaprint, like `fpcalc` on the PATH
+    pub fpcalc: Option<String>,
+    /// api key of an application registered at https://acoustid.org
+    pub acoustid_key: Option<String>,
+    /// AcoustID compatible lookup api
+    #[serde(default = "default_acoustid_endpoint")]
+    pub endpoint: String,
+    /// bytes of an uploaded clip
+    #[serde(default = "default_max_clip")]
+    pub max_clip: usize,
+}
+
+fn default_acoustid_endpoint() -> String {
+    "https://api.acoustid.org/v2/lookup".to_string()
+}
+
+fn default_max_clip() -> usize {
+    8 * 1024 * 1024
+}
+
+impl Default for RecognizeSettings {
+    fn default() -> Self {
+        Self {
+            fpcalc: None,
+            acoustid_key: None,
+            endpoint: default_acoustid_endpoint(),
+            max_clip: default_max_clip(),
+        }
+    }
+}
+
+/// Sizes guarding the memory of the server against pathological upstream responses and huge
+/// collections
+#[derive(Debug, Clone, Deserialize)]
+pub struct LimitSettings {
+    /// bytes of an upstream api response body, larger ones fail the request
+    #[serde(default = "default_upstream_body_limit")]
+    pub upstream_body: usize,
+    /// songs of a collection in one response, further ones are paged by `offset`. Whole
+    /// collections if absent
+    pub collection_page: Option<usize>,
+}
+
+fn default_upstream_body_limit() -> usize {
+    16 * 1024 * 1024
+}
+
+impl Default for LimitSettings {
+    fn default() -> Self {
+        Self {
+            upstream_body: default_upstream_body_limit(),
+            collection_page: None,
+        }
+    }
+}
+
+/// Checking the followed artists for new releases
+#[derive(Debug, Clone, Deserialize)]
+pub struct FollowSettings {
+    /// once a minute at most, 0 counts as 1
+    #[serde(default = "default_follow_interval")]
+    pub interval_minutes: u64,
+    /// urls posted `{"releases": [...]}` when new releases are found
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+fn default_follow_interval() -> u64 {
+    60
+}
+
+impl Default for FollowSettings {
+    fn default() -> Self {
+        Self {
+            interval_minutes: default_follow_interval(),
+            webhooks: vec![],
+        }
+    }
+}
+
+/// Order of the streams returned for a song. Criteria apply in the order listed here, the
+/// provider order is kept for ties. Streams are returned as the provider orders them by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct StreamSortSettings {
+    /// put streams of dolby audio after all the others
+    #[serde(default)]
+    pub dolby_last: bool,
+    /// put lossless streams, like flac, first
+    #[serde(default)]
+    pub prefer_lossless: bool,
+    /// codec prefixes in order of preference, like `["opus", "mp4a"]`. Unlisted codecs come last
+    #[serde(default)]
+    pub prefer_codecs: Vec<String>,
+    /// order by bitrate. Streams of unknown bitrate come last
+    pub bitrate: Option<BitrateOrder>,
+}
+
+/// Order of the providers in merged results, instead of the order they happen to be stored in.
+/// Unlisted providers follow the listed ones in name order.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct PrioritySettings {
+    /// providers first to last, like `["netease", "youtube"]`
+    #[serde(default)]
+    pub providers: Vec<Provider>,
+    /// order for the search of one type, like `song` or `artist`, instead of `providers`
+    #[serde(default)]
+    pub zones: BTreeMap<ScrapeType, Vec<Provider>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BitrateOrder {
+    Highest,
+    Smallest,
+}
+
+/// Playback capabilities of a client. Streams it cannot play are left out of its stream results
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// codec prefixes the client plays, like `["mp4a", "flac"]`. Any codec if empty
+    #[serde(default)]
+    pub codecs: Vec<String>,
+    /// bits per second
+    #[serde(alias = "maxBitrate")]
+    pub max_bitrate: Option<u64>,
+}
+
+/// Capabilities of the clients sending a matching User-Agent and no capabilities header
+#[derive(Debug, Clone, Deserialize)]
+pub struct ClientProfile {
+    /// case insensitive regex of the User-Agent
+    pub user_agent: String,
+    #[serde(flatten)]
+    pub capabilities: Capabilities,
+}
+
+/// Replace the host of returned stream urls matching `from`, where `*` matches any characters,
+/// e.g. `upos-sz-mirror*.bilivideo.com` to a preferred mirror host
+#[derive(Debug, Clone, Deserialize)]
+pub struct HostRewrite {
+    pub from: String,
+    pub to: String,
+}
+
+/// Results dropped from searches and from the songs of collections, across providers
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FilterSettings {
+    /// artist, uploader or channel ids of each provider whose results are dropped
+    #[serde(default)]
+    pub blocked_artists: BTreeMap<Provider, Vec<String>>,
+    /// artist ids of each provider whose results skip the title and duration filters
+    #[serde(default)]
+    pub allowed_artists: BTreeMap<Provider, Vec<String>>,
+    /// case insensitive regexes, a plain keyword works as well. Matching titles are dropped
+    #[serde(default)]
+    pub blocked_titles: Vec<String>,
+    /// songs shorter than this many seconds are dropped. Songs of unknown duration are kept
+    pub min_duration: Option<u32>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -17,13 +576,87 @@ pub struct NeteaseSettings {
     pub enabled: bool,
 
     pub instance: String,
+    /// call NetEase directly for search, suggestions, playlists and stream urls. The instance is
+    /// called for the other endpoints, and when a direct call fails
+    #[serde(default = "default_native")]
+    pub native: bool,
     pub cookie_path: String,
+    /// stream url host rewrites, the first matching rule applies
+    #[serde(default)]
+    pub host_rewrites: Vec<HostRewrite>,
+
+    #[serde(default)]
+    pub budget: BudgetSettings,
+    #[serde(default)]
+    pub keyword_variants: Vec<KeywordVariant>,
+    /// types searched for `All` instead of the provider default, e.g. `["song", "playlist"]`
+    #[serde(default)]
+    pub search_zones: Vec<ScrapeType>,
+}
+
+fn default_native() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct YouTubeSettings {
     pub enabled: bool,
-    pub instance: String,
+    pub instance: Instances,
+    /// deprecated, appended to `instance` with a warning
+    #[serde(default)]
+    pub fallback_instances: Vec<String>,
+    /// failures in a row after which an instance is skipped until a probe finds it working
+    #[serde(default = "default_bench_after")]
+    pub bench_after: u32,
+    /// seconds between probes of the skipped instances
+    #[serde(default = "default_probe_interval")]
+    pub probe_interval: u64,
+    /// drop Shorts, taken as videos up to a minute, from search results
+    #[serde(default)]
+    pub exclude_shorts: bool,
+    /// drop live and upcoming broadcasts from search results
+    #[serde(default)]
+    pub exclude_live: bool,
+    /// key of the YouTube Data API v3. Searches and playlists go through the official api if set,
+    /// streams still through the instances
+    pub api_key: Option<String>,
+    /// yt-dlp executable, like `yt-dlp` on the PATH, extracting the streams when the instances
+    /// fail to or hand out broken urls. No fallback if absent
+    pub ytdlp: Option<String>,
+
+    #[serde(default)]
+    pub budget: BudgetSettings,
+    #[serde(default)]
+    pub keyword_variants: Vec<KeywordVariant>,
+    /// types searched for `All` instead of the provider default, e.g. `["song", "playlist"]`
+    #[serde(default)]
+    pub search_zones: Vec<ScrapeType>,
+}
+
+/// One instance like `"https://..."`, or a list of them called in turn when the ones before fail,
+/// the fastest working one first
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Instances {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Instances {
+    pub fn into_vec(self) -> Vec<String> {
+        match self {
+            Instances::One(instance) => vec![instance],
+            Instances::Many(instances) => instances,
+        }
+    }
+}
+
+fn default_bench_after() -> u32 {
+    3
+}
+
+fn default_probe_interval() -> u64 {
+    300
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -33,15 +666,132 @@ pub struct BiliSettings {
     pub cookie_path: String,
     pub wbi_path: String,
     pub enable_dolby: bool,
+    /// rank the backup CDN urls of each quality by a quick latency probe, for hosts where the
+    /// primary upos CDN is often unreachable
+    #[serde(default)]
+    pub probe_mirrors: bool,
+    /// stream url host rewrites, the first matching rule applies
+    #[serde(default)]
+    pub host_rewrites: Vec<HostRewrite>,
+
+    #[serde(default)]
+    pub budget: BudgetSettings,
+    #[serde(default)]
+    pub keyword_variants: Vec<KeywordVariant>,
+    /// types searched for `All` instead of the provider default, e.g. `["song", "playlist"]`
+    #[serde(default)]
+    pub search_zones: Vec<ScrapeType>,
+}
+
+/// Files of a local music directory
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalSettings {
+    pub enabled: bool,
+
+    pub dir: String,
+    /// seconds between indexing the directory again, never if 0
+    #[serde(default = "default_rescan_interval")]
+    pub rescan_interval: u64,
+}
+
+fn default_rescan_interval() -> u64 {
+    3600
+}
+
+/// Internet radio stations of radio-browser.info
+#[derive(Debug, Clone, Deserialize)]
+pub struct RadioSettings {
+    pub enabled: bool,
+
+    /// any server of the radio-browser network
+    #[serde(default = "default_radio_instance")]
+    pub instance: String,
+
+    #[serde(default)]
+    pub budget: BudgetSettings,
+}
+
+fn default_radio_instance() -> String {
+    "https://de1.api.radio-browser.info".to_string()
+}
+
+/// Spotify Web API with the client credentials of an app. Catalog and 30 second previews only,
+/// full streams are for the clients of Spotify.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpotifySettings {
+    pub enabled: bool,
+    /// of an app registered at https://developer.spotify.com/dashboard
+    #[serde(default)]
+    pub client_id: String,
+    #[serde(default)]
+    pub client_secret: String,
+    /// ISO 3166-1 alpha-2 country whose catalog is served
+    #[serde(default = "default_spotify_market")]
+    pub market: String,
+
+    #[serde(default)]
+    pub budget: BudgetSettings,
+}
+
+fn default_spotify_market() -> String {
+    "US".to_string()
+}
+
+/// Dev mode: serve providers from json fixture files instead of upstream, for developing clients
+/// with reproducible data and no network access. Requires the `test-util` cargo feature.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct FixtureSettings {
+    #[serde(default)]
+    pub enabled: bool,
+    /// fixture file of each provider
+    #[serde(default)]
+    pub files: BTreeMap<Provider, String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
 
+    #[serde(default)]
+    pub keyword: KeywordSettings,
+    #[serde(default)]
+    pub stale: StaleSettings,
+    #[serde(default)]
+    pub stream_sort: StreamSortSettings,
+    #[serde(default)]
+    pub priority: PrioritySettings,
+    /// first matching profile applies
+    #[serde(default)]
+    pub client_profiles: Vec<ClientProfile>,
+    #[serde(default)]
+    pub timeout: TimeoutSettings,
+    #[serde(default)]
+    pub filter: FilterSettings,
+    #[serde(default)]
+    pub analytics: AnalyticsSettings,
+    #[serde(default)]
+    pub follow: FollowSettings,
+    #[serde(default)]
+    pub quota: QuotaSettings,
+    #[serde(default)]
+    pub lyrics: LyricsSettings,
+    #[serde(default)]
+    pub transcode: TranscodeSettings,
+    #[serde(default)]
+    pub limits: LimitSettings,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    #[serde(default)]
+    pub recognize: RecognizeSettings,
+
     pub netease: Option<NeteaseSettings>,
     pub youtube: Option<YouTubeSettings>,
     pub bilibili: Option<BiliSettings>,
+    pub local: Option<LocalSettings>,
+    pub radio: Option<RadioSettings>,
+    pub spotify: Option<SpotifySettings>,
+
+    pub fixtures: Option<FixtureSettings>,
 }
 
 impl Settings {
@@ -60,6 +810,8 @@ impl Settings {
             config_builder = config_builder.add_source(Environment::with_prefix(prefix));
         }
 
-        Ok(config_builder.build()?.try_deserialize()?)
+        let settings: Self = config_builder.build()?.try_deserialize()?;
+        settings.timeout.validate()?;
+        Ok(settings)
     }
 }
@@ -0,0 +1,74 @@
+use std::{
+    net::TcpListener,
+    os::{fd::FromRawFd, linux::net::SocketAddrExt, unix::net::UnixDatagram},
+};
+
+use tracing::{info, warn};
+
+/// First file descriptor passed by systemd socket activation
+const LISTEN_FDS_START: i32 = 3;
+
+/// Number of sockets passed to this process, following `sd_listen_fds`
+fn parse_listen_fds(listen_pid: Option<&str>, listen_fds: Option<&str>, pid: u32) -> i32 {
+    match (listen_pid.and_then(|p| p.parse::<u32>().ok()), listen_fds) {
+        (Some(listen_pid), Some(fds)) if listen_pid == pid => fds.parse().unwrap_or_default(),
+        _ => 0,
+    }
+}
+
+/// Listeners passed by systemd socket activation. Empty if the process is not socket activated.
+/// The environment is unset so that child processes don't inherit the sockets.
+pub fn listen_fds() -> Vec<TcpListener> {
+    let n = parse_listen_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    );
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    info!("socket activated listeners: {}", n);
+    (LISTEN_FDS_START..LISTEN_FDS_START + n)
+        // SAFETY: systemd passes the sockets as fds starting from 3 and nothing else owns them
+        .map(|fd| unsafe { TcpListener::from_raw_fd(fd) })
+        .collect()
+}
+
+/// Send the state to the service manager, following `sd_notify`, like: `READY=1`.
+/// Nothing is sent if the process is not managed by systemd.
+pub fn notify(state: &str) {
+    let Ok(path) = std::env::var("NOTIFY_SOCKET") else {
+        return;
+    };
+
+    let sent = UnixDatagram::unbound().and_then(|socket| {
+        match path.strip_prefix('@') {
+            // abstract socket
+            Some(name) => socket.send_to_addr(
+                state.as_bytes(),
+                &std::os::unix::net::SocketAddr::from_abstract_name(name)?,
+            ),
+            None => socket.send_to(state.as_bytes(), &path),
+        }
+    });
+    match sent {
+        Ok(_) => info!("notify systemd: {}", state),
+        Err(e) => warn!("notify systemd {} failed: {}", state, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::parse_listen_fds;
+
+    #[test]
+    fn test_parse_listen_fds() {
+        assert_eq!(parse_listen_fds(Some("42"), Some("2"), 42), 2);
+        // passed to another process
+        assert_eq!(parse_listen_fds(Some("41"), Some("2"), 42), 0);
+        assert_eq!(parse_listen_fds(None, Some("2"), 42), 0);
+        assert_eq!(parse_listen_fds(Some("42"), None, 42), 0);
+        assert_eq!(parse_listen_fds(Some("42"), Some("x"), 42), 0);
+    }
+}
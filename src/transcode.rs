@@ -0,0 +1,259 @@
+use std::{fmt::Display, process::Stdio, sync::Arc};
+
+use actix_web::web::Bytes;
+use bragi_core::{
+    scraper::{Provider, Stream},
+    settings::TranscodeSettings,
+};
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    process::Command,
+    sync::Semaphore,
+};
+use tracing::{info, warn};
+
+use crate::proxy::proxy_url;
+
+/// Formats streams are converted to, playable by about every client
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Aac,
+    Mp3,
+    Wav,
+}
+
+impl Format {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Format::Aac => "audio/aac",
+            Format::Mp3 => "audio/mpeg",
+            Format::Wav => "audio/wav",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Aac => "aac",
+            Format::Mp3 => "mp3",
+            Format::Wav => "wav",
+        }
+    }
+
+    /// Codec of the converted stream, as in `Stream::codec`
+    pub fn codec(self) -> &'static str {
+        match self {
+            Format::Aac => "mp4a.40.2",
+            Format::Mp3 => "mp3",
+            Format::Wav => "pcm",
+        }
+    }
+
+    fn lossy(self) -> bool {
+        self != Format::Wav
+    }
+
+    /// Output options of ffmpeg
+    fn args(self, bitrate: u32) -> Vec<String> {
+        let (codec, muxer) = match self {
+            Format::Aac => ("aac", "adts"),
+            Format::Mp3 => ("libmp3lame", "mp3"),
+            Format::Wav => ("pcm_s16le", "wav"),
+        };
+        let mut args = vec!["-c:a".to_string(), codec.to_string()];
+        if self.lossy() {
+            args.extend(["-b:a".to_string(), format!("{}k", bitrate)]);
+        }
+        args.extend(["-f".to_string(), muxer.to_string()]);
+        args
+    }
+}
+
+/// Converts audio by piping it through ffmpeg, so any container ffmpeg can read from a pipe, like
+/// the fragmented mp4 of DASH, ogg or flac, works as input
+#[derive(Debug)]
+pub struct Transcoder {
+    program: String,
+    bitrate: u32,
+    permits: Arc<Semaphore>,
+}
+
+impl Transcoder {
+    pub fn from_setting(setting: &TranscodeSettings) -> Option<Self> {
+        let program = setting.ffmpeg.clone()?;
+        info!("transcode with {}", program);
+        Some(Self {
+            program,
+            bitrate: setting.bitrate,
+            permits: Arc::new(Semaphore::new(setting.max_concurrency.max(1))),
+        })
+    }
+
+    /// bits per second of the converted stream, None if lossless
+    pub fn bitrate(&self, format: Format) -> Option<u64> {
+        format.lossy().then_some(u64::from(self.bitrate) * 1000)
+    }
+
+    /// Output of ffmpeg fed with `input`. ffmpeg is killed once the output is dropped, like when
+    /// the client goes away.
+    pub async fn transcode<S, E>(
+        &self,
+        input: S,
+        format: Format,
+    ) -> anyhow::Result<impl futures::Stream<Item = std::io::Result<Bytes>> + 'static>
+    where
+        S: futures::Stream<Item = Result<Bytes, E>> + 'static,
+        E: Display,
+    {
+        let permit = self.permits.clone().acquire_owned().await?;
+        let mut child = Command::new(&self.program)
+            .args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0", "-vn"])
+            .args(format.args(self.bitrate))
+            .arg("pipe:1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .spawn()?;
+        let (Some(mut stdin), Some(stdout)) = (child.stdin.take(), child.stdout.take()) else {
+            anyhow::bail!("pipes of ffmpeg not available");
+        };
+
+        actix_web::rt::spawn(async move {
+            let mut input = Box::pin(input);
+            while let Some(chunk) = input.next().await {
+                let written = match chunk {
+                    Ok(chunk) => stdin.write_all(&chunk).await,
+                    Err(e) => {
+                        warn!("read transcode input failed: {}", e);
+                        break;
+                    }
+                };
+                // ffmpeg quit, its exit status tells why
+                if written.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(futures::stream::unfold(
+            Some((stdout, child, permit)),
+            |state| async move {
+                let (mut stdout, mut child, permit) = state?;
+                let mut buf = vec![0; 64 * 1024];
+                match stdout.read(&mut buf).await {
+                    Ok(0) => match child.wait().await {
+                        Ok(status) if status.success() => None,
+                        Ok(status) => Some((
+                            Err(std::io::Error::other(format!(
+                                "ffmpeg exited with {}",
+                                status
+                            ))),
+                            None,
+                        )),
+                        Err(e) => Some((Err(e), None)),
+                    },
+                    Ok(n) => {
+                        buf.truncate(n);
+                        Some((Ok(Bytes::from(buf)), Some((stdout, child, permit))))
+                    }
+                    Err(e) => Some((Err(e), None)),
+                }
+            },
+        ))
+    }
+}
+
+/// The streams as urls of the stream proxy converting them to `format`. Mirrors are left out, the
+/// proxy picks the stream by quality.
+pub fn proxied(
+    streams: Vec<Stream>,
+    provider: &Provider,
+    id: &str,
+    format: Format,
+    bitrate: Option<u64>,
+) -> Vec<Stream> {
+    streams
+        .into_iter()
+        .filter(|s| !s.mirror)
+        .map(|s| Stream {
+            url: proxy_url(provider, id, &s.quality, Some(format)),
+            bitrate,
+            codec: Some(format.codec().to_string()),
+            ..s
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{convert::Infallible, os::unix::fs::PermissionsExt};
+
+    use actix_web::web::Bytes;
+    use bragi_core::{
+        scraper::{Provider, Stream},
+        settings::TranscodeSettings,
+    };
+    use futures::StreamExt;
+
+    use super::{proxied, Format, Transcoder};
+
+    #[actix_web::test]
+    async fn test_transcode() {
+        // stands in for ffmpeg, copying the input as is
+        let program = std::env::temp_dir().join(format!("bragi-ffmpeg-{}", std::process::id()));
+        std::fs::write(&program, "#!/bin/sh\ncat\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let transcoder = Transcoder::from_setting(&TranscodeSettings {
+            ffmpeg: Some(program.to_string_lossy().to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+
+        let input =
+            futures::stream::iter(["fLaC", "frames"].map(|c| Ok::<_, Infallible>(Bytes::from(c))));
+        let output = transcoder
+            .transcode(input, Format::Mp3)
+            .await
+            .unwrap()
+            .map(|c| c.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat();
+        assert_eq!(output, b"fLaCframes");
+        assert_eq!(transcoder.bitrate(Format::Mp3), Some(192_000));
+        assert_eq!(transcoder.bitrate(Format::Wav), None);
+        std::fs::remove_file(program).unwrap();
+    }
+
+    #[test]
+    fn test_proxied() {
+        let stream = |quality: &str, mirror| Stream {
+            quality: quality.into(),
+            url: "https://upos/1.m4s".into(),
+            bitrate: Some(3_000_000),
+            codec: Some("flac".into()),
+            mirror,
+            loudness: None,
+            preview: false,
+        };
+        let streams = proxied(
+            vec![
+                stream("Hi-Res lossless", false),
+                stream("Hi-Res lossless", true),
+            ],
+            &Provider::Bilibili,
+            "BV1xx411c7mD::1",
+            Format::Aac,
+            Some(192_000),
+        );
+        assert_eq!(streams.len(), 1);
+        assert_eq!(
+            streams[0].url,
+            "/api/v1/stream/proxy?provider=bilibili&id=BV1xx411c7mD%3A%3A1&quality=Hi-Res+lossless&format=aac"
+        );
+        assert_eq!(streams[0].codec.as_deref(), Some("mp4a.40.2"));
+    }
+}
@@ -0,0 +1,29 @@
+use actix_web::{http::header::ContentType, web, HttpResponse};
+use rust_embed::RustEmbed;
+
+/// Single page UI built into the binary. A reference client of the api and a quick way to check a
+/// deployment works.
+#[derive(RustEmbed)]
+#[folder = "web/"]
+struct Assets;
+
+async fn asset(path: web::Path<String>) -> HttpResponse {
+    let path = match path.as_str() {
+        "" => "index.html",
+        p => p,
+    };
+
+    match Assets::get(path) {
+        Some(file) => HttpResponse::Ok()
+            .content_type(file.metadata.mimetype())
+            .body(file.data.into_owned()),
+        None => HttpResponse::NotFound()
+            .content_type(ContentType::plaintext())
+            .body("not found"),
+    }
+}
+
+/// Register the UI. Must come after the api services since it matches every path
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/{path:.*}", web::get().to(asset));
+}
@@ -0,0 +1,117 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::bail;
+use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+
+static LIMIT: AtomicUsize = AtomicUsize::new(16 * 1024 * 1024);
+
+/// Limit the upstream bodies read from now on, for the whole process
+pub fn set_limit(limit: usize) {
+    LIMIT.store(limit, Ordering::Relaxed);
+}
+
+fn limit() -> usize {
+    LIMIT.load(Ordering::Relaxed)
+}
+
+/// Bodies of upstream responses read up to the limit, so that a pathological response fails the
+/// request instead of filling the memory
+#[async_trait]
+pub trait LimitedBody {
+    async fn limited_bytes(self) -> anyhow::Result<Vec<u8>>;
+
+    /// Read up to `limit` bytes instead of the process-wide limit, for bodies like audio which
+    /// are larger than api responses
+    async fn bytes_up_to(self, limit: usize) -> anyhow::Result<Vec<u8>>;
+
+    async fn limited_json<T: DeserializeOwned>(self) -> anyhow::Result<T>
+    where
+        Self: Sized,
+    {
+        Ok(serde_json::from_slice(&self.limited_bytes().await?)?)
+    }
+}
+
+#[async_trait]
+impl LimitedBody for reqwest::Response {
+    async fn limited_bytes(self) -> anyhow::Result<Vec<u8>> {
+        read(self, limit()).await
+    }
+
+    async fn bytes_up_to(self, limit: usize) -> anyhow::Result<Vec<u8>> {
+        read(self, limit).await
+    }
+}
+
+async fn read(mut resp: reqwest::Response, limit: usize) -> anyhow::Result<Vec<u8>> {
+    // the declared length fails early, the chunks catch bodies without one or lying about it
+    let declared = resp.content_length().unwrap_or(0);
+    if declared > limit as u64 {
+        bail!(
+            "upstream response of {} bytes over the limit of {}",
+            declared,
+            limit
+        );
+    }
+    let mut body = Vec::with_capacity(declared as usize);
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > limit {
+            bail!("upstream response over the limit of {} bytes", limit);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+#[cfg(test)]
+mod test {
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use futures::StreamExt;
+
+    use super::{read, LimitedBody};
+
+    #[actix_web::test]
+    async fn test_limited_body() {
+        let server = HttpServer::new(|| {
+            App::new()
+                .route(
+                    "/small",
+                    web::get().to(|| async { HttpResponse::Ok().body(r#"{"a":1}"#) }),
+                )
+                .route(
+                    "/large",
+                    web::get().to(|| async { HttpResponse::Ok().body(vec![b' '; 4096]) }),
+                )
+                // no content length
+                .route(
+                    "/chunked",
+                    web::get().to(|| async {
+                        let chunks = futures::stream::iter(0..8).map(|_| {
+                            Ok::<_, std::io::Error>(web::Bytes::from_static(&[b' '; 512]))
+                        });
+                        HttpResponse::Ok().streaming(chunks)
+                    }),
+                )
+        })
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let addr = server.addrs()[0];
+        actix_web::rt::spawn(server.run());
+
+        let get = |path: &str| reqwest::get(format!("http://{}{}", addr, path));
+
+        // under the default limit
+        let value: serde_json::Value = get("/small").await.unwrap().limited_json().await.unwrap();
+        assert_eq!(value["a"], 1);
+        assert_eq!(
+            read(get("/large").await.unwrap(), 4096)
+                .await
+                .unwrap()
+                .len(),
+            4096
+        );
+        assert!(read(get("/large").await.unwrap(), 1024).await.is_err());
+        assert!(read(get("/chunked").await.unwrap(), 1024).await.is_err());
+    }
+}
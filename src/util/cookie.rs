@@ -1,5 +1,6 @@
 use anyhow::anyhow;
 
+#[derive(Debug)]
 pub struct PersistCookieStore {
     filename: String,
     store: reqwest_cookie_store::CookieStoreRwLock,
@@ -1,6 +1,17 @@
-use tracing::info;
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Serialize};
+use tracing::{error, info};
 
+pub mod body;
+#[cfg(any(feature = "bili", feature = "netease"))]
 pub mod cookie;
+// parts of text cleaning are only used by some of the providers
+pub mod task;
+#[cfg_attr(
+    not(all(feature = "bili", feature = "netease", feature = "youtube")),
+    allow(dead_code)
+)]
+pub mod text;
 
 pub fn ensure_file(filename: &String) -> anyhow::Result<()> {
     let file_path = std::path::Path::new(filename);
@@ -16,3 +27,66 @@ pub fn ensure_file(filename: &String) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+/// The json of the file, created empty if missing, or the default of an empty one. A file that
+/// cannot be parsed is an error rather than the default, which the next save would write over.
+pub fn load_json<T: DeserializeOwned + Default>(filename: &String) -> anyhow::Result<T> {
+    ensure_file(filename)?;
+    let content = std::fs::read(filename)?;
+    if content.iter().all(u8::is_ascii_whitespace) {
+        return Ok(T::default());
+    }
+    serde_json::from_slice(&content).map_err(|e| {
+        error!("{} is corrupt and left as it is: {}", filename, e);
+        anyhow!("parse {} failed, fix or move it away: {}", filename, e)
+    })
+}
+
+/// Write the value as json to a temporary file renamed over the file, so that a crash midway
+/// leaves the old file whole
+pub fn save_json<T: Serialize + ?Sized>(filename: &str, value: &T) -> anyhow::Result<()> {
+    let temp = format!("{}.tmp", filename);
+    serde_json::to_vec(value)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| Ok(std::fs::write(&temp, content)?))
+        .and_then(|()| Ok(std::fs::rename(&temp, filename)?))
+        .inspect_err(|e| error!("save {} failed: {}", filename, e))
+}
+
+/// Change the value by `f`, which returns whether anything changed, and save it if a file is
+/// given. The change is made to a copy kept only once saved, so that a failed save leaves the
+/// value as it is on disk.
+pub fn update_json<T: Clone + Serialize>(
+    filename: Option<&str>,
+    value: &mut T,
+    f: impl FnOnce(&mut T) -> bool,
+) -> anyhow::Result<bool> {
+    let Some(filename) = filename else {
+        return Ok(f(value));
+    };
+    let mut changed = value.clone();
+    if !f(&mut changed) {
+        return Ok(false);
+    }
+    save_json(filename, &changed)?;
+    *value = changed;
+    Ok(true)
+}
+
+/// Outbound http client builder bound to the preferred address family, if any
+#[cfg(any(
+    feature = "bili",
+    feature = "netease",
+    feature = "radio",
+    feature = "spotify"
+))]
+pub fn client_builder(family: Option<crate::settings::IpFamily>) -> reqwest::ClientBuilder {
+    use crate::settings::IpFamily;
+    use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+    let local_address = family.map(|f| match f {
+        IpFamily::Ipv4 => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpFamily::Ipv6 => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    });
+    reqwest::Client::builder().local_address(local_address)
+}
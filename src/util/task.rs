@@ -0,0 +1,80 @@
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, info};
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// Run the loop made by `task` in the background for as long as the process, logging how it
+/// ends. A panicking loop is made again and restarted after a backoff, doubling while it keeps
+/// panicking soon after starting. A loop returning, like once its owner is dropped, stays stopped.
+pub fn supervise<F, Fut>(name: &'static str, task: F)
+where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let mut backoff = MIN_BACKOFF;
+        loop {
+            let started = Instant::now();
+            match tokio::spawn(task()).await {
+                Ok(()) => {
+                    info!("background task {} stopped", name);
+                    return;
+                }
+                Err(e) if e.is_cancelled() => return,
+                Err(e) => {
+                    if started.elapsed() > MAX_BACKOFF {
+                        backoff = MIN_BACKOFF;
+                    }
+                    error!(
+                        "background task {} died, restart in {}s: {}",
+                        name,
+                        backoff.as_secs(),
+                        e
+                    );
+                    tokio::time::sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use super::supervise;
+
+    #[tokio::test]
+    async fn test_supervise() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        supervise("test", {
+            let runs = runs.clone();
+            move || {
+                let runs = runs.clone();
+                async move {
+                    // panics the first time, returns the second
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("first run");
+                    }
+                }
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        // stays stopped once returned
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+}
@@ -0,0 +1,79 @@
+use html_escape::decode_html_entities;
+use lazy_static::lazy_static;
+use serde::{Deserialize, Deserializer};
+
+lazy_static! {
+    static ref TAG_REPLACER: regex::Regex = regex::RegexBuilder::new(r#"</?[a-zA-Z][^<>]*>"#)
+        .build()
+        .unwrap();
+}
+
+/// Clean human readable text from upstream providers.
+/// Search keywords are highlighted with tags like: 【永雏塔菲】<em class=\"keyword\">taffy</em>已经开摆了
+/// and titles may be html escaped like: Tom &amp; Jerry.
+/// Therefore, remove the tags first and then decode the entities, so that escaped brackets
+/// survive as text.
+pub fn clean(s: &str) -> String {
+    decode_html_entities(&TAG_REPLACER.replace_all(s, ""))
+        .trim()
+        .to_string()
+}
+
+pub fn deserialize_text<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: String = Deserialize::deserialize(deserializer)?;
+    Ok(clean(&s))
+}
+
+pub fn deserialize_optional_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let s: Option<String> = Deserialize::deserialize(deserializer)?;
+    Ok(s.map(|s| clean(&s)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::clean;
+
+    #[test]
+    fn test_clean_bili_highlight() {
+        assert_eq!(
+            clean(r#"【永雏塔菲】<em class="keyword">taffy</em>已经开摆了"#),
+            "【永雏塔菲】taffy已经开摆了"
+        );
+        assert_eq!(
+            clean(r#"<em class="keyword">早稻叽</em>翻唱<em class="keyword">合集</em>"#),
+            "早稻叽翻唱合集"
+        );
+    }
+
+    #[test]
+    fn test_clean_entities() {
+        assert_eq!(clean("Tom &amp; Jerry"), "Tom & Jerry");
+        assert_eq!(
+            clean("I&#39;m fine &quot;really&quot;"),
+            r#"I'm fine "really""#
+        );
+        assert_eq!(clean("&lt;3 &gt;_&lt;"), "<3 >_<");
+        assert_eq!(clean("a&nbsp;b"), "a\u{a0}b");
+    }
+
+    #[test]
+    fn test_clean_mixed() {
+        assert_eq!(
+            clean(r#"<em class="keyword">R&amp;B</em> &lt;live&gt; "#),
+            "R&B <live>"
+        );
+    }
+
+    #[test]
+    fn test_clean_plain() {
+        assert_eq!(clean("夜に駆ける"), "夜に駆ける");
+        assert_eq!(clean(""), "");
+        assert_eq!(clean("1 < 2 and 3 > 2"), "1 < 2 and 3 > 2");
+    }
+}
@@ -0,0 +1,160 @@
+const api = "/api/v1/scrape";
+
+const $ = (id) => document.getElementById(id);
+
+let cursor = null;
+let lastQuery = null;
+
+async function get(path, params) {
+  const resp = await fetch(`${api}/${path}?${new URLSearchParams(params)}`);
+  if (!resp.ok) {
+    throw new Error(`${path} failed: ${resp.status} ${await resp.text()}`);
+  }
+  return resp;
+}
+
+function artistNames(artists) {
+  return (artists || []).map((a) => a.name).join(", ");
+}
+
+function row({ cover, title, meta, unavailable, stale, onClick }) {
+  const div = document.createElement("div");
+  div.className = "item";
+  div.classList.toggle("unavailable", !!unavailable);
+  div.classList.toggle("stale", !!stale);
+
+  const img = document.createElement("img");
+  img.loading = "lazy";
+  if (cover) img.src = cover;
+
+  const text = document.createElement("div");
+  const name = document.createElement("div");
+  name.textContent = title;
+  const sub = document.createElement("div");
+  sub.className = "meta";
+  sub.textContent = meta;
+  text.append(name, sub);
+
+  div.append(img, text);
+  if (!unavailable && onClick) {
+    div.addEventListener("click", () => onClick().catch(alert));
+  }
+  return div;
+}
+
+function itemRow(provider, stale, data) {
+  const [type, value] = Object.entries(data)[0];
+  switch (type) {
+    case "song":
+      return row({
+        cover: value.cover,
+        title: value.name,
+        meta: `${provider} · song · ${artistNames(value.artists)}`,
+        unavailable: value.unavailable,
+        stale,
+        onClick: () => play(provider, value),
+      });
+    case "playlist":
+    case "album":
+      return row({
+        cover: value.cover,
+        title: value.name,
+        meta: `${provider} · ${type} · ${artistNames(value.artists)}`,
+        unavailable: value.unavailable,
+        stale,
+        onClick: () => openCollection(provider, value.id),
+      });
+    default:
+      return row({
+        cover: value.avatar,
+        title: value.name,
+        meta: `${provider} · ${type}`,
+        stale,
+      });
+  }
+}
+
+async function search(more) {
+  const params = { keyword: $("keyword").value, t: $("type").value };
+  if (more && cursor) {
+    Object.assign(params, lastQuery, { cursor });
+  } else {
+    $("results").replaceChildren();
+    lastQuery = params;
+  }
+
+  $("collection").hidden = true;
+  $("results").hidden = false;
+
+  const resp = await get("search", params);
+  cursor = resp.headers.get("X-Bragi-Cursor");
+  $("more").hidden = !cursor;
+
+  for (const item of await resp.json()) {
+    $("results").append(itemRow(item.provider, item.stale, item.data));
+  }
+}
+
+async function openCollection(provider, id) {
+  const collection = await (await get("collection", { provider, id })).json();
+
+  const section = $("collection");
+  const back = document.createElement("button");
+  back.textContent = "back";
+  back.addEventListener("click", () => {
+    section.hidden = true;
+    $("results").hidden = false;
+    $("more").hidden = !cursor;
+  });
+
+  const title = document.createElement("h2");
+  title.textContent = collection.stale ? `${collection.name} (stale)` : collection.name;
+
+  section.replaceChildren(back, title);
+  for (const song of collection.songs) {
+    section.append(
+      row({
+        cover: song.cover,
+        title: song.name,
+        meta: artistNames(song.artists),
+        unavailable: song.unavailable,
+        onClick: () => play(provider, song),
+      }),
+    );
+  }
+
+  $("results").hidden = true;
+  $("more").hidden = true;
+  section.hidden = false;
+}
+
+async function play(provider, song) {
+  const streams = await (await get("stream", { provider, id: song.id })).json();
+  if (!streams.length) {
+    throw new Error(`no stream of ${provider} ${song.id}`);
+  }
+
+  $("playing").textContent = `${song.name} · ${streams[0].quality}`;
+  $("player").src = streams[0].url;
+  await $("player").play();
+}
+
+let suggestTimer = null;
+$("keyword").addEventListener("input", () => {
+  clearTimeout(suggestTimer);
+  suggestTimer = setTimeout(async () => {
+    const keyword = $("keyword").value;
+    if (!keyword) return;
+    const suggestions = await (await get("suggest", { keyword })).json();
+    $("suggestions").replaceChildren(
+      ...suggestions.map((s) => Object.assign(document.createElement("option"), { value: s.data })),
+    );
+  }, 300);
+});
+
+$("search").addEventListener("submit", (e) => {
+  e.preventDefault();
+  search(false).catch(alert);
+});
+
+$("more").addEventListener("click", () => search(true).catch(alert));